@@ -0,0 +1,337 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Derive macros accompanying `Rust` SDK for WebAssembly-based `Envoy` extensions.
+//!
+//! Use them through `envoy-sdk` with `derive` feature enabled rather than directly.
+//!
+//! ## Supported derives
+//!
+//! * `Stats` - generates a constructor of a struct holding stats,
+//!   see `envoy::host::stats::Stats` for details.
+
+#![doc(html_root_url = "https://docs.rs/envoy-sdk-derive/0.0.1")]
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, GenericArgument, Lit, Meta, NestedMeta, Path,
+    PathArguments, Type, TypeParamBound,
+};
+
+/// Generates `pub fn new(stats: &dyn Stats) -> Result<Self>` that creates every stat
+/// of the struct.
+///
+/// Kind of a stat is inferred from the type of the field, e.g. `Box<dyn Counter>`,
+/// `Rc<dyn Gauge>` or `HistogramFamily<'a>`. Stat name is the name of the field.
+///
+/// # Attributes
+///
+/// Struct attributes:
+///
+/// * `#[stats(prefix = "...")]` - prefix of all stat names.
+/// * `#[stats(crate = "...")]`  - path to `envoy-sdk` crate, `envoy` by default.
+///
+/// Field attributes:
+///
+/// * `#[stats(name = "...")]`              - stat name to use instead of the field name.
+/// * `#[stats(counter)]`, `#[stats(gauge)]`, `#[stats(histogram)]` - kind of the stat,
+///   if it cannot be inferred from the type.
+/// * `#[stats(labels("...", ...))]`        - label names of a metric family.
+/// * `#[stats(max_cardinality = N)]`       - limit on distinct label values of a metric family.
+///
+/// # Examples
+///
+/// ```
+/// use envoy::host::stats::{Counter, CounterFamily, Gauge, Histogram, Stats};
+///
+/// #[derive(Stats)]
+/// #[stats(prefix = "examples.http_filter.")]
+/// struct MyStats<'a> {
+///     requests_total: Box<dyn Counter>,
+///     #[stats(name = "active_requests")]
+///     requests_active: Box<dyn Gauge>,
+///     response_body_size_bytes: Box<dyn Histogram>,
+///     #[stats(labels("response_code"), max_cardinality = 64)]
+///     responses_total: CounterFamily<'a>,
+/// }
+///
+/// # fn action() -> envoy::host::Result<()> {
+/// let stats = MyStats::new(Stats::default())?;
+///
+/// stats.requests_total.inc()?;
+/// stats.responses_total.with_labels(&["200"])?.inc()?;
+/// # Ok(())
+/// # }
+/// ```
+#[proc_macro_derive(Stats, attributes(stats))]
+pub fn derive_stats(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_stats(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Kind of a stat.
+#[derive(Clone, Copy)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn from_ident(ident: &str) -> Option<Self> {
+        match ident {
+            "Counter" | "CounterFamily" => Some(Kind::Counter),
+            "Gauge" | "GaugeFamily" => Some(Kind::Gauge),
+            "Histogram" | "HistogramFamily" => Some(Kind::Histogram),
+            _ => None,
+        }
+    }
+}
+
+/// Options of the struct.
+struct StructOptions {
+    prefix: String,
+    krate: Path,
+}
+
+/// Options of a field.
+#[derive(Default)]
+struct FieldOptions {
+    name: Option<String>,
+    kind: Option<Kind>,
+    labels: Option<Vec<String>>,
+    max_cardinality: Option<TokenStream2>,
+}
+
+fn expand_stats(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.ident.span(),
+                    "`Stats` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "`Stats` can only be derived for structs",
+            ))
+        }
+    };
+    let options = parse_struct_options(&input)?;
+    let krate = &options.krate;
+
+    let mut initializers = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let field_options = parse_field_options(&field.attrs)?;
+        let name = format!(
+            "{}{}",
+            options.prefix,
+            field_options
+                .name
+                .clone()
+                .unwrap_or_else(|| ident.to_string())
+        );
+        let initializer = match &field_options.labels {
+            Some(labels) => {
+                let with_max_cardinality = field_options
+                    .max_cardinality
+                    .as_ref()
+                    .map(|max| quote! { .with_max_cardinality(#max) });
+                quote! {
+                    #krate::host::stats::MetricFamily::new(stats, #name, &[#(#labels),*])?
+                        #with_max_cardinality
+                }
+            }
+            None => {
+                if let Some(max_cardinality) = &field_options.max_cardinality {
+                    return Err(syn::Error::new_spanned(
+                        max_cardinality,
+                        "`max_cardinality` requires `labels`",
+                    ));
+                }
+                let kind = match field_options.kind.or_else(|| infer_kind(&field.ty)) {
+                    Some(kind) => kind,
+                    None => {
+                        return Err(syn::Error::new(
+                            field.ty.span(),
+                            "cannot infer kind of the stat, \
+                             use `#[stats(counter)]`, `#[stats(gauge)]` or `#[stats(histogram)]`",
+                        ))
+                    }
+                };
+                let method = match kind {
+                    Kind::Counter => quote! { counter },
+                    Kind::Gauge => quote! { gauge },
+                    Kind::Histogram => quote! { histogram },
+                };
+                quote! {
+                    ::core::convert::From::from(#krate::host::stats::Stats::#method(stats, #name)?)
+                }
+            }
+        };
+        initializers.push(quote! { #ident: #initializer });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    // families borrow `Stats`, so tie it to the lifetime of the struct if there is one
+    let mut lifetimes = input.generics.lifetimes();
+    let stats_lifetime = match (lifetimes.next(), lifetimes.next()) {
+        (Some(def), None) => {
+            let lifetime = &def.lifetime;
+            quote! { #lifetime }
+        }
+        _ => quote! {},
+    };
+    Ok(quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Creates all stats of the struct.
+            pub fn new(
+                stats: &#stats_lifetime dyn #krate::host::stats::Stats,
+            ) -> #krate::host::Result<Self> {
+                ::core::result::Result::Ok(#ident {
+                    #(#initializers,)*
+                })
+            }
+        }
+    })
+}
+
+fn parse_struct_options(input: &DeriveInput) -> syn::Result<StructOptions> {
+    let mut options = StructOptions {
+        prefix: String::new(),
+        krate: syn::parse_str("envoy")?,
+    };
+    for meta in stats_attributes(&input.attrs)? {
+        match &meta {
+            Meta::NameValue(nv) if nv.path.is_ident("prefix") => {
+                options.prefix = lit_str(&nv.lit)?;
+            }
+            Meta::NameValue(nv) if nv.path.is_ident("crate") => {
+                options.krate = syn::parse_str(&lit_str(&nv.lit)?)?;
+            }
+            _ => return Err(syn::Error::new_spanned(meta, "unknown `stats` attribute")),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_field_options(attrs: &[syn::Attribute]) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for meta in stats_attributes(attrs)? {
+        match &meta {
+            Meta::NameValue(nv) if nv.path.is_ident("name") => {
+                options.name = Some(lit_str(&nv.lit)?);
+            }
+            Meta::NameValue(nv) if nv.path.is_ident("max_cardinality") => match &nv.lit {
+                Lit::Int(value) => options.max_cardinality = Some(quote! { #value }),
+                lit => return Err(syn::Error::new_spanned(lit, "expected integer literal")),
+            },
+            Meta::List(list) if list.path.is_ident("labels") => {
+                let mut labels = Vec::new();
+                for nested in &list.nested {
+                    match nested {
+                        NestedMeta::Lit(lit) => labels.push(lit_str(lit)?),
+                        _ => {
+                            return Err(syn::Error::new_spanned(nested, "expected string literal"))
+                        }
+                    }
+                }
+                options.labels = Some(labels);
+            }
+            Meta::Path(path) => {
+                match path
+                    .get_ident()
+                    .and_then(|ident| match ident.to_string().as_str() {
+                        "counter" => Some(Kind::Counter),
+                        "gauge" => Some(Kind::Gauge),
+                        "histogram" => Some(Kind::Histogram),
+                        _ => None,
+                    }) {
+                    Some(kind) => options.kind = Some(kind),
+                    None => return Err(syn::Error::new_spanned(meta, "unknown `stats` attribute")),
+                }
+            }
+            _ => return Err(syn::Error::new_spanned(meta, "unknown `stats` attribute")),
+        }
+    }
+    Ok(options)
+}
+
+/// Returns contents of all `#[stats(...)]` attributes.
+fn stats_attributes(attrs: &[syn::Attribute]) -> syn::Result<Vec<Meta>> {
+    let mut metas = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("stats")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(meta) => metas.push(meta),
+                        NestedMeta::Lit(lit) => {
+                            return Err(syn::Error::new_spanned(lit, "unknown `stats` attribute"))
+                        }
+                    }
+                }
+            }
+            meta => return Err(syn::Error::new_spanned(meta, "expected `#[stats(...)]`")),
+        }
+    }
+    Ok(metas)
+}
+
+fn lit_str(lit: &Lit) -> syn::Result<String> {
+    match lit {
+        Lit::Str(value) => Ok(value.value()),
+        _ => Err(syn::Error::new_spanned(lit, "expected string literal")),
+    }
+}
+
+/// Infers kind of a stat from its type, e.g. `Box<dyn Counter>`.
+fn infer_kind(ty: &Type) -> Option<Kind> {
+    match ty {
+        Type::Path(ty) => ty.path.segments.iter().rev().find_map(|segment| {
+            Kind::from_ident(&segment.ident.to_string()).or_else(|| match &segment.arguments {
+                PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+                    GenericArgument::Type(ty) => infer_kind(ty),
+                    _ => None,
+                }),
+                _ => None,
+            })
+        }),
+        Type::TraitObject(ty) => ty.bounds.iter().find_map(|bound| match bound {
+            TypeParamBound::Trait(bound) => bound
+                .path
+                .segments
+                .last()
+                .and_then(|segment| Kind::from_ident(&segment.ident.to_string())),
+            _ => None,
+        }),
+        Type::Reference(ty) => infer_kind(&ty.elem),
+        Type::Paren(ty) => infer_kind(&ty.elem),
+        Type::Group(ty) => infer_kind(&ty.elem),
+        _ => None,
+    }
+}
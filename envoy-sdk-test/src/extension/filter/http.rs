@@ -0,0 +1,202 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fake `HTTP Filter` ops.
+//!
+//! # Examples
+//!
+//! #### Basic usage of [`FakeRequestOps`]:
+//!
+//! ```
+//! # use envoy_sdk_test as envoy_test;
+//! use envoy::extension::filter::http::{RequestFlowOps, RequestHeadersOps};
+//! use envoy_test::FakeRequestOps;
+//!
+//! # fn main() -> envoy::host::Result<()> {
+//! let ops = FakeRequestOps::default();
+//!
+//! ops.set_request_header("x-canary", "true")?;
+//! ops.send_response(403, &[], None)?;
+//!
+//! assert_eq!(ops.request_header("x-canary")?, Some("true".into()));
+//! assert_eq!(ops.local_response().map(|response| response.status_code), Some(403));
+//! assert!(!ops.is_request_resumed());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`FakeRequestOps`]: struct.FakeRequestOps.html
+
+use std::cell::{Cell, Ref, RefCell};
+
+use envoy::extension::filter::http::grpc::GrpcStatus;
+use envoy::extension::filter::http::{RequestFlowOps, RequestHeadersOps};
+use envoy::host::{ByteString, HeaderMap, Result};
+
+use crate::host::FakeStreamInfo;
+
+/// Fake request headers and request flow ops of an `HTTP Filter`.
+///
+/// Keeps request headers in memory and records what the filter has done
+/// to the request flow.
+#[derive(Debug, Default)]
+pub struct FakeRequestOps {
+    request_headers: RefCell<HeaderMap>,
+    request_resumed: Cell<bool>,
+    route_cache_clears: Cell<usize>,
+    cluster_header: Option<String>,
+    stream_info: RefCell<FakeStreamInfo>,
+    local_response: RefCell<Option<FakeLocalResponse>>,
+}
+
+/// Local response sent by an `HTTP Filter`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeLocalResponse {
+    pub status_code: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<ByteString>,
+    pub grpc_status: Option<GrpcStatus>,
+    pub grpc_message: Option<String>,
+}
+
+impl FakeRequestOps {
+    /// Sets initial properties of the stream.
+    pub fn with_stream_info(mut self, stream_info: FakeStreamInfo) -> Self {
+        self.stream_info = RefCell::new(stream_info);
+        self
+    }
+
+    /// Simulates a route with [`cluster_header`] setting, i.e. once the route cache
+    /// has been cleared, the request is routed to the cluster named by a given request header.
+    ///
+    /// [`cluster_header`]: https://www.envoyproxy.io/docs/envoy/latest/api-v3/config/route/v3/route_components.proto#envoy-v3-api-field-config-route-v3-routeaction-cluster-header
+    pub fn with_cluster_header<T>(mut self, name: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.cluster_header = Some(name.into());
+        self
+    }
+
+    /// Returns properties of the stream, including the cluster the request has been routed to.
+    pub fn stream_info(&self) -> Ref<'_, FakeStreamInfo> {
+        self.stream_info.borrow()
+    }
+
+    /// Returns `true` if the filter has resumed the request.
+    pub fn is_request_resumed(&self) -> bool {
+        self.request_resumed.get()
+    }
+
+    /// Returns how many times the filter has cleared the route cache.
+    pub fn route_cache_clears(&self) -> usize {
+        self.route_cache_clears.get()
+    }
+
+    /// Returns the local response sent by the filter, if any.
+    pub fn local_response(&self) -> Option<FakeLocalResponse> {
+        self.local_response.borrow().clone()
+    }
+
+    fn respond(&self, response: FakeLocalResponse) {
+        *self.local_response.borrow_mut() = Some(response);
+    }
+}
+
+fn to_owned(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+impl RequestFlowOps for FakeRequestOps {
+    fn resume_request(&self) -> Result<()> {
+        self.request_resumed.set(true);
+        Ok(())
+    }
+
+    fn send_response(
+        &self,
+        status_code: u32,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<()> {
+        self.respond(FakeLocalResponse {
+            status_code,
+            headers: to_owned(headers),
+            body: body.map(ByteString::from),
+            grpc_status: None,
+            grpc_message: None,
+        });
+        Ok(())
+    }
+
+    fn send_grpc_response(
+        &self,
+        grpc_status: GrpcStatus,
+        grpc_message: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> Result<()> {
+        self.respond(FakeLocalResponse {
+            status_code: 200,
+            headers: to_owned(headers),
+            body: None,
+            grpc_status: Some(grpc_status),
+            grpc_message: grpc_message.map(str::to_owned),
+        });
+        Ok(())
+    }
+}
+
+impl RequestHeadersOps for FakeRequestOps {
+    fn request_headers(&self) -> Result<HeaderMap> {
+        Ok(self.request_headers.borrow().clone())
+    }
+
+    fn request_header(&self, name: &str) -> Result<Option<ByteString>> {
+        Ok(self.request_headers.borrow().get(name).cloned())
+    }
+
+    fn set_request_headers(&self, headers: &HeaderMap) -> Result<()> {
+        *self.request_headers.borrow_mut() = headers.clone();
+        Ok(())
+    }
+
+    fn set_request_header_bytes(&self, name: &str, value: &[u8]) -> Result<()> {
+        self.request_headers.borrow_mut().insert(name, value);
+        Ok(())
+    }
+
+    fn remove_request_header(&self, name: &str) -> Result<()> {
+        self.request_headers.borrow_mut().remove(name);
+        Ok(())
+    }
+
+    fn clear_route_cache(&self) -> Result<()> {
+        self.route_cache_clears
+            .set(self.route_cache_clears.get() + 1);
+        let cluster = self
+            .cluster_header
+            .as_ref()
+            .and_then(|name| self.request_headers.borrow().get(name).cloned());
+        if let Some(cluster) = cluster {
+            self.stream_info
+                .borrow_mut()
+                .cluster()
+                .name(cluster.to_string());
+        }
+        Ok(())
+    }
+}
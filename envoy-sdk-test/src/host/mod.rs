@@ -15,11 +15,15 @@
 //! Fake `Envoy` `Host APIs` for use in unit tests.
 
 pub use self::http::client::{FakeHttpClient, FakeHttpClientRequest, FakeHttpClientResponse};
+pub use self::shared_data::FakeSharedData;
+pub use self::shared_queue::FakeSharedQueue;
 pub use self::stats::FakeStats;
 pub use self::stream_info::FakeStreamInfo;
 pub use self::time::FakeClock;
 
 pub mod http;
+pub mod shared_data;
+pub mod shared_queue;
 pub mod stats;
 pub mod stream_info;
 pub mod time;
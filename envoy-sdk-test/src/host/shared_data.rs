@@ -0,0 +1,105 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fake `Shared Data API`.
+//!
+//! # Examples
+//!
+//! #### Basic usage of [`FakeSharedData`]:
+//!
+//! ```
+//! # use envoy_sdk_test as envoy_test;
+//! use envoy::host::SharedData;
+//! use envoy::host::error::is_cas_mismatch;
+//! use envoy_test::FakeSharedData;
+//!
+//! # fn main() -> envoy::host::Result<()> {
+//! let shared_data = FakeSharedData::default();
+//!
+//! shared_data.set("my_key", b"v1", None)?;
+//!
+//! let (value, version) = shared_data.get("my_key")?;
+//! assert_eq!(value, Some("v1".into()));
+//!
+//! shared_data.set("my_key", b"v2", version)?;
+//!
+//! // stale version
+//! let err = shared_data.set("my_key", b"v3", version).unwrap_err();
+//! assert!(is_cas_mismatch(&err));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`FakeSharedData`]: struct.FakeSharedData.html
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use envoy::host::error::CasMismatchError;
+use envoy::host::shared_data::{OptimisticLockVersion, SharedData};
+use envoy::host::{ByteString, Result};
+
+/// Fake `Shared Data API`.
+///
+/// Like in `Envoy`, every update of a key increments its version,
+/// and an update of an existing key with a stale version fails with `CasMismatchError`.
+///
+/// Share a single instance between multiple extensions to simulate
+/// multiple worker threads.
+#[derive(Debug, Default)]
+pub struct FakeSharedData {
+    entries: RefCell<HashMap<String, (ByteString, OptimisticLockVersion)>>,
+}
+
+impl SharedData for FakeSharedData {
+    /// Returns shared data by key.
+    fn get(&self, key: &str) -> Result<(Option<ByteString>, Option<OptimisticLockVersion>)> {
+        Ok(match self.entries.borrow().get(key) {
+            Some((value, version)) => (Some(value.clone()), Some(*version)),
+            None => (None, None),
+        })
+    }
+
+    /// Shares data under a given key.
+    fn set(&self, key: &str, value: &[u8], version: Option<OptimisticLockVersion>) -> Result<()> {
+        let mut entries = self.entries.borrow_mut();
+        let current = entries.get(key).map(|(_, version)| *version);
+        // `Envoy` only checks the version of an existing key, `0` means "no version check"
+        if let (Some(version), Some(current)) = (version, current) {
+            if version != 0 && version != current {
+                return Err(CasMismatchError::new(key).into());
+            }
+        }
+        let next = current.unwrap_or(0) + 1;
+        entries.insert(key.to_owned(), (value.into(), next));
+        Ok(())
+    }
+}
+
+impl FakeSharedData {
+    /// Returns the number of keys.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    /// Returns `true` if there are no keys.
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    /// Removes all keys.
+    pub fn reset(&self) {
+        self.entries.borrow_mut().clear();
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fake `Shared Queue API`.
+//!
+//! # Examples
+//!
+//! #### Basic usage of [`FakeSharedQueue`]:
+//!
+//! ```
+//! # use envoy_sdk_test as envoy_test;
+//! use envoy::host::SharedQueue;
+//! use envoy_test::FakeSharedQueue;
+//!
+//! # fn main() -> envoy::host::Result<()> {
+//! let shared_queue = FakeSharedQueue::new("my_vm");
+//!
+//! assert_eq!(shared_queue.lookup("my_vm", "my_queue")?, None);
+//!
+//! let queue_handle = shared_queue.register("my_queue")?;
+//! assert_eq!(shared_queue.lookup("my_vm", "my_queue")?, Some(queue_handle));
+//!
+//! shared_queue.enqueue(queue_handle, b"some value")?;
+//! assert_eq!(shared_queue.dequeue(queue_handle)?, Some("some value".into()));
+//! assert_eq!(shared_queue.dequeue(queue_handle)?, None);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`FakeSharedQueue`]: struct.FakeSharedQueue.html
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use envoy::error::bail;
+use envoy::host::shared_queue::{SharedQueue, SharedQueueHandle};
+use envoy::host::{ByteString, Result};
+
+/// Fake `Shared Queue API`.
+///
+/// Queues registered through a [`FakeSharedQueue`] belong to a single VM,
+/// the one with the `vm_id` the fake has been created with.
+///
+/// Share a single instance between multiple extensions to simulate
+/// multiple worker threads.
+///
+/// [`FakeSharedQueue`]: struct.FakeSharedQueue.html
+#[derive(Debug, Default)]
+pub struct FakeSharedQueue {
+    vm_id: String,
+    queues: RefCell<Vec<FakeQueue>>,
+}
+
+#[derive(Debug)]
+struct FakeQueue {
+    name: String,
+    handle: SharedQueueHandle,
+    values: VecDeque<ByteString>,
+}
+
+impl SharedQueue for FakeSharedQueue {
+    /// Registers a queue under a given name.
+    fn register(&self, name: &str) -> Result<SharedQueueHandle> {
+        let mut queues = self.queues.borrow_mut();
+        if let Some(queue) = queues.iter().find(|queue| queue.name == name) {
+            return Ok(queue.handle);
+        }
+        let handle = SharedQueueHandle::from(queues.len() as u32 + 1);
+        queues.push(FakeQueue {
+            name: name.to_owned(),
+            handle,
+            values: VecDeque::new(),
+        });
+        Ok(handle)
+    }
+
+    /// Looks up a queue registered by a given VM.
+    fn lookup(&self, vm_id: &str, name: &str) -> Result<Option<SharedQueueHandle>> {
+        if vm_id != self.vm_id {
+            return Ok(None);
+        }
+        Ok(self
+            .queues
+            .borrow()
+            .iter()
+            .find(|queue| queue.name == name)
+            .map(|queue| queue.handle))
+    }
+
+    /// Removes the first value from a queue.
+    fn dequeue(&self, queue_id: SharedQueueHandle) -> Result<Option<ByteString>> {
+        let mut queues = self.queues.borrow_mut();
+        match queues.iter_mut().find(|queue| queue.handle == queue_id) {
+            Some(queue) => Ok(queue.values.pop_front()),
+            None => bail!("unknown shared queue: {}", queue_id),
+        }
+    }
+
+    /// Appends a value to a queue.
+    fn enqueue(&self, queue_id: SharedQueueHandle, value: &[u8]) -> Result<()> {
+        let mut queues = self.queues.borrow_mut();
+        match queues.iter_mut().find(|queue| queue.handle == queue_id) {
+            Some(queue) => {
+                queue.values.push_back(value.into());
+                Ok(())
+            }
+            None => bail!("unknown shared queue: {}", queue_id),
+        }
+    }
+}
+
+impl FakeSharedQueue {
+    /// Returns a new `FakeSharedQueue` of a VM with a given `vm_id`.
+    pub fn new<T>(vm_id: T) -> Self
+    where
+        T: Into<String>,
+    {
+        FakeSharedQueue {
+            vm_id: vm_id.into(),
+            queues: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns the number of values in a queue with a given name.
+    pub fn len(&self, name: &str) -> usize {
+        self.queues
+            .borrow()
+            .iter()
+            .find(|queue| queue.name == name)
+            .map_or(0, |queue| queue.values.len())
+    }
+
+    /// Removes all queues, e.g. to simulate a restart of the VM.
+    pub fn reset(&self) {
+        self.queues.borrow_mut().clear();
+    }
+}
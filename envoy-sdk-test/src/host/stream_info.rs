@@ -63,9 +63,11 @@
 //!
 //! [`FakeStreamInfo`]: struct.FakeStreamInfo.html
 
+use std::collections::HashMap;
 use std::time::{Duration, SystemTime};
 
-use envoy::extension::access_logger;
+use envoy::extension::access_logger::{self, LogEntryKind};
+use envoy::extension::filter::{http, network};
 use envoy::host::stream_info::{ResponseFlags, StreamInfo, TrafficDirection};
 use envoy::host::{self, ByteString, HeaderMap};
 
@@ -74,6 +76,7 @@ use crate::host::http::FakeHttpMessage;
 /// Represents fake `Stream Info`.
 #[derive(Debug, Default, Clone)]
 pub struct FakeStreamInfo {
+    entry_kind: Option<LogEntryKind>,
     connection: Option<FakeConnectionInfo>,
     request: Option<FakeRequestInfo>,
     response: Option<FakeResponseInfo>,
@@ -91,6 +94,7 @@ pub struct FakeStreamInfo {
 struct FakeConnectionInfo {
     id: u64,
     requested_server_name: String,
+    termination_details: Option<String>,
     tls: Option<FakeTlsInfo>,
 }
 
@@ -154,6 +158,7 @@ struct FakeListenerInfo {
 #[derive(Debug, Default, Clone)]
 struct FakeRouteInfo {
     name: String,
+    metadata: HashMap<(String, String), String>,
 }
 
 /// Represents `cluster` info.
@@ -282,6 +287,38 @@ impl FakeStreamInfo {
         self
     }
 
+    /// Sets the kind of the access log entry, i.e. HTTP request or TCP connection.
+    ///
+    /// Defaults to [`LogEntryKind::Http`]. In case of [`LogEntryKind::Tcp`], HTTP headers
+    /// and trailers are not available to an `Access Logger`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use envoy_sdk_test as envoy_test;
+    /// use std::time::Duration;
+    /// use envoy::extension::access_logger::LogEntryKind;
+    /// use envoy_test::FakeStreamInfo;
+    ///
+    /// let fake_info = FakeStreamInfo::new().with(|info| {
+    ///     info.entry_kind(LogEntryKind::Tcp);
+    ///     info.connection()
+    ///         .termination_details("idle_timeout");
+    ///     info.request()
+    ///         .size(1024)
+    ///         .duration(Duration::from_secs(60));
+    ///     info.response()
+    ///         .size(2048);
+    /// });
+    /// ```
+    ///
+    /// [`LogEntryKind::Http`]: https://docs.rs/envoy-sdk/latest/envoy_sdk/extension/access_logger/enum.LogEntryKind.html#variant.Http
+    /// [`LogEntryKind::Tcp`]: https://docs.rs/envoy-sdk/latest/envoy_sdk/extension/access_logger/enum.LogEntryKind.html#variant.Tcp
+    pub fn entry_kind(&mut self, kind: LogEntryKind) -> &mut Self {
+        self.entry_kind = Some(kind);
+        self
+    }
+
     /// Returns a builder for `connection` properties.
     pub fn connection(&mut self) -> FakeConnectionInfoBuilder<'_> {
         FakeConnectionInfoBuilder {
@@ -427,6 +464,17 @@ impl<'a> FakeConnectionInfoBuilder<'a> {
         self
     }
 
+    /// Sets the value of connection `termination_details` property.
+    pub fn termination_details<T>(&mut self, value: T) -> &mut Self
+    where
+        T: AsRef<str>,
+    {
+        self.connection
+            .get_or_insert_with(Default::default)
+            .termination_details = Some(value.as_ref().to_owned());
+        self
+    }
+
     /// Returns a builder for `tls` properties of the downstream connection.
     pub fn tls(&mut self) -> FakeTlsInfoBuilder<'_> {
         FakeTlsInfoBuilder {
@@ -527,6 +575,20 @@ impl<'a> FakeRequestInfoBuilder<'a> {
         self
     }
 
+    /// Sets the value of an HTTP request trailer.
+    pub fn trailer<K, V>(&mut self, name: K, value: V) -> &mut Self
+    where
+        K: Into<ByteString>,
+        V: Into<ByteString>,
+    {
+        self.request
+            .get_or_insert_with(Default::default)
+            .message
+            .trailers
+            .insert(name, value);
+        self
+    }
+
     /// Sets the value of `:method` pseudo-header.
     pub fn method<V>(&mut self, value: V) -> &mut Self
     where
@@ -793,6 +855,23 @@ impl<'a> FakeRouteInfoBuilder<'a> {
         self.route.get_or_insert_with(Default::default).name = value.as_ref().to_owned();
         self
     }
+
+    /// Sets the value of a given key in the route metadata under a given filter namespace.
+    pub fn metadata<F, K, V>(&mut self, filter: F, key: K, value: V) -> &mut Self
+    where
+        F: AsRef<str>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.route
+            .get_or_insert_with(Default::default)
+            .metadata
+            .insert(
+                (filter.as_ref().to_owned(), key.as_ref().to_owned()),
+                value.as_ref().to_owned(),
+            );
+        self
+    }
 }
 
 impl<'a> FakeClusterInfoBuilder<'a> {
@@ -856,6 +935,11 @@ impl StreamInfo for FakeStreamInfo {
                 .as_ref()
                 .map(|con| &con.requested_server_name)
                 .map(Encoder::encode_str),
+            ["connection", "termination_details"] => self
+                .connection
+                .as_ref()
+                .and_then(|con| con.termination_details.as_ref())
+                .map(Encoder::encode_str),
             ["connection", "tls_version"] => self
                 .connection
                 .as_ref()
@@ -1153,6 +1237,15 @@ impl StreamInfo for FakeStreamInfo {
                 .as_ref()
                 .map(|route| &route.name)
                 .map(Encoder::encode_str),
+            ["route_metadata", "filter_metadata", filter, key] => self
+                .route
+                .as_ref()
+                .and_then(|route| {
+                    route
+                        .metadata
+                        .get(&((*filter).to_owned(), (*key).to_owned()))
+                })
+                .map(Encoder::encode_str),
             // cluster
             ["cluster_name"] => self
                 .cluster
@@ -1185,8 +1278,21 @@ impl StreamInfo for FakeStreamInfo {
     }
 }
 
+impl FakeStreamInfo {
+    fn is_tcp(&self) -> bool {
+        self.entry_kind == Some(LogEntryKind::Tcp)
+    }
+}
+
 impl access_logger::LogOps for FakeStreamInfo {
+    fn entry_kind(&self) -> host::Result<LogEntryKind> {
+        Ok(self.entry_kind.unwrap_or(LogEntryKind::Http))
+    }
+
     fn request_headers(&self) -> host::Result<HeaderMap> {
+        if self.is_tcp() {
+            return Ok(HeaderMap::default());
+        }
         Ok(self
             .request
             .as_ref()
@@ -1195,6 +1301,9 @@ impl access_logger::LogOps for FakeStreamInfo {
     }
 
     fn request_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        if self.is_tcp() {
+            return Ok(None);
+        }
         Ok(self
             .request
             .as_ref()
@@ -1203,6 +1312,9 @@ impl access_logger::LogOps for FakeStreamInfo {
     }
 
     fn response_headers(&self) -> host::Result<HeaderMap> {
+        if self.is_tcp() {
+            return Ok(HeaderMap::default());
+        }
         Ok(self
             .response
             .as_ref()
@@ -1211,6 +1323,9 @@ impl access_logger::LogOps for FakeStreamInfo {
     }
 
     fn response_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        if self.is_tcp() {
+            return Ok(None);
+        }
         Ok(self
             .response
             .as_ref()
@@ -1219,6 +1334,9 @@ impl access_logger::LogOps for FakeStreamInfo {
     }
 
     fn response_trailers(&self) -> host::Result<HeaderMap> {
+        if self.is_tcp() {
+            return Ok(HeaderMap::default());
+        }
         Ok(self
             .response
             .as_ref()
@@ -1227,6 +1345,9 @@ impl access_logger::LogOps for FakeStreamInfo {
     }
 
     fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        if self.is_tcp() {
+            return Ok(None);
+        }
         Ok(self
             .response
             .as_ref()
@@ -1239,6 +1360,59 @@ impl access_logger::LogOps for FakeStreamInfo {
     }
 }
 
+impl http::ExchangeCompleteOps for FakeStreamInfo {
+    fn request_headers(&self) -> host::Result<HeaderMap> {
+        access_logger::LogOps::request_headers(self)
+    }
+
+    fn request_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        access_logger::LogOps::request_header(self, name)
+    }
+
+    fn request_trailers(&self) -> host::Result<HeaderMap> {
+        Ok(self
+            .request
+            .as_ref()
+            .map(|request| request.message.trailers.clone())
+            .unwrap_or_default())
+    }
+
+    fn request_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        Ok(self
+            .request
+            .as_ref()
+            .and_then(|request| request.message.trailers.get(name).cloned()))
+    }
+
+    fn response_headers(&self) -> host::Result<HeaderMap> {
+        access_logger::LogOps::response_headers(self)
+    }
+
+    fn response_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        access_logger::LogOps::response_header(self, name)
+    }
+
+    fn response_trailers(&self) -> host::Result<HeaderMap> {
+        access_logger::LogOps::response_trailers(self)
+    }
+
+    fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        access_logger::LogOps::response_trailer(self, name)
+    }
+
+    fn stream_info(&self) -> &dyn StreamInfo {
+        self
+    }
+}
+
+impl network::ConnectionCompleteOps for FakeStreamInfo {}
+
+impl network::ConnectionInfoOps for FakeStreamInfo {
+    fn stream_info(&self) -> &dyn StreamInfo {
+        self
+    }
+}
+
 struct Encoder;
 
 impl Encoder {
@@ -18,16 +18,24 @@
 //!
 //! * [`FakeClock`]
 //! * [`FakeHttpClient`]
+//! * [`FakeRequestOps`]
+//! * [`FakeSharedData`]
+//! * [`FakeSharedQueue`]
 //! * [`FakeStats`]
 //! * [`FakeStreamInfo`]
 //!
 //! [`FakeClock`]: host/time/index.html
 //! [`FakeHttpClient`]: host/http/client/index.html
+//! [`FakeRequestOps`]: extension/filter/http/index.html
+//! [`FakeSharedData`]: host/shared_data/index.html
+//! [`FakeSharedQueue`]: host/shared_queue/index.html
 //! [`FakeStats`]: host/stats/index.html
 //! [`FakeStreamInfo`]: host/stream_info/index.html
 
 #![doc(html_root_url = "https://docs.rs/envoy-sdk-test/0.0.1")]
 
+pub use self::extension::*;
 pub use self::host::*;
 
+pub mod extension;
 pub mod host;
@@ -0,0 +1,277 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use envoy::extension::access_logger::filter::{
+    FilteredAccessLogger, HeaderMatch, HeaderMatcher, LogFilter, Sampling, ValueRange,
+};
+use envoy::extension::access_logger::{ConfigureOps, LogOps};
+use envoy::extension::{AccessLogger, ConfigStatus, InstanceId, Result};
+use envoy::host::stream_info::ResponseFlags;
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::FakeStreamInfo;
+
+fn request(status_code: u16, duration_ms: u64) -> FakeStreamInfo {
+    FakeStreamInfo::new().with(|info| {
+        info.request()
+            .header("user-agent", "curl/7.64.1")
+            .duration(Duration::from_millis(duration_ms));
+        info.response().status_code(status_code);
+    })
+}
+
+#[test]
+fn test_log_filter_status_code_and_duration_ranges() -> Result<()> {
+    let server_errors = LogFilter::StatusCode(ValueRange::between(500, 599));
+    assert!(server_errors.matches(&request(503, 10))?);
+    assert!(!server_errors.matches(&request(404, 10))?);
+    assert!(!server_errors.matches(&FakeStreamInfo::new())?);
+
+    let slow = LogFilter::DurationMs(ValueRange::at_least(1000));
+    assert!(slow.matches(&request(200, 1000))?);
+    assert!(!slow.matches(&request(200, 999))?);
+    assert!(!slow.matches(&FakeStreamInfo::new())?);
+    Ok(())
+}
+
+#[test]
+fn test_log_filter_response_flags() -> Result<()> {
+    let no_healthy_upstream = FakeStreamInfo::new().with(|info| {
+        info.response()
+            .response_flags(ResponseFlags::NO_HEALTHY_UPSTREAM);
+    });
+
+    let any_flag = LogFilter::ResponseFlags(ResponseFlags::empty());
+    assert!(any_flag.matches(&no_healthy_upstream)?);
+    assert!(!any_flag.matches(&request(200, 1))?);
+
+    let upstream_failure = LogFilter::ResponseFlags(ResponseFlags::UPSTREAM_CONNECTION_FAILURE);
+    assert!(!upstream_failure.matches(&no_healthy_upstream)?);
+    Ok(())
+}
+
+#[test]
+fn test_log_filter_request_headers() -> Result<()> {
+    let info = request(200, 1);
+
+    let curl = LogFilter::Header(HeaderMatcher::new(
+        "user-agent",
+        HeaderMatch::Prefix("curl/".into()),
+    ));
+    assert!(curl.matches(&info)?);
+
+    let not_curl = LogFilter::Header(HeaderMatcher {
+        invert: true,
+        ..HeaderMatcher::new("user-agent", HeaderMatch::Contains("curl".into()))
+    });
+    assert!(!not_curl.matches(&info)?);
+
+    let debug = LogFilter::Header(HeaderMatcher::new("x-debug", HeaderMatch::Present(true)));
+    assert!(!debug.matches(&info)?);
+
+    let exact = LogFilter::Header(HeaderMatcher::new(
+        "user-agent",
+        HeaderMatch::Exact("curl/7.64.1".into()),
+    ));
+    assert!(exact.matches(&info)?);
+    Ok(())
+}
+
+#[test]
+fn test_log_filter_samples_consistently_by_request_id() -> Result<()> {
+    let sampling = LogFilter::Sampling(Sampling::new(50.0));
+
+    let mut sampled = 0;
+    for i in 0..1000 {
+        let info = FakeStreamInfo::new().with(|info| {
+            info.request()
+                .header("x-request-id", format!("request-{}", i));
+        });
+        let decision = sampling.matches(&info)?;
+        // same request id, same decision
+        assert_eq!(decision, sampling.matches(&info)?);
+        if decision {
+            sampled += 1;
+        }
+    }
+    assert!((400..600).contains(&sampled), "sampled {} of 1000", sampled);
+
+    let all = LogFilter::Sampling(Sampling::new(100.0).with_independent_randomness());
+    let none = LogFilter::Sampling(Sampling::new(0.0).with_independent_randomness());
+    for _ in 0..100 {
+        assert!(all.matches(&FakeStreamInfo::new())?);
+        assert!(!none.matches(&FakeStreamInfo::new())?);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_log_filter_combination() -> Result<()> {
+    let filter = LogFilter::Or(vec![
+        LogFilter::StatusCode(ValueRange::at_least(500)),
+        LogFilter::And(vec![
+            LogFilter::DurationMs(ValueRange::at_least(100)),
+            LogFilter::Not(Box::new(LogFilter::StatusCode(ValueRange::at_most(299)))),
+        ]),
+    ]);
+
+    assert!(filter.matches(&request(500, 1))?);
+    assert!(filter.matches(&request(404, 100))?);
+    assert!(!filter.matches(&request(404, 99))?);
+    assert!(!filter.matches(&request(200, 100))?);
+    assert!(LogFilter::And(vec![]).matches(&request(200, 1))?);
+    assert!(!LogFilter::Or(vec![]).matches(&request(200, 1))?);
+    Ok(())
+}
+
+#[test]
+fn test_log_filter_from_json() -> Result<()> {
+    let filter = LogFilter::from_json(
+        br#"{
+            "or": [
+                { "status_code": { "min": 500 } },
+                { "response_flags": ["UH", "UF"] },
+                { "duration_ms": { "min": 1000, "max": 2000 } },
+                { "not": { "header": { "name": "x-debug", "present": true } } },
+                { "header": { "name": "user-agent", "exact": "curl", "invert": true } },
+                { "sampling": { "percent": 10.0 } }
+            ]
+        }"#,
+    )?;
+
+    assert_eq!(
+        filter,
+        LogFilter::Or(vec![
+            LogFilter::StatusCode(ValueRange::at_least(500)),
+            LogFilter::ResponseFlags(
+                ResponseFlags::NO_HEALTHY_UPSTREAM | ResponseFlags::UPSTREAM_CONNECTION_FAILURE
+            ),
+            LogFilter::DurationMs(ValueRange::between(1000, 2000)),
+            LogFilter::Not(Box::new(LogFilter::Header(HeaderMatcher::new(
+                "x-debug",
+                HeaderMatch::Present(true)
+            )))),
+            LogFilter::Header(HeaderMatcher {
+                invert: true,
+                ..HeaderMatcher::new("user-agent", HeaderMatch::Exact("curl".into()))
+            }),
+            LogFilter::Sampling(Sampling::new(10.0)),
+        ])
+    );
+    Ok(())
+}
+
+#[test]
+fn test_log_filter_invalid_json() {
+    assert!(LogFilter::from_json(br#"{ "response_flags": ["XYZ"] }"#).is_err());
+    assert!(LogFilter::from_json(br#"{ "status_code": { "from": 500 } }"#).is_err());
+    assert!(LogFilter::from_json(br#"{ "unknown": {} }"#).is_err());
+}
+
+struct NoopConfigureOps;
+
+impl ConfigureOps for NoopConfigureOps {}
+
+#[derive(Default)]
+struct TestAccessLogger {
+    config: String,
+    logged: usize,
+}
+
+impl AccessLogger for TestAccessLogger {
+    fn name() -> &'static str {
+        "test_access_logger"
+    }
+
+    fn on_configure(
+        &mut self,
+        config: envoy::host::ByteString,
+        _ops: &dyn ConfigureOps,
+    ) -> Result<ConfigStatus> {
+        self.config = config.to_string();
+        Ok(ConfigStatus::Accepted)
+    }
+
+    fn on_log(&mut self, _ops: &dyn LogOps) -> Result<()> {
+        self.logged += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_log_filter_applies_before_user_logic() -> Result<()> {
+    let mut logger = FilteredAccessLogger::new(InstanceId::from(1), TestAccessLogger::default());
+    assert_eq!(
+        FilteredAccessLogger::<TestAccessLogger>::name(),
+        "test_access_logger"
+    );
+
+    // no filter - log everything
+    logger.on_log(&request(200, 1))?;
+    assert_eq!(logger.logger().logged, 1);
+
+    let config = r#"{"filter": {"status_code": {"min": 500}}, "collector": "logs"}"#;
+    logger.on_configure(config.into(), &NoopConfigureOps)?;
+    assert_eq!(
+        logger.filter(),
+        Some(&LogFilter::StatusCode(ValueRange::at_least(500)))
+    );
+    assert_eq!(logger.logger().config, config);
+
+    logger.on_log(&request(200, 1))?;
+    assert_eq!(logger.logger().logged, 1);
+    logger.on_log(&request(502, 1))?;
+    assert_eq!(logger.logger().logged, 2);
+
+    // configuration without a filter keeps the filter
+    logger.on_configure(r#"{"collector": "logs"}"#.into(), &NoopConfigureOps)?;
+    assert!(logger.filter().is_some());
+
+    // configuration that is not a JSON object keeps the filter
+    logger.on_configure("collector=logs".into(), &NoopConfigureOps)?;
+    assert!(logger.filter().is_some());
+    assert_eq!(logger.logger().config, "collector=logs");
+
+    // `null` removes the filter
+    logger.on_configure(r#"{"filter": null}"#.into(), &NoopConfigureOps)?;
+    assert_eq!(logger.filter(), None);
+
+    assert!(logger
+        .on_configure(
+            r#"{"filter": {"status_code": 500}}"#.into(),
+            &NoopConfigureOps
+        )
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_log_filter_seeds_sampling_by_instance_id() -> Result<()> {
+    let sampling = || LogFilter::Sampling(Sampling::new(50.0).with_independent_randomness());
+    let decisions = |instance_id: u32| -> Result<Vec<bool>> {
+        let logger = FilteredAccessLogger::with_filter(
+            InstanceId::from(instance_id),
+            TestAccessLogger::default(),
+            sampling(),
+        );
+        let filter = logger.filter().unwrap();
+        (0..64).map(|_| filter.matches(&request(200, 1))).collect()
+    };
+
+    assert_eq!(decisions(1)?, decisions(1)?);
+    assert_ne!(decisions(1)?, decisions(2)?);
+    Ok(())
+}
@@ -0,0 +1,148 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use envoy::extension::access_logger::format::LogFormat;
+use envoy::extension::Result;
+use envoy::host::stream_info::ResponseFlags;
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::FakeStreamInfo;
+
+fn fake_stream_info() -> FakeStreamInfo {
+    FakeStreamInfo::new().with(|info| {
+        info.request()
+            .method("GET")
+            .path("/index.html")
+            .protocol("HTTP/1.1")
+            .header(":authority", "example.org")
+            .header("user-agent", "curl/7.64.1")
+            .header("x-request-id", "abcd-1234")
+            .time(UNIX_EPOCH + Duration::from_millis(1_604_000_000_123))
+            .duration(Duration::from_millis(42))
+            .size(10);
+        info.response()
+            .status_code(200)
+            .header("x-envoy-upstream-service-time", "37")
+            .trailer("grpc-message", "OK")
+            .size(1024)
+            .response_flags(ResponseFlags::empty());
+        info.upstream().address("10.0.0.1:8080");
+        info.cluster().name("backend");
+    })
+}
+
+#[test]
+fn test_log_format_default() -> Result<()> {
+    let format = LogFormat::default();
+
+    let line = format.render(&fake_stream_info())?;
+
+    assert_eq!(
+        line,
+        "[2020-10-29T19:33:20.123Z] \"GET /index.html HTTP/1.1\" 200 - 10 1024 42 37 \
+         \"-\" \"curl/7.64.1\" \"abcd-1234\" \"example.org\" \"10.0.0.1:8080\"\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_log_format_headers_with_fallback_and_length_limit() -> Result<()> {
+    let format = LogFormat::text(
+        "%REQ(X-ENVOY-ORIGINAL-PATH?:PATH)% %REQ(USER-AGENT):4% %RESP(X-MISSING)% %TRAILER(GRPC-MESSAGE)%",
+    )?;
+
+    let line = format.render(&fake_stream_info())?;
+
+    assert_eq!(line, "/index.html curl - OK");
+    Ok(())
+}
+
+#[test]
+fn test_log_format_start_time_with_custom_format() -> Result<()> {
+    let format = LogFormat::text("%START_TIME(%F %T.%3f %z|%s|%%)%")?;
+
+    let line = format.render(&fake_stream_info())?;
+
+    assert_eq!(line, "2020-10-29 19:33:20.123 +0000|1604000000|%");
+    Ok(())
+}
+
+#[test]
+fn test_log_format_response_flags_and_missing_values() -> Result<()> {
+    let info = FakeStreamInfo::new().with(|info| {
+        info.response()
+            .response_flags(ResponseFlags::NO_HEALTHY_UPSTREAM);
+    });
+    let format = LogFormat::text(
+        "%RESPONSE_CODE% %RESPONSE_FLAGS% %DURATION% %START_TIME% %UPSTREAM_HOST%",
+    )?;
+
+    let line = format.render(&info)?;
+
+    assert_eq!(line, "0 UH - - -");
+    Ok(())
+}
+
+#[test]
+fn test_log_format_json() -> Result<()> {
+    let format = LogFormat::json(vec![
+        ("method", "%REQ(:METHOD)%"),
+        ("status", "%RESPONSE_CODE%"),
+        ("duration", "%DURATION%"),
+        ("forwarded_for", "%REQ(X-FORWARDED-FOR)%"),
+        ("upstream", "%UPSTREAM_CLUSTER%/%UPSTREAM_HOST%"),
+        ("agent", "\"%REQ(USER-AGENT)%\""),
+    ])?;
+
+    let line = format.render(&fake_stream_info())?;
+
+    assert_eq!(
+        line,
+        r#"{"method":"GET","status":200,"duration":42,"forwarded_for":null,"upstream":"backend/10.0.0.1:8080","agent":"\"curl/7.64.1\""}"#
+            .to_owned()
+            + "\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_log_format_invalid() {
+    for format in &[
+        "%REQ(:METHOD)",
+        "%UNKNOWN_OPERATOR%",
+        "%REQ()%",
+        "%REQ(:PATH):abc%",
+        "%RESPONSE_CODE:10%",
+        "%START_TIME(%Q)%",
+        "%REQ%",
+    ] {
+        assert!(
+            LogFormat::text(format).is_err(),
+            "format {:?} must be rejected",
+            format
+        );
+    }
+}
+
+#[test]
+fn test_log_format_literal_text() -> Result<()> {
+    let format = LogFormat::text("no operators here\n")?;
+
+    let line = format.render(&FakeStreamInfo::new())?;
+
+    assert_eq!(line, "no operators here\n");
+    Ok(())
+}
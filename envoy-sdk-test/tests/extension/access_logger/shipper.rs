@@ -0,0 +1,294 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use envoy::extension::access_logger::shipper::{LogShipper, LogShipperConfig};
+use envoy::extension::Result;
+use envoy::host::{HttpClientRequestHandle, Stats};
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{FakeClock, FakeHttpClient, FakeHttpClientResponse, FakeStats};
+
+fn config() -> LogShipperConfig {
+    LogShipperConfig {
+        cluster: "log_collector".into(),
+        path: "/logs".into(),
+        headers: vec![("x-api-key".into(), "secret".into())],
+        max_batch_entries: 3,
+        max_batch_bytes: 1024,
+        flush_interval: Duration::from_secs(1),
+        max_retries: 2,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(150),
+        max_buffered_bytes: 16,
+        stat_prefix: "test".into(),
+        ..LogShipperConfig::default()
+    }
+}
+
+fn respond(
+    shipper: &mut LogShipper,
+    request_id: HttpClientRequestHandle,
+    status: &str,
+) -> Result<bool> {
+    let response = FakeHttpClientResponse::builder()
+        .header(":status", status)
+        .build();
+    shipper.on_http_call_response(request_id, 1, &response)
+}
+
+fn counter(stats: &FakeStats, name: &str) -> Result<u64> {
+    stats.counter(name)?.value()
+}
+
+#[test]
+fn test_log_shipper_sends_batch_when_max_entries_is_reached() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut shipper = LogShipper::new(config(), &http_client, &clock, &stats)?;
+
+    shipper.push("a\n")?;
+    shipper.push("b\n")?;
+    assert!(http_client.drain_pending_requests().is_empty());
+
+    shipper.push("c\n")?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0].request;
+    assert_eq!(request.upstream, "log_collector");
+    assert_eq!(request.message.body, "a\nb\nc\n");
+    assert_eq!(
+        request
+            .message
+            .headers
+            .get(":method")
+            .map(|v| v.to_string()),
+        Some("POST".to_string())
+    );
+    assert_eq!(
+        request.message.headers.get(":path").map(|v| v.to_string()),
+        Some("/logs".to_string())
+    );
+    assert_eq!(
+        request
+            .message
+            .headers
+            .get(":authority")
+            .map(|v| v.to_string()),
+        Some("log_collector".to_string())
+    );
+    assert_eq!(
+        request
+            .message
+            .headers
+            .get("x-api-key")
+            .map(|v| v.to_string()),
+        Some("secret".to_string())
+    );
+    assert!(!shipper.is_idle());
+
+    assert!(respond(&mut shipper, requests[0].handle, "200")?);
+    assert!(shipper.is_idle());
+    assert_eq!(counter(&stats, "test.sent")?, 3);
+    assert_eq!(counter(&stats, "test.dropped")?, 0);
+    assert_eq!(counter(&stats, "test.retried")?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_log_shipper_sends_batch_when_flush_interval_elapses() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut shipper = LogShipper::new(config(), &http_client, &clock, &stats)?;
+
+    shipper.push("a\n")?;
+    clock.advance(Duration::from_millis(999));
+    shipper.poll()?;
+    assert!(http_client.drain_pending_requests().is_empty());
+
+    clock.advance(Duration::from_millis(1));
+    shipper.poll()?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].request.message.body, "a\n");
+    Ok(())
+}
+
+#[test]
+fn test_log_shipper_retries_failed_batch_with_backoff() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut shipper = LogShipper::new(config(), &http_client, &clock, &stats)?;
+
+    shipper.push("a\n")?;
+    shipper.flush()?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+
+    // collector fails
+    assert!(respond(&mut shipper, requests[0].handle, "503")?);
+    assert!(http_client.drain_pending_requests().is_empty());
+
+    // first retry after the initial backoff
+    clock.advance(Duration::from_millis(100));
+    shipper.poll()?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].request.message.body, "a\n");
+    assert_eq!(counter(&stats, "test.retried")?, 1);
+
+    // no response at all, e.g. a timeout
+    let response = FakeHttpClientResponse::builder().build();
+    assert!(shipper.on_http_call_response(requests[0].handle, 0, &response)?);
+
+    // second retry after a doubled backoff capped by the max backoff
+    clock.advance(Duration::from_millis(149));
+    shipper.poll()?;
+    assert!(http_client.drain_pending_requests().is_empty());
+    clock.advance(Duration::from_millis(1));
+    shipper.poll()?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(counter(&stats, "test.retried")?, 2);
+
+    assert!(respond(&mut shipper, requests[0].handle, "204")?);
+    assert!(shipper.is_idle());
+    assert_eq!(counter(&stats, "test.sent")?, 1);
+    assert_eq!(counter(&stats, "test.dropped")?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_log_shipper_drops_batch_when_retries_are_exhausted() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut shipper = LogShipper::new(config(), &http_client, &clock, &stats)?;
+
+    shipper.push("a\n")?;
+    shipper.push("b\n")?;
+    shipper.flush()?;
+
+    for _ in 0..3 {
+        let requests = http_client.drain_pending_requests();
+        assert_eq!(requests.len(), 1);
+        assert!(respond(&mut shipper, requests[0].handle, "500")?);
+        clock.advance(Duration::from_secs(1));
+        shipper.poll()?;
+    }
+
+    assert!(http_client.drain_pending_requests().is_empty());
+    assert!(shipper.is_idle());
+    assert_eq!(counter(&stats, "test.sent")?, 0);
+    assert_eq!(counter(&stats, "test.retried")?, 2);
+    assert_eq!(counter(&stats, "test.dropped")?, 2);
+    Ok(())
+}
+
+#[test]
+fn test_log_shipper_drops_entries_beyond_max_buffered_bytes() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut shipper = LogShipper::new(config(), &http_client, &clock, &stats)?;
+
+    shipper.push("0123456789\n")?;
+    shipper.push("0123456789\n")?;
+    assert_eq!(counter(&stats, "test.dropped")?, 1);
+
+    shipper.flush()?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].request.message.body, "0123456789\n");
+
+    // buffer space is released once the batch is delivered
+    assert!(respond(&mut shipper, requests[0].handle, "200")?);
+    shipper.push("0123456789\n")?;
+    assert_eq!(counter(&stats, "test.dropped")?, 1);
+    Ok(())
+}
+
+#[test]
+fn test_log_shipper_ignores_responses_to_foreign_requests() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut shipper = LogShipper::new(config(), &http_client, &clock, &stats)?;
+
+    assert!(!respond(
+        &mut shipper,
+        HttpClientRequestHandle::from(42),
+        "200"
+    )?);
+    Ok(())
+}
+
+#[test]
+fn test_log_shipper_frames_batch() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let config = LogShipperConfig {
+        batch_prefix: b"[".to_vec(),
+        batch_separator: b",".to_vec(),
+        batch_suffix: b"]".to_vec(),
+        ..config()
+    };
+    let mut shipper = LogShipper::new(config, &http_client, &clock, &stats)?;
+
+    shipper.push("1")?;
+    shipper.push("2")?;
+    shipper.push("3")?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].request.message.body, "[1,2,3]");
+    Ok(())
+}
+
+#[test]
+fn test_log_shipper_gives_up_waiting_for_response_after_timeout() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let config = LogShipperConfig {
+        timeout: Duration::from_secs(2),
+        max_retries: 0,
+        ..config()
+    };
+    let mut shipper = LogShipper::new(config, &http_client, &clock, &stats)?;
+
+    shipper.push("a\n")?;
+    shipper.flush()?;
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+
+    clock.advance(Duration::from_secs(2));
+    shipper.poll()?;
+    assert!(!shipper.is_idle());
+
+    // response never arrives, e.g. because it was delivered to another context
+    clock.advance(Duration::from_secs(2));
+    shipper.poll()?;
+    assert!(shipper.is_idle());
+    assert_eq!(counter(&stats, "test.dropped")?, 1);
+
+    // late response is no longer recognized
+    assert!(!respond(&mut shipper, requests[0].handle, "200")?);
+    assert_eq!(counter(&stats, "test.sent")?, 0);
+    Ok(())
+}
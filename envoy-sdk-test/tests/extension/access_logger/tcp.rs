@@ -0,0 +1,113 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use envoy::extension::access_logger::format::LogFormat;
+use envoy::extension::access_logger::{LogEntryKind, LogOps};
+use envoy::extension::Result;
+use envoy::host::stream_info::ResponseFlags;
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::FakeStreamInfo;
+
+fn tcp_connection() -> FakeStreamInfo {
+    FakeStreamInfo::new().with(|info| {
+        info.entry_kind(LogEntryKind::Tcp);
+        info.connection().id(7).termination_details("idle_timeout");
+        info.request().size(1024).duration(Duration::from_secs(60));
+        info.response()
+            .size(4096)
+            .response_flags(ResponseFlags::UPSTREAM_CONNECTION_TERMINATION);
+        info.upstream().address("10.0.0.1:6379");
+        info.cluster().name("redis");
+        info.source().address("192.168.0.1:54321");
+        info.destination().address("192.168.0.2:6379");
+    })
+}
+
+#[test]
+fn test_tcp_log_entry_kind() -> Result<()> {
+    let http = FakeStreamInfo::new().with(|info| {
+        info.request().method("GET").protocol("HTTP/1.1");
+    });
+    let tcp = tcp_connection();
+
+    assert_eq!(http.entry_kind()?, LogEntryKind::Http);
+    assert_eq!(tcp.entry_kind()?, LogEntryKind::Tcp);
+    Ok(())
+}
+
+#[test]
+fn test_tcp_log_entry_without_http_headers() -> Result<()> {
+    let ops: &dyn LogOps = &FakeStreamInfo::new().with(|info| {
+        info.entry_kind(LogEntryKind::Tcp);
+        info.request().header(":method", "GET");
+        info.response()
+            .header(":status", "200")
+            .trailer("grpc-status", "0");
+    });
+
+    assert!(ops.request_headers()?.is_empty());
+    assert_eq!(ops.request_header(":method")?, None);
+    assert!(ops.response_headers()?.is_empty());
+    assert_eq!(ops.response_header(":status")?, None);
+    assert!(ops.response_trailers()?.is_empty());
+    assert_eq!(ops.response_trailer("grpc-status")?, None);
+    Ok(())
+}
+
+#[test]
+fn test_tcp_log_entry_connection_level_data() -> Result<()> {
+    let ops: &dyn LogOps = &tcp_connection();
+
+    let entry = ops.connection_entry()?;
+
+    assert_eq!(entry.bytes_received, 1024);
+    assert_eq!(entry.bytes_sent, 4096);
+    assert_eq!(entry.duration, Some(Duration::from_secs(60)));
+    assert_eq!(entry.upstream_host, Some("10.0.0.1:6379".to_owned()));
+    assert_eq!(entry.upstream_cluster, Some("redis".to_owned()));
+    assert_eq!(entry.upstream_transport_failure_reason, None);
+    assert_eq!(
+        entry.downstream_remote_address,
+        Some("192.168.0.1:54321".to_owned())
+    );
+    assert_eq!(
+        entry.downstream_local_address,
+        Some("192.168.0.2:6379".to_owned())
+    );
+    assert_eq!(entry.termination_details, Some("idle_timeout".to_owned()));
+    assert_eq!(
+        entry.response_flags,
+        ResponseFlags::UPSTREAM_CONNECTION_TERMINATION
+    );
+    Ok(())
+}
+
+#[test]
+fn test_tcp_log_entry_format() -> Result<()> {
+    let format = LogFormat::text(
+        "%DOWNSTREAM_REMOTE_ADDRESS% -> %UPSTREAM_HOST% %BYTES_RECEIVED% %BYTES_SENT% \
+         %DURATION% %RESPONSE_FLAGS% %CONNECTION_TERMINATION_DETAILS% %REQ(:METHOD)%",
+    )?;
+
+    let line = format.render(&tcp_connection())?;
+
+    assert_eq!(
+        line,
+        "192.168.0.1:54321 -> 10.0.0.1:6379 1024 4096 60000 UC idle_timeout -"
+    );
+    Ok(())
+}
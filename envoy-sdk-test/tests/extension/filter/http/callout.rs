@@ -0,0 +1,444 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use envoy::error::format_err;
+use envoy::extension::filter::http::callout::{
+    CalloutCache, CalloutCacheConfig, CalloutResponse, Lookup, StreamWaker,
+};
+use envoy::extension::filter::http::RequestHeadersOps;
+use envoy::extension::{InstanceId, Result};
+use envoy::host::http::client::{HttpClient, HttpClientRequestHandle, HttpClientResponseOps};
+use envoy::host::{self, ByteString, HeaderMap, SharedData};
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{
+    FakeClock, FakeHttpClient, FakeHttpClientResponse, FakeRequestOps, FakeSharedData,
+};
+
+/// Records what has been done on behalf of waiting `HTTP Filter` instances.
+#[derive(Default)]
+struct FakeStreamWaker {
+    actions: Rc<RefCell<Vec<String>>>,
+}
+
+impl StreamWaker for FakeStreamWaker {
+    fn wake(
+        &self,
+        current: InstanceId,
+        waiter: InstanceId,
+        f: &mut dyn FnMut(&dyn RequestHeadersOps) -> Result<()>,
+    ) -> Result<()> {
+        let ops = FakeRequestOps::default();
+        let result = f(&ops);
+        if ops.is_request_resumed() {
+            self.actions
+                .borrow_mut()
+                .push(format!("{} -> {}: resume", current, waiter));
+        }
+        if let Some(response) = ops.local_response() {
+            self.actions.borrow_mut().push(format!(
+                "{} -> {}: {}",
+                current, waiter, response.status_code
+            ));
+        }
+        result
+    }
+}
+
+/// Response that can no longer be read, e.g. because it has been released by `Envoy`.
+struct UnreadableResponse;
+
+impl HttpClientResponseOps for UnreadableResponse {
+    fn http_call_response_headers(&self) -> host::Result<HeaderMap> {
+        Err(format_err!("Status::NotFound"))
+    }
+
+    fn http_call_response_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        Err(format_err!("Status::NotFound"))
+    }
+
+    fn http_call_response_body(
+        &self,
+        _offset: usize,
+        _max_size: usize,
+    ) -> host::Result<ByteString> {
+        Err(format_err!("Status::NotFound"))
+    }
+
+    fn http_call_response_trailers(&self) -> host::Result<HeaderMap> {
+        Err(format_err!("Status::NotFound"))
+    }
+
+    fn http_call_response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        Err(format_err!("Status::NotFound"))
+    }
+}
+
+fn authorize(response: &CalloutResponse, ops: &dyn RequestHeadersOps) -> Result<()> {
+    if response.is_success() {
+        ops.resume_request()
+    } else {
+        ops.send_response(403, &[], None)
+    }
+}
+
+fn fake_clock() -> FakeClock {
+    FakeClock::new(UNIX_EPOCH + Duration::from_secs(1_000))
+}
+
+fn config() -> CalloutCacheConfig {
+    CalloutCacheConfig {
+        key_prefix: "auth".into(),
+        ttl: Duration::from_secs(60),
+        negative_ttl: Duration::from_secs(5),
+        timeout: Duration::from_secs(2),
+    }
+}
+
+fn lookup(
+    cache: &CalloutCache,
+    http_client: &FakeHttpClient,
+    instance_id: u32,
+    key: &str,
+) -> Result<Lookup> {
+    cache.lookup(
+        InstanceId::from(instance_id),
+        key,
+        || {
+            http_client.send_request(
+                "auth_service",
+                &[("authorization", key)],
+                None,
+                None,
+                Duration::from_secs(2),
+            )
+        },
+        authorize,
+    )
+}
+
+fn respond(
+    cache: &CalloutCache,
+    instance_id: u32,
+    request_id: HttpClientRequestHandle,
+    response: Option<FakeHttpClientResponse>,
+) -> Result<Option<CalloutResponse>> {
+    match response {
+        Some(response) => cache.on_http_call_response(
+            InstanceId::from(instance_id),
+            request_id,
+            1,
+            response.message.body.len(),
+            &response,
+        ),
+        None => cache.on_http_call_response(
+            InstanceId::from(instance_id),
+            request_id,
+            0,
+            0,
+            &FakeHttpClientResponse::builder().build(),
+        ),
+    }
+}
+
+fn response(status: &str, body: &str) -> Option<FakeHttpClientResponse> {
+    Some(
+        FakeHttpClientResponse::builder()
+            .header(":status", status)
+            .body(body)
+            .build(),
+    )
+}
+
+fn sent(lookup: Lookup) -> HttpClientRequestHandle {
+    match lookup {
+        Lookup::Sent(request_id) => request_id,
+        other => panic!("expected a callout to be sent, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_callout_cache_successful_responses_across_workers() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let worker1 = CalloutCache::new(config(), &shared_data, &clock, &waker);
+    let worker2 = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&worker1, &http_client, 1, "token")?);
+    assert_eq!(http_client.drain_pending_requests().len(), 1);
+
+    let result = respond(&worker1, 1, request_id, response("200", "user=alice"))?;
+    assert_eq!(result, Some(CalloutResponse::new(Some(200), "user=alice")));
+
+    let expected = Lookup::Cached(CalloutResponse::new(Some(200), "user=alice"));
+    assert_eq!(lookup(&worker1, &http_client, 2, "token")?, expected);
+    assert_eq!(lookup(&worker2, &http_client, 3, "token")?, expected);
+    assert!(http_client.drain_pending_requests().is_empty());
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(worker2.get("token")?, None);
+    sent(lookup(&worker2, &http_client, 4, "token")?);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_negative_responses() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    let result = respond(&cache, 1, request_id, response("403", ""))?.unwrap();
+    assert!(!result.is_success());
+
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(
+        lookup(&cache, &http_client, 2, "token")?,
+        Lookup::Cached(CalloutResponse::new(Some(403), ""))
+    );
+
+    clock.advance(Duration::from_secs(1));
+    sent(lookup(&cache, &http_client, 3, "token")?);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_failed_callouts() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    assert_eq!(lookup(&cache, &http_client, 2, "token")?, Lookup::Waiting);
+
+    let result = respond(&cache, 1, request_id, None)?;
+    assert_eq!(result, Some(CalloutResponse::new(None, "")));
+    assert_eq!(*waker.actions.borrow(), vec!["1 -> 2: 403"]);
+    assert!(shared_data.is_empty());
+
+    sent(lookup(&cache, &http_client, 3, "token")?);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_coalesces_callouts_within_worker() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    assert_eq!(lookup(&cache, &http_client, 2, "token")?, Lookup::Waiting);
+    assert_eq!(lookup(&cache, &http_client, 3, "token")?, Lookup::Waiting);
+    let other_id = sent(lookup(&cache, &http_client, 4, "other")?);
+    assert_eq!(http_client.drain_pending_requests().len(), 2);
+
+    // responses to unrelated requests are ignored
+    assert_eq!(
+        respond(
+            &cache,
+            1,
+            HttpClientRequestHandle::from(100),
+            response("200", "")
+        )?,
+        None
+    );
+
+    respond(&cache, 1, request_id, response("200", ""))?;
+    assert_eq!(
+        *waker.actions.borrow(),
+        vec!["1 -> 2: resume", "1 -> 3: resume"]
+    );
+
+    respond(&cache, 4, other_id, response("401", ""))?;
+    assert_eq!(waker.actions.borrow().len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_supersedes_callouts_without_response() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let lost_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    assert_eq!(lookup(&cache, &http_client, 2, "token")?, Lookup::Waiting);
+
+    // timeout + grace period
+    clock.advance(Duration::from_secs(3));
+    let request_id = sent(lookup(&cache, &http_client, 3, "token")?);
+    assert_ne!(request_id, lost_id);
+
+    assert_eq!(respond(&cache, 1, lost_id, response("200", ""))?, None);
+    assert!(waker.actions.borrow().is_empty());
+
+    respond(&cache, 3, request_id, response("200", ""))?;
+    assert_eq!(*waker.actions.borrow(), vec!["3 -> 2: resume"]);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_does_not_wake_cancelled_waiters() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+    let called = Rc::new(Cell::new(false));
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    let flag = Rc::clone(&called);
+    let waiting = cache.lookup(
+        InstanceId::from(2),
+        "token",
+        || unreachable!(),
+        move |_, _| {
+            flag.set(true);
+            Ok(())
+        },
+    )?;
+    assert_eq!(waiting, Lookup::Waiting);
+
+    cache.cancel(InstanceId::from(2));
+    respond(&cache, 1, request_id, response("200", ""))?;
+    assert!(!called.get());
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_fails_waiters_of_cancelled_callouts() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    assert_eq!(lookup(&cache, &http_client, 2, "token")?, Lookup::Waiting);
+
+    cache.cancel(InstanceId::from(1));
+    assert_eq!(*waker.actions.borrow(), vec!["1 -> 2: 403"]);
+
+    // the response is no longer expected
+    assert_eq!(respond(&cache, 1, request_id, response("200", ""))?, None);
+    assert_eq!(waker.actions.borrow().len(), 1);
+
+    sent(lookup(&cache, &http_client, 3, "token")?);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_fails_waiters_if_callout_cannot_be_sent() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    sent(lookup(&cache, &http_client, 1, "token")?);
+    assert_eq!(lookup(&cache, &http_client, 2, "token")?, Lookup::Waiting);
+
+    // timeout + grace period
+    clock.advance(Duration::from_secs(3));
+    let result = cache.lookup(
+        InstanceId::from(3),
+        "token",
+        || Err(format_err!("no healthy upstream")),
+        authorize,
+    );
+    assert_eq!(result.unwrap_err().to_string(), "no healthy upstream");
+    assert_eq!(*waker.actions.borrow(), vec!["3 -> 2: 403"]);
+
+    sent(lookup(&cache, &http_client, 4, "token")?);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_fails_waiters_if_response_cannot_be_read() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    assert_eq!(lookup(&cache, &http_client, 2, "token")?, Lookup::Waiting);
+
+    let result =
+        cache.on_http_call_response(InstanceId::from(1), request_id, 1, 0, &UnreadableResponse);
+    assert_eq!(result.unwrap_err().to_string(), "Status::NotFound");
+    assert_eq!(*waker.actions.borrow(), vec!["1 -> 2: 403"]);
+
+    assert_eq!(cache.get("token")?, None);
+    sent(lookup(&cache, &http_client, 3, "token")?);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_isolates_errors_of_waiters() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "token")?);
+    let waiting = cache.lookup(
+        InstanceId::from(2),
+        "token",
+        || unreachable!(),
+        |_, _| Err(format_err!("stream has been reset")),
+    )?;
+    assert_eq!(waiting, Lookup::Waiting);
+    assert_eq!(lookup(&cache, &http_client, 3, "token")?, Lookup::Waiting);
+
+    let result = respond(&cache, 1, request_id, response("200", ""))?;
+    assert_eq!(result, Some(CalloutResponse::new(Some(200), "")));
+    assert_eq!(*waker.actions.borrow(), vec!["1 -> 3: resume"]);
+    Ok(())
+}
+
+#[test]
+fn test_callout_cache_hashes_keys_in_shared_data() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let waker = FakeStreamWaker::default();
+    let http_client = FakeHttpClient::default();
+    let cache = CalloutCache::new(config(), &shared_data, &clock, &waker);
+
+    let request_id = sent(lookup(&cache, &http_client, 1, "Bearer secret")?);
+    respond(&cache, 1, request_id, response("200", ""))?;
+
+    assert_eq!(shared_data.len(), 1);
+    assert_eq!(shared_data.get("auth.Bearer secret")?, (None, None));
+    assert_eq!(
+        cache.get("Bearer secret")?,
+        Some(CalloutResponse::new(Some(200), ""))
+    );
+    assert_eq!(cache.get("Bearer other")?, None);
+    Ok(())
+}
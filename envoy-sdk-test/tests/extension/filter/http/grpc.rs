@@ -0,0 +1,100 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use envoy::error::Result;
+use envoy::extension::filter::http::grpc::{self, GrpcMessage, GrpcMessageDecoder};
+
+#[test]
+fn test_grpc_message_decoder_reassembles_chunks() -> Result<()> {
+    let body = grpc::encode_messages(&[
+        GrpcMessage::new("hello"),
+        GrpcMessage::compressed("world"),
+        GrpcMessage::new(""),
+    ]);
+    let mut decoder = GrpcMessageDecoder::new();
+
+    let mut messages = Vec::new();
+    for chunk in body.chunks(3) {
+        messages.extend(decoder.decode(chunk)?);
+    }
+
+    assert_eq!(
+        messages,
+        vec![
+            GrpcMessage::new("hello"),
+            GrpcMessage::compressed("world"),
+            GrpcMessage::new(""),
+        ]
+    );
+    assert!(decoder.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_grpc_message_decoder_keeps_incomplete_message() -> Result<()> {
+    let mut decoder = GrpcMessageDecoder::new();
+
+    assert_eq!(decoder.decode(&[0, 0, 0])?, vec![]);
+    assert_eq!(decoder.buffered_len(), 3);
+    assert_eq!(decoder.decode(&[0, 2, b'h'])?, vec![]);
+    assert_eq!(decoder.buffered_len(), 6);
+    assert_eq!(decoder.decode(&[b'i'])?, vec![GrpcMessage::new("hi")]);
+    assert!(decoder.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_grpc_message_decoder_invalid_flag() -> Result<()> {
+    let mut decoder = GrpcMessageDecoder::new();
+
+    let mut data = GrpcMessage::new("ok").encode();
+    data.extend_from_slice(&[2, 0, 0, 0, 0]);
+
+    // complete messages are not lost
+    assert_eq!(decoder.decode(&data)?, vec![GrpcMessage::new("ok")]);
+    assert!(decoder.is_empty());
+
+    let err = decoder
+        .decode(&GrpcMessage::new("next").encode())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid gRPC message: unexpected Compressed-Flag 2"
+    );
+    assert!(decoder.decode(&[]).is_err());
+    assert!(decoder.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_grpc_message_decoder_message_size_limit() {
+    let mut decoder = GrpcMessageDecoder::with_max_message_size(4);
+
+    let err = decoder
+        .decode(&GrpcMessage::new("hello").encode())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid gRPC message: message size 5 exceeds the limit of 4 bytes"
+    );
+    assert!(decoder.is_empty());
+
+    let err = decoder
+        .decode(&GrpcMessage::new("hi").encode())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid gRPC message: message size 5 exceeds the limit of 4 bytes"
+    );
+}
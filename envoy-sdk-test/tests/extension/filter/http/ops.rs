@@ -0,0 +1,58 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use envoy::extension::filter::http::RequestHeadersOps;
+use envoy::host;
+use envoy::host::stream_info::StreamInfo;
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{FakeRequestOps, FakeStreamInfo};
+
+#[test]
+fn test_set_upstream_cluster() -> host::Result<()> {
+    let ops = FakeRequestOps::default();
+
+    ops.set_upstream_cluster("x-upstream-cluster", "backend-canary")?;
+
+    assert_eq!(
+        ops.request_header("x-upstream-cluster")?,
+        Some("backend-canary".into())
+    );
+    assert_eq!(ops.route_cache_clears(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_set_upstream_cluster_reroutes_request() -> host::Result<()> {
+    let ops = FakeRequestOps::default()
+        .with_stream_info(FakeStreamInfo::new().with(|info| {
+            info.route().name("default");
+            info.cluster().name("backend");
+        }))
+        .with_cluster_header("x-upstream-cluster");
+
+    ops.set_request_header("x-upstream-cluster", "backend-canary")?;
+    {
+        let stream_info: &dyn StreamInfo = &*ops.stream_info();
+        // the route is cached until cleared
+        assert_eq!(stream_info.cluster().name()?, Some("backend".into()));
+    }
+
+    ops.set_upstream_cluster("x-upstream-cluster", "backend-canary")?;
+
+    let stream_info: &dyn StreamInfo = &*ops.stream_info();
+    assert_eq!(stream_info.route().name()?, Some("default".into()));
+    assert_eq!(stream_info.cluster().name()?, Some("backend-canary".into()));
+    Ok(())
+}
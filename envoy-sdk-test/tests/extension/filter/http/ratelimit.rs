@@ -0,0 +1,269 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+use std::time::{Duration, UNIX_EPOCH};
+
+use envoy::extension::filter::http::ratelimit::{
+    RateLimitKey, RateLimitPolicy, RateLimiter, SlidingWindow, TokenBucket,
+};
+use envoy::extension::filter::http::RequestHeadersOps;
+use envoy::host::shared_data::OptimisticLockVersion;
+use envoy::host::{self, ByteString, SharedData};
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{FakeClock, FakeRequestOps, FakeSharedData, FakeStreamInfo};
+
+fn token_bucket(max_tokens: u64, tokens_per_fill: u64, fill_interval: u64) -> RateLimitPolicy {
+    RateLimitPolicy::TokenBucket(TokenBucket {
+        max_tokens,
+        tokens_per_fill,
+        fill_interval: Duration::from_secs(fill_interval),
+    })
+}
+
+fn sliding_window(limit: u64, window: u64) -> RateLimitPolicy {
+    RateLimitPolicy::SlidingWindow(SlidingWindow {
+        limit,
+        window: Duration::from_secs(window),
+    })
+}
+
+fn fake_clock() -> FakeClock {
+    FakeClock::new(UNIX_EPOCH + Duration::from_secs(1_000))
+}
+
+/// `SharedData` that lets another worker modify the value right before every update.
+struct RacingSharedData<'a> {
+    inner: &'a FakeSharedData,
+    races: Cell<usize>,
+}
+
+impl<'a> SharedData for RacingSharedData<'a> {
+    fn get(&self, key: &str) -> host::Result<(Option<ByteString>, Option<OptimisticLockVersion>)> {
+        self.inner.get(key)
+    }
+
+    fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        version: Option<OptimisticLockVersion>,
+    ) -> host::Result<()> {
+        if self.races.get() > 0 {
+            self.races.set(self.races.get() - 1);
+            let (current, current_version) = self.inner.get(key)?;
+            self.inner
+                .set(key, &current.unwrap_or_default(), current_version)?;
+        }
+        self.inner.set(key, value, version)
+    }
+}
+
+#[test]
+fn test_rate_limiter_shares_token_bucket_between_workers() -> host::Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let worker1 = RateLimiter::new("test", token_bucket(3, 1, 10), &shared_data, &clock);
+    let worker2 = RateLimiter::new("test", token_bucket(3, 1, 10), &shared_data, &clock);
+
+    let decision = worker1.check("10.0.0.1")?;
+    assert!(decision.is_allowed());
+    assert_eq!(decision.remaining(), 2);
+    assert_eq!(decision.reset_after(), Duration::from_secs(10));
+
+    assert_eq!(worker2.check("10.0.0.1")?.remaining(), 1);
+    assert_eq!(worker1.check("10.0.0.1")?.remaining(), 0);
+
+    let decision = worker2.check("10.0.0.1")?;
+    assert!(!decision.is_allowed());
+    assert_eq!(decision.remaining(), 0);
+
+    // other descriptors are limited independently
+    assert!(worker2.check("10.0.0.2")?.is_allowed());
+    assert_eq!(shared_data.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_refills_token_bucket() -> host::Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let limiter = RateLimiter::new("test", token_bucket(2, 1, 10), &shared_data, &clock);
+
+    assert!(limiter.check("client")?.is_allowed());
+    assert!(limiter.check("client")?.is_allowed());
+    assert!(!limiter.check("client")?.is_allowed());
+
+    clock.advance(Duration::from_secs(4));
+    let decision = limiter.check("client")?;
+    assert!(!decision.is_allowed());
+    assert_eq!(decision.reset_after(), Duration::from_secs(6));
+
+    clock.advance(Duration::from_secs(6));
+    let decision = limiter.check("client")?;
+    assert!(decision.is_allowed());
+    assert_eq!(decision.remaining(), 0);
+    assert!(!limiter.check("client")?.is_allowed());
+
+    // refill never exceeds the capacity of the bucket
+    clock.advance(Duration::from_secs(100));
+    assert_eq!(limiter.check("client")?.remaining(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_keeps_token_bucket_stored_by_worker_with_clock_ahead() -> host::Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock1 = fake_clock();
+    let clock2 = fake_clock();
+    clock1.advance(Duration::from_millis(10));
+    let worker1 = RateLimiter::new("test", token_bucket(2, 1, 10), &shared_data, &clock1);
+    let worker2 = RateLimiter::new("test", token_bucket(2, 1, 10), &shared_data, &clock2);
+
+    assert_eq!(worker1.check("client")?.remaining(), 1);
+    // the clock of `worker2` is slightly behind
+    assert_eq!(worker2.check("client")?.remaining(), 0);
+    assert!(!worker1.check("client")?.is_allowed());
+    assert!(!worker2.check("client")?.is_allowed());
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_keeps_sliding_window_stored_by_worker_with_clock_ahead() -> host::Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock1 = fake_clock();
+    let clock2 = fake_clock();
+    clock2.advance(Duration::from_millis(9_990));
+    clock1.advance(Duration::from_secs(10));
+    let worker1 = RateLimiter::new("test", sliding_window(2, 10), &shared_data, &clock1);
+    let worker2 = RateLimiter::new("test", sliding_window(2, 10), &shared_data, &clock2);
+
+    assert_eq!(worker1.check("client")?.remaining(), 1);
+    // the clock of `worker2` is still in the previous window
+    assert_eq!(worker2.check("client")?.remaining(), 0);
+    assert!(!worker1.check("client")?.is_allowed());
+    assert!(!worker2.check("client")?.is_allowed());
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_does_not_consume_limit_on_limited_requests() -> host::Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let limiter = RateLimiter::new("test", token_bucket(1, 1, 10), &shared_data, &clock);
+
+    assert!(limiter.check("client")?.is_allowed());
+    let (_, version) = shared_data.get("test.client")?;
+
+    assert!(!limiter.check("client")?.is_allowed());
+    assert!(!limiter.check("client")?.is_allowed());
+    assert_eq!(shared_data.get("test.client")?.1, version);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_weights_previous_sliding_window() -> host::Result<()> {
+    let shared_data = FakeSharedData::default();
+    let clock = fake_clock();
+    let limiter = RateLimiter::new("test", sliding_window(4, 10), &shared_data, &clock);
+
+    for remaining in (0..4).rev() {
+        assert_eq!(limiter.check("client")?.remaining(), remaining);
+    }
+    let decision = limiter.check("client")?;
+    assert!(!decision.is_allowed());
+    assert_eq!(decision.reset_after(), Duration::from_secs(10));
+
+    // a quarter into the next window, 3 out of 4 previous requests still count
+    clock.advance(Duration::from_millis(12_500));
+    let decision = limiter.check("client")?;
+    assert!(decision.is_allowed());
+    assert_eq!(decision.remaining(), 0);
+    assert!(!limiter.check("client")?.is_allowed());
+
+    // half way through, 2 out of 4 previous requests still count
+    clock.advance(Duration::from_millis(2_500));
+    assert!(limiter.check("client")?.is_allowed());
+    assert!(!limiter.check("client")?.is_allowed());
+
+    // windows older than the previous one are forgotten
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(limiter.check("client")?.remaining(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_retries_check_on_concurrent_modification() -> host::Result<()> {
+    let fake = FakeSharedData::default();
+    let shared_data = RacingSharedData {
+        inner: &fake,
+        races: Cell::new(2),
+    };
+    let clock = fake_clock();
+    let limiter = RateLimiter::new("test", token_bucket(3, 1, 10), &shared_data, &clock);
+
+    assert!(limiter.check("client")?.is_allowed());
+    shared_data.races.set(2);
+    assert_eq!(limiter.check("client")?.remaining(), 1);
+    assert_eq!(shared_data.races.get(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_allows_request_after_max_attempts() -> host::Result<()> {
+    let fake = FakeSharedData::default();
+    let shared_data = RacingSharedData {
+        inner: &fake,
+        races: Cell::new(0),
+    };
+    let clock = fake_clock();
+    let limiter =
+        RateLimiter::new("test", token_bucket(3, 1, 10), &shared_data, &clock).with_max_attempts(2);
+
+    assert!(limiter.check("client")?.is_allowed());
+    shared_data.races.set(2);
+    assert!(limiter.check("client")?.is_allowed());
+    assert_eq!(shared_data.races.get(), 0);
+    // the request that has been let through has not consumed the limit
+    assert_eq!(limiter.check("client")?.remaining(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limit_descriptors() -> host::Result<()> {
+    let headers = FakeRequestOps::default();
+    headers.set_request_header("x-api-key", "secret")?;
+    let stream_info = FakeStreamInfo::new().with(|info| {
+        info.source().address("10.0.0.1:51234");
+    });
+
+    assert_eq!(
+        RateLimitKey::ClientIp.descriptor(&headers, &stream_info)?,
+        Some("10.0.0.1".to_owned())
+    );
+    assert_eq!(
+        RateLimitKey::Header("x-api-key".to_owned()).descriptor(&headers, &stream_info)?,
+        Some("secret".to_owned())
+    );
+    assert_eq!(
+        RateLimitKey::Header("x-other".to_owned()).descriptor(&headers, &stream_info)?,
+        None
+    );
+    assert_eq!(
+        RateLimitKey::ClientIp.descriptor(&headers, &FakeStreamInfo::new())?,
+        None
+    );
+    Ok(())
+}
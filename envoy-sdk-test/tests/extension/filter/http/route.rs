@@ -0,0 +1,129 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use envoy::error::bail;
+use envoy::extension::filter::http::route::{PerRouteConfig, RouteConfig};
+use envoy::extension::Result;
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::FakeStreamInfo;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct TestConfig {
+    policy: String,
+    merges: Rc<Cell<usize>>,
+}
+
+impl RouteConfig for TestConfig {
+    fn merge(&self, route_config: &[u8]) -> Result<Self> {
+        self.merges.set(self.merges.get() + 1);
+        if route_config.is_empty() {
+            bail!("route config must not be empty");
+        }
+        Ok(TestConfig {
+            policy: String::from_utf8(route_config.to_vec())?,
+            merges: Rc::clone(&self.merges),
+        })
+    }
+}
+
+fn fake_route(name: &str, config: Option<&str>) -> FakeStreamInfo {
+    FakeStreamInfo::new().with(|info| {
+        let mut route = info.route();
+        route.name(name);
+        if let Some(config) = config {
+            route.metadata("my_filter", "config", config);
+        }
+    })
+}
+
+#[test]
+fn test_per_route_config_without_overrides() -> Result<()> {
+    let base = TestConfig {
+        policy: "allow".to_owned(),
+        ..Default::default()
+    };
+    let merges = Rc::clone(&base.merges);
+    let configs = PerRouteConfig::new("my_filter", base);
+
+    let config = configs.get(&fake_route("default", None))?;
+
+    assert_eq!(config.policy, "allow");
+    assert_eq!(merges.get(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_per_route_config_is_parsed_once_per_route() -> Result<()> {
+    let base = TestConfig {
+        policy: "allow".to_owned(),
+        ..Default::default()
+    };
+    let merges = Rc::clone(&base.merges);
+    let configs = PerRouteConfig::new("my_filter", base);
+
+    let admin = fake_route("admin", Some("deny"));
+    let public = fake_route("public", Some("audit"));
+
+    assert_eq!(configs.get(&admin)?.policy, "deny");
+    assert_eq!(configs.get(&public)?.policy, "audit");
+    assert_eq!(configs.get(&admin)?.policy, "deny");
+    assert_eq!(configs.get(&public)?.policy, "audit");
+    assert_eq!(merges.get(), 2);
+
+    // route configuration has been updated
+    let admin = fake_route("admin", Some("audit"));
+
+    assert_eq!(configs.get(&admin)?.policy, "audit");
+    assert_eq!(merges.get(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_per_route_config_is_discarded_on_reconfiguration() -> Result<()> {
+    let base = TestConfig::default();
+    let merges = Rc::clone(&base.merges);
+    let configs = PerRouteConfig::new("my_filter", base);
+
+    let admin = fake_route("admin", Some("deny"));
+
+    configs.get(&admin)?;
+    configs.set_base(TestConfig {
+        policy: "allow".to_owned(),
+        merges: Rc::clone(&merges),
+    });
+
+    assert_eq!(configs.base().policy, "allow");
+    assert_eq!(configs.get(&admin)?.policy, "deny");
+    assert_eq!(merges.get(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_per_route_config_invalid() {
+    let configs = PerRouteConfig::new("my_filter", TestConfig::default());
+
+    let err = configs.get(&fake_route("admin", Some(""))).unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "failed to apply configuration of the route \"admin\""
+    );
+}
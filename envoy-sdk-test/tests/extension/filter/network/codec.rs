@@ -0,0 +1,271 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::{Cell, RefCell};
+
+use envoy::error::bail;
+use envoy::extension::filter::network::codec::{
+    Codec, FrameHandler, FrameStatus, FramedNetworkFilter,
+};
+use envoy::extension::filter::network::{
+    ConnectionFlowOps, DownstreamDataOps, FilterStatus, UpstreamDataOps,
+};
+use envoy::extension::{NetworkFilter, Result};
+use envoy::host::{self, ByteString};
+
+/// Frames are a length byte followed by that many bytes of payload.
+struct LengthPrefixedCodec;
+
+impl Codec for LengthPrefixedCodec {
+    type Frame = Vec<u8>;
+
+    fn decode(&mut self, data: &[u8]) -> Result<Option<(Self::Frame, usize)>> {
+        match data.first() {
+            Some(0) => bail!("empty frame"),
+            Some(&len) if data.len() > len as usize => {
+                Ok(Some((data[1..=len as usize].to_vec(), 1 + len as usize)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn encode(&mut self, frame: &Self::Frame, buf: &mut Vec<u8>) -> Result<()> {
+        buf.push(frame.len() as u8);
+        buf.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+/// Upper-cases frames starting with `u`, drops frames starting with `d`
+/// and closes the connection on frames starting with `x`.
+#[derive(Default)]
+struct TestHandler {
+    downstream_frames: Vec<Vec<u8>>,
+    upstream_frames: Vec<Vec<u8>>,
+}
+
+impl TestHandler {
+    fn handle(frame: &mut [u8], ops: &dyn ConnectionFlowOps) -> Result<FrameStatus> {
+        match frame[0] {
+            b'u' => {
+                frame.make_ascii_uppercase();
+                Ok(FrameStatus::Modified)
+            }
+            b'd' => Ok(FrameStatus::Drop),
+            b'x' => {
+                ops.close_downstream()?;
+                Ok(FrameStatus::Drop)
+            }
+            _ => Ok(FrameStatus::Continue),
+        }
+    }
+}
+
+impl FrameHandler for TestHandler {
+    type DownstreamCodec = LengthPrefixedCodec;
+    type UpstreamCodec = LengthPrefixedCodec;
+
+    fn on_downstream_frame(
+        &mut self,
+        frame: &mut Vec<u8>,
+        ops: &dyn ConnectionFlowOps,
+    ) -> Result<FrameStatus> {
+        self.downstream_frames.push(frame.clone());
+        Self::handle(frame, ops)
+    }
+
+    fn on_upstream_frame(
+        &mut self,
+        frame: &mut Vec<u8>,
+        ops: &dyn ConnectionFlowOps,
+    ) -> Result<FrameStatus> {
+        self.upstream_frames.push(frame.clone());
+        Self::handle(frame, ops)
+    }
+}
+
+/// Emulates the read and write buffers of a connection.
+#[derive(Default)]
+struct FakeConnection {
+    downstream: RefCell<Vec<u8>>,
+    upstream: RefCell<Vec<u8>>,
+    rewrites: Cell<usize>,
+    closed: Cell<bool>,
+}
+
+impl FakeConnection {
+    fn replace(buffer: &RefCell<Vec<u8>>, start: usize, size: usize, data: &[u8]) {
+        let mut buffer = buffer.borrow_mut();
+        let end = buffer.len().min(start + size);
+        buffer.splice(start..end, data.iter().cloned());
+    }
+}
+
+impl ConnectionFlowOps for FakeConnection {
+    fn close_downstream(&self) -> host::Result<()> {
+        self.closed.set(true);
+        Ok(())
+    }
+}
+
+impl DownstreamDataOps for FakeConnection {
+    fn downstream_data(&self, offset: usize, max_size: usize) -> host::Result<ByteString> {
+        let buffer = self.downstream.borrow();
+        let end = buffer.len().min(offset + max_size);
+        Ok(buffer[offset..end].into())
+    }
+
+    fn set_downstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        self.rewrites.set(self.rewrites.get() + 1);
+        Self::replace(&self.downstream, start, size, data);
+        Ok(())
+    }
+}
+
+impl UpstreamDataOps for FakeConnection {
+    fn upstream_data(&self, offset: usize, max_size: usize) -> host::Result<ByteString> {
+        let buffer = self.upstream.borrow();
+        let end = buffer.len().min(offset + max_size);
+        Ok(buffer[offset..end].into())
+    }
+
+    fn set_upstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        self.rewrites.set(self.rewrites.get() + 1);
+        Self::replace(&self.upstream, start, size, data);
+        Ok(())
+    }
+}
+
+/// Feeds a chunk of downstream data to the filter and returns data that would be forwarded.
+fn downstream_chunk<F: NetworkFilter>(
+    filter: &mut F,
+    conn: &FakeConnection,
+    chunk: &[u8],
+    end_of_stream: bool,
+) -> Result<Vec<u8>> {
+    conn.downstream.replace(chunk.to_vec());
+    let status = filter.on_downstream_data(chunk.len(), end_of_stream, conn)?;
+    assert_eq!(status, FilterStatus::Continue);
+    Ok(conn.downstream.replace(Vec::new()))
+}
+
+#[test]
+fn test_framed_filter_passes_through_untouched_frames() -> Result<()> {
+    let conn = FakeConnection::default();
+    let mut filter = FramedNetworkFilter::new(
+        TestHandler::default(),
+        LengthPrefixedCodec,
+        LengthPrefixedCodec,
+    );
+
+    let data = downstream_chunk(&mut filter, &conn, b"\x02ab\x01c", false)?;
+
+    assert_eq!(data, b"\x02ab\x01c");
+    assert_eq!(conn.rewrites.get(), 0);
+    assert_eq!(
+        filter.handler().downstream_frames,
+        vec![b"ab".to_vec(), b"c".to_vec()]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_framed_filter_holds_back_partial_frames() -> Result<()> {
+    let conn = FakeConnection::default();
+    let mut filter = FramedNetworkFilter::new(
+        TestHandler::default(),
+        LengthPrefixedCodec,
+        LengthPrefixedCodec,
+    );
+
+    assert_eq!(
+        downstream_chunk(&mut filter, &conn, b"\x02ab\x03c", false)?,
+        b"\x02ab"
+    );
+    assert_eq!(downstream_chunk(&mut filter, &conn, b"d", false)?, b"");
+    assert_eq!(
+        downstream_chunk(&mut filter, &conn, b"e\x01f", false)?,
+        b"\x03cde\x01f"
+    );
+    assert_eq!(
+        filter.handler().downstream_frames,
+        vec![b"ab".to_vec(), b"cde".to_vec(), b"f".to_vec()]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_framed_filter_rewrites_modified_and_dropped_frames() -> Result<()> {
+    let conn = FakeConnection::default();
+    let mut filter = FramedNetworkFilter::new(
+        TestHandler::default(),
+        LengthPrefixedCodec,
+        LengthPrefixedCodec,
+    );
+
+    conn.upstream.replace(b"\x02up\x02dn\x01k".to_vec());
+    let status = filter.on_upstream_data(8, false, &conn)?;
+
+    assert_eq!(status, FilterStatus::Continue);
+    assert_eq!(&conn.upstream.borrow()[..], b"\x02UP\x01k");
+    assert_eq!(filter.handler().upstream_frames.len(), 3);
+    assert!(filter.handler().downstream_frames.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_framed_filter_passes_through_trailing_bytes_at_end_of_stream() -> Result<()> {
+    let conn = FakeConnection::default();
+    let mut filter = FramedNetworkFilter::new(
+        TestHandler::default(),
+        LengthPrefixedCodec,
+        LengthPrefixedCodec,
+    );
+
+    assert_eq!(
+        downstream_chunk(&mut filter, &conn, b"\x01a\x05b", false)?,
+        b"\x01a"
+    );
+    assert_eq!(downstream_chunk(&mut filter, &conn, b"c", true)?, b"\x05bc");
+    assert_eq!(filter.handler().downstream_frames, vec![b"a".to_vec()]);
+    Ok(())
+}
+
+#[test]
+fn test_framed_filter_closes_connection_from_handler() -> Result<()> {
+    let conn = FakeConnection::default();
+    let mut filter = FramedNetworkFilter::new(
+        TestHandler::default(),
+        LengthPrefixedCodec,
+        LengthPrefixedCodec,
+    );
+
+    assert_eq!(downstream_chunk(&mut filter, &conn, b"\x01x", false)?, b"");
+    assert!(conn.closed.get());
+    Ok(())
+}
+
+#[test]
+fn test_framed_filter_rejects_protocol_violation() {
+    let conn = FakeConnection::default();
+    let mut filter = FramedNetworkFilter::new(
+        TestHandler::default(),
+        LengthPrefixedCodec,
+        LengthPrefixedCodec,
+    );
+
+    let err = downstream_chunk(&mut filter, &conn, b"\x01a\x00", false).unwrap_err();
+
+    assert_eq!(err.to_string(), "empty frame");
+}
@@ -0,0 +1,90 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+
+use envoy::extension::filter::network::{
+    ConnectionFlowOps, ConnectionInfoOps, FilterStatus, NewConnectionOps,
+};
+use envoy::extension::{NetworkFilter, Result};
+use envoy::host::{self, StreamInfo};
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::FakeStreamInfo;
+
+/// Accepts only connections with the expected `SNI`.
+struct SniFilter;
+
+impl NetworkFilter for SniFilter {
+    fn on_new_connection(&mut self, ops: &dyn NewConnectionOps) -> Result<FilterStatus> {
+        let info = ops.stream_info();
+        if info.connection().requested_server_name()?.as_deref() == Some("db.internal") {
+            return Ok(FilterStatus::Continue);
+        }
+        ops.close_downstream()?;
+        Ok(FilterStatus::StopIteration)
+    }
+}
+
+struct FakeNewConnection {
+    stream_info: FakeStreamInfo,
+    closed: Cell<bool>,
+}
+
+impl FakeNewConnection {
+    fn new(sni: &str) -> Self {
+        FakeNewConnection {
+            stream_info: FakeStreamInfo::new().with(|info| {
+                info.connection().requested_server_name(sni);
+            }),
+            closed: Cell::new(false),
+        }
+    }
+}
+
+impl ConnectionFlowOps for FakeNewConnection {
+    fn close_downstream(&self) -> host::Result<()> {
+        self.closed.set(true);
+        Ok(())
+    }
+}
+
+impl ConnectionInfoOps for FakeNewConnection {
+    fn stream_info(&self) -> &dyn StreamInfo {
+        &self.stream_info
+    }
+}
+
+impl NewConnectionOps for FakeNewConnection {}
+
+#[test]
+fn test_new_connection_accepted() -> Result<()> {
+    let ops = FakeNewConnection::new("db.internal");
+
+    assert_eq!(SniFilter.on_new_connection(&ops)?, FilterStatus::Continue);
+    assert!(!ops.closed.get());
+    Ok(())
+}
+
+#[test]
+fn test_new_connection_rejected() -> Result<()> {
+    let ops = FakeNewConnection::new("db.example.org");
+
+    assert_eq!(
+        SniFilter.on_new_connection(&ops)?,
+        FilterStatus::StopIteration
+    );
+    assert!(ops.closed.get());
+    Ok(())
+}
@@ -0,0 +1,203 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use envoy::extension::filter::network::sniff::{
+    self, ClientHello, Detection, Http1Request, PostgresStartup, Protocol, ProtocolSniffer,
+};
+
+fn vec8(data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn vec16(data: &[u8]) -> Vec<u8> {
+    let mut bytes = (data.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+    let mut bytes = extension_type.to_be_bytes().to_vec();
+    bytes.extend(vec16(data));
+    bytes
+}
+
+/// Builds a `ClientHello` handshake message with `SNI` and `ALPN` extensions.
+fn client_hello(server_name: &str, alpn: &[&str]) -> Vec<u8> {
+    let mut server_names = vec![0u8];
+    server_names.extend(vec16(server_name.as_bytes()));
+    let protocols: Vec<u8> = alpn.iter().flat_map(|p| vec8(p.as_bytes())).collect();
+
+    let mut extensions = extension(0x000a, &[0, 2, 0, 0x1d]);
+    extensions.extend(extension(0, &vec16(&server_names)));
+    extensions.extend(extension(16, &vec16(&protocols)));
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[0xab; 32]);
+    body.extend(vec8(&[0x01; 32]));
+    body.extend(vec16(&[0x13, 0x01, 0x13, 0x02]));
+    body.extend(vec8(&[0]));
+    body.extend(vec16(&extensions));
+
+    let mut message = vec![0x01];
+    message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    message.extend(body);
+    message
+}
+
+/// Wraps a handshake message into `TLS` records of a given maximum size.
+fn tls_records(message: &[u8], max_record_size: usize) -> Vec<u8> {
+    message
+        .chunks(max_record_size)
+        .flat_map(|fragment| {
+            let mut record = vec![0x16, 0x03, 0x01];
+            record.extend(vec16(fragment));
+            record
+        })
+        .collect()
+}
+
+fn postgres_startup(params: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = 196_608u32.to_be_bytes().to_vec();
+    for (name, value) in params {
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+    }
+    body.push(0);
+    let mut message = ((body.len() + 4) as u32).to_be_bytes().to_vec();
+    message.extend(body);
+    message
+}
+
+#[test]
+fn test_detect_http1() {
+    assert_eq!(
+        sniff::detect(b"GET /index.html HTTP/1.1\r\nHost: example.org\r\n\r\n"),
+        Detection::Detected(Protocol::Http1(Http1Request {
+            method: "GET".to_owned(),
+            path: "/index.html".to_owned(),
+            version: "HTTP/1.1".to_owned(),
+        }))
+    );
+    assert_eq!(sniff::detect(b"POST /api HT"), Detection::NeedMoreData);
+    assert_eq!(sniff::detect(b"GET / HTTP/3.0\r\n"), Detection::Unknown);
+    assert_eq!(sniff::detect(b"FETCH / HTTP/1.1\r\n"), Detection::Unknown);
+}
+
+#[test]
+fn test_detect_http2() {
+    assert_eq!(
+        sniff::detect(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\x00\x00\x12\x04"),
+        Detection::Detected(Protocol::Http2)
+    );
+    assert_eq!(
+        sniff::detect(b"PRI * HTTP/2.0\r\n"),
+        Detection::NeedMoreData
+    );
+}
+
+#[test]
+fn test_detect_tls() {
+    let expected = Detection::Detected(Protocol::Tls(ClientHello {
+        server_name: Some("api.example.org".to_owned()),
+        alpn: vec!["h2".to_owned(), "http/1.1".to_owned()],
+    }));
+    let hello = client_hello("api.example.org", &["h2", "http/1.1"]);
+
+    // single record
+    let records = tls_records(&hello, 16 * 1024);
+    assert_eq!(sniff::detect(&records), expected);
+    assert_eq!(sniff::detect(&records[..20]), Detection::NeedMoreData);
+
+    // handshake message fragmented across several records
+    let records = tls_records(&hello, 50);
+    assert_eq!(sniff::detect(&records), expected);
+    assert_eq!(sniff::detect(&records[..60]), Detection::NeedMoreData);
+
+    // not a `ClientHello`
+    assert_eq!(
+        sniff::detect(&[0x16, 0x03, 0x03, 0x00, 0x04, 0x02, 0x00, 0x00, 0x00]),
+        Detection::Unknown
+    );
+}
+
+#[test]
+fn test_detect_redis() {
+    assert_eq!(
+        sniff::detect(b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n"),
+        Detection::Detected(Protocol::Redis)
+    );
+    assert_eq!(sniff::detect(b"*2\r\n$"), Detection::NeedMoreData);
+    assert_eq!(sniff::detect(b"*x\r\n"), Detection::Unknown);
+}
+
+#[test]
+fn test_detect_postgres() {
+    assert_eq!(
+        sniff::detect(&postgres_startup(&[
+            ("user", "alice"),
+            ("database", "orders")
+        ])),
+        Detection::Detected(Protocol::Postgres(PostgresStartup::Startup {
+            user: Some("alice".to_owned()),
+            database: Some("orders".to_owned()),
+        }))
+    );
+    assert_eq!(
+        sniff::detect(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]),
+        Detection::Detected(Protocol::Postgres(PostgresStartup::SslRequest))
+    );
+    assert_eq!(
+        sniff::detect(&postgres_startup(&[("user", "alice")])[..10]),
+        Detection::NeedMoreData
+    );
+}
+
+#[test]
+fn test_detect_unknown() {
+    assert_eq!(
+        sniff::detect(b"SSH-2.0-OpenSSH_8.2\r\n"),
+        Detection::Unknown
+    );
+    assert_eq!(sniff::detect(b"\xff\xff\xff\xff"), Detection::Unknown);
+}
+
+#[test]
+fn test_protocol_sniffer() {
+    let mut sniffer = ProtocolSniffer::new();
+
+    assert_eq!(sniffer.feed(b"GET /pa"), Detection::NeedMoreData);
+    assert!(!sniffer.is_done());
+    let detected = Detection::Detected(Protocol::Http1(Http1Request {
+        method: "GET".to_owned(),
+        path: "/path".to_owned(),
+        version: "HTTP/1.0".to_owned(),
+    }));
+    assert_eq!(sniffer.feed(b"th HTTP/1.0\r\n"), detected);
+    assert!(sniffer.is_done());
+    assert_eq!(sniffer.feed(b"garbage"), detected);
+    assert_eq!(sniffer.result(), Some(&detected));
+}
+
+#[test]
+fn test_protocol_sniffer_max_size() {
+    let mut sniffer = ProtocolSniffer::with_max_size(16);
+
+    assert_eq!(sniffer.feed(b"GET /very/long/"), Detection::NeedMoreData);
+    assert_eq!(sniffer.feed(b"path HTTP/1.1\r\n"), Detection::Unknown);
+    assert!(sniffer.is_done());
+}
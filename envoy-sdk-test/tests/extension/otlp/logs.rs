@@ -0,0 +1,204 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use envoy::extension::access_logger::format::LogFormat;
+use envoy::extension::access_logger::LogEntryKind;
+use envoy::extension::otlp::{OtlpConfig, OtlpLogExporter};
+use envoy::extension::Result;
+use envoy::host::stream_info::ResponseFlags;
+use envoy::host::Stats;
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{FakeClock, FakeHttpClient, FakeHttpClientResponse, FakeStats, FakeStreamInfo};
+
+fn config() -> OtlpConfig {
+    let mut config = OtlpConfig::default();
+    config.shipper.cluster = "otel_collector".into();
+    config.service_name = "frontend".into();
+    config.resource_attributes = vec![("deployment.environment".into(), "test".into())];
+    config
+}
+
+fn attribute<'a>(record: &'a Value, key: &str) -> Option<&'a Value> {
+    record["attributes"]
+        .as_array()?
+        .iter()
+        .find(|attribute| attribute["key"] == key)
+        .map(|attribute| &attribute["value"])
+}
+
+#[test]
+fn test_otlp_log_exporter_http_entries() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::new(UNIX_EPOCH + Duration::from_secs(10));
+    let stats = FakeStats::default();
+    let mut exporter = OtlpLogExporter::new(&config(), &http_client, &clock, &stats)?
+        .with_body_format(LogFormat::text("%RESPONSE_CODE% %REQ(:PATH)%")?);
+
+    let info = FakeStreamInfo::new().with(|info| {
+        info.request()
+            .method("GET")
+            .path("/orders")
+            .protocol("HTTP/1.1")
+            .header(
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            )
+            .time(UNIX_EPOCH + Duration::from_secs(9))
+            .duration(Duration::from_millis(25));
+        info.response().status_code(503);
+        info.cluster().name("orders");
+    });
+    exporter.export(&info)?;
+    exporter.flush()?;
+
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0].request;
+    assert_eq!(request.upstream, "otel_collector");
+    assert_eq!(
+        request.message.headers.get(":path").map(|v| v.to_string()),
+        Some("/v1/logs".to_string())
+    );
+    assert_eq!(
+        request
+            .message
+            .headers
+            .get("content-type")
+            .map(|v| v.to_string()),
+        Some("application/json".to_string())
+    );
+
+    let body: Value = serde_json::from_slice(&request.message.body)?;
+    let resource = &body["resourceLogs"][0]["resource"];
+    assert_eq!(
+        resource["attributes"][0],
+        json!({"key": "service.name", "value": {"stringValue": "frontend"}})
+    );
+    assert_eq!(
+        resource["attributes"][1],
+        json!({"key": "deployment.environment", "value": {"stringValue": "test"}})
+    );
+    let records = &body["resourceLogs"][0]["scopeLogs"][0]["logRecords"];
+    assert_eq!(records.as_array().map(Vec::len), Some(1));
+    let record = &records[0];
+    assert_eq!(record["timeUnixNano"], "9000000000");
+    assert_eq!(record["observedTimeUnixNano"], "10000000000");
+    assert_eq!(record["severityNumber"], 17);
+    assert_eq!(record["severityText"], "ERROR");
+    assert_eq!(record["body"], json!({"stringValue": "503 /orders"}));
+    assert_eq!(record["traceId"], "0af7651916cd43dd8448eb211c80319c");
+    assert_eq!(record["spanId"], "b7ad6b7169203331");
+    assert_eq!(
+        attribute(record, "http.request.method"),
+        Some(&json!({"stringValue": "GET"}))
+    );
+    assert_eq!(
+        attribute(record, "http.response.status_code"),
+        Some(&json!({"intValue": "503"}))
+    );
+    assert_eq!(
+        attribute(record, "envoy.upstream.cluster"),
+        Some(&json!({"stringValue": "orders"}))
+    );
+    assert_eq!(
+        attribute(record, "envoy.duration_ms"),
+        Some(&json!({"intValue": "25"}))
+    );
+
+    let response = FakeHttpClientResponse::builder()
+        .header(":status", "200")
+        .build();
+    assert!(exporter.on_http_call_response(requests[0].handle, 1, &response)?);
+    assert!(exporter.is_idle());
+    assert_eq!(stats.counter("otlp.logs.sent")?.value()?, 1);
+    Ok(())
+}
+
+#[test]
+fn test_otlp_log_exporter_tcp_entries() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut exporter = OtlpLogExporter::new(&config(), &http_client, &clock, &stats)?;
+
+    let info = FakeStreamInfo::new().with(|info| {
+        info.entry_kind(LogEntryKind::Tcp);
+        info.connection().termination_details("idle_timeout");
+        info.request().size(1024);
+        info.response()
+            .size(4096)
+            .response_flags(ResponseFlags::UPSTREAM_CONNECTION_TERMINATION);
+    });
+    exporter.export(&info)?;
+    exporter.flush()?;
+
+    let requests = http_client.drain_pending_requests();
+    let body: Value = serde_json::from_slice(&requests[0].request.message.body)?;
+    let record = &body["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+    assert_eq!(record["severityText"], "ERROR");
+    assert_eq!(record.get("body"), None);
+    assert_eq!(record.get("traceId"), None);
+    assert_eq!(
+        attribute(record, "envoy.log.kind"),
+        Some(&json!({"stringValue": "tcp"}))
+    );
+    assert_eq!(
+        attribute(record, "network.io.received"),
+        Some(&json!({"intValue": "1024"}))
+    );
+    assert_eq!(
+        attribute(record, "network.io.sent"),
+        Some(&json!({"intValue": "4096"}))
+    );
+    assert_eq!(
+        attribute(record, "envoy.connection.termination_details"),
+        Some(&json!({"stringValue": "idle_timeout"}))
+    );
+    assert_eq!(
+        attribute(record, "envoy.response.flags"),
+        Some(&json!({"stringValue": "UC"}))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_otlp_log_exporter_batches_records() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut config = config();
+    config.shipper.max_batch_entries = 2;
+    let mut exporter = OtlpLogExporter::new(&config, &http_client, &clock, &stats)?;
+
+    let info = FakeStreamInfo::new().with(|info| {
+        info.request().method("GET");
+        info.response().status_code(200);
+    });
+    exporter.export(&info)?;
+    assert!(http_client.drain_pending_requests().is_empty());
+    exporter.export(&info)?;
+
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    let body: Value = serde_json::from_slice(&requests[0].request.message.body)?;
+    let records = &body["resourceLogs"][0]["scopeLogs"][0]["logRecords"];
+    assert_eq!(records.as_array().map(Vec::len), Some(2));
+    assert_eq!(records[0]["severityText"], "INFO");
+    Ok(())
+}
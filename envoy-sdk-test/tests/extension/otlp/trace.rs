@@ -0,0 +1,213 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use envoy::extension::filter::http::RequestHeadersOps;
+use envoy::extension::otlp::{
+    IdGenerator, OtlpConfig, OtlpSpan, OtlpSpanExporter, SpanContext, TraceParent,
+};
+use envoy::extension::Result;
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{FakeClock, FakeHttpClient, FakeRequestOps, FakeStats, FakeStreamInfo};
+
+const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+#[test]
+fn test_traceparent() -> Result<()> {
+    let parent = TraceParent::parse(TRACEPARENT)?;
+    assert_eq!(
+        parent.trace_id,
+        [
+            0x0a, 0xf7, 0x65, 0x19, 0x16, 0xcd, 0x43, 0xdd, 0x84, 0x48, 0xeb, 0x21, 0x1c, 0x80,
+            0x31, 0x9c
+        ]
+    );
+    assert_eq!(
+        parent.parent_id,
+        [0xb7, 0xad, 0x6b, 0x71, 0x69, 0x20, 0x33, 0x31]
+    );
+    assert!(parent.is_sampled());
+    assert_eq!(parent.to_string(), TRACEPARENT);
+
+    // future versions may carry extra fields
+    assert!(
+        TraceParent::parse("cc-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra").is_ok()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_traceparent_invalid() {
+    for value in &[
+        "",
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+        "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+        "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+        "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+        "00_0af7651916cd43dd8448eb211c80319c_b7ad6b7169203331_01",
+    ] {
+        assert!(TraceParent::parse(value).is_err(), "{:?}", value);
+    }
+}
+
+#[test]
+fn test_span_context_propagated_to_upstream() -> Result<()> {
+    let ids = IdGenerator::new(42);
+    let ops = FakeRequestOps::default();
+    ops.set_request_header(TraceParent::HEADER, TRACEPARENT)?;
+
+    let span = SpanContext::propagate(&ops, &ids)?;
+
+    let parent = TraceParent::parse(TRACEPARENT)?;
+    assert_eq!(span.trace_id, parent.trace_id);
+    assert_eq!(span.parent_span_id, Some(parent.parent_id));
+    assert_ne!(span.span_id, parent.parent_id);
+    assert_eq!(
+        ops.request_header(TraceParent::HEADER)?
+            .map(|value| value.to_string()),
+        Some(span.traceparent().to_string())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_span_context_without_valid_traceparent() -> Result<()> {
+    let ids = IdGenerator::new(42);
+    let ops = FakeRequestOps::default();
+    ops.set_request_header(TraceParent::HEADER, "garbage")?;
+
+    let span = SpanContext::propagate(&ops, &ids)?;
+
+    assert_eq!(span.parent_span_id, None);
+    assert_ne!(span.trace_id, [0; 16]);
+    assert!(span.is_sampled());
+    assert_eq!(
+        TraceParent::parse(
+            &ops.request_header(TraceParent::HEADER)?
+                .unwrap()
+                .to_string()
+        )?,
+        span.traceparent()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_otlp_span_exporter_exchange() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::new(UNIX_EPOCH + Duration::from_secs(10));
+    let stats = FakeStats::default();
+    let mut config = OtlpConfig::default();
+    config.shipper.cluster = "otel_collector".into();
+    let mut exporter = OtlpSpanExporter::new(&config, &http_client, &clock, &stats)?;
+
+    let parent = TraceParent::parse(TRACEPARENT)?;
+    let span = SpanContext::child_of(Some(&parent), &IdGenerator::new(1));
+    let info = FakeStreamInfo::new().with(|info| {
+        info.request()
+            .method("POST")
+            .path("/orders")
+            .time(UNIX_EPOCH + Duration::from_secs(9))
+            .duration(Duration::from_millis(250));
+        info.response().status_code(500);
+        info.route().name("orders");
+    });
+    exporter.export_exchange(&info, &span)?;
+    exporter.flush()?;
+
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0].request;
+    assert_eq!(
+        request.message.headers.get(":path").map(|v| v.to_string()),
+        Some("/v1/traces".to_string())
+    );
+    let body: Value = serde_json::from_slice(&request.message.body)?;
+    let spans = &body["resourceSpans"][0]["scopeSpans"][0]["spans"];
+    assert_eq!(spans.as_array().map(Vec::len), Some(1));
+    let exported = &spans[0];
+    assert_eq!(exported["traceId"], "0af7651916cd43dd8448eb211c80319c");
+    assert_eq!(exported["parentSpanId"], "b7ad6b7169203331");
+    assert_eq!(exported["spanId"], span.traceparent().to_string()[36..52]);
+    assert_eq!(exported["name"], "POST orders");
+    assert_eq!(exported["kind"], 2);
+    assert_eq!(exported["startTimeUnixNano"], "9000000000");
+    assert_eq!(exported["endTimeUnixNano"], "9250000000");
+    assert_eq!(exported["status"], json!({"code": 2}));
+    Ok(())
+}
+
+#[test]
+fn test_otlp_span_exporter_unsampled_spans() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::default();
+    let stats = FakeStats::default();
+    let mut exporter = OtlpSpanExporter::new(&OtlpConfig::default(), &http_client, &clock, &stats)?;
+
+    let parent = TraceParent::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00")?;
+    let span = SpanContext::child_of(Some(&parent), &IdGenerator::new(1));
+    exporter.export_exchange(&FakeStreamInfo::new(), &span)?;
+    exporter.flush()?;
+
+    assert!(http_client.drain_pending_requests().is_empty());
+    assert!(exporter.is_idle());
+    Ok(())
+}
+
+#[test]
+fn test_otlp_span_exporter_span_handed_over_by_another_context() -> Result<()> {
+    let http_client = FakeHttpClient::default();
+    let clock = FakeClock::new(UNIX_EPOCH + Duration::from_secs(10));
+    let stats = FakeStats::default();
+    let mut exporter = OtlpSpanExporter::new(&OtlpConfig::default(), &http_client, &clock, &stats)?;
+
+    let parent = TraceParent::parse(TRACEPARENT)?;
+    let span = SpanContext::child_of(Some(&parent), &IdGenerator::new(1));
+    let info = FakeStreamInfo::new().with(|info| {
+        info.request().method("GET");
+    });
+    let recorded = OtlpSpan::from_exchange(&info, &span, &clock)?.unwrap();
+
+    // e.g., through a shared queue
+    let received: OtlpSpan = serde_json::from_slice(&serde_json::to_vec(&recorded)?)?;
+    assert_eq!(received, recorded);
+    exporter.export(&received)?;
+    exporter.flush()?;
+
+    let requests = http_client.drain_pending_requests();
+    assert_eq!(requests.len(), 1);
+    let body: Value = serde_json::from_slice(&requests[0].request.message.body)?;
+    let exported = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+    assert_eq!(exported["traceId"], "0af7651916cd43dd8448eb211c80319c");
+    assert_eq!(exported["name"], "GET");
+    Ok(())
+}
+
+#[test]
+fn test_otlp_span_unsampled() -> Result<()> {
+    let parent = TraceParent::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00")?;
+    let span = SpanContext::child_of(Some(&parent), &IdGenerator::new(1));
+
+    assert_eq!(
+        OtlpSpan::from_exchange(&FakeStreamInfo::new(), &span, &FakeClock::default())?,
+        None
+    );
+    Ok(())
+}
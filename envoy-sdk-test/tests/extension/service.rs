@@ -0,0 +1,115 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use envoy::extension::service::{ConfigureOps, TickOps, TimerOps};
+use envoy::extension::{ConfigStatus, Result, Service};
+use envoy::host::shared_queue::{QueueReceiver, QueueSender, SharedQueueHandle};
+use envoy::host::{self, ByteString};
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{FakeClock, FakeSharedQueue};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Event {
+    status: u16,
+}
+
+/// Aggregates events published by `HTTP Filter`s.
+struct Aggregator<'a> {
+    events: QueueReceiver<'a, Event>,
+    errors: u64,
+    reported: Vec<u64>,
+}
+
+impl<'a> Service for Aggregator<'a> {
+    fn name() -> &'static str {
+        "aggregator"
+    }
+
+    fn on_configure(&mut self, config: ByteString, ops: &dyn ConfigureOps) -> Result<ConfigStatus> {
+        let seconds = config.to_string().parse()?;
+        ops.set_tick_period(Duration::from_secs(seconds))?;
+        Ok(ConfigStatus::Accepted)
+    }
+
+    fn on_tick(&mut self, _ops: &dyn TickOps) -> Result<()> {
+        self.reported.push(self.errors);
+        Ok(())
+    }
+
+    fn on_queue_ready(&mut self, queue_id: SharedQueueHandle) -> Result<()> {
+        if queue_id != self.events.handle() {
+            return Ok(());
+        }
+        for event in self.events.receive_batch(2)? {
+            if event.status >= 500 {
+                self.errors += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FakeTimerOps {
+    tick_period: RefCell<Option<Duration>>,
+}
+
+impl TimerOps for FakeTimerOps {
+    fn set_tick_period(&self, period: Duration) -> host::Result<()> {
+        self.tick_period.replace(Some(period));
+        Ok(())
+    }
+}
+
+impl ConfigureOps for FakeTimerOps {}
+
+impl TickOps for FakeTimerOps {}
+
+#[test]
+fn test_service_aggregates_events_on_queue_ready() -> Result<()> {
+    let shared_queue = FakeSharedQueue::new("singleton");
+    let clock = FakeClock::default();
+    let ops = FakeTimerOps::default();
+    let mut service = Aggregator {
+        events: QueueReceiver::register(&shared_queue, "events")?,
+        errors: 0,
+        reported: Vec::new(),
+    };
+
+    assert_eq!(
+        service.on_configure("10".into(), &ops)?,
+        ConfigStatus::Accepted
+    );
+    assert_eq!(*ops.tick_period.borrow(), Some(Duration::from_secs(10)));
+
+    let sender = QueueSender::new(&shared_queue, &clock, "singleton", "events");
+    for status in &[200, 503, 500] {
+        sender.send(&Event { status: *status })?;
+    }
+
+    // messages are consumed in batches
+    service.on_queue_ready(service.events.handle())?;
+    service.on_tick(&ops)?;
+    service.on_queue_ready(service.events.handle())?;
+    service.on_tick(&ops)?;
+    assert_eq!(service.reported, vec![1, 2]);
+    assert_eq!(shared_queue.len("events"), 0);
+    Ok(())
+}
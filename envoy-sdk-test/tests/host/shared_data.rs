@@ -0,0 +1,143 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+
+use envoy::host::error::{is_cas_mismatch, CasMismatchError};
+use envoy::host::shared_data::TypedSharedData;
+use envoy::host::{Result, SharedData};
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::FakeSharedData;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Quota {
+    used: u64,
+    limit: u64,
+}
+
+#[test]
+fn test_fake_shared_data_versions() -> Result<()> {
+    let shared_data = FakeSharedData::default();
+    assert_eq!(shared_data.get("key")?, (None, None));
+
+    shared_data.set("key", b"a", None)?;
+    assert_eq!(shared_data.get("key")?, (Some("a".into()), Some(1)));
+
+    shared_data.set("key", b"b", Some(1))?;
+    assert_eq!(shared_data.get("key")?, (Some("b".into()), Some(2)));
+
+    let err = shared_data.set("key", b"c", Some(1)).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<CasMismatchError>(),
+        Some(&CasMismatchError::new("key"))
+    );
+    assert_eq!(shared_data.get("key")?, (Some("b".into()), Some(2)));
+
+    // like `Envoy`, version of a missing key is not checked
+    shared_data.set("other", b"x", Some(7))?;
+    assert_eq!(shared_data.get("other")?, (Some("x".into()), Some(1)));
+    assert_eq!(shared_data.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_typed_shared_data_encoding() -> Result<()> {
+    let fake = FakeSharedData::default();
+    let shared_data = TypedSharedData::new(&fake);
+
+    assert_eq!(shared_data.get::<Quota>("quota")?, None);
+
+    shared_data.set("quota", &Quota { used: 1, limit: 10 }, None)?;
+
+    assert_eq!(
+        shared_data.get("quota")?,
+        Some(Quota { used: 1, limit: 10 })
+    );
+    assert_eq!(
+        fake.get("quota")?.0,
+        Some(r#"{"used":1,"limit":10}"#.into())
+    );
+
+    fake.set("quota", b"not json", None)?;
+    assert!(shared_data.get::<Quota>("quota").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_typed_shared_data_update_retries_on_concurrent_modification() -> Result<()> {
+    let fake = FakeSharedData::default();
+    let shared_data = TypedSharedData::new(&fake);
+    shared_data.set("counter", &0u64, None)?;
+
+    let calls = Cell::new(0);
+    let value = shared_data.update("counter", |value: Option<u64>| {
+        calls.set(calls.get() + 1);
+        if calls.get() < 3 {
+            // another worker wins the race
+            let (current, version) = fake.get("counter")?;
+            let current: u64 = serde_json::from_slice(&current.unwrap())?;
+            fake.set("counter", (current + 10).to_string().as_bytes(), version)?;
+        }
+        Ok(value.unwrap_or(0) + 1)
+    })?;
+
+    assert_eq!(calls.get(), 3);
+    assert_eq!(value, 21);
+    assert_eq!(shared_data.get::<u64>("counter")?, Some(21));
+    Ok(())
+}
+
+#[test]
+fn test_typed_shared_data_update_gives_up_after_max_attempts() -> Result<()> {
+    let fake = FakeSharedData::default();
+    let shared_data = TypedSharedData::new(&fake).with_max_attempts(2);
+    shared_data.set("counter", &0u64, None)?;
+
+    let calls = Cell::new(0);
+    let err = shared_data
+        .update("counter", |value: Option<u64>| {
+            calls.set(calls.get() + 1);
+            let (current, version) = fake.get("counter")?;
+            fake.set("counter", &current.unwrap(), version)?;
+            Ok(value.unwrap_or(0) + 1)
+        })
+        .unwrap_err();
+
+    assert_eq!(calls.get(), 2);
+    assert!(is_cas_mismatch(&err));
+    assert_eq!(shared_data.get::<u64>("counter")?, Some(0));
+    Ok(())
+}
+
+#[test]
+fn test_typed_shared_data_update_does_not_retry_other_errors() -> Result<()> {
+    let fake = FakeSharedData::default();
+    let shared_data = TypedSharedData::new(&fake);
+
+    let calls = Cell::new(0);
+    let err = shared_data
+        .update("counter", |_: Option<u64>| -> Result<u64> {
+            calls.set(calls.get() + 1);
+            envoy::error::bail!("invalid state")
+        })
+        .unwrap_err();
+
+    assert_eq!(calls.get(), 1);
+    assert!(!is_cas_mismatch(&err));
+    assert!(fake.is_empty());
+    Ok(())
+}
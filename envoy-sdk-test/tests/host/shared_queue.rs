@@ -0,0 +1,160 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use envoy::host::shared_queue::{QueueReceiver, QueueSender};
+use envoy::host::{Result, SharedQueue};
+
+use envoy_sdk_test as envoy_test;
+use envoy_test::{FakeClock, FakeSharedQueue};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Event {
+    path: String,
+    status: u16,
+}
+
+fn event(status: u16) -> Event {
+    Event {
+        path: "/".into(),
+        status,
+    }
+}
+
+#[test]
+fn test_queue_sender_and_receiver() -> Result<()> {
+    let shared_queue = FakeSharedQueue::new("aggregator");
+    let clock = FakeClock::default();
+    let receiver = QueueReceiver::<Event>::register(&shared_queue, "events")?;
+    let sender = QueueSender::new(&shared_queue, &clock, "aggregator", "events");
+
+    sender.send(&event(200))?;
+    sender.send(&event(404))?;
+    assert_eq!(sender.pending(), 0);
+
+    let value = shared_queue.dequeue(receiver.handle())?.unwrap();
+    assert_eq!(
+        value.to_string(),
+        r#"{"version":1,"message":{"path":"/","status":200}}"#
+    );
+
+    assert_eq!(receiver.receive()?, Some(event(404)));
+    assert_eq!(receiver.receive()?, None);
+    Ok(())
+}
+
+#[test]
+fn test_queue_receiver_batches() -> Result<()> {
+    let shared_queue = FakeSharedQueue::new("aggregator");
+    let clock = FakeClock::default();
+    let receiver = QueueReceiver::<Event>::register(&shared_queue, "events")?;
+    let sender = QueueSender::new(&shared_queue, &clock, "aggregator", "events");
+
+    for status in 200..205 {
+        sender.send(&event(status))?;
+    }
+
+    assert_eq!(
+        receiver.receive_batch(3)?,
+        vec![event(200), event(201), event(202)]
+    );
+    assert_eq!(receiver.receive_batch(3)?, vec![event(203), event(204)]);
+    assert_eq!(receiver.receive_batch(3)?, vec![]);
+    Ok(())
+}
+
+#[test]
+fn test_queue_receiver_skips_messages_of_other_versions() -> Result<()> {
+    let shared_queue = FakeSharedQueue::new("aggregator");
+    let clock = FakeClock::default();
+    let receiver = QueueReceiver::<Event>::register(&shared_queue, "events")?.with_version(2);
+    let v1 = QueueSender::new(&shared_queue, &clock, "aggregator", "events");
+    let v2 = QueueSender::new(&shared_queue, &clock, "aggregator", "events").with_version(2);
+
+    v1.send(&event(200))?;
+    shared_queue.enqueue(receiver.handle(), b"not json")?;
+    v2.send(&event(201))?;
+
+    assert_eq!(receiver.receive_batch(10)?, vec![event(201)]);
+    assert_eq!(receiver.skipped(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_queue_sender_retries_resolution_until_consumer_is_up() -> Result<()> {
+    let shared_queue = FakeSharedQueue::new("aggregator");
+    let clock = FakeClock::default();
+    let sender = QueueSender::new(&shared_queue, &clock, "aggregator", "events")
+        .with_retry_interval(Duration::from_secs(5));
+
+    sender.send(&event(200))?;
+    assert_eq!(sender.pending(), 1);
+
+    // the queue is not looked up again until the retry interval elapses
+    let receiver = QueueReceiver::<Event>::register(&shared_queue, "events")?;
+    sender.send(&event(201))?;
+    assert_eq!(sender.pending(), 2);
+    assert_eq!(shared_queue.len("events"), 0);
+
+    clock.advance(Duration::from_secs(5));
+    sender.send(&event(202))?;
+    assert_eq!(sender.pending(), 0);
+    assert_eq!(
+        receiver.receive_batch(10)?,
+        vec![event(200), event(201), event(202)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_queue_sender_drops_oldest_pending_messages() -> Result<()> {
+    let shared_queue = FakeSharedQueue::new("aggregator");
+    let clock = FakeClock::default();
+    let sender =
+        QueueSender::new(&shared_queue, &clock, "aggregator", "events").with_max_pending(2);
+
+    for status in 200..205 {
+        sender.send(&event(status))?;
+    }
+    assert_eq!(sender.pending(), 2);
+    assert_eq!(sender.dropped(), 3);
+
+    let receiver = QueueReceiver::<Event>::register(&shared_queue, "events")?;
+    clock.advance(QueueSender::<Event>::DEFAULT_RETRY_INTERVAL);
+    sender.flush()?;
+    assert_eq!(receiver.receive_batch(10)?, vec![event(203), event(204)]);
+    Ok(())
+}
+
+#[test]
+fn test_queue_sender_resolves_queue_again_after_consumer_restart() -> Result<()> {
+    let shared_queue = FakeSharedQueue::new("aggregator");
+    let clock = FakeClock::default();
+    QueueReceiver::<Event>::register(&shared_queue, "other")?;
+    QueueReceiver::<Event>::register(&shared_queue, "events")?;
+    let sender = QueueSender::new(&shared_queue, &clock, "aggregator", "events");
+
+    sender.send(&event(200))?;
+
+    // handles change once the consumer VM registers queues in a different order
+    shared_queue.reset();
+    let receiver = QueueReceiver::<Event>::register(&shared_queue, "events")?;
+
+    sender.send(&event(201))?;
+    assert_eq!(receiver.receive_batch(10)?, vec![event(201)]);
+    Ok(())
+}
@@ -93,3 +93,370 @@ fn test_fake_histogram() -> Result<()> {
 
     Ok(())
 }
+
+mod family {
+    use std::cell::Cell;
+
+    use envoy::host::stats::{Counter, CounterFamily, Gauge, GaugeFamily, Histogram};
+    use envoy::host::{Result, Stats};
+
+    use super::envoy_test::FakeStats;
+
+    /// Counts how many times metrics have been defined.
+    #[derive(Default)]
+    struct CountingStats {
+        stats: FakeStats,
+        defined: Cell<usize>,
+    }
+
+    impl Stats for CountingStats {
+        fn counter(&self, name: &str) -> Result<Box<dyn Counter>> {
+            self.defined.set(self.defined.get() + 1);
+            self.stats.counter(name)
+        }
+
+        fn gauge(&self, name: &str) -> Result<Box<dyn Gauge>> {
+            self.defined.set(self.defined.get() + 1);
+            self.stats.gauge(name)
+        }
+
+        fn histogram(&self, name: &str) -> Result<Box<dyn Histogram>> {
+            self.defined.set(self.defined.get() + 1);
+            self.stats.histogram(name)
+        }
+    }
+
+    #[test]
+    fn test_metric_family_stat_names() -> Result<()> {
+        let stats = FakeStats::default();
+        let family = CounterFamily::new(&stats, "requests_total", &["route", "response_code"])?;
+
+        family.with_labels(&["orders", "200"])?.inc()?;
+        family.with_labels(&["orders", "503"])?.add(2)?;
+
+        assert_eq!(
+            stats
+                .counter("route=.=orders;.;response_code=.=200;.;requests_total")?
+                .value()?,
+            1
+        );
+        assert_eq!(
+            stats
+                .counter("route=.=orders;.;response_code=.=503;.;requests_total")?
+                .value()?,
+            2
+        );
+        assert_eq!(
+            family.stat_name(&["a;.;b", "c=.=d"])?,
+            "route=.=a_._b;.;response_code=.=c_._d;.;requests_total"
+        );
+        assert_eq!(
+            CounterFamily::tag_extraction_regex("route"),
+            r"(route=\.=(.*?);\.;)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_metric_family_caches_metric_per_label_set() -> Result<()> {
+        let stats = CountingStats::default();
+        let family = GaugeFamily::new(&stats, "connections_active", &["cluster"])?;
+
+        family.with_labels(&["a"])?.inc()?;
+        family.with_labels(&["a"])?.inc()?;
+        family.with_labels(&["b"])?.inc()?;
+
+        assert_eq!(stats.defined.get(), 2);
+        assert_eq!(family.cardinality(), 2);
+        assert_eq!(family.with_labels(&["a"])?.value()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metric_family_overflow() -> Result<()> {
+        let stats = CountingStats::default();
+        let family =
+            CounterFamily::new(&stats, "requests_total", &["path"])?.with_max_cardinality(2);
+
+        family.with_labels(&["/a"])?.inc()?;
+        family.with_labels(&["/b"])?.inc()?;
+        family.with_labels(&["/c"])?.inc()?;
+        family.with_labels(&["/d"])?.inc()?;
+        family.with_labels(&["/a"])?.inc()?;
+
+        assert_eq!(family.cardinality(), 2);
+        assert_eq!(stats.defined.get(), 3);
+        assert_eq!(
+            stats
+                .stats
+                .counter("path=.=overflow;.;requests_total")?
+                .value()?,
+            2
+        );
+        assert_eq!(
+            stats.stats.counter("path=.=/a;.;requests_total")?.value()?,
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_metric_family_invalid_labels() -> Result<()> {
+        let stats = FakeStats::default();
+        assert!(CounterFamily::new(&stats, "requests_total", &["response code"]).is_err());
+        assert!(CounterFamily::new(&stats, "requests_total", &[""]).is_err());
+
+        let family = CounterFamily::new(&stats, "requests_total", &["route"])?;
+        assert!(family.with_labels(&[]).is_err());
+        assert!(family.with_labels(&["a", "b"]).is_err());
+        Ok(())
+    }
+}
+
+mod derive {
+    use std::rc::Rc;
+
+    use envoy::host::stats::{Counter, Gauge, Histogram, HistogramFamily, Stats};
+    use envoy::host::Result;
+
+    use super::envoy_test::FakeStats;
+
+    #[derive(Stats)]
+    #[stats(prefix = "my_filter.")]
+    struct MyStats<'a> {
+        requests_total: Box<dyn Counter>,
+        #[stats(name = "active_requests")]
+        requests_active: Rc<dyn Gauge>,
+        #[stats(histogram)]
+        response_size: ResponseSize,
+        #[stats(labels("route"), max_cardinality = 1)]
+        request_duration_ms: HistogramFamily<'a>,
+    }
+
+    type ResponseSize = Box<dyn Histogram>;
+
+    #[derive(Stats)]
+    struct Unprefixed {
+        total: Box<dyn Counter>,
+    }
+
+    #[test]
+    fn test_derive_stats() -> Result<()> {
+        let fake_stats = FakeStats::default();
+        let stats = MyStats::new(&fake_stats)?;
+
+        stats.requests_total.inc()?;
+        stats.requests_active.set(3)?;
+        stats.response_size.record(1024)?;
+        stats
+            .request_duration_ms
+            .with_labels(&["orders"])?
+            .record(10)?;
+
+        assert_eq!(fake_stats.counter("my_filter.requests_total")?.value()?, 1);
+        assert_eq!(fake_stats.gauge("my_filter.active_requests")?.value()?, 3);
+        assert_eq!(
+            stats.request_duration_ms.name(),
+            "my_filter.request_duration_ms"
+        );
+        assert_eq!(stats.request_duration_ms.labels(), &["route".to_string()]);
+        assert_eq!(stats.request_duration_ms.cardinality(), 1);
+        stats
+            .request_duration_ms
+            .with_labels(&["users"])?
+            .record(20)?;
+        assert_eq!(stats.request_duration_ms.cardinality(), 1);
+
+        let unprefixed = Unprefixed::new(&fake_stats)?;
+        unprefixed.total.add(2)?;
+        assert_eq!(fake_stats.counter("total")?.value()?, 2);
+        Ok(())
+    }
+}
+
+mod scope {
+    use envoy::extension::{self, ExtensionFactory, InstanceId};
+    use envoy::host::stats::{CounterFamily, ScopedStats, Stats};
+    use envoy::host::Result;
+
+    use super::envoy_test::{FakeStats, FakeStreamInfo};
+
+    struct MyFactory;
+
+    impl ExtensionFactory for MyFactory {
+        type Extension = ();
+
+        fn name() -> &'static str {
+            "my_extension"
+        }
+
+        fn new_extension(&mut self, _instance_id: InstanceId) -> extension::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_scoped_stats_prefixes_stat_names() -> Result<()> {
+        let fake_stats = FakeStats::default();
+        let stats = ScopedStats::new(&fake_stats, "my_filter");
+
+        stats.counter("requests_total")?.inc()?;
+        stats.gauge("requests_active")?.set(2)?;
+        stats.histogram("response_size")?.record(1)?;
+
+        assert_eq!(stats.prefix(), "my_filter.");
+        assert_eq!(fake_stats.counter("my_filter.requests_total")?.value()?, 1);
+        assert_eq!(fake_stats.gauge("my_filter.requests_active")?.value()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoped_stats_nested() -> Result<()> {
+        let fake_stats = FakeStats::default();
+        let stats = ScopedStats::new(&fake_stats, "my_filter");
+        let upstream = stats.scope("upstream");
+        let nested = upstream.scope("retries");
+
+        upstream.counter("requests_total")?.inc()?;
+        nested.counter("total")?.add(2)?;
+        stats.scope("").counter("requests_total")?.add(3)?;
+
+        assert_eq!(nested.prefix(), "my_filter.upstream.retries.");
+        assert_eq!(
+            fake_stats
+                .counter("my_filter.upstream.requests_total")?
+                .value()?,
+            1
+        );
+        assert_eq!(
+            fake_stats
+                .counter("my_filter.upstream.retries.total")?
+                .value()?,
+            2
+        );
+        assert_eq!(fake_stats.counter("my_filter.requests_total")?.value()?, 3);
+        assert_eq!(ScopedStats::new(&fake_stats, "").prefix(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoped_stats_for_plugin_or_extension() -> Result<()> {
+        let fake_stats = FakeStats::default();
+        let stream_info = FakeStreamInfo::new().with(|info| {
+            info.plugin().name("my_plugin");
+        });
+
+        let plugin = ScopedStats::for_plugin(&fake_stats, &stream_info)?;
+        assert_eq!(plugin.prefix(), "my_plugin.");
+        assert!(ScopedStats::for_plugin(&fake_stats, &FakeStreamInfo::new()).is_err());
+
+        let extension = ScopedStats::for_extension::<MyFactory>(&fake_stats);
+        assert_eq!(extension.prefix(), "my_extension.");
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoped_stats_with_metric_families() -> Result<()> {
+        let fake_stats = FakeStats::default();
+        let stats = ScopedStats::new(&fake_stats, "my_filter");
+        let family = CounterFamily::new(&stats, "requests_total", &["route"])?;
+
+        family.with_labels(&["orders"])?.inc()?;
+
+        assert_eq!(
+            fake_stats
+                .counter("my_filter.route=.=orders;.;requests_total")?
+                .value()?,
+            1
+        );
+        Ok(())
+    }
+}
+
+mod timer {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use envoy::host::stats::{ExchangeTimer, Histogram, TimeUnit, Timer};
+    use envoy::host::Result;
+
+    use super::envoy_test::FakeClock;
+
+    /// Keeps recorded values.
+    #[derive(Default)]
+    struct RecordingHistogram(RefCell<Vec<u64>>);
+
+    impl Histogram for RecordingHistogram {
+        fn record(&self, value: u64) -> Result<()> {
+            self.0.borrow_mut().push(value);
+            Ok(())
+        }
+    }
+
+    impl RecordingHistogram {
+        fn values(&self) -> Vec<u64> {
+            self.0.borrow().clone()
+        }
+    }
+
+    #[test]
+    fn test_time_unit_convert() {
+        let duration = Duration::from_millis(1500);
+        assert_eq!(TimeUnit::Nanoseconds.convert(duration), 1_500_000_000);
+        assert_eq!(TimeUnit::Microseconds.convert(duration), 1_500_000);
+        assert_eq!(TimeUnit::Milliseconds.convert(duration), 1_500);
+        assert_eq!(TimeUnit::Seconds.convert(duration), 1);
+        assert_eq!(
+            TimeUnit::Nanoseconds.convert(Duration::from_secs(u64::MAX)),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn test_latency_timer_records_when_stopped_or_dropped() -> Result<()> {
+        let clock = FakeClock::default();
+        let histogram = Rc::new(RecordingHistogram::default());
+
+        let timer = Timer::start(&clock, histogram.clone(), TimeUnit::Milliseconds)?;
+        clock.advance(Duration::from_millis(25));
+        assert_eq!(timer.elapsed()?, Duration::from_millis(25));
+        assert_eq!(timer.stop()?, Duration::from_millis(25));
+        assert_eq!(histogram.values(), vec![25]);
+
+        {
+            let _timer = Timer::start(&clock, histogram.clone(), TimeUnit::Microseconds)?;
+            clock.advance(Duration::from_millis(2));
+        }
+        assert_eq!(histogram.values(), vec![25, 2000]);
+
+        let timer = Timer::start(&clock, histogram.clone(), TimeUnit::Milliseconds)?;
+        clock.advance(Duration::from_millis(5));
+        timer.cancel();
+        assert_eq!(histogram.values(), vec![25, 2000]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_latency_timer_exchange() -> Result<()> {
+        let clock = FakeClock::default();
+        let histogram = Rc::new(RecordingHistogram::default());
+        let mut latency = ExchangeTimer::new(&clock, histogram.clone(), TimeUnit::Milliseconds);
+
+        assert_eq!(latency.on_exchange_complete()?, None);
+
+        latency.on_request_headers()?;
+        clock.advance(Duration::from_millis(10));
+        // repeated calls do not restart measurement
+        latency.on_request_headers()?;
+        clock.advance(Duration::from_millis(20));
+
+        assert_eq!(
+            latency.on_exchange_complete()?,
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(latency.on_exchange_complete()?, None);
+        assert_eq!(histogram.values(), vec![30]);
+        Ok(())
+    }
+}
@@ -15,6 +15,7 @@
 use std::time::{Duration, SystemTime};
 
 use envoy::extension::access_logger;
+use envoy::extension::filter::{http, network};
 use envoy::host::stream_info::{ResponseFlags, TrafficDirection};
 use envoy::host::{HeaderMap, Result, StreamInfo};
 
@@ -282,11 +283,22 @@ fn test_listener() -> Result<()> {
 #[test]
 fn test_route() -> Result<()> {
     let fake_info = FakeStreamInfo::new().with(|info| {
-        info.route().name("my_route");
+        info.route()
+            .name("my_route")
+            .metadata("my_filter", "config", "{}");
     });
     let stream_info: &dyn StreamInfo = &fake_info;
 
     assert_eq!(stream_info.route().name()?, Some("my_route".to_owned()));
+    assert_eq!(
+        stream_info.route().metadata("my_filter", "config")?,
+        Some("{}".into())
+    );
+    assert_eq!(stream_info.route().metadata("my_filter", "other")?, None);
+    assert_eq!(
+        stream_info.route().metadata("other_filter", "config")?,
+        None
+    );
 
     Ok(())
 }
@@ -394,3 +406,64 @@ fn test_headers() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_exchange_complete() -> Result<()> {
+    let fake_info = FakeStreamInfo::new().with(|info| {
+        info.request()
+            .method("POST")
+            .path("/upload")
+            .header("content-type", "application/grpc")
+            .trailer("x-checksum", "abcdef");
+        info.response()
+            .status_code(200)
+            .header("content-type", "application/grpc")
+            .total_size(4096)
+            .grpc_status(0)
+            .response_flags(ResponseFlags::UPSTREAM_REQUEST_TIMEOUT);
+    });
+    let ops: &dyn http::ExchangeCompleteOps = &fake_info;
+
+    assert_eq!(
+        ops.request_header("content-type")?,
+        Some("application/grpc".into())
+    );
+    assert_eq!(
+        ops.request_trailers()?,
+        HeaderMap::builder().header("x-checksum", "abcdef").build()
+    );
+    assert_eq!(ops.request_trailer("x-checksum")?, Some("abcdef".into()));
+    assert_eq!(ops.request_trailer("x-custom-trailer")?, None);
+    assert_eq!(
+        ops.response_headers()?,
+        HeaderMap::builder()
+            .header(":status", "200")
+            .header("content-type", "application/grpc")
+            .build()
+    );
+    assert_eq!(ops.response_trailer("grpc-status")?, Some("0".into()));
+    assert_eq!(ops.stream_info().response().total_size()?, Some(4096));
+    assert_eq!(
+        ops.stream_info().response().flags()?,
+        Some(ResponseFlags::UPSTREAM_REQUEST_TIMEOUT)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_complete() -> Result<()> {
+    let fake_info = FakeStreamInfo::new().with(|info| {
+        info.connection().id(123);
+        info.upstream().address("192.168.0.1").port(5432);
+    });
+    let ops: &dyn network::ConnectionCompleteOps = &fake_info;
+
+    assert_eq!(ops.stream_info().connection().id()?, Some(123));
+    assert_eq!(
+        ops.stream_info().upstream().address()?,
+        Some("192.168.0.1".to_owned())
+    );
+
+    Ok(())
+}
@@ -21,12 +21,21 @@ use std::time::{Duration, SystemTime};
 use proxy_wasm::hostcalls;
 
 use super::types::{
-    BufferType, HttpRequestHandle, MapType, MetricHandle, MetricType, OptimisticLockVersion,
-    SharedQueueHandle, Status, StreamType,
+    BufferType, ConnectionStreamType, HttpRequestHandle, MapType, MetricHandle, MetricType,
+    OptimisticLockVersion, SharedQueueHandle, Status, StreamType,
 };
 use crate::error::format_err;
+use crate::host::error::CasMismatchError;
 use crate::host::{self, ByteString, HeaderMap};
 
+// Logging API
+
+// with the `log` feature, logging goes through the `log` crate instead
+#[cfg(not(feature = "log"))]
+pub fn log(level: super::types::LogLevel, message: &str) -> host::Result<()> {
+    hostcalls::log(level, message).map_err(|err| format_err!(err))
+}
+
 // Configuration API
 
 pub fn get_plugin_configuration(start: usize, max_size: usize) -> host::Result<ByteString> {
@@ -34,12 +43,24 @@ pub fn get_plugin_configuration(start: usize, max_size: usize) -> host::Result<B
     get_buffer(BufferType::PluginConfiguration, start, max_size)
 }
 
+pub fn get_vm_configuration(start: usize, max_size: usize) -> host::Result<ByteString> {
+    get_buffer(BufferType::VmConfiguration, start, max_size)
+}
+
 // Lifecycle API
 
 pub fn done() -> host::Result<()> {
     hostcalls::done().map_err(|err| format_err!(err))
 }
 
+pub fn set_effective_context(context_id: u32) -> host::Result<()> {
+    hostcalls::set_effective_context(context_id).map_err(|err| format_err!(err))
+}
+
+pub fn set_tick_period(period: Duration) -> host::Result<()> {
+    hostcalls::set_tick_period(period).map_err(|err| format_err!(err))
+}
+
 // Headers/Body manipulation API
 
 pub fn get_buffer(
@@ -52,6 +73,15 @@ pub fn get_buffer(
         .map_err(|err| format_err!(err))
 }
 
+pub fn set_buffer(
+    buffer_type: BufferType,
+    start: usize,
+    size: usize,
+    value: &[u8],
+) -> host::Result<()> {
+    hostcalls::set_buffer(buffer_type, start, size, value).map_err(|err| format_err!(err))
+}
+
 pub fn get_map(map_type: MapType) -> host::Result<HeaderMap> {
     hostcalls::get_map(map_type)
         .map(HeaderMap::from)
@@ -87,6 +117,49 @@ pub fn send_http_response(
     hostcalls::send_http_response(status_code, headers, body).map_err(|err| format_err!(err))
 }
 
+extern "C" {
+    fn proxy_send_local_response(
+        status_code: u32,
+        status_code_details_data: *const u8,
+        status_code_details_size: usize,
+        body_data: *const u8,
+        body_size: usize,
+        headers_data: *const u8,
+        headers_size: usize,
+        grpc_status: i32,
+    ) -> Status;
+}
+
+/// Sends a `gRPC` trailers-only response.
+///
+/// `Envoy` sets `content-type`, `grpc-status` and (percent-encoded) `grpc-message`
+/// by itself, the latter from the body of the local response.
+pub fn send_grpc_response(
+    grpc_status: u32,
+    grpc_message: Option<&str>,
+    headers: &[(&str, &str)],
+) -> host::Result<()> {
+    let serialized_headers = serialize_map(headers);
+    let message = grpc_message.unwrap_or_default();
+    unsafe {
+        match proxy_send_local_response(
+            200,
+            std::ptr::null(),
+            0,
+            message.as_ptr(),
+            message.len(),
+            serialized_headers.as_ptr(),
+            serialized_headers.len(),
+            grpc_status as i32,
+        ) {
+            Status::Ok => Ok(()),
+            status => Err(host::function("env", "proxy_send_local_response")
+                .into_call_error(status)
+                .into()),
+        }
+    }
+}
+
 pub fn resume_http_request() -> host::Result<()> {
     hostcalls::continue_stream(StreamType::Request).map_err(|err| format_err!(err))
 }
@@ -95,6 +168,65 @@ pub fn resume_http_response() -> host::Result<()> {
     hostcalls::continue_stream(StreamType::Response).map_err(|err| format_err!(err))
 }
 
+// Connection manipulation API
+
+extern "C" {
+    fn proxy_close_stream(stream_type: ConnectionStreamType) -> Status;
+}
+
+pub fn close_stream(stream_type: ConnectionStreamType) -> host::Result<()> {
+    unsafe {
+        match proxy_close_stream(stream_type) {
+            Status::Ok => Ok(()),
+            status => Err(host::function("env", "proxy_close_stream")
+                .into_call_error(status)
+                .into()),
+        }
+    }
+}
+
+extern "C" {
+    fn proxy_call_foreign_function(
+        function_name_data: *const u8,
+        function_name_size: usize,
+        arguments_data: *const u8,
+        arguments_size: usize,
+        results_data: *mut *mut u8,
+        results_size: *mut usize,
+    ) -> Status;
+}
+
+pub fn clear_route_cache() -> host::Result<()> {
+    let function_name = "clear_route_cache";
+    unsafe {
+        let mut results_data: *mut u8 = std::ptr::null_mut();
+        let mut results_size: usize = 0;
+        match proxy_call_foreign_function(
+            function_name.as_ptr(),
+            function_name.len(),
+            std::ptr::null(),
+            0,
+            &mut results_data,
+            &mut results_size,
+        ) {
+            Status::Ok => {
+                if !results_data.is_null() {
+                    // `clear_route_cache` doesn't return any results, but the memory must be released anyway
+                    drop(Vec::from_raw_parts(
+                        results_data,
+                        results_size,
+                        results_size,
+                    ));
+                }
+                Ok(())
+            }
+            status => Err(host::function("env", "proxy_call_foreign_function")
+                .into_call_error(status)
+                .into()),
+        }
+    }
+}
+
 // Shared Queue
 
 pub fn register_shared_queue(name: &str) -> host::Result<SharedQueueHandle> {
@@ -182,7 +314,14 @@ pub fn set_shared_data(
         if value.is_empty() { None } else { Some(value) },
         version,
     )
-    .map_err(|err| format_err!(err))
+    .map_err(
+        |err| match err.downcast_ref::<proxy_wasm::error::HostCallError>() {
+            Some(call_err) if call_err.status() == Status::CasMismatch => {
+                CasMismatchError::new(key).into()
+            }
+            _ => format_err!(err),
+        },
+    )
 }
 
 // Stats API
@@ -258,3 +397,188 @@ pub fn get_metric(metric_handle: MetricHandle) -> host::Result<u64> {
         }
     }
 }
+
+// Utils
+
+fn serialize_map<K, V>(map: &[(K, V)]) -> Vec<u8>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    let mut size: usize = 4;
+    for (name, value) in map {
+        size += name.as_ref().len() + value.as_ref().len() + 10;
+    }
+    let mut bytes: Vec<u8> = Vec::with_capacity(size);
+    bytes.extend_from_slice(&(map.len() as u32).to_le_bytes());
+    for (name, value) in map {
+        bytes.extend_from_slice(&(name.as_ref().len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(value.as_ref().len() as u32).to_le_bytes());
+    }
+    for (name, value) in map {
+        bytes.extend_from_slice(name.as_ref());
+        bytes.push(0);
+        bytes.extend_from_slice(value.as_ref());
+        bytes.push(0);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// Fake implementations of `Envoy` host functions that are not covered by `proxy_wasm`.
+    mod host {
+        use super::*;
+
+        thread_local! {
+            pub static FOREIGN_FUNCTION: RefCell<ForeignFunction> = RefCell::new(ForeignFunction::default());
+            pub static LOCAL_RESPONSES: RefCell<Vec<LocalResponse>> = const { RefCell::new(Vec::new()) };
+        }
+
+        #[derive(Debug, PartialEq)]
+        pub struct LocalResponse {
+            pub status_code: u32,
+            pub body: Vec<u8>,
+            pub headers: Vec<u8>,
+            pub grpc_status: i32,
+        }
+
+        unsafe fn to_vec(data: *const u8, size: usize) -> Vec<u8> {
+            if data.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(data, size).to_vec()
+            }
+        }
+
+        #[no_mangle]
+        extern "C" fn proxy_send_local_response(
+            status_code: u32,
+            _status_code_details_data: *const u8,
+            _status_code_details_size: usize,
+            body_data: *const u8,
+            body_size: usize,
+            headers_data: *const u8,
+            headers_size: usize,
+            grpc_status: i32,
+        ) -> Status {
+            let response = unsafe {
+                LocalResponse {
+                    status_code,
+                    body: to_vec(body_data, body_size),
+                    headers: to_vec(headers_data, headers_size),
+                    grpc_status,
+                }
+            };
+            LOCAL_RESPONSES.with(|responses| responses.borrow_mut().push(response));
+            Status::Ok
+        }
+
+        #[derive(Default)]
+        pub struct ForeignFunction {
+            pub calls: Vec<String>,
+            pub status: Option<Status>,
+            pub results: Vec<u8>,
+        }
+
+        #[no_mangle]
+        extern "C" fn proxy_call_foreign_function(
+            function_name_data: *const u8,
+            function_name_size: usize,
+            _arguments_data: *const u8,
+            _arguments_size: usize,
+            results_data: *mut *mut u8,
+            results_size: *mut usize,
+        ) -> Status {
+            FOREIGN_FUNCTION.with(|function| {
+                let mut function = function.borrow_mut();
+                let name =
+                    unsafe { std::slice::from_raw_parts(function_name_data, function_name_size) };
+                function
+                    .calls
+                    .push(String::from_utf8_lossy(name).into_owned());
+                if let Some(status) = function.status.take() {
+                    return status;
+                }
+                if !function.results.is_empty() {
+                    // just like `Envoy`, allocate results using the allocator of the module
+                    let results = function.results.clone().into_boxed_slice();
+                    unsafe {
+                        *results_size = results.len();
+                        *results_data = Box::into_raw(results) as *mut u8;
+                    }
+                }
+                Status::Ok
+            })
+        }
+    }
+
+    #[test]
+    fn test_clear_route_cache() {
+        host::FOREIGN_FUNCTION.with(|function| {
+            function.borrow_mut().results = b"ignored".to_vec();
+        });
+
+        assert!(clear_route_cache().is_ok());
+
+        host::FOREIGN_FUNCTION.with(|function| {
+            assert_eq!(function.borrow().calls, vec!["clear_route_cache"]);
+        });
+    }
+
+    #[test]
+    fn test_clear_route_cache_without_results() {
+        assert!(clear_route_cache().is_ok());
+
+        host::FOREIGN_FUNCTION.with(|function| {
+            assert_eq!(function.borrow().calls, vec!["clear_route_cache"]);
+        });
+    }
+
+    #[test]
+    fn test_clear_route_cache_failure() {
+        host::FOREIGN_FUNCTION.with(|function| {
+            function.borrow_mut().status = Some(Status::NotFound);
+        });
+
+        let err = clear_route_cache().unwrap_err();
+
+        assert!(err.to_string().contains("proxy_call_foreign_function"));
+    }
+
+    #[test]
+    fn test_send_grpc_response() {
+        assert!(
+            send_grpc_response(16, Some("missing credentials"), &[("x-reason", "auth")]).is_ok()
+        );
+
+        host::LOCAL_RESPONSES.with(|responses| {
+            assert_eq!(
+                *responses.borrow(),
+                vec![host::LocalResponse {
+                    status_code: 200,
+                    body: b"missing credentials".to_vec(),
+                    headers: serialize_map(&[("x-reason", "auth")]),
+                    grpc_status: 16,
+                }]
+            );
+        });
+    }
+
+    #[test]
+    fn test_send_grpc_response_without_message() {
+        assert!(send_grpc_response(0, None, &[]).is_ok());
+
+        host::LOCAL_RESPONSES.with(|responses| {
+            let responses = responses.borrow();
+            assert_eq!(responses.len(), 1);
+            assert!(responses[0].body.is_empty());
+            assert_eq!(responses[0].headers, serialize_map::<&str, &str>(&[]));
+            assert_eq!(responses[0].grpc_status, 0);
+        });
+    }
+}
@@ -23,3 +23,6 @@ pub use proxy_wasm::{set_log_level, set_root_context, traits};
 
 pub mod hostcalls;
 pub mod types;
+
+#[cfg(test)]
+mod testing;
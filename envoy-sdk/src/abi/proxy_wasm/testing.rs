@@ -0,0 +1,106 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stubs of `Envoy` host functions for unit tests.
+//!
+//! Default methods of `proxy_wasm` context traits refer to `Envoy` host functions,
+//! so a unit test that puts a context behind a trait object, e.g. `Box<dyn RootContext>`,
+//! would otherwise fail to load. Unit tests are not supposed to call these stubs.
+
+#![allow(clippy::too_many_arguments)]
+
+use proxy_wasm::types::{BufferType, MapType, Status};
+
+macro_rules! stub {
+    ($(fn $name:ident($($arg:ident: $ty:ty),* $(,)?);)*) => {
+        $(
+            #[no_mangle]
+            extern "C" fn $name($(_: $ty),*) -> Status {
+                // unwinding out of an `extern "C"` function aborts the test binary
+                eprintln!("unit test called `Envoy` host function `{}`", stringify!($name));
+                std::process::abort()
+            }
+        )*
+    };
+}
+
+stub! {
+    fn proxy_done();
+    fn proxy_get_current_time_nanoseconds(return_time: *mut u64);
+    fn proxy_set_tick_period_milliseconds(period: u32);
+    fn proxy_get_buffer_bytes(
+        buffer_type: BufferType,
+        start: usize,
+        max_size: usize,
+        return_buffer_data: *mut *mut u8,
+        return_buffer_size: *mut usize,
+    );
+    fn proxy_get_header_map_pairs(
+        map_type: MapType,
+        return_map_data: *mut *mut u8,
+        return_map_size: *mut usize,
+    );
+    fn proxy_get_property(
+        path_data: *const u8,
+        path_size: usize,
+        return_value_data: *mut *mut u8,
+        return_value_size: *mut usize,
+    );
+    fn proxy_set_property(
+        path_data: *const u8,
+        path_size: usize,
+        value_data: *const u8,
+        value_size: usize,
+    );
+    fn proxy_get_shared_data(
+        key_data: *const u8,
+        key_size: usize,
+        return_value_data: *mut *mut u8,
+        return_value_size: *mut usize,
+        return_cas: *mut u32,
+    );
+    fn proxy_set_shared_data(
+        key_data: *const u8,
+        key_size: usize,
+        value_data: *const u8,
+        value_size: usize,
+        cas: u32,
+    );
+    fn proxy_register_shared_queue(name_data: *const u8, name_size: usize, return_id: *mut u32);
+    fn proxy_resolve_shared_queue(
+        vm_id_data: *const u8,
+        vm_id_size: usize,
+        name_data: *const u8,
+        name_size: usize,
+        return_id: *mut u32,
+    );
+    fn proxy_dequeue_shared_queue(
+        queue_id: u32,
+        return_value_data: *mut *mut u8,
+        return_value_size: *mut usize,
+    );
+    fn proxy_enqueue_shared_queue(queue_id: u32, value_data: *const u8, value_size: usize);
+    fn proxy_http_call(
+        upstream_data: *const u8,
+        upstream_size: usize,
+        headers_data: *const u8,
+        headers_size: usize,
+        body_data: *const u8,
+        body_size: usize,
+        trailers_data: *const u8,
+        trailers_size: usize,
+        timeout: u32,
+        return_token: *mut u32,
+    );
+}
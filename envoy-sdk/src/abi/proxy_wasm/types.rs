@@ -38,6 +38,19 @@ impl fmt::Display for HttpRequestHandle {
     }
 }
 
+// Stream API
+
+/// Type of a stream of a TCP connection, complementing [`StreamType`] of HTTP streams.
+///
+/// Only `Downstream` is listed since `Envoy` closes the same connection for either type.
+///
+/// [`StreamType`]: enum.StreamType.html
+#[repr(u32)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ConnectionStreamType {
+    Downstream = 2,
+}
+
 // Shared Queue API
 
 /// Opaque identifier of a queue accessible via `Shared Queue API`.
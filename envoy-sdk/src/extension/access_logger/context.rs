@@ -15,6 +15,7 @@
 use super::{AccessLogger, ContextOps, Ops};
 use crate::abi::proxy_wasm::traits::{Context, RootContext};
 use crate::extension::error::ErrorSink;
+use crate::extension::panic::Poisonable;
 use crate::extension::{ConfigStatus, DrainStatus};
 use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
 use crate::host::ByteString;
@@ -23,7 +24,7 @@ pub(crate) struct AccessLoggerContext<'a, L>
 where
     L: AccessLogger,
 {
-    logger: L,
+    logger: Poisonable<L>,
     context_ops: &'a dyn ContextOps,
     logger_ops: &'a dyn Ops,
     http_client_ops: &'a dyn HttpClientResponseOps,
@@ -40,9 +41,10 @@ where
         } else {
             self.context_ops.configuration(0, configuration_size)
         };
+        let ops = self.logger_ops.as_configure_ops();
+        let logger = &mut self.logger;
         match config.and_then(|config| {
-            self.logger
-                .on_configure(config, self.logger_ops.as_configure_ops())
+            logger.call("on_configure", |logger| logger.on_configure(config, ops))
         }) {
             Ok(status) => status.as_bool(),
             Err(err) => {
@@ -54,7 +56,8 @@ where
     }
 
     fn on_log(&mut self) {
-        if let Err(err) = self.logger.on_log(self.logger_ops.as_log_ops()) {
+        let ops = self.logger_ops.as_log_ops();
+        if let Err(err) = self.logger.call("on_log", |logger| logger.on_log(ops)) {
             self.error_sink.observe("failed to log a request", &err);
 
             // TODO(yskopets): can we do anything other than crashing Envoy ?
@@ -67,12 +70,17 @@ where
     L: AccessLogger,
 {
     fn on_done(&mut self) -> bool {
-        match self.logger.on_drain() {
+        match self.logger.call("on_drain", |logger| logger.on_drain()) {
             Ok(status) => status.as_bool(),
             Err(err) => {
                 self.error_sink
                     .observe("failed to initiate draining of the extension", &err);
-                DrainStatus::Ongoing.as_bool()
+                // a poisoned extension is never going to complete draining
+                if self.logger.is_poisoned() {
+                    DrainStatus::Complete.as_bool()
+                } else {
+                    DrainStatus::Ongoing.as_bool()
+                }
             }
         }
     }
@@ -86,13 +94,16 @@ where
         body_size: usize,
         num_trailers: usize,
     ) {
-        if let Err(err) = self.logger.on_http_call_response(
-            HttpClientRequestHandle::from(token_id),
-            num_headers,
-            body_size,
-            num_trailers,
-            self.http_client_ops,
-        ) {
+        let http_client_ops = self.http_client_ops;
+        if let Err(err) = self.logger.call("on_http_call_response", |logger| {
+            logger.on_http_call_response(
+                HttpClientRequestHandle::from(token_id),
+                num_headers,
+                body_size,
+                num_trailers,
+                http_client_ops,
+            )
+        }) {
             self.error_sink.observe(
                 "failed to process a response to an HTTP request made by the extension",
                 &err,
@@ -115,7 +126,7 @@ where
         error_sink: &'a dyn ErrorSink,
     ) -> Self {
         AccessLoggerContext {
-            logger,
+            logger: Poisonable::new(L::name(), logger),
             context_ops,
             logger_ops,
             http_client_ops,
@@ -134,3 +145,145 @@ where
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::super::{ConfigureOps, LogEntryKind, LogOps};
+    use super::*;
+    use crate::extension::testing::FakeErrorSink;
+    use crate::extension::Result;
+    use crate::host::{self, HeaderMap, StreamInfo};
+
+    /// Ops that are not supposed to be used by the logger under test.
+    struct UnusedOps;
+
+    impl ContextOps for UnusedOps {
+        fn configuration(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+            unimplemented!()
+        }
+    }
+
+    impl ConfigureOps for UnusedOps {}
+
+    impl LogOps for UnusedOps {
+        fn entry_kind(&self) -> host::Result<LogEntryKind> {
+            unimplemented!()
+        }
+
+        fn request_headers(&self) -> host::Result<HeaderMap> {
+            unimplemented!()
+        }
+
+        fn request_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+            unimplemented!()
+        }
+
+        fn response_headers(&self) -> host::Result<HeaderMap> {
+            unimplemented!()
+        }
+
+        fn response_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+            unimplemented!()
+        }
+
+        fn response_trailers(&self) -> host::Result<HeaderMap> {
+            unimplemented!()
+        }
+
+        fn response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+            unimplemented!()
+        }
+
+        fn stream_info(&self) -> &dyn StreamInfo {
+            unimplemented!()
+        }
+    }
+
+    impl HttpClientResponseOps for UnusedOps {
+        fn http_call_response_headers(&self) -> host::Result<HeaderMap> {
+            unimplemented!()
+        }
+
+        fn http_call_response_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+            unimplemented!()
+        }
+
+        fn http_call_response_body(
+            &self,
+            _start: usize,
+            _max_size: usize,
+        ) -> host::Result<ByteString> {
+            unimplemented!()
+        }
+
+        fn http_call_response_trailers(&self) -> host::Result<HeaderMap> {
+            unimplemented!()
+        }
+
+        fn http_call_response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+            unimplemented!()
+        }
+    }
+
+    /// Panics on the first log entry and counts the callbacks it has been called with.
+    #[derive(Default)]
+    struct PanickingLogger {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl AccessLogger for PanickingLogger {
+        fn name() -> &'static str {
+            "panicking_logger"
+        }
+
+        fn on_log(&mut self, _ops: &dyn LogOps) -> Result<()> {
+            self.calls.set(self.calls.get() + 1);
+            panic!("unexpected log entry")
+        }
+
+        fn on_drain(&mut self) -> Result<DrainStatus> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(DrainStatus::Ongoing)
+        }
+    }
+
+    #[test]
+    fn test_poisoned_logger_is_not_called() {
+        let ops = UnusedOps;
+        let error_sink = FakeErrorSink::default();
+        let logger = PanickingLogger::default();
+        let calls = Rc::clone(&logger.calls);
+        let mut ctx = AccessLoggerContext::new(logger, &ops, &ops, &ops, &error_sink);
+
+        ctx.on_log();
+        ctx.on_log();
+        assert!(!ctx.on_configure(0));
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(
+            error_sink.errors(),
+            vec![
+                "failed to log a request: extension panicked: unexpected log entry",
+                "failed to log a request: extension is poisoned since it has panicked earlier: unexpected log entry",
+                "failed to configure extension: extension is poisoned since it has panicked earlier: unexpected log entry",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_poisoned_logger_completes_draining() {
+        let ops = UnusedOps;
+        let error_sink = FakeErrorSink::default();
+        let logger = PanickingLogger::default();
+        let calls = Rc::clone(&logger.calls);
+        let mut ctx = AccessLoggerContext::new(logger, &ops, &ops, &ops, &error_sink);
+
+        ctx.on_log();
+
+        assert!(ctx.on_done());
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative filtering of access log entries.
+//!
+//! [`LogFilter`] decides whether an HTTP request or TCP connection should be logged at all.
+//! [`FilteredAccessLogger`] evaluates a [`LogFilter`] before the logic of the wrapped
+//! [`AccessLogger`] runs.
+//!
+//! With `json` feature enabled, [`FilteredAccessLogger`] reads its filter from the `filter` field
+//! of the logger's JSON configuration, e.g.
+//!
+//! ```json
+//! {
+//!   "filter": {
+//!     "or": [
+//!       { "status_code": { "min": 500 } },
+//!       { "response_flags": ["UH", "UF"] },
+//!       { "duration_ms": { "min": 1000 } },
+//!       { "and": [
+//!         { "header": { "name": "x-debug", "exact": "true" } },
+//!         { "sampling": { "percent": 10.0 } }
+//!       ] }
+//!     ]
+//!   }
+//! }
+//! ```
+//!
+//! # Examples
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use envoy::extension::{AccessLogger, Module, Result};
+//! use envoy::extension::access_logger::filter::{FilteredAccessLogger, LogFilter, ValueRange};
+//!
+//! struct MyAccessLogger;
+//!
+//! impl AccessLogger for MyAccessLogger {
+//!     fn name() -> &'static str { "my_access_logger" }
+//! }
+//!
+//! fn initialize() -> Result<Module> {
+//!     Module::new().add_access_logger(|instance_id| {
+//!         // only log failed requests
+//!         Ok(FilteredAccessLogger::with_filter(
+//!             instance_id,
+//!             MyAccessLogger,
+//!             LogFilter::StatusCode(ValueRange::at_least(500)),
+//!         ))
+//!     })
+//! }
+//! ```
+//!
+//! [`LogFilter`]: enum.LogFilter.html
+//! [`FilteredAccessLogger`]: struct.FilteredAccessLogger.html
+//! [`AccessLogger`]: ../trait.AccessLogger.html
+
+use std::cell::Cell;
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Deserializer};
+
+use super::{AccessLogger, ConfigureOps, LogOps};
+use crate::extension::{ConfigStatus, DrainStatus, InstanceId, Result};
+use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
+use crate::host::stream_info::ResponseFlags;
+use crate::host::ByteString;
+
+/// Header that is used to make consistent sampling decisions for the same request.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// A rule that decides whether an HTTP request or TCP connection should be logged.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "json",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LogFilter {
+    /// Matches if the response status code is within a given range.
+    ///
+    /// Requests without a response are treated as if they had status code `0`.
+    StatusCode(ValueRange),
+    /// Matches if any of the given response flags is set, e.g. `["UH", "UF"]`.
+    ///
+    /// An empty set of flags matches if any response flag is set.
+    #[cfg_attr(
+        feature = "json",
+        serde(deserialize_with = "deserialize_response_flags")
+    )]
+    ResponseFlags(ResponseFlags),
+    /// Matches if the duration of the request, in milliseconds, is within a given range.
+    DurationMs(ValueRange),
+    /// Matches a request header.
+    Header(HeaderMatcher),
+    /// Matches a given percentage of requests.
+    Sampling(Sampling),
+    /// Matches if all nested filters match.
+    And(Vec<LogFilter>),
+    /// Matches if any of nested filters matches.
+    Or(Vec<LogFilter>),
+    /// Matches if the nested filter doesn't match.
+    Not(Box<LogFilter>),
+}
+
+/// Inclusive range of values with optional bounds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Deserialize), serde(deny_unknown_fields))]
+pub struct ValueRange {
+    #[cfg_attr(feature = "json", serde(default))]
+    pub min: Option<u64>,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub max: Option<u64>,
+}
+
+/// Matcher of a request header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Deserialize))]
+pub struct HeaderMatcher {
+    /// Header name.
+    pub name: String,
+    /// Rule to match the header value against.
+    #[cfg_attr(feature = "json", serde(flatten))]
+    pub rule: HeaderMatch,
+    /// Whether the result of the match should be inverted.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub invert: bool,
+}
+
+/// Rule to match a header value against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json",
+    derive(Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum HeaderMatch {
+    /// Header value is equal to a given string.
+    Exact(String),
+    /// Header value starts with a given string.
+    Prefix(String),
+    /// Header value ends with a given string.
+    Suffix(String),
+    /// Header value contains a given string.
+    Contains(String),
+    /// Header is present (`true`) or absent (`false`).
+    Present(bool),
+}
+
+/// Random sampling of a given percentage of requests.
+///
+/// Unless `use_independent_randomness` is set, the decision is derived from the value
+/// of `x-request-id` header, so that all `Access Logger`s, as well as other `Envoy`
+/// components that sample on `x-request-id`, make the same decision for the same request.
+/// Requests without `x-request-id` header are sampled independently.
+///
+/// Independent decisions are pseudo-random, seeded by the instance id of
+/// [`FilteredAccessLogger`] the rule belongs to.
+///
+/// Sampling doesn't depend on `Envoy` `Runtime`.
+///
+/// [`FilteredAccessLogger`]: struct.FilteredAccessLogger.html
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Deserialize))]
+pub struct Sampling {
+    /// Percentage of requests to log, from `0.0` to `100.0`.
+    pub percent: f64,
+    /// Whether to make a decision regardless of `x-request-id` header.
+    #[cfg_attr(feature = "json", serde(default))]
+    pub use_independent_randomness: bool,
+    #[cfg_attr(feature = "json", serde(skip))]
+    random: Random,
+}
+
+impl LogFilter {
+    /// Re-seeds random number generators of all nested [`Sampling`] rules,
+    /// giving each of them a distinct seed.
+    ///
+    /// [`Sampling`]: struct.Sampling.html
+    fn seed(&mut self, seed: u64, index: &mut u64) {
+        match self {
+            LogFilter::Sampling(sampling) => {
+                sampling.random = Random::new(seed ^ index.rotate_left(32));
+                *index += 1;
+            }
+            LogFilter::And(filters) | LogFilter::Or(filters) => {
+                for filter in filters {
+                    filter.seed(seed, index);
+                }
+            }
+            LogFilter::Not(filter) => filter.seed(seed, index),
+            _ => {}
+        }
+    }
+
+    /// Returns `true` if the HTTP request or TCP connection should be logged.
+    pub fn matches(&self, ops: &dyn LogOps) -> Result<bool> {
+        let info = ops.stream_info();
+        let matches = match self {
+            LogFilter::StatusCode(range) => {
+                range.contains(info.response().status_code()?.unwrap_or(0).into())
+            }
+            LogFilter::ResponseFlags(flags) => {
+                let actual = info.response().flags()?.unwrap_or_default();
+                if flags.is_empty() {
+                    !actual.is_empty()
+                } else {
+                    actual.intersects(*flags)
+                }
+            }
+            LogFilter::DurationMs(range) => match info.request().duration()? {
+                Some(duration) => range.contains(duration.as_millis() as u64),
+                None => false,
+            },
+            LogFilter::Header(matcher) => matcher.matches(ops.request_header(&matcher.name)?),
+            LogFilter::Sampling(sampling) => {
+                if sampling.use_independent_randomness {
+                    sampling.sample_independently()
+                } else {
+                    match ops.request_header(REQUEST_ID_HEADER)? {
+                        Some(request_id) => sampling.sample(fnv1a(request_id.as_bytes())),
+                        None => sampling.sample_independently(),
+                    }
+                }
+            }
+            LogFilter::And(filters) => {
+                for filter in filters {
+                    if !filter.matches(ops)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            LogFilter::Or(filters) => {
+                for filter in filters {
+                    if filter.matches(ops)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+            LogFilter::Not(filter) => !filter.matches(ops)?,
+        };
+        Ok(matches)
+    }
+
+    /// Parses a filter out of its JSON representation.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(json)?)
+    }
+}
+
+impl ValueRange {
+    /// Returns a range that includes values starting from `min`.
+    pub fn at_least(min: u64) -> Self {
+        ValueRange {
+            min: Some(min),
+            max: None,
+        }
+    }
+
+    /// Returns a range that includes values up to `max`.
+    pub fn at_most(max: u64) -> Self {
+        ValueRange {
+            min: None,
+            max: Some(max),
+        }
+    }
+
+    /// Returns a range that includes values from `min` to `max`.
+    pub fn between(min: u64, max: u64) -> Self {
+        ValueRange {
+            min: Some(min),
+            max: Some(max),
+        }
+    }
+
+    /// Returns `true` if a given value is within the range.
+    pub fn contains(&self, value: u64) -> bool {
+        !matches!(self.min, Some(min) if value < min)
+            && !matches!(self.max, Some(max) if value > max)
+    }
+}
+
+impl HeaderMatcher {
+    /// Creates a new header matcher.
+    pub fn new<N>(name: N, rule: HeaderMatch) -> Self
+    where
+        N: Into<String>,
+    {
+        HeaderMatcher {
+            name: name.into(),
+            rule,
+            invert: false,
+        }
+    }
+
+    /// Returns `true` if a given header value matches.
+    pub fn matches(&self, value: Option<ByteString>) -> bool {
+        let matches = match (&self.rule, value) {
+            (HeaderMatch::Present(present), value) => *present == value.is_some(),
+            (_, None) => false,
+            (HeaderMatch::Exact(expected), Some(value)) => value == expected.as_str(),
+            (HeaderMatch::Prefix(prefix), Some(value)) => {
+                value.as_bytes().starts_with(prefix.as_bytes())
+            }
+            (HeaderMatch::Suffix(suffix), Some(value)) => {
+                value.as_bytes().ends_with(suffix.as_bytes())
+            }
+            (HeaderMatch::Contains(needle), Some(value)) => {
+                needle.is_empty()
+                    || value
+                        .as_bytes()
+                        .windows(needle.len())
+                        .any(|window| window == needle.as_bytes())
+            }
+        };
+        matches != self.invert
+    }
+}
+
+impl Sampling {
+    /// Creates a new sampling rule that matches a given percentage of requests.
+    pub fn new(percent: f64) -> Self {
+        Sampling {
+            percent,
+            use_independent_randomness: false,
+            random: Random::default(),
+        }
+    }
+
+    /// Makes decisions regardless of `x-request-id` header.
+    pub fn with_independent_randomness(mut self) -> Self {
+        self.use_independent_randomness = true;
+        self
+    }
+
+    fn sample_independently(&self) -> bool {
+        self.sample(self.random.next())
+    }
+
+    fn sample(&self, random: u64) -> bool {
+        let threshold = (self.percent.clamp(0.0, 100.0) * 100.0) as u64;
+        random % 10_000 < threshold
+    }
+}
+
+impl PartialEq for Sampling {
+    fn eq(&self, other: &Self) -> bool {
+        self.percent == other.percent
+            && self.use_independent_randomness == other.use_independent_randomness
+    }
+}
+
+/// `xorshift64*` pseudo-random number generator.
+#[derive(Debug, Clone)]
+struct Random(Cell<u64>);
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new(0)
+    }
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        // `xorshift` must never be seeded with `0`
+        let state = (seed ^ 0x2545_f491_4f6c_dd1d).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        Random(Cell::new(if state == 0 { 1 } else { state }))
+    }
+
+    fn next(&self) -> u64 {
+        let mut x = self.0.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// `FNV-1a` hash.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(feature = "json")]
+fn deserialize_response_flags<'de, D>(
+    deserializer: D,
+) -> std::result::Result<ResponseFlags, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let mut flags = ResponseFlags::empty();
+    for code in Vec::<String>::deserialize(deserializer)? {
+        let flag = (0..64)
+            .filter_map(|bit| ResponseFlags::from_bits(1 << bit))
+            .find(|flag| flag.to_string() == code)
+            .ok_or_else(|| D::Error::custom(format!("unknown response flag \"{}\"", code)))?;
+        flags |= flag;
+    }
+    Ok(flags)
+}
+
+/// [`AccessLogger`] that only logs HTTP requests and TCP connections
+/// accepted by a [`LogFilter`].
+///
+/// With `json` feature enabled, the filter is replaced on every [`on_configure`] if the logger's
+/// configuration is a JSON object with a `filter` field, e.g. `null` removes the filter.
+/// Configuration of any other shape leaves the filter as it is. The whole configuration is then
+/// passed on to the wrapped [`AccessLogger`].
+///
+/// [`AccessLogger`]: ../trait.AccessLogger.html
+/// [`LogFilter`]: enum.LogFilter.html
+/// [`on_configure`]: ../trait.AccessLogger.html#method.on_configure
+pub struct FilteredAccessLogger<L> {
+    logger: L,
+    filter: Option<LogFilter>,
+    seed: u64,
+}
+
+impl<L> FilteredAccessLogger<L>
+where
+    L: AccessLogger,
+{
+    /// Wraps a given [`AccessLogger`] with no filter.
+    ///
+    /// `instance_id` seeds random [`Sampling`], so that different `Access Logger`
+    /// instances make independent decisions.
+    ///
+    /// [`AccessLogger`]: ../trait.AccessLogger.html
+    /// [`Sampling`]: struct.Sampling.html
+    pub fn new(instance_id: InstanceId, logger: L) -> Self {
+        FilteredAccessLogger {
+            logger,
+            filter: None,
+            seed: fnv1a(&instance_id.0.to_le_bytes()),
+        }
+    }
+
+    /// Wraps a given [`AccessLogger`] with a given filter.
+    ///
+    /// [`AccessLogger`]: ../trait.AccessLogger.html
+    pub fn with_filter(instance_id: InstanceId, logger: L, filter: LogFilter) -> Self {
+        let mut logger = Self::new(instance_id, logger);
+        logger.set_filter(Some(filter));
+        logger
+    }
+
+    /// Returns the filter.
+    pub fn filter(&self) -> Option<&LogFilter> {
+        self.filter.as_ref()
+    }
+
+    /// Replaces the filter.
+    pub fn set_filter(&mut self, mut filter: Option<LogFilter>) {
+        if let Some(filter) = filter.as_mut() {
+            filter.seed(self.seed, &mut 0);
+        }
+        self.filter = filter;
+    }
+
+    /// Returns the wrapped [`AccessLogger`].
+    ///
+    /// [`AccessLogger`]: ../trait.AccessLogger.html
+    pub fn logger(&self) -> &L {
+        &self.logger
+    }
+
+    /// Returns the wrapped [`AccessLogger`].
+    ///
+    /// [`AccessLogger`]: ../trait.AccessLogger.html
+    pub fn logger_mut(&mut self) -> &mut L {
+        &mut self.logger
+    }
+}
+
+impl<L> AccessLogger for FilteredAccessLogger<L>
+where
+    L: AccessLogger,
+{
+    fn name() -> &'static str {
+        L::name()
+    }
+
+    fn on_configure(&mut self, config: ByteString, ops: &dyn ConfigureOps) -> Result<ConfigStatus> {
+        #[cfg(feature = "json")]
+        {
+            // configuration of the wrapped logger doesn't have to be a JSON object
+            if let Ok(serde_json::Value::Object(mut fields)) =
+                serde_json::from_slice(config.as_bytes())
+            {
+                if let Some(filter) = fields.remove("filter") {
+                    self.set_filter(serde_json::from_value(filter)?);
+                }
+            }
+        }
+        self.logger.on_configure(config, ops)
+    }
+
+    fn on_log(&mut self, ops: &dyn LogOps) -> Result<()> {
+        if let Some(filter) = &self.filter {
+            if !filter.matches(ops)? {
+                return Ok(());
+            }
+        }
+        self.logger.on_log(ops)
+    }
+
+    fn on_drain(&mut self) -> Result<DrainStatus> {
+        self.logger.on_drain()
+    }
+
+    fn on_http_call_response(
+        &mut self,
+        request_id: HttpClientRequestHandle,
+        num_headers: usize,
+        body_size: usize,
+        num_trailers: usize,
+        http_client_ops: &dyn HttpClientResponseOps,
+    ) -> Result<()> {
+        self.logger.on_http_call_response(
+            request_id,
+            num_headers,
+            body_size,
+            num_trailers,
+            http_client_ops,
+        )
+    }
+}
@@ -0,0 +1,675 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `Envoy`-style access log formats.
+//!
+//! [`LogFormat`] understands the [`command operators`] of `Envoy` access log format strings,
+//! e.g. `%REQ(:METHOD)%` or `%RESPONSE_CODE%`.
+//!
+//! A format is meant to be parsed once, in [`AccessLogger::on_configure`],
+//! and then rendered for every log entry in [`AccessLogger::on_log`].
+//!
+//! Supported command operators:
+//! * `%REQ(X?Y):Z%`, `%RESP(X?Y):Z%`, `%TRAILER(X?Y):Z%` - request header, response header
+//!   and response trailer respectively, `Y` is an optional fallback header and `Z` is
+//!   an optional limit on the length of the value,
+//! * `%START_TIME%`, `%START_TIME(format)%` - start time of the request (in `UTC`),
+//!   `format` supports `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%E<n>S`, `%<n>f`, `%s`, `%z`, `%Z`,
+//!   `%F`, `%T` and `%%`,
+//! * `%DURATION%`, `%RESPONSE_CODE%`, `%RESPONSE_FLAGS%`, `%GRPC_STATUS%`,
+//!   `%BYTES_RECEIVED%`, `%BYTES_SENT%`, `%PROTOCOL%`,
+//! * `%UPSTREAM_HOST%`, `%UPSTREAM_CLUSTER%`, `%UPSTREAM_LOCAL_ADDRESS%`,
+//!   `%UPSTREAM_TRANSPORT_FAILURE_REASON%`,
+//! * `%DOWNSTREAM_REMOTE_ADDRESS%`, `%DOWNSTREAM_LOCAL_ADDRESS%`, `%REQUESTED_SERVER_NAME%`,
+//! * `%ROUTE_NAME%`, `%CONNECTION_ID%`, `%CONNECTION_TERMINATION_DETAILS%`.
+//!
+//! # Examples
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use envoy::extension::{AccessLogger, ConfigStatus, Result};
+//! use envoy::extension::access_logger::{ConfigureOps, LogOps};
+//! use envoy::extension::access_logger::format::LogFormat;
+//! use envoy::host::{log, ByteString};
+//!
+//! struct MyAccessLogger {
+//!     format: LogFormat,
+//! }
+//!
+//! impl AccessLogger for MyAccessLogger {
+//!     fn name() -> &'static str { "my_access_logger" }
+//!
+//!     fn on_configure(&mut self, config: ByteString, _ops: &dyn ConfigureOps) -> Result<ConfigStatus> {
+//!         self.format = if config.is_empty() {
+//!             LogFormat::default()
+//!         } else {
+//!             LogFormat::text(&String::from_utf8(config.into_bytes())?)?
+//!         };
+//!         Ok(ConfigStatus::Accepted)
+//!     }
+//!
+//!     fn on_log(&mut self, ops: &dyn LogOps) -> Result<()> {
+//!         log::info!("{}", self.format.render(ops)?);
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! [`LogFormat`]: struct.LogFormat.html
+//! [`command operators`]: https://www.envoyproxy.io/docs/envoy/latest/configuration/observability/access_log/usage#command-operators
+//! [`AccessLogger::on_configure`]: ../trait.AccessLogger.html#method.on_configure
+//! [`AccessLogger::on_log`]: ../trait.AccessLogger.html#method.on_log
+
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::LogOps;
+use crate::error::{bail, format_err, Result};
+use crate::host::ByteString;
+
+/// Default format of `Envoy` access log.
+pub const DEFAULT_FORMAT: &str =
+    "[%START_TIME%] \"%REQ(:METHOD)% %REQ(X-ENVOY-ORIGINAL-PATH?:PATH)% %PROTOCOL%\" \
+%RESPONSE_CODE% %RESPONSE_FLAGS% %BYTES_RECEIVED% %BYTES_SENT% %DURATION% \
+%RESP(X-ENVOY-UPSTREAM-SERVICE-TIME)% \"%REQ(X-FORWARDED-FOR)%\" \"%REQ(USER-AGENT)%\" \
+\"%REQ(X-REQUEST-ID)%\" \"%REQ(:AUTHORITY)%\" \"%UPSTREAM_HOST%\"\n";
+
+/// Default format of `%START_TIME%`.
+pub const DEFAULT_START_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%E3SZ";
+
+/// Placeholder for values that are not available in text output.
+const MISSING_VALUE: &str = "-";
+
+/// Parsed access log format.
+///
+/// # Examples
+///
+/// #### Text format:
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # fn main() -> envoy::error::Result<()> {
+/// use envoy::extension::access_logger::format::LogFormat;
+///
+/// let format = LogFormat::text("%REQ(:METHOD)% %REQ(:PATH)% %RESPONSE_CODE% %DURATION%ms\n")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// #### JSON format:
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # fn main() -> envoy::error::Result<()> {
+/// use envoy::extension::access_logger::format::LogFormat;
+///
+/// let format = LogFormat::json(vec![
+///     ("method", "%REQ(:METHOD)%"),
+///     ("status", "%RESPONSE_CODE%"),
+///     ("upstream", "%UPSTREAM_CLUSTER%/%UPSTREAM_HOST%"),
+/// ])?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LogFormat {
+    output: Output,
+}
+
+#[derive(Debug, Clone)]
+enum Output {
+    Text(Template),
+    Json(Vec<(String, Template)>),
+}
+
+impl Default for LogFormat {
+    /// Returns the [`default`] format of `Envoy` access log.
+    ///
+    /// [`default`]: constant.DEFAULT_FORMAT.html
+    fn default() -> Self {
+        Self::text(DEFAULT_FORMAT).expect("default access log format must be valid")
+    }
+}
+
+impl LogFormat {
+    /// Parses a text format, e.g. `"%REQ(:METHOD)% %RESPONSE_CODE%\n"`.
+    ///
+    /// Values that are not available are rendered as `-`.
+    pub fn text(format: &str) -> Result<Self> {
+        Ok(LogFormat {
+            output: Output::Text(Template::parse(format)?),
+        })
+    }
+
+    /// Parses a JSON format out of pairs of a JSON key and a format string.
+    ///
+    /// Keys appear in the output in the given order. If the value of a key consists of
+    /// a single command operator, it is rendered as a JSON number or a JSON string,
+    /// depending on the operator, or as `null` if it is not available.
+    pub fn json<I, K, V>(fields: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: AsRef<str>,
+    {
+        let fields = fields
+            .into_iter()
+            .map(|(key, format)| Ok((key.into(), Template::parse(format.as_ref())?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(LogFormat {
+            output: Output::Json(fields),
+        })
+    }
+
+    /// Renders a log entry for the HTTP stream or TCP connection that is being logged.
+    pub fn render(&self, ops: &dyn LogOps) -> Result<String> {
+        let mut out = String::new();
+        match &self.output {
+            Output::Text(template) => template.render_text(ops, &mut out)?,
+            Output::Json(fields) => {
+                out.push('{');
+                for (i, (key, template)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(&mut out, key);
+                    out.push(':');
+                    match template.single_operator() {
+                        Some(operator) => match operator.evaluate(ops)? {
+                            Value::Missing => out.push_str("null"),
+                            Value::Number(number) => write!(out, "{}", number)?,
+                            Value::String(value) => write_json_string(&mut out, &value),
+                        },
+                        None => {
+                            let mut value = String::new();
+                            template.render_text(ops, &mut value)?;
+                            write_json_string(&mut out, &value);
+                        }
+                    }
+                }
+                out.push_str("}\n");
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// A format string split into literal text and command operators.
+#[derive(Debug, Clone)]
+struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Operator(Operator),
+}
+
+impl Template {
+    fn parse(format: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('%') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            let command = &rest[start + 1..];
+            let end = find_command_end(command).ok_or_else(|| {
+                format_err!(
+                    "invalid access log format \"{}\": command operator \"%{}\" is not terminated with '%'",
+                    format,
+                    command
+                )
+            })?;
+            segments.push(Segment::Operator(Operator::parse(&command[..end])?));
+            rest = &command[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+        Ok(Template { segments })
+    }
+
+    fn single_operator(&self) -> Option<&Operator> {
+        match self.segments.as_slice() {
+            [Segment::Operator(operator)] => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn render_text(&self, ops: &dyn LogOps, out: &mut String) -> Result<()> {
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Operator(operator) => match operator.evaluate(ops)? {
+                    Value::Missing => out.push_str(MISSING_VALUE),
+                    Value::Number(number) => write!(out, "{}", number)?,
+                    Value::String(value) => out.push_str(&value),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns position of the '%' that terminates a command operator.
+///
+/// '%' characters inside parentheses, e.g. in `%START_TIME(%Y-%m-%d)%`, don't count.
+fn find_command_end(command: &str) -> Option<usize> {
+    let mut in_parens = false;
+    for (i, c) in command.char_indices() {
+        match c {
+            '(' => in_parens = true,
+            ')' => in_parens = false,
+            '%' if !in_parens => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Rendered value of a command operator.
+enum Value {
+    Missing,
+    Number(u64),
+    String(String),
+}
+
+impl From<Option<String>> for Value {
+    fn from(value: Option<String>) -> Self {
+        value.map_or(Value::Missing, Value::String)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeaderSource {
+    Request,
+    Response,
+    ResponseTrailer,
+}
+
+#[derive(Debug, Clone)]
+enum Operator {
+    Header {
+        source: HeaderSource,
+        name: String,
+        fallback: Option<String>,
+        max_length: Option<usize>,
+    },
+    StartTime(TimeFormat),
+    Duration,
+    ResponseCode,
+    ResponseFlags,
+    GrpcStatus,
+    BytesReceived,
+    BytesSent,
+    Protocol,
+    UpstreamHost,
+    UpstreamCluster,
+    UpstreamLocalAddress,
+    UpstreamTransportFailureReason,
+    DownstreamRemoteAddress,
+    DownstreamLocalAddress,
+    RequestedServerName,
+    RouteName,
+    ConnectionId,
+    ConnectionTerminationDetails,
+}
+
+impl Operator {
+    fn parse(command: &str) -> Result<Self> {
+        let (name, arg, max_length) = match command.find('(') {
+            Some(open) => {
+                let close = match command.rfind(')') {
+                    Some(close) if close > open => close,
+                    _ => bail!(
+                        "invalid access log command operator \"%{}%\": missing ')'",
+                        command
+                    ),
+                };
+                let max_length = match &command[close + 1..] {
+                    "" => None,
+                    suffix => match suffix.strip_prefix(':').map(str::parse::<usize>) {
+                        Some(Ok(max_length)) => Some(max_length),
+                        _ => bail!(
+                            "invalid access log command operator \"%{}%\": invalid length limit \"{}\"",
+                            command,
+                            suffix
+                        ),
+                    },
+                };
+                (
+                    &command[..open],
+                    Some(&command[open + 1..close]),
+                    max_length,
+                )
+            }
+            None => (command, None, None),
+        };
+
+        let operator = match (name, arg) {
+            ("REQ", Some(arg)) => Self::header(HeaderSource::Request, arg, max_length)?,
+            ("RESP", Some(arg)) => Self::header(HeaderSource::Response, arg, max_length)?,
+            ("TRAILER", Some(arg)) => Self::header(HeaderSource::ResponseTrailer, arg, max_length)?,
+            ("START_TIME", arg) => {
+                Operator::StartTime(TimeFormat::parse(arg.unwrap_or(DEFAULT_START_TIME_FORMAT))?)
+            }
+            ("DURATION", None) => Operator::Duration,
+            ("RESPONSE_CODE", None) => Operator::ResponseCode,
+            ("RESPONSE_FLAGS", None) => Operator::ResponseFlags,
+            ("GRPC_STATUS", None) => Operator::GrpcStatus,
+            ("BYTES_RECEIVED", None) => Operator::BytesReceived,
+            ("BYTES_SENT", None) => Operator::BytesSent,
+            ("PROTOCOL", None) => Operator::Protocol,
+            ("UPSTREAM_HOST", None) => Operator::UpstreamHost,
+            ("UPSTREAM_CLUSTER", None) => Operator::UpstreamCluster,
+            ("UPSTREAM_LOCAL_ADDRESS", None) => Operator::UpstreamLocalAddress,
+            ("UPSTREAM_TRANSPORT_FAILURE_REASON", None) => Operator::UpstreamTransportFailureReason,
+            ("DOWNSTREAM_REMOTE_ADDRESS", None) => Operator::DownstreamRemoteAddress,
+            ("DOWNSTREAM_LOCAL_ADDRESS", None) => Operator::DownstreamLocalAddress,
+            ("REQUESTED_SERVER_NAME", None) => Operator::RequestedServerName,
+            ("ROUTE_NAME", None) => Operator::RouteName,
+            ("CONNECTION_ID", None) => Operator::ConnectionId,
+            ("CONNECTION_TERMINATION_DETAILS", None) => Operator::ConnectionTerminationDetails,
+            _ => bail!(
+                "invalid access log format: command operator \"%{}%\" is not supported",
+                command
+            ),
+        };
+        if max_length.is_some() && !matches!(operator, Operator::Header { .. }) {
+            bail!(
+                "invalid access log command operator \"%{}%\": length limit is only supported by REQ, RESP and TRAILER",
+                command
+            );
+        }
+        Ok(operator)
+    }
+
+    fn header(source: HeaderSource, arg: &str, max_length: Option<usize>) -> Result<Self> {
+        let mut names = arg.splitn(2, '?').map(str::trim);
+        let name = names.next().unwrap_or_default();
+        if name.is_empty() {
+            bail!("invalid access log format: header name must not be empty");
+        }
+        Ok(Operator::Header {
+            source,
+            name: name.to_ascii_lowercase(),
+            fallback: names.next().map(str::to_ascii_lowercase),
+            max_length,
+        })
+    }
+
+    fn evaluate(&self, ops: &dyn LogOps) -> Result<Value> {
+        let info = ops.stream_info();
+        let value = match self {
+            Operator::Header {
+                source,
+                name,
+                fallback,
+                max_length,
+            } => {
+                let get = |name: &str| -> Result<Option<ByteString>> {
+                    Ok(match source {
+                        HeaderSource::Request => ops.request_header(name)?,
+                        HeaderSource::Response => ops.response_header(name)?,
+                        HeaderSource::ResponseTrailer => ops.response_trailer(name)?,
+                    })
+                };
+                let value = match get(name)? {
+                    Some(value) => Some(value),
+                    None => match fallback {
+                        Some(fallback) => get(fallback)?,
+                        None => None,
+                    },
+                };
+                match value {
+                    Some(value) => {
+                        let mut value = value.to_string();
+                        if let Some(max_length) = max_length {
+                            truncate(&mut value, *max_length);
+                        }
+                        Value::String(value)
+                    }
+                    None => Value::Missing,
+                }
+            }
+            Operator::StartTime(format) => match info.request().time()? {
+                Some(time) => Value::String(format.render(time)),
+                None => Value::Missing,
+            },
+            Operator::Duration => match info.request().duration()? {
+                Some(duration) => Value::Number(duration.as_millis() as u64),
+                None => Value::Missing,
+            },
+            Operator::ResponseCode => {
+                Value::Number(info.response().status_code()?.unwrap_or(0).into())
+            }
+            Operator::ResponseFlags => match info.response().flags()? {
+                Some(flags) if !flags.is_empty() => Value::String(flags.to_string()),
+                _ => Value::Missing,
+            },
+            Operator::GrpcStatus => match info.response().grpc_status()? {
+                Some(status) if status >= 0 => Value::Number(status as u64),
+                _ => Value::Missing,
+            },
+            Operator::BytesReceived => Value::Number(info.request().size()?.unwrap_or(0)),
+            Operator::BytesSent => Value::Number(info.response().size()?.unwrap_or(0)),
+            Operator::Protocol => info.request().protocol()?.into(),
+            Operator::UpstreamHost => info.upstream().address()?.into(),
+            Operator::UpstreamCluster => info.cluster().name()?.into(),
+            Operator::UpstreamLocalAddress => info.upstream().local_address()?.into(),
+            Operator::UpstreamTransportFailureReason => {
+                info.upstream().transport_failure_reason()?.into()
+            }
+            Operator::DownstreamRemoteAddress => info.source().address()?.into(),
+            Operator::DownstreamLocalAddress => info.destination().address()?.into(),
+            Operator::RequestedServerName => info.connection().requested_server_name()?.into(),
+            Operator::RouteName => info.route().name()?.into(),
+            Operator::ConnectionId => match info.connection().id()? {
+                Some(id) => Value::Number(id),
+                None => Value::Missing,
+            },
+            Operator::ConnectionTerminationDetails => {
+                info.connection().termination_details()?.into()
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Truncates a string to at most `max_length` bytes without splitting a character.
+fn truncate(value: &mut String, max_length: usize) {
+    if value.len() > max_length {
+        let mut end = max_length;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// `strftime`-like format of `%START_TIME(...)%`.
+#[derive(Debug, Clone)]
+struct TimeFormat {
+    items: Vec<TimeItem>,
+}
+
+#[derive(Debug, Clone)]
+enum TimeItem {
+    Literal(String),
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    /// Seconds with a given number of fractional digits, i.e. `%E<n>S`.
+    SecondWithFraction(usize),
+    /// Fractional seconds with a given number of digits, i.e. `%<n>f`.
+    Fraction(usize),
+    EpochSeconds,
+    UtcOffset,
+    TimeZone,
+}
+
+impl TimeFormat {
+    fn parse(format: &str) -> Result<Self> {
+        use TimeItem::*;
+
+        let mut items = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let mut digits = String::new();
+            while let Some(&d) = chars.peek() {
+                if !d.is_ascii_digit() {
+                    break;
+                }
+                digits.push(d);
+                chars.next();
+            }
+            let width = if digits.is_empty() {
+                None
+            } else {
+                Some(digits.parse::<usize>()?.min(9))
+            };
+            let spec = chars.next();
+            let items_for_spec: Vec<TimeItem> = match (spec, width) {
+                (Some('%'), None) => {
+                    literal.push('%');
+                    continue;
+                }
+                (Some('Y'), None) => vec![Year],
+                (Some('m'), None) => vec![Month],
+                (Some('d'), None) => vec![Day],
+                (Some('H'), None) => vec![Hour],
+                (Some('M'), None) => vec![Minute],
+                (Some('S'), None) => vec![Second],
+                (Some('s'), None) => vec![EpochSeconds],
+                (Some('z'), None) => vec![UtcOffset],
+                (Some('Z'), None) => vec![TimeZone],
+                (Some('F'), None) => {
+                    vec![Year, Literal("-".into()), Month, Literal("-".into()), Day]
+                }
+                (Some('T'), None) => vec![
+                    Hour,
+                    Literal(":".into()),
+                    Minute,
+                    Literal(":".into()),
+                    Second,
+                ],
+                (Some('f'), width) => vec![Fraction(width.unwrap_or(9))],
+                (Some('E'), None) => {
+                    let mut digits = String::new();
+                    while let Some(&d) = chars.peek() {
+                        if !d.is_ascii_digit() {
+                            break;
+                        }
+                        digits.push(d);
+                        chars.next();
+                    }
+                    match (digits.parse::<usize>(), chars.next()) {
+                        (Ok(width), Some('S')) => vec![SecondWithFraction(width.min(9))],
+                        _ => bail!("invalid START_TIME format \"{}\": expected %E<n>S", format),
+                    }
+                }
+                _ => bail!(
+                    "invalid START_TIME format \"{}\": unsupported conversion specification",
+                    format
+                ),
+            };
+            if !literal.is_empty() {
+                items.push(Literal(std::mem::take(&mut literal)));
+            }
+            items.extend(items_for_spec);
+        }
+        if !literal.is_empty() {
+            items.push(Literal(literal));
+        }
+        Ok(TimeFormat { items })
+    }
+
+    fn render(&self, time: SystemTime) -> String {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let nanos = since_epoch.subsec_nanos();
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let secs_of_day = secs % 86_400;
+
+        let mut out = String::new();
+        for item in &self.items {
+            let _ = match item {
+                TimeItem::Literal(text) => write!(out, "{}", text),
+                TimeItem::Year => write!(out, "{:04}", year),
+                TimeItem::Month => write!(out, "{:02}", month),
+                TimeItem::Day => write!(out, "{:02}", day),
+                TimeItem::Hour => write!(out, "{:02}", secs_of_day / 3600),
+                TimeItem::Minute => write!(out, "{:02}", secs_of_day % 3600 / 60),
+                TimeItem::Second => write!(out, "{:02}", secs_of_day % 60),
+                TimeItem::SecondWithFraction(0) => write!(out, "{:02}", secs_of_day % 60),
+                TimeItem::SecondWithFraction(width) => write!(
+                    out,
+                    "{:02}.{}",
+                    secs_of_day % 60,
+                    &format!("{:09}", nanos)[..*width]
+                ),
+                TimeItem::Fraction(width) => write!(out, "{}", &format!("{:09}", nanos)[..*width]),
+                TimeItem::EpochSeconds => write!(out, "{}", secs),
+                TimeItem::UtcOffset => write!(out, "+0000"),
+                TimeItem::TimeZone => write!(out, "UTC"),
+            };
+        }
+        out
+    }
+}
+
+/// Converts days since Unix epoch into a `(year, month, day)` triple of the proleptic
+/// Gregorian calendar.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
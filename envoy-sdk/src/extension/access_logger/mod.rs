@@ -61,12 +61,19 @@
 //! [`AccessLogger`]: trait.AccessLogger.html
 //! [`Register`]: ../../macro.entrypoint.html
 
+use std::time::Duration;
+
 use crate::extension::{ConfigStatus, DrainStatus, Result};
 use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
+use crate::host::stream_info::ResponseFlags;
 use crate::host::{self, ByteString, HeaderMap, StreamInfo};
 
 pub(crate) use self::context::AccessLoggerContext;
 
+pub mod filter;
+pub mod format;
+pub mod shipper;
+
 mod context;
 mod ops;
 
@@ -224,28 +231,132 @@ pub trait DrainOps {
     fn done(&self) -> host::Result<()>;
 }
 
+/// Kind of the log entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LogEntryKind {
+    /// HTTP request, e.g. logged by `HTTP Connection Manager`.
+    Http,
+    /// TCP connection, e.g. logged by `TCP Proxy`.
+    Tcp,
+}
+
+/// Connection-level data of the log entry.
+///
+/// In case of a [`Tcp`] entry, sizes and duration refer to the whole connection.
+/// In case of an [`Http`] entry, they refer to the HTTP request.
+///
+/// [`Tcp`]: enum.LogEntryKind.html#variant.Tcp
+/// [`Http`]: enum.LogEntryKind.html#variant.Http
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConnectionLogEntry {
+    /// Number of bytes received from the downstream.
+    pub bytes_received: u64,
+    /// Number of bytes sent to the downstream.
+    pub bytes_sent: u64,
+    /// Duration of the connection (or request).
+    pub duration: Option<Duration>,
+    /// Address of the upstream host.
+    pub upstream_host: Option<String>,
+    /// Name of the upstream cluster.
+    pub upstream_cluster: Option<String>,
+    /// Reason of the upstream transport failure, if any.
+    pub upstream_transport_failure_reason: Option<String>,
+    /// Address of the downstream peer.
+    pub downstream_remote_address: Option<String>,
+    /// Local address of the downstream connection.
+    pub downstream_local_address: Option<String>,
+    /// Details on why the downstream connection has been terminated.
+    pub termination_details: Option<String>,
+    /// Response flags, e.g. `UF` if connection to the upstream has failed.
+    pub response_flags: ResponseFlags,
+}
+
 /// An interface for accessing data of the HTTP stream or TCP connection that is being logged.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::{AccessLogger, Result};
+/// use envoy::extension::access_logger::{LogEntryKind, LogOps};
+/// use envoy::host::log;
+///
+/// struct MyAccessLogger;
+///
+/// impl AccessLogger for MyAccessLogger {
+///     fn name() -> &'static str { "my_access_logger" }
+///
+///     fn on_log(&mut self, ops: &dyn LogOps) -> Result<()> {
+///         let entry = ops.connection_entry()?;
+///         match ops.entry_kind()? {
+///             LogEntryKind::Tcp => log::info!(
+///                 "TCP connection to {:?}: {} bytes received, {} bytes sent",
+///                 entry.upstream_host, entry.bytes_received, entry.bytes_sent,
+///             ),
+///             _ => log::info!(
+///                 "HTTP request {:?} to {:?}",
+///                 ops.request_header(":path")?, entry.upstream_host,
+///             ),
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
 pub trait LogOps {
+    /// Returns whether the log entry describes an HTTP request or a TCP connection.
+    fn entry_kind(&self) -> host::Result<LogEntryKind>;
+
     /// Returns request headers.
+    ///
+    /// Returns an empty map in case of a TCP connection.
     fn request_headers(&self) -> host::Result<HeaderMap>;
 
     /// Returns request header by name.
+    ///
+    /// Returns `None` in case of a TCP connection.
     fn request_header(&self, name: &str) -> host::Result<Option<ByteString>>;
 
     /// Returns response headers.
+    ///
+    /// Returns an empty map in case of a TCP connection.
     fn response_headers(&self) -> host::Result<HeaderMap>;
 
     /// Returns response header by name.
+    ///
+    /// Returns `None` in case of a TCP connection.
     fn response_header(&self, name: &str) -> host::Result<Option<ByteString>>;
 
     /// Returns response trailers.
+    ///
+    /// Returns an empty map in case of a TCP connection.
     fn response_trailers(&self) -> host::Result<HeaderMap>;
 
     /// Returns response trailer by name.
+    ///
+    /// Returns `None` in case of a TCP connection.
     fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>>;
 
     /// Provides access to properties of the stream.
     fn stream_info(&self) -> &dyn StreamInfo;
+
+    /// Returns connection-level data of the log entry.
+    fn connection_entry(&self) -> host::Result<ConnectionLogEntry> {
+        let info = self.stream_info();
+        Ok(ConnectionLogEntry {
+            bytes_received: info.request().size()?.unwrap_or(0),
+            bytes_sent: info.response().size()?.unwrap_or(0),
+            duration: info.request().duration()?,
+            upstream_host: info.upstream().address()?,
+            upstream_cluster: info.cluster().name()?,
+            upstream_transport_failure_reason: info.upstream().transport_failure_reason()?,
+            downstream_remote_address: info.source().address()?,
+            downstream_local_address: info.destination().address()?,
+            termination_details: info.connection().termination_details()?,
+            response_flags: info.response().flags()?.unwrap_or_default(),
+        })
+    }
 }
 
 #[doc(hidden)]
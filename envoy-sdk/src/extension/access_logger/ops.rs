@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{ConfigureOps, ContextOps, DrainOps, LogOps};
+use super::{ConfigureOps, ContextOps, DrainOps, LogEntryKind, LogOps};
 use crate::abi::proxy_wasm::hostcalls;
 use crate::abi::proxy_wasm::types::MapType;
 use crate::host::{self, ByteString, HeaderMap, StreamInfo};
@@ -28,28 +28,36 @@ impl ContextOps for Host {
 impl ConfigureOps for Host {}
 
 impl LogOps for Host {
+    fn entry_kind(&self) -> host::Result<LogEntryKind> {
+        // `Envoy` only knows the protocol of HTTP requests
+        match self.stream_info().request().protocol()? {
+            Some(_) => Ok(LogEntryKind::Http),
+            None => Ok(LogEntryKind::Tcp),
+        }
+    }
+
     fn request_headers(&self) -> host::Result<HeaderMap> {
-        hostcalls::get_map(MapType::HttpRequestHeaders)
+        self.get_map(MapType::HttpRequestHeaders)
     }
 
     fn request_header(&self, name: &str) -> host::Result<Option<ByteString>> {
-        hostcalls::get_map_value(MapType::HttpRequestHeaders, name)
+        self.get_map_value(MapType::HttpRequestHeaders, name)
     }
 
     fn response_headers(&self) -> host::Result<HeaderMap> {
-        hostcalls::get_map(MapType::HttpResponseHeaders)
+        self.get_map(MapType::HttpResponseHeaders)
     }
 
     fn response_header(&self, name: &str) -> host::Result<Option<ByteString>> {
-        hostcalls::get_map_value(MapType::HttpResponseHeaders, name)
+        self.get_map_value(MapType::HttpResponseHeaders, name)
     }
 
     fn response_trailers(&self) -> host::Result<HeaderMap> {
-        hostcalls::get_map(MapType::HttpResponseTrailers)
+        self.get_map(MapType::HttpResponseTrailers)
     }
 
     fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
-        hostcalls::get_map_value(MapType::HttpResponseTrailers, &name)
+        self.get_map_value(MapType::HttpResponseTrailers, name)
     }
 
     fn stream_info(&self) -> &dyn StreamInfo {
@@ -57,6 +65,25 @@ impl LogOps for Host {
     }
 }
 
+impl Host {
+    // Header maps are not available in case of a TCP connection
+    // and `Envoy` responds with an error.
+
+    fn get_map(&self, map_type: MapType) -> host::Result<HeaderMap> {
+        match self.entry_kind()? {
+            LogEntryKind::Tcp => Ok(HeaderMap::default()),
+            _ => hostcalls::get_map(map_type),
+        }
+    }
+
+    fn get_map_value(&self, map_type: MapType, name: &str) -> host::Result<Option<ByteString>> {
+        match self.entry_kind()? {
+            LogEntryKind::Tcp => Ok(None),
+            _ => hostcalls::get_map_value(map_type, name),
+        }
+    }
+}
+
 impl DrainOps for Host {
     fn done(&self) -> host::Result<()> {
         hostcalls::done()
@@ -0,0 +1,452 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Batching shipper of access log entries.
+//!
+//! [`LogShipper`] accumulates encoded access log entries and ships them in batches
+//! to a log collector through [`HttpClient`].
+//!
+//! A batch is sent once it reaches [`max_batch_entries`] or [`max_batch_bytes`],
+//! or once [`flush_interval`] has elapsed since the first entry has been added to it.
+//! Since `Access Logger` has no timer of its own, time-based thresholds are evaluated
+//! whenever a new entry gets [`pushed`] or [`poll`] gets called explicitly.
+//!
+//! Failed batches are retried with exponential backoff. Entries that exceed
+//! [`max_buffered_bytes`] or run out of retries get dropped.
+//!
+//! [`LogShipper`] keeps the following counters:
+//! * `<stat_prefix>.sent` - number of entries successfully delivered to the collector,
+//! * `<stat_prefix>.dropped` - number of entries that have been dropped,
+//! * `<stat_prefix>.retried` - number of times a batch has been re-sent.
+//!
+//! # Examples
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use envoy::extension::{AccessLogger, Result};
+//! use envoy::extension::access_logger::LogOps;
+//! use envoy::extension::access_logger::format::LogFormat;
+//! use envoy::extension::access_logger::shipper::{LogShipper, LogShipperConfig};
+//! use envoy::host::{Clock, HttpClient, Stats};
+//! use envoy::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
+//!
+//! struct MyAccessLogger<'a> {
+//!     format: LogFormat,
+//!     shipper: LogShipper<'a>,
+//! }
+//!
+//! impl<'a> MyAccessLogger<'a> {
+//!     fn new(http_client: &'a dyn HttpClient, clock: &'a dyn Clock, stats: &dyn Stats) -> Result<Self> {
+//!         let config = LogShipperConfig {
+//!             cluster: "log_collector".into(),
+//!             path: "/logs".into(),
+//!             ..LogShipperConfig::default()
+//!         };
+//!         Ok(MyAccessLogger {
+//!             format: LogFormat::default(),
+//!             shipper: LogShipper::new(config, http_client, clock, stats)?,
+//!         })
+//!     }
+//! }
+//!
+//! impl<'a> AccessLogger for MyAccessLogger<'a> {
+//!     fn name() -> &'static str { "my_access_logger" }
+//!
+//!     fn on_log(&mut self, ops: &dyn LogOps) -> Result<()> {
+//!         let entry = self.format.render(ops)?;
+//!         self.shipper.push(entry)
+//!     }
+//!
+//!     fn on_http_call_response(
+//!         &mut self,
+//!         request_id: HttpClientRequestHandle,
+//!         num_headers: usize,
+//!         _body_size: usize,
+//!         _num_trailers: usize,
+//!         http_client_ops: &dyn HttpClientResponseOps,
+//!     ) -> Result<()> {
+//!         self.shipper.on_http_call_response(request_id, num_headers, http_client_ops)?;
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! [`LogShipper`]: struct.LogShipper.html
+//! [`HttpClient`]: ../../../host/http/client/trait.HttpClient.html
+//! [`max_batch_entries`]: struct.LogShipperConfig.html#structfield.max_batch_entries
+//! [`max_batch_bytes`]: struct.LogShipperConfig.html#structfield.max_batch_bytes
+//! [`flush_interval`]: struct.LogShipperConfig.html#structfield.flush_interval
+//! [`max_buffered_bytes`]: struct.LogShipperConfig.html#structfield.max_buffered_bytes
+//! [`pushed`]: struct.LogShipper.html#method.push
+//! [`poll`]: struct.LogShipper.html#method.poll
+
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, SystemTime};
+
+use crate::extension::Result;
+use crate::host::http::client::{
+    HttpClient, HttpClientRequestHandle, HttpClientResponseOps, IN_FLIGHT_GRACE_PERIOD,
+};
+use crate::host::stats::{Counter, Stats};
+use crate::host::{self, Clock};
+
+/// Configuration of a [`LogShipper`].
+///
+/// [`LogShipper`]: struct.LogShipper.html
+#[derive(Debug, Clone)]
+pub struct LogShipperConfig {
+    /// Name of `Envoy` `Cluster` of the log collector.
+    pub cluster: String,
+    /// Path of the HTTP request.
+    pub path: String,
+    /// Value of the `:authority` header. Defaults to the cluster name if empty.
+    pub authority: String,
+    /// Value of the `content-type` header.
+    pub content_type: String,
+    /// Extra headers of the HTTP request.
+    pub headers: Vec<(String, String)>,
+    /// Timeout of the HTTP request.
+    pub timeout: Duration,
+    /// Maximum number of entries in a batch.
+    pub max_batch_entries: usize,
+    /// Maximum size of a batch in bytes.
+    pub max_batch_bytes: usize,
+    /// Maximum time an entry can wait before its batch is sent.
+    pub flush_interval: Duration,
+    /// Maximum number of times a failed batch is re-sent.
+    pub max_retries: u32,
+    /// Delay before the first retry. Every next retry doubles the delay.
+    pub initial_backoff: Duration,
+    /// Maximum delay between retries.
+    pub max_backoff: Duration,
+    /// Maximum size of entries that are either waiting to be sent,
+    /// waiting for a response or waiting for a retry.
+    pub max_buffered_bytes: usize,
+    /// Prefix of names of the counters.
+    pub stat_prefix: String,
+    /// Bytes to put in front of the entries of a batch, e.g. `[` of a JSON array.
+    pub batch_prefix: Vec<u8>,
+    /// Bytes to put in between the entries of a batch, e.g. `,` of a JSON array.
+    pub batch_separator: Vec<u8>,
+    /// Bytes to put after the entries of a batch, e.g. `]` of a JSON array.
+    pub batch_suffix: Vec<u8>,
+}
+
+impl Default for LogShipperConfig {
+    fn default() -> Self {
+        LogShipperConfig {
+            cluster: String::new(),
+            path: "/".into(),
+            authority: String::new(),
+            content_type: "application/x-ndjson".into(),
+            headers: Vec::new(),
+            timeout: Duration::from_secs(5),
+            max_batch_entries: 100,
+            max_batch_bytes: 64 * 1024,
+            flush_interval: Duration::from_secs(1),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            max_buffered_bytes: 1024 * 1024,
+            stat_prefix: "access_log_shipper".into(),
+            batch_prefix: Vec::new(),
+            batch_separator: Vec::new(),
+            batch_suffix: Vec::new(),
+        }
+    }
+}
+
+/// Batching shipper of access log entries.
+///
+/// Entries of a batch are concatenated into a single request body. Unless [`batch_separator`]
+/// is configured, entries are expected to carry their own delimiters, e.g. a trailing `\n`.
+///
+/// A request that has been left without a response for longer than its timeout, e.g. because
+/// the response has been delivered to a context that no longer exists, is considered failed.
+///
+/// [`batch_separator`]: struct.LogShipperConfig.html#structfield.batch_separator
+pub struct LogShipper<'a> {
+    config: LogShipperConfig,
+    http_client: &'a dyn HttpClient,
+    clock: &'a dyn Clock,
+    stats: LogShipperStats,
+
+    batch: Batch,
+    batch_started_at: Option<SystemTime>,
+    in_flight: HashMap<HttpClientRequestHandle, (SystemTime, Batch)>,
+    backoff: Vec<(SystemTime, Batch)>,
+    buffered_bytes: usize,
+}
+
+struct LogShipperStats {
+    sent: Box<dyn Counter>,
+    dropped: Box<dyn Counter>,
+    retried: Box<dyn Counter>,
+}
+
+#[derive(Default)]
+struct Batch {
+    body: Vec<u8>,
+    entries: usize,
+    attempts: u32,
+}
+
+impl<'a> LogShipper<'a> {
+    /// Creates a new shipper.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`      - configuration of the shipper.
+    /// * `http_client` - [`HttpClient`] to send batches with.
+    /// * `clock`       - [`Clock`] to evaluate time-based thresholds with.
+    /// * `stats`       - [`Stats`] to create counters with.
+    ///
+    /// [`HttpClient`]: ../../../host/http/client/trait.HttpClient.html
+    /// [`Clock`]: ../../../host/time/trait.Clock.html
+    /// [`Stats`]: ../../../host/stats/trait.Stats.html
+    pub fn new(
+        config: LogShipperConfig,
+        http_client: &'a dyn HttpClient,
+        clock: &'a dyn Clock,
+        stats: &dyn Stats,
+    ) -> Result<Self> {
+        let stats = LogShipperStats {
+            sent: stats.counter(&format!("{}.sent", config.stat_prefix))?,
+            dropped: stats.counter(&format!("{}.dropped", config.stat_prefix))?,
+            retried: stats.counter(&format!("{}.retried", config.stat_prefix))?,
+        };
+        Ok(LogShipper {
+            config,
+            http_client,
+            clock,
+            stats,
+            batch: Batch::default(),
+            batch_started_at: None,
+            in_flight: HashMap::new(),
+            backoff: Vec::new(),
+            buffered_bytes: 0,
+        })
+    }
+
+    /// Returns configuration of the shipper.
+    pub fn config(&self) -> &LogShipperConfig {
+        &self.config
+    }
+
+    /// Returns `true` if there are no entries waiting to be sent, waiting for a response
+    /// or waiting for a retry.
+    ///
+    /// Useful to decide whether `Access Logger` can be drained.
+    pub fn is_idle(&self) -> bool {
+        self.batch.entries == 0 && self.in_flight.is_empty() && self.backoff.is_empty()
+    }
+
+    /// Adds an encoded entry to the current batch.
+    ///
+    /// The entry gets dropped if the shipper is already holding [`max_buffered_bytes`].
+    ///
+    /// [`max_buffered_bytes`]: struct.LogShipperConfig.html#structfield.max_buffered_bytes
+    pub fn push<E>(&mut self, entry: E) -> Result<()>
+    where
+        E: AsRef<[u8]>,
+    {
+        let entry = entry.as_ref();
+        let separator: &[u8] = if self.batch.entries == 0 {
+            &[]
+        } else {
+            &self.config.batch_separator
+        };
+        let size = separator.len() + entry.len();
+        if self.buffered_bytes + size > self.config.max_buffered_bytes {
+            self.stats.dropped.inc()?;
+        } else {
+            if self.batch.entries == 0 {
+                self.batch_started_at = Some(self.clock.now()?);
+            }
+            self.batch.body.extend_from_slice(separator);
+            self.batch.body.extend_from_slice(entry);
+            self.batch.entries += 1;
+            self.buffered_bytes += size;
+        }
+        self.poll()
+    }
+
+    /// Sends the current batch if it has reached one of its thresholds
+    /// and re-sends failed batches whose backoff has elapsed.
+    pub fn poll(&mut self) -> Result<()> {
+        let now = self.clock.now()?;
+        let expired: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (deadline, _))| *deadline <= now)
+            .map(|(request_id, _)| *request_id)
+            .collect();
+        for request_id in expired {
+            if let Some((_, batch)) = self.in_flight.remove(&request_id) {
+                self.on_failure(batch, now)?;
+            }
+        }
+        if self.batch.entries > 0
+            && (self.batch.entries >= self.config.max_batch_entries
+                || self.batch.body.len() >= self.config.max_batch_bytes
+                || self.is_flush_due(now))
+        {
+            self.flush()?;
+        }
+        let (due, not_due) = mem::take(&mut self.backoff)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(retry_at, _)| *retry_at <= now);
+        self.backoff = not_due;
+        for (_, batch) in due {
+            self.stats.retried.inc()?;
+            self.send(batch, now)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the current batch regardless of thresholds.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.batch.entries == 0 {
+            return Ok(());
+        }
+        let batch = mem::take(&mut self.batch);
+        self.batch_started_at = None;
+        let now = self.clock.now()?;
+        self.send(batch, now)
+    }
+
+    /// Handles a response to a batch sent by the shipper.
+    ///
+    /// Is meant to be called from [`AccessLogger::on_http_call_response`].
+    ///
+    /// A batch is considered delivered if the collector has responded with `2xx` status.
+    ///
+    /// # Return value
+    ///
+    /// `true` if the response belongs to a request made by the shipper, `false` otherwise.
+    ///
+    /// [`AccessLogger::on_http_call_response`]: ../trait.AccessLogger.html#method.on_http_call_response
+    pub fn on_http_call_response(
+        &mut self,
+        request_id: HttpClientRequestHandle,
+        num_headers: usize,
+        http_client_ops: &dyn HttpClientResponseOps,
+    ) -> Result<bool> {
+        let batch = match self.in_flight.remove(&request_id) {
+            Some((_, batch)) => batch,
+            None => return Ok(false),
+        };
+        let status = if num_headers == 0 {
+            None
+        } else {
+            http_client_ops
+                .http_call_response_header(":status")?
+                .and_then(|status| status.to_string().parse::<u16>().ok())
+        };
+        let now = self.clock.now()?;
+        match status {
+            Some(status) if (200..300).contains(&status) => {
+                self.buffered_bytes -= batch.body.len();
+                self.stats.sent.add(batch.entries as u64)?;
+            }
+            _ => self.on_failure(batch, now)?,
+        }
+        self.poll()?;
+        Ok(true)
+    }
+
+    fn is_flush_due(&self, now: SystemTime) -> bool {
+        match self.batch_started_at {
+            Some(started_at) => match now.duration_since(started_at) {
+                Ok(elapsed) => elapsed >= self.config.flush_interval,
+                Err(_) => false,
+            },
+            None => false,
+        }
+    }
+
+    fn send(&mut self, mut batch: Batch, now: SystemTime) -> Result<()> {
+        batch.attempts += 1;
+        match self.send_request(&batch) {
+            Ok(request_id) => {
+                let deadline = now + self.config.timeout + IN_FLIGHT_GRACE_PERIOD;
+                self.in_flight.insert(request_id, (deadline, batch));
+                Ok(())
+            }
+            // failure to send a request is retried the same way as a failed response
+            Err(_) => self.on_failure(batch, now),
+        }
+    }
+
+    fn send_request(&self, batch: &Batch) -> host::Result<HttpClientRequestHandle> {
+        let authority = if self.config.authority.is_empty() {
+            &self.config.cluster
+        } else {
+            &self.config.authority
+        };
+        let mut headers: Vec<(&str, &str)> = vec![
+            (":method", "POST"),
+            (":path", &self.config.path),
+            (":authority", authority),
+            ("content-type", &self.config.content_type),
+        ];
+        headers.extend(
+            self.config
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+        let framed;
+        let body = if self.config.batch_prefix.is_empty() && self.config.batch_suffix.is_empty() {
+            &batch.body
+        } else {
+            framed = [
+                self.config.batch_prefix.as_slice(),
+                &batch.body,
+                &self.config.batch_suffix,
+            ]
+            .concat();
+            &framed
+        };
+        self.http_client.send_request(
+            &self.config.cluster,
+            &headers,
+            Some(body),
+            None,
+            self.config.timeout,
+        )
+    }
+
+    fn on_failure(&mut self, batch: Batch, now: SystemTime) -> Result<()> {
+        if batch.attempts > self.config.max_retries {
+            self.buffered_bytes -= batch.body.len();
+            self.stats.dropped.add(batch.entries as u64)?;
+        } else {
+            let retry_at = now + self.backoff_delay(batch.attempts);
+            self.backoff.push((retry_at, batch));
+        }
+        Ok(())
+    }
+
+    fn backoff_delay(&self, attempts: u32) -> Duration {
+        let factor = 1u32 << attempts.saturating_sub(1).min(16);
+        self.config
+            .initial_backoff
+            .checked_mul(factor)
+            .map_or(self.config.max_backoff, |delay| {
+                delay.min(self.config.max_backoff)
+            })
+    }
+}
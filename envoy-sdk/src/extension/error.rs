@@ -76,6 +76,68 @@ impl std::error::Error for ConfigurationError {
     }
 }
 
+/// An error caused by an extension calling an operation at a point of the stream lifecycle
+/// where that operation is not allowed, e.g. modifying response headers in `on_request_body`.
+///
+/// Reported instead of making a call to `Envoy` ABI that would fail with an opaque status code.
+#[derive(Debug)]
+pub(crate) struct PhaseError {
+    op: &'static str,
+    phase: &'static str,
+    allowed: String,
+}
+
+impl PhaseError {
+    pub fn new(op: &'static str, phase: &'static str, allowed: String) -> Self {
+        PhaseError { op, phase, allowed }
+    }
+}
+
+impl fmt::Display for PhaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "operation `{}` is not allowed in `{}`: {}",
+            self.op, self.phase, self.allowed
+        )
+    }
+}
+
+impl std::error::Error for PhaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+/// An error caused by a panic inside an extension callback.
+#[derive(Debug)]
+pub(crate) enum PanicError {
+    /// Extension callback panicked.
+    Panicked(String),
+    /// Extension callback was not called since the extension has panicked earlier.
+    Poisoned(String),
+}
+
+impl fmt::Display for PanicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use PanicError::*;
+        match self {
+            Panicked(message) => write!(f, "extension panicked: {}", message),
+            Poisoned(message) => write!(
+                f,
+                "extension is poisoned since it has panicked earlier: {}",
+                message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PanicError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
 pub(crate) trait ErrorSink {
     fn observe(&self, context: &str, err: &Error);
 }
@@ -92,13 +154,23 @@ impl dyn ErrorSink {
 
 mod impls {
     use super::{Error, ErrorSink};
+    #[cfg(not(feature = "log"))]
+    use crate::abi::proxy_wasm::{hostcalls, types::LogLevel};
+    #[cfg(feature = "log")]
     use crate::host::log;
 
     pub(super) struct DefaultErrorSink;
 
     impl ErrorSink for DefaultErrorSink {
+        #[cfg(feature = "log")]
         fn observe(&self, context: &str, err: &Error) {
             log::error!("{}: {}", context, err);
         }
+
+        #[cfg(not(feature = "log"))]
+        fn observe(&self, context: &str, err: &Error) {
+            // there is nobody to report a failure to log the error to
+            let _ = hostcalls::log(LogLevel::Error, &format!("{}: {}", context, err));
+        }
     }
 }
@@ -16,7 +16,8 @@ use super::{ContextOps, DrainStatus, ExtensionFactory, Ops};
 use crate::abi::proxy_wasm::traits::{Context, HttpContext, RootContext, StreamContext};
 use crate::abi::proxy_wasm::types::ContextType;
 use crate::extension::error::ErrorSink;
-use crate::extension::{ConfigStatus, InstanceId};
+use crate::extension::panic::Poisonable;
+use crate::extension::{ConfigStatus, InstanceId, Result};
 use crate::host::ByteString;
 use std::cell::RefCell;
 
@@ -24,15 +25,15 @@ pub(crate) enum ChildContextFactory<F>
 where
     F: ExtensionFactory,
 {
-    StreamContextFactory(fn(&mut F, InstanceId) -> Box<dyn StreamContext>),
-    HttpContextFactory(fn(&mut F, InstanceId) -> Box<dyn HttpContext>),
+    StreamContextFactory(fn(Result<F::Extension>) -> Box<dyn StreamContext>),
+    HttpContextFactory(fn(InstanceId, Result<F::Extension>) -> Box<dyn HttpContext>),
 }
 
 pub(crate) struct ExtensionFactoryContext<'a, F>
 where
     F: ExtensionFactory,
 {
-    factory: RefCell<F>,
+    factory: RefCell<Poisonable<F>>,
     context_ops: &'a dyn ContextOps,
     factory_ops: &'a dyn Ops,
     error_sink: &'a dyn ErrorSink,
@@ -50,9 +51,9 @@ where
             self.context_ops.configuration(0, configuration_size)
         };
         match config.and_then(|config| {
-            self.factory
-                .borrow_mut()
-                .on_configure(config, self.factory_ops.as_configure_ops())
+            self.factory.borrow_mut().call("on_configure", |factory| {
+                factory.on_configure(config, self.factory_ops.as_configure_ops())
+            })
         }) {
             Ok(status) => status.as_bool(),
             Err(err) => {
@@ -73,8 +74,8 @@ where
     fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
         match self.child_context_factory {
             ChildContextFactory::HttpContextFactory(f) => Some(f(
-                &mut self.factory.borrow_mut(),
                 InstanceId::from(context_id),
+                self.new_extension(context_id),
             )),
             _ => None,
         }
@@ -82,10 +83,7 @@ where
 
     fn create_stream_context(&self, context_id: u32) -> Option<Box<dyn StreamContext>> {
         match self.child_context_factory {
-            ChildContextFactory::StreamContextFactory(f) => Some(f(
-                &mut self.factory.borrow_mut(),
-                InstanceId::from(context_id),
-            )),
+            ChildContextFactory::StreamContextFactory(f) => Some(f(self.new_extension(context_id))),
             _ => None,
         }
     }
@@ -96,12 +94,21 @@ where
     F: ExtensionFactory,
 {
     fn on_done(&mut self) -> bool {
-        match self.factory.borrow_mut().on_drain() {
+        let status = self
+            .factory
+            .borrow_mut()
+            .call("on_drain", |factory| factory.on_drain());
+        match status {
             Ok(status) => status.as_bool(),
             Err(err) => {
                 self.error_sink
                     .observe("failed to initiate draining of the extension", &err);
-                DrainStatus::Ongoing.as_bool()
+                // a poisoned extension is never going to complete draining
+                if self.factory.borrow().is_poisoned() {
+                    DrainStatus::Complete.as_bool()
+                } else {
+                    DrainStatus::Ongoing.as_bool()
+                }
             }
         }
     }
@@ -119,7 +126,7 @@ where
         child_context_factory: ChildContextFactory<F>,
     ) -> Self {
         ExtensionFactoryContext {
-            factory: RefCell::new(factory),
+            factory: RefCell::new(Poisonable::new(F::name(), factory)),
             context_ops,
             factory_ops,
             error_sink,
@@ -137,4 +144,128 @@ where
             child_context_factory,
         )
     }
+
+    fn new_extension(&self, context_id: u32) -> Result<F::Extension> {
+        self.factory.borrow_mut().call("new_extension", |factory| {
+            factory.new_extension(InstanceId::from(context_id))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::super::{ConfigureOps, DrainOps};
+    use super::*;
+    use crate::extension::testing::FakeErrorSink;
+    use crate::extension::HttpFilter;
+    use crate::host;
+
+    /// Ops that are not supposed to be used by the factory under test.
+    struct UnusedOps;
+
+    impl ContextOps for UnusedOps {
+        fn configuration(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+            unimplemented!()
+        }
+    }
+
+    impl ConfigureOps for UnusedOps {}
+
+    impl DrainOps for UnusedOps {
+        fn done(&self) -> host::Result<()> {
+            unimplemented!()
+        }
+    }
+
+    struct MyHttpFilter;
+
+    impl HttpFilter for MyHttpFilter {}
+
+    /// Panics on creating an extension and counts the callbacks it has been called with.
+    #[derive(Default)]
+    struct PanickingFactory {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl ExtensionFactory for PanickingFactory {
+        type Extension = MyHttpFilter;
+
+        fn name() -> &'static str {
+            "panicking_factory"
+        }
+
+        fn on_configure(
+            &mut self,
+            _config: ByteString,
+            _ops: &dyn ConfigureOps,
+        ) -> Result<ConfigStatus> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(ConfigStatus::Accepted)
+        }
+
+        fn new_extension(&mut self, _instance_id: InstanceId) -> Result<Self::Extension> {
+            self.calls.set(self.calls.get() + 1);
+            panic!("unexpected instance")
+        }
+
+        fn on_drain(&mut self) -> Result<DrainStatus> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(DrainStatus::Ongoing)
+        }
+    }
+
+    fn new_context<'a>(
+        factory: PanickingFactory,
+        ops: &'a UnusedOps,
+        error_sink: &'a FakeErrorSink,
+    ) -> ExtensionFactoryContext<'a, PanickingFactory> {
+        ExtensionFactoryContext::new(
+            factory,
+            ops,
+            ops,
+            error_sink,
+            ChildContextFactory::HttpContextFactory(|_, _| unreachable!()),
+        )
+    }
+
+    #[test]
+    fn test_poisoned_factory_is_not_called() {
+        let error_sink = FakeErrorSink::default();
+        let factory = PanickingFactory::default();
+        let calls = Rc::clone(&factory.calls);
+        let mut ctx = new_context(factory, &UnusedOps, &error_sink);
+
+        let err = ctx.new_extension(1).err().unwrap();
+        assert_eq!(err.to_string(), "extension panicked: unexpected instance");
+
+        let err = ctx.new_extension(2).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "extension is poisoned since it has panicked earlier: unexpected instance"
+        );
+        assert!(!ctx.on_configure(0));
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to configure extension: extension is poisoned since it has panicked earlier: unexpected instance"]
+        );
+    }
+
+    #[test]
+    fn test_poisoned_factory_completes_draining() {
+        let error_sink = FakeErrorSink::default();
+        let factory = PanickingFactory::default();
+        let calls = Rc::clone(&factory.calls);
+        let mut ctx = new_context(factory, &UnusedOps, &error_sink);
+
+        assert!(!ctx.on_done());
+        let _ = ctx.new_extension(1);
+        assert!(ctx.on_done());
+
+        assert_eq!(calls.get(), 2);
+    }
 }
@@ -182,13 +182,18 @@ impl DrainStatus {
 ///
 /// #### Sharing `Stats` between filter instances:
 ///
-/// ```
+/// Requires `derive` feature.
+///
+#[cfg_attr(feature = "derive", doc = "```")]
+#[cfg_attr(not(feature = "derive"), doc = "```ignore")]
 /// # use envoy_sdk as envoy;
 /// use std::rc::Rc;
 /// use envoy::extension::{factory, ConfigStatus, ExtensionFactory, InstanceId, Result};
 /// use envoy::host::stats::{Counter, Stats};
 ///
 /// /// Stats shared between multiple filter instances.
+/// #[derive(Stats)]
+/// #[stats(prefix = "examples.http_filter.")]
 /// pub struct MyStats {
 ///     requests_total: Box<dyn Counter>,
 /// }
@@ -225,11 +230,8 @@ impl DrainStatus {
 /// impl MyHttpFilterFactory {
 ///     /// Creates a new factory.
 ///     pub fn new(stats: &dyn Stats) -> Result<Self> {
-///         let my_stats = MyStats{
-///             requests_total: stats.counter("examples.http_filter.requests_total")?,
-///         };
 ///         Ok(MyHttpFilterFactory {
-///             stats: Rc::new(my_stats),
+///             stats: Rc::new(MyStats::new(stats)?),
 ///         })
 ///     }
 ///
@@ -0,0 +1,607 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cache of results of HTTP callouts made by `HTTP Filter`s.
+//!
+//! `HTTP Filter`s that consult an external service, e.g. to authorize a request,
+//! can use [`CalloutCache`] to avoid calling that service on every request:
+//!
+//! * results are kept in [`SharedData`], i.e. they are shared by all `Envoy` worker threads,
+//!   and expire after [`ttl`],
+//! * non-`2xx` responses are cached as well, but expire after [`negative_ttl`],
+//! * at most one callout per key is in flight on a worker thread; other `HTTP Filter`
+//!   instances that need the same result wait for it and get resumed by the instance
+//!   that has received the response.
+//!
+//! Keys are hashed before being used as names of [`SharedData`] entries, so a raw credential,
+//! e.g. a value of the `authorization` header, never becomes visible in the name of an entry.
+//! However, [`SharedData`] entries are never removed, so every distinct key takes up memory
+//! until `Envoy` restarts. Only use keys of bounded cardinality, e.g. credentials issued to
+//! a known set of clients rather than per-session tokens.
+//!
+//! # Examples
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use std::rc::Rc;
+//! use std::time::Duration;
+//!
+//! use envoy::extension::{HttpFilter, InstanceId, Result};
+//! use envoy::extension::filter::http::{
+//!     ExchangeCompleteOps, FilterHeadersStatus, Ops, RequestHeadersOps,
+//! };
+//! use envoy::extension::filter::http::callout::{CalloutCache, CalloutResponse, Lookup};
+//! use envoy::host::HttpClient;
+//! use envoy::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
+//!
+//! struct MyHttpFilter<'a> {
+//!     instance_id: InstanceId,
+//!     cache: Rc<CalloutCache<'a>>,
+//!     http_client: &'a dyn HttpClient,
+//! }
+//!
+//! fn authorize(response: &CalloutResponse, ops: &dyn RequestHeadersOps) -> Result<()> {
+//!     if response.is_success() {
+//!         ops.resume_request()
+//!     } else {
+//!         ops.send_response(403, &[], None)
+//!     }
+//! }
+//!
+//! impl<'a> HttpFilter for MyHttpFilter<'a> {
+//!     fn on_request_headers(&mut self, _num_headers: usize, _end_of_stream: bool, ops: &dyn RequestHeadersOps) -> Result<FilterHeadersStatus> {
+//!         let token = match ops.request_header("authorization")? {
+//!             Some(token) => token.to_string(),
+//!             None => {
+//!                 ops.send_response(401, &[], None)?;
+//!                 return Ok(FilterHeadersStatus::StopIteration);
+//!             }
+//!         };
+//!         let http_client = self.http_client;
+//!         let lookup = self.cache.lookup(
+//!             self.instance_id,
+//!             &token,
+//!             || {
+//!                 http_client.send_request(
+//!                     "auth_service",
+//!                     &[(":method", "GET"), (":path", "/check"), (":authority", "auth"), ("authorization", &token)],
+//!                     None,
+//!                     None,
+//!                     Duration::from_secs(1),
+//!                 )
+//!             },
+//!             authorize,
+//!         )?;
+//!         match lookup {
+//!             Lookup::Cached(response) if response.is_success() => Ok(FilterHeadersStatus::Continue),
+//!             Lookup::Cached(_) => {
+//!                 ops.send_response(403, &[], None)?;
+//!                 Ok(FilterHeadersStatus::StopIteration)
+//!             }
+//!             // wait for the callout
+//!             _ => Ok(FilterHeadersStatus::StopIteration),
+//!         }
+//!     }
+//!
+//!     fn on_http_call_response(
+//!         &mut self,
+//!         request_id: HttpClientRequestHandle,
+//!         num_headers: usize,
+//!         body_size: usize,
+//!         _num_trailers: usize,
+//!         filter_ops: &dyn Ops,
+//!         http_client_ops: &dyn HttpClientResponseOps,
+//!     ) -> Result<()> {
+//!         if let Some(response) = self.cache.on_http_call_response(
+//!             self.instance_id,
+//!             request_id,
+//!             num_headers,
+//!             body_size,
+//!             http_client_ops,
+//!         )? {
+//!             authorize(&response, filter_ops.as_request_headers_ops())?;
+//!         }
+//!         Ok(())
+//!     }
+//!
+//!     fn on_exchange_complete(&mut self, _ops: &dyn ExchangeCompleteOps) -> Result<()> {
+//!         // stop waiting for the callout and fail other instances that wait for
+//!         // the callout made by this one
+//!         self.cache.cancel(self.instance_id);
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! [`CalloutCache`]: struct.CalloutCache.html
+//! [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+//! [`ttl`]: struct.CalloutCacheConfig.html#structfield.ttl
+//! [`negative_ttl`]: struct.CalloutCacheConfig.html#structfield.negative_ttl
+
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime};
+
+use super::context::InstanceHandle;
+use super::{ops, RequestHeadersOps};
+use crate::abi::proxy_wasm::hostcalls;
+use crate::error::bail;
+use crate::extension::error::ErrorSink;
+use crate::extension::{InstanceId, Result};
+use crate::host::http::client::{
+    HttpClientRequestHandle, HttpClientResponseOps, IN_FLIGHT_GRACE_PERIOD,
+};
+use crate::host::time::unix_nanos;
+use crate::host::{self, ByteString, Clock, SharedData};
+
+/// Configuration of a [`CalloutCache`].
+///
+/// [`CalloutCache`]: struct.CalloutCache.html
+#[derive(Debug, Clone)]
+pub struct CalloutCacheConfig {
+    /// Prefix of [`SharedData`] keys the results are kept under.
+    ///
+    /// The rest of a [`SharedData`] key is a hash of the cache key.
+    ///
+    /// [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+    pub key_prefix: String,
+    /// Time to keep `2xx` responses for.
+    pub ttl: Duration,
+    /// Time to keep non-`2xx` responses for.
+    pub negative_ttl: Duration,
+    /// Timeout of callouts.
+    ///
+    /// A callout that has been left without a response for longer than that,
+    /// e.g. because the `HTTP Filter` that has made it no longer exists,
+    /// gets superseded by the next lookup of the same key.
+    pub timeout: Duration,
+}
+
+impl Default for CalloutCacheConfig {
+    fn default() -> Self {
+        CalloutCacheConfig {
+            key_prefix: "callout_cache".into(),
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(5),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Result of a callout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalloutResponse {
+    status: Option<u16>,
+    body: ByteString,
+}
+
+impl CalloutResponse {
+    /// Creates a new result of a callout.
+    pub fn new<B>(status: Option<u16>, body: B) -> Self
+    where
+        B: Into<ByteString>,
+    {
+        CalloutResponse {
+            status,
+            body: body.into(),
+        }
+    }
+
+    /// Returns the `:status` of the response or `None` if the callout has failed
+    /// without a response, e.g. on timeout.
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// Returns the body of the response.
+    pub fn body(&self) -> &ByteString {
+        &self.body
+    }
+
+    /// Returns `true` if the response has `2xx` status.
+    pub fn is_success(&self) -> bool {
+        matches!(self.status, Some(status) if (200..300).contains(&status))
+    }
+
+    fn failed() -> Self {
+        CalloutResponse::new(None, Vec::new())
+    }
+
+    /// Encodes the result together with the key it belongs to, so that a hash collision
+    /// of keys can be told apart from a match.
+    fn encode(&self, key: &str, status: u16, expires_at: u64) -> Vec<u8> {
+        let mut value = Vec::with_capacity(14 + key.len() + self.body.len());
+        value.extend_from_slice(&expires_at.to_le_bytes());
+        value.extend_from_slice(&status.to_le_bytes());
+        value.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        value.extend_from_slice(key.as_bytes());
+        value.extend_from_slice(&self.body);
+        value
+    }
+
+    fn decode(key: &str, value: &[u8]) -> Option<(Self, u64)> {
+        if value.len() < 14 {
+            return None;
+        }
+        let expires_at = u64::from_le_bytes(value[..8].try_into().ok()?);
+        let status = u16::from_le_bytes(value[8..10].try_into().ok()?);
+        let key_len = u32::from_le_bytes(value[10..14].try_into().ok()?) as usize;
+        let rest = &value[14..];
+        if rest.len() < key_len || &rest[..key_len] != key.as_bytes() {
+            return None;
+        }
+        Some((
+            CalloutResponse::new(Some(status), &rest[key_len..]),
+            expires_at,
+        ))
+    }
+}
+
+/// Outcome of a [`lookup`].
+///
+/// [`lookup`]: struct.CalloutCache.html#method.lookup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// Result has been found in the cache.
+    Cached(CalloutResponse),
+    /// A callout has been made on behalf of the caller. Its response has to be passed
+    /// to [`CalloutCache::on_http_call_response`].
+    ///
+    /// [`CalloutCache::on_http_call_response`]: struct.CalloutCache.html#method.on_http_call_response
+    Sent(HttpClientRequestHandle),
+    /// A callout made by another `HTTP Filter` instance is in flight. The caller will be
+    /// resumed once it completes.
+    Waiting,
+}
+
+/// An interface for operating on behalf of other `HTTP Filter` instances of the same
+/// worker thread.
+pub trait StreamWaker {
+    /// Calls `f` with [`RequestHeadersOps`] of the `waiter` instance,
+    /// then switches back to the `current` one.
+    ///
+    /// `f` is subject to the same checks and isolation of panics as callbacks
+    /// of the `waiter` instance, i.e. a panic inside `f` affects the `waiter` rather
+    /// than the `current` instance.
+    ///
+    /// [`RequestHeadersOps`]: ../trait.RequestHeadersOps.html
+    fn wake(
+        &self,
+        current: InstanceId,
+        waiter: InstanceId,
+        f: &mut dyn FnMut(&dyn RequestHeadersOps) -> Result<()>,
+    ) -> Result<()>;
+}
+
+impl dyn StreamWaker {
+    /// Returns the default implementation that interacts with `Envoy`
+    /// through its [`ABI`].
+    ///
+    /// [`ABI`]: https://github.com/proxy-wasm/spec
+    pub fn default() -> &'static dyn StreamWaker {
+        &Host
+    }
+}
+
+struct Host;
+
+impl StreamWaker for Host {
+    fn wake(
+        &self,
+        current: InstanceId,
+        waiter: InstanceId,
+        f: &mut dyn FnMut(&dyn RequestHeadersOps) -> Result<()>,
+    ) -> Result<()> {
+        let instance = match InstanceHandle::lookup(waiter) {
+            Some(instance) => instance,
+            None => bail!("HTTP Filter instance {} no longer exists", waiter),
+        };
+        hostcalls::set_effective_context(waiter.0)?;
+        let result = instance.resume(&ops::Host, f);
+        hostcalls::set_effective_context(current.0)?;
+        result
+    }
+}
+
+type Waiter<'a> = (
+    InstanceId,
+    Box<dyn FnOnce(&CalloutResponse, &dyn RequestHeadersOps) -> Result<()> + 'a>,
+);
+
+struct InFlight<'a> {
+    owner: InstanceId,
+    request_id: HttpClientRequestHandle,
+    deadline: SystemTime,
+    waiters: Vec<Waiter<'a>>,
+}
+
+/// Cache of results of HTTP callouts.
+///
+/// Is meant to be created by an [`ExtensionFactory`] and shared with `HTTP Filter` instances
+/// through an [`Rc`].
+///
+/// Every distinct key takes up a [`SharedData`] entry until `Envoy` restarts,
+/// so only keys of bounded cardinality should be used.
+///
+/// [`ExtensionFactory`]: ../../../factory/trait.ExtensionFactory.html
+/// [`Rc`]: https://doc.rust-lang.org/std/rc/struct.Rc.html
+/// [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+pub struct CalloutCache<'a> {
+    config: CalloutCacheConfig,
+    shared_data: &'a dyn SharedData,
+    clock: &'a dyn Clock,
+    waker: &'a dyn StreamWaker,
+    error_sink: &'a dyn ErrorSink,
+
+    in_flight: RefCell<HashMap<String, InFlight<'a>>>,
+    requests: RefCell<HashMap<HttpClientRequestHandle, String>>,
+}
+
+impl<'a> CalloutCache<'a> {
+    /// Creates a new cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`      - configuration of the cache.
+    /// * `shared_data` - [`SharedData`] to keep results in.
+    /// * `clock`       - [`Clock`] to evaluate expiration with.
+    /// * `waker`       - [`StreamWaker`] to resume waiting `HTTP Filter` instances with.
+    ///
+    /// [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+    /// [`Clock`]: ../../../../host/time/trait.Clock.html
+    /// [`StreamWaker`]: trait.StreamWaker.html
+    pub fn new(
+        config: CalloutCacheConfig,
+        shared_data: &'a dyn SharedData,
+        clock: &'a dyn Clock,
+        waker: &'a dyn StreamWaker,
+    ) -> Self {
+        CalloutCache {
+            config,
+            shared_data,
+            clock,
+            waker,
+            error_sink: <dyn ErrorSink>::default(),
+            in_flight: RefCell::new(HashMap::new()),
+            requests: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns configuration of the cache.
+    pub fn config(&self) -> &CalloutCacheConfig {
+        &self.config
+    }
+
+    /// Returns a cached result, if any.
+    pub fn get(&self, key: &str) -> Result<Option<CalloutResponse>> {
+        let now = unix_nanos(self.clock.now()?);
+        let (value, _) = self.shared_data.get(&self.shared_key(key))?;
+        Ok(value
+            .and_then(|value| CalloutResponse::decode(key, &value))
+            .filter(|(_, expires_at)| now < *expires_at)
+            .map(|(response, _)| response))
+    }
+
+    /// Looks up the result of a callout, making the callout if necessary.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - identifier of the calling `HTTP Filter` instance.
+    /// * `key`         - key of the result, e.g. a value of the `authorization` header.
+    ///   Must be of bounded cardinality since cached results are never removed.
+    /// * `send`        - sends the callout through [`HttpClient`] unless the result is cached
+    ///   or a callout for the same key is already in flight.
+    /// * `on_response` - gets called if the caller ends up [`Waiting`] for a callout made by
+    ///   another `HTTP Filter` instance, with [`RequestHeadersOps`] of the caller.
+    ///   If that callout cannot complete, e.g. because the instance that has made it
+    ///   has been [`cancel`]led, gets called with a failed [`CalloutResponse`].
+    ///
+    /// [`HttpClient`]: ../../../../host/http/client/trait.HttpClient.html
+    /// [`Waiting`]: enum.Lookup.html#variant.Waiting
+    /// [`RequestHeadersOps`]: ../trait.RequestHeadersOps.html
+    /// [`cancel`]: #method.cancel
+    /// [`CalloutResponse`]: struct.CalloutResponse.html
+    pub fn lookup<S, R>(
+        &self,
+        instance_id: InstanceId,
+        key: &str,
+        send: S,
+        on_response: R,
+    ) -> Result<Lookup>
+    where
+        S: FnOnce() -> host::Result<HttpClientRequestHandle>,
+        R: FnOnce(&CalloutResponse, &dyn RequestHeadersOps) -> Result<()> + 'a,
+    {
+        if let Some(response) = self.get(key)? {
+            return Ok(Lookup::Cached(response));
+        }
+        let now = self.clock.now()?;
+        let callout = self.in_flight.borrow_mut().remove(key);
+        let waiters = match callout {
+            Some(mut callout) if now < callout.deadline => {
+                callout.waiters.push((instance_id, Box::new(on_response)));
+                self.in_flight.borrow_mut().insert(key.to_owned(), callout);
+                return Ok(Lookup::Waiting);
+            }
+            Some(callout) => {
+                // the response is not going to arrive
+                self.requests.borrow_mut().remove(&callout.request_id);
+                callout.waiters
+            }
+            None => Vec::new(),
+        };
+        let request_id = match send() {
+            Ok(request_id) => request_id,
+            Err(err) => {
+                // nobody is going to receive a response on behalf of the waiters
+                self.resume(instance_id, waiters, &CalloutResponse::failed());
+                return Err(err);
+            }
+        };
+        self.requests
+            .borrow_mut()
+            .insert(request_id, key.to_owned());
+        self.in_flight.borrow_mut().insert(
+            key.to_owned(),
+            InFlight {
+                owner: instance_id,
+                request_id,
+                deadline: now + self.config.timeout + IN_FLIGHT_GRACE_PERIOD,
+                waiters,
+            },
+        );
+        Ok(Lookup::Sent(request_id))
+    }
+
+    /// Handles a response to a callout made by the cache.
+    ///
+    /// Is meant to be called from [`HttpFilter::on_http_call_response`].
+    ///
+    /// The result gets cached unless the callout has failed without a response.
+    /// `HTTP Filter` instances that have been waiting for the result get resumed,
+    /// with a failed [`CalloutResponse`] if the response cannot be read.
+    /// Errors of waiting instances get logged rather than returned, since they have
+    /// nothing to do with the calling one.
+    ///
+    /// # Return value
+    ///
+    /// the result of the callout if the response belongs to a callout made by the cache,
+    /// `None` otherwise.
+    ///
+    /// [`HttpFilter::on_http_call_response`]: ../trait.HttpFilter.html#method.on_http_call_response
+    /// [`CalloutResponse`]: struct.CalloutResponse.html
+    pub fn on_http_call_response(
+        &self,
+        instance_id: InstanceId,
+        request_id: HttpClientRequestHandle,
+        num_headers: usize,
+        body_size: usize,
+        http_client_ops: &dyn HttpClientResponseOps,
+    ) -> Result<Option<CalloutResponse>> {
+        let key = match self.requests.borrow_mut().remove(&request_id) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let waiters = self
+            .in_flight
+            .borrow_mut()
+            .remove(&key)
+            .map(|callout| callout.waiters)
+            .unwrap_or_default();
+        let response = match Self::read_response(num_headers, body_size, http_client_ops) {
+            Ok(response) => response,
+            Err(err) => {
+                // the waiters must not be left paused until the stream timeout
+                self.resume(instance_id, waiters, &CalloutResponse::failed());
+                return Err(err);
+            }
+        };
+        let result = self.store(&key, &response);
+        self.resume(instance_id, waiters, &response);
+        result.map(|_| Some(response))
+    }
+
+    /// Stops waiting on behalf of a given `HTTP Filter` instance, e.g. once its
+    /// HTTP stream has completed or has been reset.
+    ///
+    /// Callouts made by that instance are abandoned, since their responses are not going
+    /// to be delivered, and `HTTP Filter` instances that have been waiting for them get
+    /// resumed with a failed [`CalloutResponse`].
+    ///
+    /// [`CalloutResponse`]: struct.CalloutResponse.html
+    pub fn cancel(&self, instance_id: InstanceId) {
+        let mut abandoned = Vec::new();
+        self.in_flight.borrow_mut().retain(|_, callout| {
+            callout.waiters.retain(|(waiter, _)| *waiter != instance_id);
+            if callout.owner == instance_id {
+                abandoned.push((callout.request_id, std::mem::take(&mut callout.waiters)));
+                false
+            } else {
+                true
+            }
+        });
+        for (request_id, waiters) in abandoned {
+            self.requests.borrow_mut().remove(&request_id);
+            self.resume(instance_id, waiters, &CalloutResponse::failed());
+        }
+    }
+
+    fn resume(&self, current: InstanceId, waiters: Vec<Waiter<'a>>, response: &CalloutResponse) {
+        for (waiter, on_response) in waiters {
+            let mut on_response = Some(on_response);
+            let result = self
+                .waker
+                .wake(current, waiter, &mut |ops| match on_response.take() {
+                    Some(on_response) => on_response(response, ops),
+                    None => Ok(()),
+                });
+            if let Err(err) = result {
+                self.error_sink.observe(
+                    &format!(
+                        "failed to resume HTTP Filter instance {} waiting for a callout",
+                        waiter
+                    ),
+                    &err,
+                );
+            }
+        }
+    }
+
+    fn read_response(
+        num_headers: usize,
+        body_size: usize,
+        http_client_ops: &dyn HttpClientResponseOps,
+    ) -> Result<CalloutResponse> {
+        if num_headers == 0 {
+            return Ok(CalloutResponse::failed());
+        }
+        let status = http_client_ops
+            .http_call_response_header(":status")?
+            .and_then(|status| status.to_string().parse::<u16>().ok());
+        let body = if body_size == 0 {
+            ByteString::default()
+        } else {
+            http_client_ops.http_call_response_body(0, body_size)?
+        };
+        Ok(CalloutResponse::new(status, body))
+    }
+
+    fn store(&self, key: &str, response: &CalloutResponse) -> Result<()> {
+        let status = match response.status {
+            Some(status) => status,
+            None => return Ok(()),
+        };
+        let ttl = if response.is_success() {
+            self.config.ttl
+        } else {
+            self.config.negative_ttl
+        };
+        let expires_at = unix_nanos(self.clock.now()? + ttl);
+        self.shared_data.set(
+            &self.shared_key(key),
+            &response.encode(key, status, expires_at),
+            None,
+        )
+    }
+
+    fn shared_key(&self, key: &str) -> String {
+        // `DefaultHasher::new()` always uses the same keys, so all worker threads agree
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        format!("{}.{:016x}", self.config.key_prefix, hasher.finish())
+    }
+}
@@ -12,20 +12,74 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
 use crate::abi::proxy_wasm::traits::{Context, HttpContext};
 use crate::abi::proxy_wasm::types::Action;
 
-use super::{FilterDataStatus, FilterHeadersStatus, FilterTrailersStatus, HttpFilter, Ops};
+use super::phase::{Phase, PhaseCheckedOps, PhaseState};
+use super::{
+    FilterDataStatus, FilterHeadersStatus, FilterTrailersStatus, HttpFilter, Ops, RequestHeadersOps,
+};
 use crate::extension::error::ErrorSink;
-use crate::extension::Error;
+use crate::extension::panic::{Poison, Poisonable};
+use crate::extension::{Error, InstanceId, Result};
 use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
 
+thread_local! {
+    /// `HTTP Filter` instances of the current worker thread.
+    static INSTANCES: RefCell<HashMap<InstanceId, InstanceHandle>> = RefCell::new(HashMap::new());
+}
+
+/// Handle to resume an `HTTP Filter` instance on behalf of another instance,
+/// e.g. once a callout made by the other instance completes.
+#[derive(Clone)]
+pub(super) struct InstanceHandle {
+    phase: Rc<PhaseState>,
+    poison: Poison,
+}
+
+impl InstanceHandle {
+    /// Returns a handle to a given `HTTP Filter` instance of the current worker thread,
+    /// if the instance still exists.
+    pub fn lookup(instance_id: InstanceId) -> Option<Self> {
+        INSTANCES.with(|instances| instances.borrow().get(&instance_id).cloned())
+    }
+
+    /// Calls `f` with operations of the instance as if the instance was handling
+    /// a response to an HTTP request, i.e. subject to the same phase checks,
+    /// isolation of panics and error handling as its own callbacks.
+    pub fn resume<R>(
+        &self,
+        ops: &dyn Ops,
+        f: &mut dyn FnMut(&dyn RequestHeadersOps) -> Result<R>,
+    ) -> Result<R> {
+        let ops = PhaseCheckedOps::with_state(ops, Rc::clone(&self.phase));
+        let result = ops.within(Phase::HttpCallResponse, |ops| {
+            self.poison
+                .guard("on_response of CalloutCache::lookup", || {
+                    f(ops.as_request_headers_ops())
+                })
+        });
+        if result.is_err() {
+            // same as on an error in a callback of the instance, otherwise the instance
+            // would stay paused until the stream timeout; the error is more relevant
+            // than a failure to terminate the stream
+            let _ = ops.unchecked().send_response(500, &[], None);
+        }
+        result
+    }
+}
+
 pub(crate) struct HttpFilterContext<'a, F>
 where
     F: HttpFilter,
 {
-    filter: F,
-    filter_ops: &'a dyn Ops,
+    instance_id: InstanceId,
+    filter: Poisonable<F>,
+    filter_ops: PhaseCheckedOps<'a>,
     http_client_ops: &'a dyn HttpClientResponseOps,
     error_sink: &'a dyn ErrorSink,
 }
@@ -35,12 +89,18 @@ where
     F: HttpFilter,
 {
     fn on_http_request_headers(&mut self, num_headers: usize, end_of_stream: bool) -> Action {
-        match self.filter.on_request_headers(
-            num_headers,
-            end_of_stream,
-            self.filter_ops.as_request_headers_ops(),
-        ) {
-            Ok(status) => status.as_action(),
+        self.filter_ops.enter(Phase::RequestHeaders);
+        let ops = self.filter_ops.as_request_headers_ops();
+        match self.filter.call("on_request_headers", |filter| {
+            filter.on_request_headers(num_headers, end_of_stream, ops)
+        }) {
+            Ok(status) => {
+                self.filter_ops.set_request_paused(
+                    Phase::RequestHeaders,
+                    status == FilterHeadersStatus::StopIteration,
+                );
+                status.as_action()
+            }
             Err(err) => {
                 self.error_sink
                     .observe("failed to handle HTTP request headers", &err);
@@ -51,12 +111,18 @@ where
     }
 
     fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
-        match self.filter.on_request_body(
-            body_size,
-            end_of_stream,
-            self.filter_ops.as_request_body_ops(),
-        ) {
-            Ok(status) => status.as_action(),
+        self.filter_ops.enter(Phase::RequestBody);
+        let ops = self.filter_ops.as_request_body_ops();
+        match self.filter.call("on_request_body", |filter| {
+            filter.on_request_body(body_size, end_of_stream, ops)
+        }) {
+            Ok(status) => {
+                self.filter_ops.set_request_paused(
+                    Phase::RequestBody,
+                    status == FilterDataStatus::StopIterationAndBuffer,
+                );
+                status.as_action()
+            }
             Err(err) => {
                 self.error_sink
                     .observe("failed to handle HTTP request body", &err);
@@ -67,11 +133,18 @@ where
     }
 
     fn on_http_request_trailers(&mut self, num_trailers: usize) -> Action {
-        match self
-            .filter
-            .on_request_trailers(num_trailers, self.filter_ops.as_request_trailers_ops())
-        {
-            Ok(status) => status.as_action(),
+        self.filter_ops.enter(Phase::RequestTrailers);
+        let ops = self.filter_ops.as_request_trailers_ops();
+        match self.filter.call("on_request_trailers", |filter| {
+            filter.on_request_trailers(num_trailers, ops)
+        }) {
+            Ok(status) => {
+                self.filter_ops.set_request_paused(
+                    Phase::RequestTrailers,
+                    status == FilterTrailersStatus::StopIteration,
+                );
+                status.as_action()
+            }
             Err(err) => {
                 self.error_sink
                     .observe("failed to handle HTTP request trailers", &err);
@@ -82,12 +155,18 @@ where
     }
 
     fn on_http_response_headers(&mut self, num_headers: usize, end_of_stream: bool) -> Action {
-        match self.filter.on_response_headers(
-            num_headers,
-            end_of_stream,
-            self.filter_ops.as_response_headers_ops(),
-        ) {
-            Ok(status) => status.as_action(),
+        self.filter_ops.enter(Phase::ResponseHeaders);
+        let ops = self.filter_ops.as_response_headers_ops();
+        match self.filter.call("on_response_headers", |filter| {
+            filter.on_response_headers(num_headers, end_of_stream, ops)
+        }) {
+            Ok(status) => {
+                self.filter_ops.set_response_paused(
+                    Phase::ResponseHeaders,
+                    status == FilterHeadersStatus::StopIteration,
+                );
+                status.as_action()
+            }
             Err(err) => {
                 self.error_sink
                     .observe("failed to handle HTTP response headers", &err);
@@ -98,12 +177,18 @@ where
     }
 
     fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
-        match self.filter.on_response_body(
-            body_size,
-            end_of_stream,
-            self.filter_ops.as_response_body_ops(),
-        ) {
-            Ok(status) => status.as_action(),
+        self.filter_ops.enter(Phase::ResponseBody);
+        let ops = self.filter_ops.as_response_body_ops();
+        match self.filter.call("on_response_body", |filter| {
+            filter.on_response_body(body_size, end_of_stream, ops)
+        }) {
+            Ok(status) => {
+                self.filter_ops.set_response_paused(
+                    Phase::ResponseBody,
+                    status == FilterDataStatus::StopIterationAndBuffer,
+                );
+                status.as_action()
+            }
             Err(err) => {
                 self.error_sink
                     .observe("failed to handle HTTP response body", &err);
@@ -114,11 +199,18 @@ where
     }
 
     fn on_http_response_trailers(&mut self, num_trailers: usize) -> Action {
-        match self
-            .filter
-            .on_response_trailers(num_trailers, self.filter_ops.as_response_trailers_ops())
-        {
-            Ok(status) => status.as_action(),
+        self.filter_ops.enter(Phase::ResponseTrailers);
+        let ops = self.filter_ops.as_response_trailers_ops();
+        match self.filter.call("on_response_trailers", |filter| {
+            filter.on_response_trailers(num_trailers, ops)
+        }) {
+            Ok(status) => {
+                self.filter_ops.set_response_paused(
+                    Phase::ResponseTrailers,
+                    status == FilterTrailersStatus::StopIteration,
+                );
+                status.as_action()
+            }
             Err(err) => {
                 self.error_sink
                     .observe("failed to handle HTTP response trailers", &err);
@@ -134,10 +226,11 @@ where
     F: HttpFilter,
 {
     fn on_done(&mut self) -> bool {
-        if let Err(err) = self
-            .filter
-            .on_exchange_complete(self.filter_ops.as_exchange_complete_ops())
-        {
+        self.filter_ops.enter(Phase::ExchangeComplete);
+        let ops = self.filter_ops.as_exchange_complete_ops();
+        if let Err(err) = self.filter.call("on_exchange_complete", |filter| {
+            filter.on_exchange_complete(ops)
+        }) {
             self.error_sink
                 .observe("failed to handle completion of an HTTP stream", &err);
             // HTTP stream is already being terminated, so there is no need to do it explicitly
@@ -154,14 +247,19 @@ where
         body_size: usize,
         num_trailers: usize,
     ) {
-        if let Err(err) = self.filter.on_http_call_response(
-            HttpClientRequestHandle::from(token_id),
-            num_headers,
-            body_size,
-            num_trailers,
-            self.filter_ops,
-            self.http_client_ops,
-        ) {
+        self.filter_ops.enter(Phase::HttpCallResponse);
+        let filter_ops = &self.filter_ops;
+        let http_client_ops = self.http_client_ops;
+        if let Err(err) = self.filter.call("on_http_call_response", |filter| {
+            filter.on_http_call_response(
+                HttpClientRequestHandle::from(token_id),
+                num_headers,
+                body_size,
+                num_trailers,
+                filter_ops,
+                http_client_ops,
+            )
+        }) {
             self.error_sink.observe(
                 "failed to process a response to an HTTP request made by the extension",
                 &err,
@@ -176,22 +274,33 @@ where
     F: HttpFilter,
 {
     pub fn new(
+        extension: &'static str,
+        instance_id: InstanceId,
         filter: F,
         filter_ops: &'a dyn Ops,
         http_client_ops: &'a dyn HttpClientResponseOps,
         error_sink: &'a dyn ErrorSink,
     ) -> Self {
-        HttpFilterContext {
-            filter,
-            filter_ops,
+        let ctx = HttpFilterContext {
+            instance_id,
+            filter: Poisonable::new(extension, filter),
+            filter_ops: PhaseCheckedOps::new(filter_ops),
             http_client_ops,
             error_sink,
-        }
+        };
+        let handle = InstanceHandle {
+            phase: Rc::clone(ctx.filter_ops.state()),
+            poison: ctx.filter.poison().clone(),
+        };
+        INSTANCES.with(|instances| instances.borrow_mut().insert(instance_id, handle));
+        ctx
     }
 
     /// Creates a new HTTP filter context bound to the actual Envoy ABI.
-    pub fn with_default_ops(filter: F) -> Self {
+    pub fn with_default_ops(extension: &'static str, instance_id: InstanceId, filter: F) -> Self {
         Self::new(
+            extension,
+            instance_id,
             filter,
             Ops::default(),
             HttpClientResponseOps::default(),
@@ -200,7 +309,7 @@ where
     }
 
     fn handle_error(&self, _err: Error) {
-        if let Err(err) = self.filter_ops.send_response(500, &[], None) {
+        if let Err(err) = self.filter_ops.unchecked().send_response(500, &[], None) {
             self.error_sink.observe(
                 "failed to terminate processing of the HTTP request: failed to send a direct reply",
                 &err,
@@ -209,6 +318,16 @@ where
     }
 }
 
+impl<'a, F> Drop for HttpFilterContext<'a, F>
+where
+    F: HttpFilter,
+{
+    fn drop(&mut self) {
+        // the registry might have already been destroyed if the thread is exiting
+        let _ = INSTANCES.try_with(|instances| instances.borrow_mut().remove(&self.instance_id));
+    }
+}
+
 /// Fake `Proxy Wasm` [`HttpContext`] that is used to postpone error handling
 /// until a proper moment in the request lifecycle.
 ///
@@ -260,3 +379,226 @@ impl<'a> HttpContext for VoidHttpFilterContext<'a> {
 }
 
 impl<'a> Context for VoidHttpFilterContext<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::super::testing::FakeOps;
+    use super::super::{RequestHeadersOps, ResponseHeadersOps};
+    use super::*;
+    use crate::extension::filter::PHASE_CHECKS_ENABLED;
+    use crate::extension::testing::FakeErrorSink;
+
+    /// Panics on request headers and counts the callbacks it has been called with.
+    #[derive(Default)]
+    struct PanickingFilter {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl HttpFilter for PanickingFilter {
+        fn on_request_headers(
+            &mut self,
+            _num_headers: usize,
+            _end_of_stream: bool,
+            _ops: &dyn RequestHeadersOps,
+        ) -> Result<FilterHeadersStatus> {
+            self.calls.set(self.calls.get() + 1);
+            panic!("unexpected request")
+        }
+
+        fn on_response_headers(
+            &mut self,
+            _num_headers: usize,
+            _end_of_stream: bool,
+            _ops: &dyn ResponseHeadersOps,
+        ) -> Result<FilterHeadersStatus> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(FilterHeadersStatus::Continue)
+        }
+    }
+
+    /// Stops iteration on request headers and counts the callbacks it has been called with.
+    #[derive(Default)]
+    struct PausingFilter {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl HttpFilter for PausingFilter {
+        fn on_request_headers(
+            &mut self,
+            _num_headers: usize,
+            _end_of_stream: bool,
+            _ops: &dyn RequestHeadersOps,
+        ) -> Result<FilterHeadersStatus> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(FilterHeadersStatus::StopIteration)
+        }
+
+        fn on_response_headers(
+            &mut self,
+            _num_headers: usize,
+            _end_of_stream: bool,
+            _ops: &dyn ResponseHeadersOps,
+        ) -> Result<FilterHeadersStatus> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(FilterHeadersStatus::Continue)
+        }
+    }
+
+    #[test]
+    fn test_filter_panic_as_error() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let mut ctx = HttpFilterContext::new(
+            "my_http_filter",
+            InstanceId::from(1),
+            PanickingFilter::default(),
+            &ops,
+            &ops,
+            &error_sink,
+        );
+
+        let action = ctx.on_http_request_headers(1, false);
+
+        assert_eq!(action, FilterHeadersStatus::StopIteration.as_action());
+        assert_eq!(ops.calls(), vec!["send_response"]);
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to handle HTTP request headers: extension panicked: unexpected request"]
+        );
+    }
+
+    #[test]
+    fn test_poisoned_filter_is_not_called() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let filter = PanickingFilter::default();
+        let calls = Rc::clone(&filter.calls);
+        let mut ctx = HttpFilterContext::new(
+            "my_http_filter",
+            InstanceId::from(1),
+            filter,
+            &ops,
+            &ops,
+            &error_sink,
+        );
+
+        ctx.on_http_request_headers(1, false);
+        let action = ctx.on_http_response_headers(1, false);
+        ctx.on_http_call_response(1, 0, 0, 0);
+        assert!(ctx.on_done());
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(action, FilterHeadersStatus::StopIteration.as_action());
+        let errors = error_sink.errors();
+        assert_eq!(errors.len(), 4);
+        for err in &errors[1..] {
+            assert!(
+                err.ends_with(
+                    "extension is poisoned since it has panicked earlier: unexpected request"
+                ),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_resumed_filter_shares_phase_state() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let mut ctx = HttpFilterContext::new(
+            "my_http_filter",
+            InstanceId::from(1),
+            PausingFilter::default(),
+            &ops,
+            &ops,
+            &error_sink,
+        );
+        ctx.on_http_request_headers(1, false);
+
+        let instance = InstanceHandle::lookup(InstanceId::from(1)).unwrap();
+        let first = instance.resume(&ops, &mut |ops| {
+            ops.set_request_header("x-callout", "done")?;
+            Ok(ops.resume_request()?)
+        });
+        let second = instance.resume(&ops, &mut |ops| Ok(ops.resume_request()?));
+
+        assert!(first.is_ok());
+        if PHASE_CHECKS_ENABLED {
+            assert!(second.is_err());
+            assert_eq!(
+                ops.calls(),
+                vec![
+                    "set_request_header_bytes",
+                    "resume_request",
+                    "send_response"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_resumed_filter_panic_poisons_that_filter() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let owner = PausingFilter::default();
+        let owner_calls = Rc::clone(&owner.calls);
+        let mut owner_ctx = HttpFilterContext::new(
+            "my_http_filter",
+            InstanceId::from(1),
+            owner,
+            &ops,
+            &ops,
+            &error_sink,
+        );
+        let waiter = PausingFilter::default();
+        let waiter_calls = Rc::clone(&waiter.calls);
+        let mut waiter_ctx = HttpFilterContext::new(
+            "my_http_filter",
+            InstanceId::from(2),
+            waiter,
+            &ops,
+            &ops,
+            &error_sink,
+        );
+        waiter_ctx.on_http_request_headers(1, false);
+
+        let instance = InstanceHandle::lookup(InstanceId::from(2)).unwrap();
+        let result: Result<()> = instance.resume(&ops, &mut |_| panic!("unexpected callout"));
+        owner_ctx.on_http_response_headers(1, false);
+        waiter_ctx.on_http_response_headers(1, false);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "extension panicked: unexpected callout"
+        );
+        assert_eq!(owner_calls.get(), 1);
+        assert_eq!(waiter_calls.get(), 1);
+        assert_eq!(ops.calls(), vec!["send_response", "send_response"]);
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to handle HTTP response headers: extension is poisoned since it has panicked earlier: unexpected callout"]
+        );
+    }
+
+    #[test]
+    fn test_dropped_filter_cannot_be_resumed() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let ctx = HttpFilterContext::new(
+            "my_http_filter",
+            InstanceId::from(1),
+            PausingFilter::default(),
+            &ops,
+            &ops,
+            &error_sink,
+        );
+
+        assert!(InstanceHandle::lookup(InstanceId::from(1)).is_some());
+        drop(ctx);
+        assert!(InstanceHandle::lookup(InstanceId::from(1)).is_none());
+    }
+}
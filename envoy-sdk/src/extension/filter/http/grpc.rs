@@ -0,0 +1,399 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for `HTTP Filter`s that proxy `gRPC` traffic.
+//!
+//! # Examples
+//!
+//! #### Reject a `gRPC` call with a trailers-only response:
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use envoy::extension::{HttpFilter, Result};
+//! use envoy::extension::filter::http::{FilterHeadersStatus, RequestHeadersOps};
+//! use envoy::extension::filter::http::grpc::GrpcStatus;
+//!
+//! struct MyHttpFilter;
+//!
+//! impl HttpFilter for MyHttpFilter {
+//!     fn on_request_headers(
+//!         &mut self,
+//!         _num_headers: usize,
+//!         _end_of_stream: bool,
+//!         filter_ops: &dyn RequestHeadersOps,
+//!     ) -> Result<FilterHeadersStatus> {
+//!         if filter_ops.request_header("authorization")?.is_none() {
+//!             filter_ops.send_grpc_response(
+//!                 GrpcStatus::Unauthenticated,
+//!                 Some("missing credentials"),
+//!                 &[],
+//!             )?;
+//!             return Ok(FilterHeadersStatus::StopIteration);
+//!         }
+//!         Ok(FilterHeadersStatus::Continue)
+//!     }
+//! }
+//! ```
+//!
+//! #### Inspect `gRPC` messages of a streaming request:
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use envoy::extension::{HttpFilter, Result};
+//! use envoy::extension::filter::http::{FilterDataStatus, RequestBodyOps};
+//! use envoy::extension::filter::http::grpc::GrpcMessageDecoder;
+//! use envoy::host::log;
+//!
+//! struct MyHttpFilter {
+//!     decoder: GrpcMessageDecoder,
+//! }
+//!
+//! impl HttpFilter for MyHttpFilter {
+//!     fn on_request_body(
+//!         &mut self,
+//!         body_size: usize,
+//!         _end_of_stream: bool,
+//!         filter_ops: &dyn RequestBodyOps,
+//!     ) -> Result<FilterDataStatus> {
+//!         for message in self.decoder.read_request_body(body_size, filter_ops)? {
+//!             log::info!("gRPC message of {} bytes", message.data().len());
+//!         }
+//!         Ok(FilterDataStatus::Continue)
+//!     }
+//! }
+//! ```
+
+use std::fmt;
+
+use super::{RequestBodyOps, ResponseBodyOps};
+use crate::error::{bail, Result};
+use crate::host::ByteString;
+
+/// Size of the prefix preceding every message in a `gRPC` stream
+/// (1 byte of `Compressed-Flag` followed by 4 bytes of `Message-Length`).
+pub const GRPC_MESSAGE_PREFIX_SIZE: usize = 5;
+
+/// Default limit on the size of a single `gRPC` message (4 MiB),
+/// same as in the reference `gRPC` implementations.
+pub const DEFAULT_MAX_GRPC_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// `gRPC` status codes.
+///
+/// See [`gRPC` status codes] for the meaning of individual codes.
+///
+/// [`gRPC` status codes]: https://github.com/grpc/grpc/blob/master/doc/statuscodes.md
+#[repr(u32)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
+pub enum GrpcStatus {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+impl GrpcStatus {
+    /// Returns a numeric code of this status.
+    pub fn as_code(&self) -> u32 {
+        *self as u32
+    }
+}
+
+impl fmt::Display for GrpcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_code())
+    }
+}
+
+/// A single length-prefixed `gRPC` message.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct GrpcMessage {
+    compressed: bool,
+    data: ByteString,
+}
+
+impl GrpcMessage {
+    /// Creates a new uncompressed message.
+    pub fn new<T>(data: T) -> Self
+    where
+        T: Into<ByteString>,
+    {
+        GrpcMessage {
+            compressed: false,
+            data: data.into(),
+        }
+    }
+
+    /// Creates a new message with data compressed according to `grpc-encoding`.
+    pub fn compressed<T>(data: T) -> Self
+    where
+        T: Into<ByteString>,
+    {
+        GrpcMessage {
+            compressed: true,
+            data: data.into(),
+        }
+    }
+
+    /// Returns `true` if the message data is compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Returns the message data (without the length prefix).
+    pub fn data(&self) -> &ByteString {
+        &self.data
+    }
+
+    /// Returns the message data (without the length prefix).
+    pub fn into_data(self) -> ByteString {
+        self.data
+    }
+
+    /// Encodes the message into its wire format, i.e. prepends the length prefix.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use envoy_sdk as envoy;
+    /// use envoy::extension::filter::http::grpc::GrpcMessage;
+    ///
+    /// assert_eq!(GrpcMessage::new("hi").encode(), vec![0, 0, 0, 0, 2, b'h', b'i']);
+    /// ```
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(GRPC_MESSAGE_PREFIX_SIZE + self.data.len());
+        self.encode_into(&mut bytes);
+        bytes
+    }
+
+    /// Appends the wire format of the message to a given buffer.
+    pub fn encode_into(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.compressed as u8);
+        bytes.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+    }
+}
+
+/// Reassembles length-prefixed `gRPC` messages out of body chunks.
+///
+/// `gRPC` messages can be split across several body chunks, and a single chunk
+/// can contain several messages. `GrpcMessageDecoder` keeps incomplete data
+/// between filter invocations and returns messages once they are complete.
+///
+/// `GrpcMessageDecoder` expects to see every chunk of the body exactly once,
+/// i.e. the filter should return [`FilterDataStatus::Continue`] from `on_request_body`/`on_response_body`.
+/// To modify messages instead, buffer the entire body with [`FilterDataStatus::StopIterationAndBuffer`]
+/// until `end_of_stream` and then replace it with [`set_request_data`]/[`set_response_data`].
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # fn main() -> envoy::error::Result<()> {
+/// use envoy::extension::filter::http::grpc::{GrpcMessage, GrpcMessageDecoder};
+///
+/// let mut decoder = GrpcMessageDecoder::new();
+///
+/// assert_eq!(decoder.decode(&[0, 0, 0, 0, 5, b'h', b'e'])?, vec![]);
+/// assert_eq!(
+///     decoder.decode(&[b'l', b'l', b'o', 0, 0, 0, 0, 0])?,
+///     vec![GrpcMessage::new("hello"), GrpcMessage::new("")],
+/// );
+/// assert!(decoder.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`FilterDataStatus::Continue`]: ../enum.FilterDataStatus.html#variant.Continue
+/// [`FilterDataStatus::StopIterationAndBuffer`]: ../enum.FilterDataStatus.html#variant.StopIterationAndBuffer
+/// [`set_request_data`]: ../trait.RequestBodyOps.html#tymethod.set_request_data
+/// [`set_response_data`]: ../trait.ResponseBodyOps.html#tymethod.set_response_data
+#[derive(Debug)]
+pub struct GrpcMessageDecoder {
+    buffer: Vec<u8>,
+    max_message_size: usize,
+    error: Option<String>,
+}
+
+impl Default for GrpcMessageDecoder {
+    fn default() -> Self {
+        Self::with_max_message_size(DEFAULT_MAX_GRPC_MESSAGE_SIZE)
+    }
+}
+
+impl GrpcMessageDecoder {
+    /// Creates a new decoder with the [`default`] limit on the message size.
+    ///
+    /// [`default`]: constant.DEFAULT_MAX_GRPC_MESSAGE_SIZE.html
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new decoder with a given limit on the message size.
+    pub fn with_max_message_size(max_message_size: usize) -> Self {
+        GrpcMessageDecoder {
+            buffer: Vec::new(),
+            max_message_size,
+            error: None,
+        }
+    }
+
+    /// Returns `true` if there is no incomplete message pending.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Returns the number of bytes of the incomplete message pending.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Consumes the next chunk of data and returns all messages that are now complete.
+    ///
+    /// Returns an error if the data does not represent a valid `gRPC` stream
+    /// or if a message exceeds the size limit.
+    ///
+    /// Once the stream has turned out to be invalid, the decoder discards buffered data
+    /// and keeps returning the same error. Messages that have been completed
+    /// before the invalid one are returned first, and the error is returned
+    /// on the next call.
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<GrpcMessage>> {
+        if let Some(err) = &self.error {
+            bail!("{}", err);
+        }
+        self.buffer.extend_from_slice(data);
+
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        while self.buffer.len() - offset >= GRPC_MESSAGE_PREFIX_SIZE {
+            let (compressed, length) = match self.decode_prefix(offset) {
+                Ok(prefix) => prefix,
+                Err(err) => {
+                    self.buffer.clear();
+                    self.error = Some(err.clone());
+                    if messages.is_empty() {
+                        bail!("{}", err);
+                    }
+                    return Ok(messages);
+                }
+            };
+            let start = offset + GRPC_MESSAGE_PREFIX_SIZE;
+            if self.buffer.len() - start < length {
+                break;
+            }
+            messages.push(GrpcMessage {
+                compressed,
+                data: self.buffer[start..start + length].into(),
+            });
+            offset = start + length;
+        }
+        self.buffer.drain(..offset);
+        Ok(messages)
+    }
+
+    fn decode_prefix(&self, offset: usize) -> std::result::Result<(bool, usize), String> {
+        let compressed = match self.buffer[offset] {
+            0 => false,
+            1 => true,
+            flag => {
+                return Err(format!(
+                    "invalid gRPC message: unexpected Compressed-Flag {}",
+                    flag
+                ))
+            }
+        };
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&self.buffer[offset + 1..offset + GRPC_MESSAGE_PREFIX_SIZE]);
+        let length = u32::from_be_bytes(length) as usize;
+        if length > self.max_message_size {
+            return Err(format!(
+                "invalid gRPC message: message size {} exceeds the limit of {} bytes",
+                length, self.max_message_size
+            ));
+        }
+        Ok((compressed, length))
+    }
+
+    /// Reads the current chunk of the request body and returns all messages that are now complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `body_size`  - size of the body chunk, as passed to `on_request_body`.
+    /// * `filter_ops` - a [`trait object`][`RequestBodyOps`] to read the body chunk through.
+    ///
+    /// [`RequestBodyOps`]: ../trait.RequestBodyOps.html
+    pub fn read_request_body(
+        &mut self,
+        body_size: usize,
+        filter_ops: &dyn RequestBodyOps,
+    ) -> Result<Vec<GrpcMessage>> {
+        let data = filter_ops.request_data(0, body_size)?;
+        self.decode(&data)
+    }
+
+    /// Reads the current chunk of the response body and returns all messages that are now complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `body_size`  - size of the body chunk, as passed to `on_response_body`.
+    /// * `filter_ops` - a [`trait object`][`ResponseBodyOps`] to read the body chunk through.
+    ///
+    /// [`ResponseBodyOps`]: ../trait.ResponseBodyOps.html
+    pub fn read_response_body(
+        &mut self,
+        body_size: usize,
+        filter_ops: &dyn ResponseBodyOps,
+    ) -> Result<Vec<GrpcMessage>> {
+        let data = filter_ops.response_data(0, body_size)?;
+        self.decode(&data)
+    }
+}
+
+/// Encodes a sequence of messages into their wire format.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::filter::http::grpc::{self, GrpcMessage};
+///
+/// let body = grpc::encode_messages(&[GrpcMessage::new("a"), GrpcMessage::compressed("b")]);
+///
+/// assert_eq!(body, vec![0, 0, 0, 0, 1, b'a', 1, 0, 0, 0, 1, b'b']);
+/// ```
+pub fn encode_messages(messages: &[GrpcMessage]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(
+        messages
+            .iter()
+            .map(|message| GRPC_MESSAGE_PREFIX_SIZE + message.data.len())
+            .sum(),
+    );
+    for message in messages {
+        message.encode_into(&mut bytes);
+    }
+    bytes
+}
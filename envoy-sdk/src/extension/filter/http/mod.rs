@@ -102,12 +102,21 @@
 use crate::abi::proxy_wasm::types::Action;
 use crate::extension::Result;
 use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
-use crate::host::{self, ByteString, HeaderMap};
+use crate::host::{self, ByteString, HeaderMap, StreamInfo};
+
+use self::grpc::GrpcStatus;
 
 pub(crate) use self::context::{HttpFilterContext, VoidHttpFilterContext};
 
+pub mod callout;
 mod context;
+pub mod grpc;
 mod ops;
+mod phase;
+pub mod ratelimit;
+pub mod route;
+#[cfg(test)]
+mod testing;
 
 /// Return codes for [`on_request_headers`] and [`on_response_headers`] filter
 /// invocations.
@@ -264,7 +273,25 @@ impl FilterTrailersStatus {
 /// For comparison, if the extension chooses to panic, this will, at best, affect all ongoing HTTP requests
 /// handled by that extension, and, at worst, will crash `Envoy` entirely (as of July 2020).
 ///
+/// On targets that support stack unwinding, `Envoy SDK` catches a panic and handles it
+/// as an error. The filter instance is considered poisoned afterwards and none of its
+/// callbacks will be called again. WebAssembly targets do not support stack unwinding,
+/// so inside `Envoy` a panic still aborts the WebAssembly VM.
+///
+/// # Phase checks
+///
+/// In debug builds (or with `phase-checks` feature enabled), `Envoy SDK` verifies that
+/// operations are called at a proper point of the HTTP stream lifecycle, e.g. it rejects
+/// calling `set_response_header` from `on_request_body` or calling `resume_request`
+/// when the request is not paused, with an error that explains where the operation is allowed.
+///
+/// Headers, body and trailers remain modifiable from later callbacks of the same direction
+/// (and from `on_http_call_response`) for as long as filter chain iteration is stopped,
+/// e.g. request headers can be modified in `on_request_body` if `on_request_headers`
+/// has returned [`FilterHeadersStatus::StopIteration`].
+///
 /// [`Result::Err(x)`]: https://doc.rust-lang.org/core/result/enum.Result.html#variant.Err
+/// [`FilterHeadersStatus::StopIteration`]: enum.FilterHeadersStatus.html#variant.StopIteration
 pub trait HttpFilter {
     /// Called with decoded request headers.
     ///
@@ -483,6 +510,64 @@ pub trait RequestHeadersOps: RequestFlowOps {
     fn set_request_header_bytes(&self, name: &str, value: &[u8]) -> host::Result<()>;
 
     fn remove_request_header(&self, name: &str) -> host::Result<()>;
+
+    /// Clears the route cached for this HTTP stream.
+    ///
+    /// `Envoy` selects a route once, before the first `HTTP Filter` is called, and keeps using
+    /// that route for the rest of the request. Call this method after changing `:path`,
+    /// `:authority` or any other header the route configuration matches on, so that `Envoy`
+    /// re-evaluates the route with the modified headers.
+    ///
+    /// Once the route has been re-evaluated, the new route and cluster become visible
+    /// through [`StreamInfo::route()`] and [`StreamInfo::cluster()`].
+    ///
+    /// Relies on the `clear_route_cache` foreign function of `Envoy`, i.e. fails with
+    /// `Status::NotFound` if `Envoy` doesn't provide it.
+    ///
+    /// [`StreamInfo::route()`]: ../../../host/stream_info/trait.StreamInfo.html#method.route
+    /// [`StreamInfo::cluster()`]: ../../../host/stream_info/trait.StreamInfo.html#method.cluster
+    fn clear_route_cache(&self) -> host::Result<()>;
+
+    /// Routes the request to a given upstream cluster.
+    ///
+    /// Relies on the [`cluster_header`] mechanism of `Envoy` routes, i.e. sets the value of
+    /// a request header the route reads the name of the upstream cluster from and then
+    /// clears the route cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `cluster_header` - name of the request header referenced by [`cluster_header`] setting of the route.
+    /// * `cluster`        - name of the upstream cluster.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use envoy_sdk as envoy;
+    /// use envoy::extension::{HttpFilter, Result};
+    /// use envoy::extension::filter::http::{FilterHeadersStatus, RequestHeadersOps};
+    ///
+    /// struct MyHttpFilter;
+    ///
+    /// impl HttpFilter for MyHttpFilter {
+    ///     fn on_request_headers(
+    ///         &mut self,
+    ///         _num_headers: usize,
+    ///         _end_of_stream: bool,
+    ///         filter_ops: &dyn RequestHeadersOps,
+    ///     ) -> Result<FilterHeadersStatus> {
+    ///         if filter_ops.request_header("x-canary")?.is_some() {
+    ///             filter_ops.set_upstream_cluster("x-upstream-cluster", "backend-canary")?;
+    ///         }
+    ///         Ok(FilterHeadersStatus::Continue)
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// [`cluster_header`]: https://www.envoyproxy.io/docs/envoy/latest/api-v3/config/route/v3/route_components.proto#envoy-v3-api-field-config-route-v3-routeaction-cluster-header
+    fn set_upstream_cluster(&self, cluster_header: &str, cluster: &str) -> host::Result<()> {
+        self.set_request_header(cluster_header, cluster)?;
+        self.clear_route_cache()
+    }
 }
 
 /// An interface for manipulating request body.
@@ -494,6 +579,15 @@ pub trait RequestBodyOps: RequestFlowOps {
     /// * `offset`   - offset to start reading data from.
     /// * `max_size` - maximum size of data to return.
     fn request_data(&self, start: usize, max_size: usize) -> host::Result<ByteString>;
+
+    /// Replaces request data received from `Downstream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - offset to start replacing data from.
+    /// * `size`  - size of data to replace.
+    /// * `data`  - new data.
+    fn set_request_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()>;
 }
 
 /// An interface for manipulating request trailers.
@@ -523,6 +617,25 @@ pub trait RequestFlowOps {
         headers: &[(&str, &str)],
         body: Option<&[u8]>,
     ) -> host::Result<()>;
+
+    /// Sends a `gRPC` response without forwarding the request to the `Upstream`.
+    ///
+    /// The response is a [`trailers-only`] one, i.e. `grpc-status` and `grpc-message`
+    /// are sent together with the response headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `grpc_status`  - `gRPC` status code.
+    /// * `grpc_message` - optional status message, will be percent-encoded as required by the `gRPC` spec.
+    /// * `headers`      - additional response headers.
+    ///
+    /// [`trailers-only`]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#responses
+    fn send_grpc_response(
+        &self,
+        grpc_status: GrpcStatus,
+        grpc_message: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> host::Result<()>;
 }
 
 /// An interface for manipulating response headers.
@@ -551,6 +664,15 @@ pub trait ResponseBodyOps: ResponseFlowOps {
     /// * `offset`   - offset to start reading data from.
     /// * `max_size` - maximum size of data to return.
     fn response_data(&self, start: usize, max_size: usize) -> host::Result<ByteString>;
+
+    /// Replaces response data received from `Upstream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - offset to start replacing data from.
+    /// * `size`  - size of data to replace.
+    /// * `data`  - new data.
+    fn set_response_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()>;
 }
 
 /// An interface for manipulating response trailers.
@@ -579,11 +701,69 @@ pub trait ResponseFlowOps {
 /// filter invocation.
 ///
 /// [`on_exchange_complete`]: trait.HttpFilter.html#method.on_exchange_complete
+///
+/// Provides read-only access to the final state of the HTTP stream, e.g. to record
+/// per-request summary metrics.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::{HttpFilter, Result};
+/// use envoy::extension::filter::http::ExchangeCompleteOps;
+/// use envoy::host::log;
+///
+/// struct MyHttpFilter;
+///
+/// impl HttpFilter for MyHttpFilter {
+///     fn on_exchange_complete(&mut self, ops: &dyn ExchangeCompleteOps) -> Result<()> {
+///         let status_code = ops.stream_info().response().status_code()?;
+///         let bytes_sent = ops.stream_info().response().total_size()?;
+///         let content_type = ops.response_header("content-type")?;
+///         log::info!("completed: status={:?} bytes={:?} content-type={:?}", status_code, bytes_sent, content_type);
+///         Ok(())
+///     }
+/// }
+/// ```
 pub trait ExchangeCompleteOps {
-    // TODO(yskopets): define
+    /// Returns final request headers.
+    fn request_headers(&self) -> host::Result<HeaderMap>;
+
+    /// Returns final request header by name.
+    fn request_header(&self, name: &str) -> host::Result<Option<ByteString>>;
+
+    /// Returns final request trailers.
+    fn request_trailers(&self) -> host::Result<HeaderMap>;
+
+    /// Returns final request trailer by name.
+    fn request_trailer(&self, name: &str) -> host::Result<Option<ByteString>>;
+
+    /// Returns final response headers.
+    fn response_headers(&self) -> host::Result<HeaderMap>;
+
+    /// Returns final response header by name.
+    fn response_header(&self, name: &str) -> host::Result<Option<ByteString>>;
+
+    /// Returns final response trailers.
+    fn response_trailers(&self) -> host::Result<HeaderMap>;
+
+    /// Returns final response trailer by name.
+    fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>>;
+
+    /// Provides access to properties of the stream.
+    fn stream_info(&self) -> &dyn StreamInfo;
 }
 
 /// An interface with all available operations over request/response.
+///
+/// [`ExchangeCompleteOps`] are intentionally not among supertraits of `Ops`
+/// since their methods share names with [`RequestHeadersOps`], [`ResponseHeadersOps`], etc.
+/// Use [`as_exchange_complete_ops`] to access them.
+///
+/// [`ExchangeCompleteOps`]: trait.ExchangeCompleteOps.html
+/// [`RequestHeadersOps`]: trait.RequestHeadersOps.html
+/// [`ResponseHeadersOps`]: trait.ResponseHeadersOps.html
+/// [`as_exchange_complete_ops`]: #tymethod.as_exchange_complete_ops
 pub trait Ops:
     RequestHeadersOps
     + RequestBodyOps
@@ -591,7 +771,6 @@ pub trait Ops:
     + ResponseHeadersOps
     + ResponseBodyOps
     + ResponseTrailersOps
-    + ExchangeCompleteOps
 {
     fn as_request_headers_ops(&self) -> &dyn RequestHeadersOps;
 
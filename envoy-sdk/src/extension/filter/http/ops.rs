@@ -12,13 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::grpc::GrpcStatus;
 use super::{
     ExchangeCompleteOps, RequestBodyOps, RequestFlowOps, RequestHeadersOps, RequestTrailersOps,
     ResponseBodyOps, ResponseFlowOps, ResponseHeadersOps, ResponseTrailersOps,
 };
 use crate::abi::proxy_wasm::hostcalls;
 use crate::abi::proxy_wasm::types::{BufferType, MapType};
-use crate::host::{self, ByteString, HeaderMap};
+use crate::host::{self, ByteString, HeaderMap, StreamInfo};
 
 pub(super) struct Host;
 
@@ -42,12 +43,20 @@ impl RequestHeadersOps for Host {
     fn remove_request_header(&self, name: &str) -> host::Result<()> {
         hostcalls::set_map_value(MapType::HttpRequestHeaders, name, None::<&[u8]>)
     }
+
+    fn clear_route_cache(&self) -> host::Result<()> {
+        hostcalls::clear_route_cache()
+    }
 }
 
 impl RequestBodyOps for Host {
     fn request_data(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
         hostcalls::get_buffer(BufferType::HttpRequestBody, start, max_size)
     }
+
+    fn set_request_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        hostcalls::set_buffer(BufferType::HttpRequestBody, start, size, data)
+    }
 }
 
 impl RequestTrailersOps for Host {
@@ -98,6 +107,10 @@ impl ResponseBodyOps for Host {
     fn response_data(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
         hostcalls::get_buffer(BufferType::HttpResponseBody, start, max_size)
     }
+
+    fn set_response_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        hostcalls::set_buffer(BufferType::HttpResponseBody, start, size, data)
+    }
 }
 
 impl ResponseTrailersOps for Host {
@@ -135,6 +148,15 @@ impl RequestFlowOps for Host {
     ) -> host::Result<()> {
         hostcalls::send_http_response(status_code, headers, body)
     }
+
+    fn send_grpc_response(
+        &self,
+        grpc_status: GrpcStatus,
+        grpc_message: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> host::Result<()> {
+        hostcalls::send_grpc_response(grpc_status.as_code(), grpc_message, headers)
+    }
 }
 
 impl ResponseFlowOps for Host {
@@ -143,4 +165,40 @@ impl ResponseFlowOps for Host {
     }
 }
 
-impl ExchangeCompleteOps for Host {}
+impl ExchangeCompleteOps for Host {
+    fn request_headers(&self) -> host::Result<HeaderMap> {
+        hostcalls::get_map(MapType::HttpRequestHeaders)
+    }
+
+    fn request_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        hostcalls::get_map_value(MapType::HttpRequestHeaders, name)
+    }
+
+    fn request_trailers(&self) -> host::Result<HeaderMap> {
+        hostcalls::get_map(MapType::HttpRequestTrailers)
+    }
+
+    fn request_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        hostcalls::get_map_value(MapType::HttpRequestTrailers, name)
+    }
+
+    fn response_headers(&self) -> host::Result<HeaderMap> {
+        hostcalls::get_map(MapType::HttpResponseHeaders)
+    }
+
+    fn response_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        hostcalls::get_map_value(MapType::HttpResponseHeaders, name)
+    }
+
+    fn response_trailers(&self) -> host::Result<HeaderMap> {
+        hostcalls::get_map(MapType::HttpResponseTrailers)
+    }
+
+    fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        hostcalls::get_map_value(MapType::HttpResponseTrailers, name)
+    }
+
+    fn stream_info(&self) -> &dyn StreamInfo {
+        <dyn StreamInfo>::default()
+    }
+}
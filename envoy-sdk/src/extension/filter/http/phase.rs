@@ -0,0 +1,688 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime checks that `HTTP Filter` operations are called at a proper point
+//! of the HTTP stream lifecycle.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use super::grpc::GrpcStatus;
+use super::{
+    ExchangeCompleteOps, Ops, RequestBodyOps, RequestFlowOps, RequestHeadersOps,
+    RequestTrailersOps, ResponseBodyOps, ResponseFlowOps, ResponseHeadersOps, ResponseTrailersOps,
+};
+use crate::extension::error::PhaseError;
+use crate::extension::filter::PHASE_CHECKS_ENABLED;
+use crate::host::{self, ByteString, HeaderMap, StreamInfo};
+
+/// Phase of the HTTP stream lifecycle, i.e. the filter callback being executed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(super) enum Phase {
+    RequestHeaders,
+    RequestBody,
+    RequestTrailers,
+    ResponseHeaders,
+    ResponseBody,
+    ResponseTrailers,
+    HttpCallResponse,
+    ExchangeComplete,
+}
+
+impl Phase {
+    fn name(&self) -> &'static str {
+        use Phase::*;
+        match self {
+            RequestHeaders => "on_request_headers",
+            RequestBody => "on_request_body",
+            RequestTrailers => "on_request_trailers",
+            ResponseHeaders => "on_response_headers",
+            ResponseBody => "on_response_body",
+            ResponseTrailers => "on_response_trailers",
+            HttpCallResponse => "on_http_call_response",
+            ExchangeComplete => "on_exchange_complete",
+        }
+    }
+
+    /// Returns the direction of the stream a callback belongs to and its position
+    /// within that direction.
+    fn position(&self) -> Option<(Direction, u8)> {
+        use Phase::*;
+        match self {
+            RequestHeaders => Some((Direction::Request, 0)),
+            RequestBody => Some((Direction::Request, 1)),
+            RequestTrailers => Some((Direction::Request, 2)),
+            ResponseHeaders => Some((Direction::Response, 0)),
+            ResponseBody => Some((Direction::Response, 1)),
+            ResponseTrailers => Some((Direction::Response, 2)),
+            HttpCallResponse | ExchangeComplete => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Direction {
+    Request,
+    Response,
+}
+
+/// Decorates [`Ops`] with checks of the current phase of the HTTP stream lifecycle.
+///
+/// Checks are only performed if [`PHASE_CHECKS_ENABLED`], otherwise all calls go straight
+/// to the underlying [`Ops`].
+///
+/// [`Ops`]: ../trait.Ops.html
+/// [`PHASE_CHECKS_ENABLED`]: ../../constant.PHASE_CHECKS_ENABLED.html
+pub(super) struct PhaseCheckedOps<'a> {
+    ops: &'a dyn Ops,
+    state: Rc<PhaseState>,
+}
+
+/// State of the HTTP stream lifecycle tracked by [`PhaseCheckedOps`].
+///
+/// Shared by all [`PhaseCheckedOps`] of the same `HTTP Filter` instance, e.g. the ones
+/// used to resume an instance that waits for a callout started by another instance.
+///
+/// [`PhaseCheckedOps`]: struct.PhaseCheckedOps.html
+#[derive(Default)]
+pub(super) struct PhaseState {
+    phase: Cell<Option<Phase>>,
+    request_paused_in: Cell<Option<Phase>>,
+    response_paused_in: Cell<Option<Phase>>,
+    local_reply_sent: Cell<bool>,
+}
+
+impl<'a> PhaseCheckedOps<'a> {
+    pub fn new(ops: &'a dyn Ops) -> Self {
+        Self::with_state(ops, Rc::default())
+    }
+
+    /// Creates phase checks that share a given state of the HTTP stream lifecycle.
+    pub fn with_state(ops: &'a dyn Ops, state: Rc<PhaseState>) -> Self {
+        PhaseCheckedOps { ops, state }
+    }
+
+    /// Returns the state of the HTTP stream lifecycle.
+    pub fn state(&self) -> &Rc<PhaseState> {
+        &self.state
+    }
+
+    /// Returns the underlying [`Ops`] that are not subject to phase checks.
+    ///
+    /// [`Ops`]: ../trait.Ops.html
+    pub fn unchecked(&self) -> &'a dyn Ops {
+        self.ops
+    }
+
+    /// Marks the beginning of a filter callback.
+    pub fn enter(&self, phase: Phase) {
+        self.state.phase.set(Some(phase));
+    }
+
+    /// Calls a given function in a given phase and then restores the current phase.
+    ///
+    /// E.g., an `HTTP Filter` instance that waits for a callout started by another instance
+    /// is resumed in `on_http_call_response` of that other instance.
+    pub fn within<R, F>(&self, phase: Phase, f: F) -> R
+    where
+        F: FnOnce(&Self) -> R,
+    {
+        let current = self.state.phase.replace(Some(phase));
+        let result = f(self);
+        self.state.phase.set(current);
+        result
+    }
+
+    /// Records whether a request callback has stopped filter chain iteration.
+    pub fn set_request_paused(&self, phase: Phase, paused: bool) {
+        self.state
+            .request_paused_in
+            .set(if paused { Some(phase) } else { None });
+    }
+
+    /// Records whether a response callback has stopped filter chain iteration.
+    pub fn set_response_paused(&self, phase: Phase, paused: bool) {
+        self.state
+            .response_paused_in
+            .set(if paused { Some(phase) } else { None });
+    }
+
+    fn current_phase(&self) -> &'static str {
+        self.state
+            .phase
+            .get()
+            .map(|phase| phase.name())
+            .unwrap_or("<none>")
+    }
+
+    fn check<F>(&self, op: &'static str, allowed: bool, describe: F) -> host::Result<()>
+    where
+        F: FnOnce() -> String,
+    {
+        if !PHASE_CHECKS_ENABLED || allowed {
+            Ok(())
+        } else {
+            Err(PhaseError::new(op, self.current_phase(), describe()).into())
+        }
+    }
+
+    /// Returns `true` if a given stage can be modified, i.e. if either its callback is being
+    /// executed or filter chain iteration has been stopped in that stage or a later stage
+    /// of the same direction and has not been resumed since.
+    ///
+    /// E.g., request headers can still be modified in `on_request_body`
+    /// if `on_request_headers` has stopped iteration.
+    fn is_modifiable(&self, stage: Phase, paused_in: Option<Phase>) -> bool {
+        let phase = self.state.phase.get();
+        if phase == Some(stage) {
+            return true;
+        }
+        let (direction, position) = match stage.position() {
+            Some(position) => position,
+            None => return false,
+        };
+        let is_paused = match paused_in.and_then(|phase| phase.position()) {
+            Some((paused_direction, paused_position)) => {
+                paused_direction == direction && paused_position >= position
+            }
+            None => false,
+        };
+        let is_same_direction = match phase.and_then(|phase| phase.position()) {
+            Some((current_direction, _)) => current_direction == direction,
+            None => phase == Some(Phase::HttpCallResponse),
+        };
+        is_paused && is_same_direction
+    }
+
+    /// Checks that a given request stage can be modified.
+    fn check_request(&self, op: &'static str, stage: Phase) -> host::Result<()> {
+        let allowed = self.is_modifiable(stage, self.state.request_paused_in.get());
+        self.check(op, allowed, || {
+            format!(
+                "it is only allowed in `{0}` or while the request is paused in `{0}` or a later request callback",
+                stage.name()
+            )
+        })
+    }
+
+    /// Checks that a given response stage can be modified.
+    fn check_response(&self, op: &'static str, stage: Phase) -> host::Result<()> {
+        let allowed = self.is_modifiable(stage, self.state.response_paused_in.get());
+        self.check(op, allowed, || {
+            format!(
+                "it is only allowed in `{0}` or while the response is paused in `{0}` or a later response callback",
+                stage.name()
+            )
+        })
+    }
+
+    fn check_local_reply(&self, op: &'static str) -> host::Result<()> {
+        if self.state.phase.get() == Some(Phase::ExchangeComplete) {
+            return self.check(op, false, || {
+                "HTTP stream has already been completed".to_owned()
+            });
+        }
+        self.check(op, !self.state.local_reply_sent.get(), || {
+            "a response has already been sent by the filter".to_owned()
+        })
+    }
+}
+
+impl<'a> RequestHeadersOps for PhaseCheckedOps<'a> {
+    fn request_headers(&self) -> host::Result<HeaderMap> {
+        self.ops.request_headers()
+    }
+
+    fn request_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.request_header(name)
+    }
+
+    fn set_request_headers(&self, headers: &HeaderMap) -> host::Result<()> {
+        self.check_request("set_request_headers", Phase::RequestHeaders)?;
+        self.ops.set_request_headers(headers)
+    }
+
+    fn set_request_header(&self, name: &str, value: &str) -> host::Result<()> {
+        self.check_request("set_request_header", Phase::RequestHeaders)?;
+        self.ops.set_request_header(name, value)
+    }
+
+    fn set_request_header_bytes(&self, name: &str, value: &[u8]) -> host::Result<()> {
+        self.check_request("set_request_header_bytes", Phase::RequestHeaders)?;
+        self.ops.set_request_header_bytes(name, value)
+    }
+
+    fn remove_request_header(&self, name: &str) -> host::Result<()> {
+        self.check_request("remove_request_header", Phase::RequestHeaders)?;
+        self.ops.remove_request_header(name)
+    }
+
+    fn clear_route_cache(&self) -> host::Result<()> {
+        self.check_request("clear_route_cache", Phase::RequestHeaders)?;
+        self.ops.clear_route_cache()
+    }
+
+    fn set_upstream_cluster(&self, cluster_header: &str, cluster: &str) -> host::Result<()> {
+        self.check_request("set_upstream_cluster", Phase::RequestHeaders)?;
+        self.ops.set_upstream_cluster(cluster_header, cluster)
+    }
+}
+
+impl<'a> RequestBodyOps for PhaseCheckedOps<'a> {
+    fn request_data(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
+        self.check_request("request_data", Phase::RequestBody)?;
+        self.ops.request_data(start, max_size)
+    }
+
+    fn set_request_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        self.check_request("set_request_data", Phase::RequestBody)?;
+        self.ops.set_request_data(start, size, data)
+    }
+}
+
+impl<'a> RequestTrailersOps for PhaseCheckedOps<'a> {
+    fn request_trailers(&self) -> host::Result<HeaderMap> {
+        self.ops.request_trailers()
+    }
+
+    fn request_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.request_trailer(name)
+    }
+
+    fn set_request_trailers(&self, trailers: &HeaderMap) -> host::Result<()> {
+        self.check_request("set_request_trailers", Phase::RequestTrailers)?;
+        self.ops.set_request_trailers(trailers)
+    }
+
+    fn set_request_trailer(&self, name: &str, value: &str) -> host::Result<()> {
+        self.check_request("set_request_trailer", Phase::RequestTrailers)?;
+        self.ops.set_request_trailer(name, value)
+    }
+
+    fn set_request_trailer_bytes(&self, name: &str, value: &[u8]) -> host::Result<()> {
+        self.check_request("set_request_trailer_bytes", Phase::RequestTrailers)?;
+        self.ops.set_request_trailer_bytes(name, value)
+    }
+
+    fn remove_request_trailer(&self, name: &str) -> host::Result<()> {
+        self.check_request("remove_request_trailer", Phase::RequestTrailers)?;
+        self.ops.remove_request_trailer(name)
+    }
+}
+
+impl<'a> RequestFlowOps for PhaseCheckedOps<'a> {
+    fn resume_request(&self) -> host::Result<()> {
+        self.check(
+            "resume_request",
+            self.state.request_paused_in.get().is_some(),
+            || "request is not paused; it is only allowed after a request callback has stopped filter chain iteration".to_owned(),
+        )?;
+        self.ops.resume_request()?;
+        self.state.request_paused_in.set(None);
+        Ok(())
+    }
+
+    fn send_response(
+        &self,
+        status_code: u32,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> host::Result<()> {
+        self.check_local_reply("send_response")?;
+        self.ops.send_response(status_code, headers, body)?;
+        self.state.local_reply_sent.set(true);
+        Ok(())
+    }
+
+    fn send_grpc_response(
+        &self,
+        grpc_status: GrpcStatus,
+        grpc_message: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> host::Result<()> {
+        self.check_local_reply("send_grpc_response")?;
+        self.ops
+            .send_grpc_response(grpc_status, grpc_message, headers)?;
+        self.state.local_reply_sent.set(true);
+        Ok(())
+    }
+}
+
+impl<'a> ResponseHeadersOps for PhaseCheckedOps<'a> {
+    fn response_headers(&self) -> host::Result<HeaderMap> {
+        self.ops.response_headers()
+    }
+
+    fn response_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.response_header(name)
+    }
+
+    fn set_response_headers(&self, headers: &HeaderMap) -> host::Result<()> {
+        self.check_response("set_response_headers", Phase::ResponseHeaders)?;
+        self.ops.set_response_headers(headers)
+    }
+
+    fn set_response_header(&self, name: &str, value: &str) -> host::Result<()> {
+        self.check_response("set_response_header", Phase::ResponseHeaders)?;
+        self.ops.set_response_header(name, value)
+    }
+
+    fn set_response_header_bytes(&self, name: &str, value: &[u8]) -> host::Result<()> {
+        self.check_response("set_response_header_bytes", Phase::ResponseHeaders)?;
+        self.ops.set_response_header_bytes(name, value)
+    }
+
+    fn remove_response_header(&self, name: &str) -> host::Result<()> {
+        self.check_response("remove_response_header", Phase::ResponseHeaders)?;
+        self.ops.remove_response_header(name)
+    }
+}
+
+impl<'a> ResponseBodyOps for PhaseCheckedOps<'a> {
+    fn response_data(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
+        self.check_response("response_data", Phase::ResponseBody)?;
+        self.ops.response_data(start, max_size)
+    }
+
+    fn set_response_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        self.check_response("set_response_data", Phase::ResponseBody)?;
+        self.ops.set_response_data(start, size, data)
+    }
+}
+
+impl<'a> ResponseTrailersOps for PhaseCheckedOps<'a> {
+    fn response_trailers(&self) -> host::Result<HeaderMap> {
+        self.ops.response_trailers()
+    }
+
+    fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.response_trailer(name)
+    }
+
+    fn set_response_trailers(&self, trailers: &HeaderMap) -> host::Result<()> {
+        self.check_response("set_response_trailers", Phase::ResponseTrailers)?;
+        self.ops.set_response_trailers(trailers)
+    }
+
+    fn set_response_trailer(&self, name: &str, value: &str) -> host::Result<()> {
+        self.check_response("set_response_trailer", Phase::ResponseTrailers)?;
+        self.ops.set_response_trailer(name, value)
+    }
+
+    fn set_response_trailer_bytes(&self, name: &str, value: &[u8]) -> host::Result<()> {
+        self.check_response("set_response_trailer_bytes", Phase::ResponseTrailers)?;
+        self.ops.set_response_trailer_bytes(name, value)
+    }
+
+    fn remove_response_trailer(&self, name: &str) -> host::Result<()> {
+        self.check_response("remove_response_trailer", Phase::ResponseTrailers)?;
+        self.ops.remove_response_trailer(name)
+    }
+}
+
+impl<'a> ResponseFlowOps for PhaseCheckedOps<'a> {
+    fn resume_response(&self) -> host::Result<()> {
+        self.check(
+            "resume_response",
+            self.state.response_paused_in.get().is_some(),
+            || "response is not paused; it is only allowed after a response callback has stopped filter chain iteration".to_owned(),
+        )?;
+        self.ops.resume_response()?;
+        self.state.response_paused_in.set(None);
+        Ok(())
+    }
+}
+
+impl<'a> ExchangeCompleteOps for PhaseCheckedOps<'a> {
+    fn request_headers(&self) -> host::Result<HeaderMap> {
+        self.ops.as_exchange_complete_ops().request_headers()
+    }
+
+    fn request_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.as_exchange_complete_ops().request_header(name)
+    }
+
+    fn request_trailers(&self) -> host::Result<HeaderMap> {
+        self.ops.as_exchange_complete_ops().request_trailers()
+    }
+
+    fn request_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.as_exchange_complete_ops().request_trailer(name)
+    }
+
+    fn response_headers(&self) -> host::Result<HeaderMap> {
+        self.ops.as_exchange_complete_ops().response_headers()
+    }
+
+    fn response_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.as_exchange_complete_ops().response_header(name)
+    }
+
+    fn response_trailers(&self) -> host::Result<HeaderMap> {
+        self.ops.as_exchange_complete_ops().response_trailers()
+    }
+
+    fn response_trailer(&self, name: &str) -> host::Result<Option<ByteString>> {
+        self.ops.as_exchange_complete_ops().response_trailer(name)
+    }
+
+    fn stream_info(&self) -> &dyn StreamInfo {
+        self.ops.as_exchange_complete_ops().stream_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::FakeOps;
+    use super::*;
+
+    fn is_phase_error(result: host::Result<()>) -> bool {
+        match result {
+            Err(err) => err.downcast_ref::<PhaseError>().is_some(),
+            Ok(()) => false,
+        }
+    }
+
+    #[test]
+    fn test_phase_check_current_phase() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestHeaders);
+        assert!(ops.set_request_header("x-key", "value").is_ok());
+        ops.enter(Phase::ResponseHeaders);
+        assert!(ops.set_response_header("x-key", "value").is_ok());
+
+        assert_eq!(
+            fake.calls(),
+            vec!["set_request_header_bytes", "set_response_header_bytes"]
+        );
+    }
+
+    #[test]
+    fn test_phase_check_other_direction() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestBody);
+        let err = ops.set_response_header("x-key", "value").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "operation `set_response_header` is not allowed in `on_request_body`: it is only allowed in `on_response_headers` or while the response is paused in `on_response_headers` or a later response callback",
+        );
+        assert!(fake.calls().is_empty());
+    }
+
+    #[test]
+    fn test_phase_check_request_headers_after_iteration_continued() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestHeaders);
+        ops.set_request_paused(Phase::RequestHeaders, false);
+        ops.enter(Phase::RequestBody);
+
+        assert!(is_phase_error(ops.set_request_header("x-key", "value")));
+        assert!(is_phase_error(ops.clear_route_cache()));
+    }
+
+    #[test]
+    fn test_phase_check_request_headers_while_paused_in_later_request_phases() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestHeaders);
+        ops.set_request_paused(Phase::RequestHeaders, true);
+
+        // e.g. to fix `content-length` after buffering the body
+        ops.enter(Phase::RequestBody);
+        assert!(ops.set_request_header("content-length", "42").is_ok());
+        assert!(ops.set_request_data(0, 0, b"").is_ok());
+        ops.set_request_paused(Phase::RequestBody, true);
+
+        ops.enter(Phase::RequestTrailers);
+        assert!(ops.remove_request_header("content-length").is_ok());
+        assert!(ops.request_data(0, 1).is_ok());
+        assert!(ops.set_request_trailer("x-key", "value").is_ok());
+
+        ops.enter(Phase::HttpCallResponse);
+        assert!(ops.set_request_header("x-key", "value").is_ok());
+
+        assert_eq!(fake.calls().len(), 6);
+    }
+
+    #[test]
+    fn test_phase_check_request_body_while_paused_in_request_headers() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestHeaders);
+        ops.set_request_paused(Phase::RequestHeaders, true);
+        ops.enter(Phase::HttpCallResponse);
+
+        assert!(is_phase_error(ops.set_request_data(0, 0, b"")));
+    }
+
+    #[test]
+    fn test_phase_check_request_headers_in_response_phases() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestHeaders);
+        ops.set_request_paused(Phase::RequestHeaders, true);
+        ops.enter(Phase::ResponseHeaders);
+
+        assert!(is_phase_error(ops.set_request_header("x-key", "value")));
+    }
+
+    #[test]
+    fn test_phase_check_response_headers_while_paused_in_later_response_phases() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::ResponseHeaders);
+        ops.set_response_paused(Phase::ResponseHeaders, true);
+        ops.enter(Phase::ResponseBody);
+        assert!(ops.set_response_header("content-length", "42").is_ok());
+        ops.set_response_paused(Phase::ResponseBody, true);
+        ops.enter(Phase::ResponseTrailers);
+        assert!(ops.set_response_header("x-key", "value").is_ok());
+        assert!(ops.response_data(0, 1).is_ok());
+
+        ops.set_response_paused(Phase::ResponseTrailers, false);
+        assert!(ops.resume_response().is_err());
+        ops.enter(Phase::HttpCallResponse);
+        assert!(is_phase_error(ops.set_response_header("x-key", "value")));
+    }
+
+    #[test]
+    fn test_phase_check_resume_request_once() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestHeaders);
+        ops.set_request_paused(Phase::RequestHeaders, true);
+        ops.enter(Phase::HttpCallResponse);
+
+        assert!(ops.resume_request().is_ok());
+        assert!(is_phase_error(ops.resume_request()));
+        assert!(is_phase_error(ops.set_request_header("x-key", "value")));
+        assert_eq!(fake.calls(), vec!["resume_request"]);
+    }
+
+    #[test]
+    fn test_phase_check_local_reply_once() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::RequestHeaders);
+        assert!(ops.send_response(403, &[], None).is_ok());
+        assert!(is_phase_error(ops.send_grpc_response(
+            GrpcStatus::PermissionDenied,
+            None,
+            &[]
+        )));
+
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+        ops.enter(Phase::ExchangeComplete);
+        assert!(is_phase_error(ops.send_response(500, &[], None)));
+    }
+
+    #[test]
+    fn test_phase_check_reads() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::ResponseTrailers);
+        assert!(ops.as_request_headers_ops().request_header("x-key").is_ok());
+        assert!(ops.as_response_trailers_ops().response_trailers().is_ok());
+    }
+
+    #[test]
+    fn test_phase_check_header_reads_on_dyn_ops() {
+        let fake = FakeOps::default();
+        let checked = PhaseCheckedOps::new(&fake);
+        let ops: &dyn Ops = &checked;
+
+        checked.enter(Phase::RequestHeaders);
+        assert!(ops.request_header("x-key").is_ok());
+        assert!(ops.response_trailers().is_ok());
+
+        assert_eq!(fake.calls(), vec!["request_header", "response_trailers"]);
+    }
+
+    #[test]
+    fn test_phase_check_exchange_complete_ops() {
+        let fake = FakeOps::default();
+        let checked = PhaseCheckedOps::new(&fake);
+        let ops: &dyn Ops = &checked;
+
+        checked.enter(Phase::ExchangeComplete);
+        let ops = ops.as_exchange_complete_ops();
+        assert!(ops.request_header("x-key").is_ok());
+        assert!(ops.response_headers().is_ok());
+        assert!(ops.response_trailer("x-key").is_ok());
+
+        assert_eq!(
+            fake.calls(),
+            vec![
+                "exchange_complete.request_header",
+                "exchange_complete.response_headers",
+                "exchange_complete.response_trailer",
+            ]
+        );
+    }
+}
@@ -0,0 +1,392 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local rate limiting shared between `Envoy` worker threads.
+//!
+//! Every `Envoy` worker thread runs its own instance of an `HTTP Filter`, so state kept
+//! in memory of a filter only limits requests handled by that thread.
+//! [`RateLimiter`] keeps state in [`SharedData`] instead, so that the limit applies to
+//! all requests handled by the `Envoy` instance.
+//!
+//! Requests are limited per descriptor, e.g. per client IP or per value of a header,
+//! see [`RateLimitKey`].
+//!
+//! # Examples
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use std::time::Duration;
+//!
+//! use envoy::extension::filter::http::{FilterHeadersStatus, HttpFilter, RequestHeadersOps};
+//! use envoy::extension::filter::http::ratelimit::{RateLimitKey, RateLimitPolicy, RateLimiter, TokenBucket};
+//! use envoy::extension::Result;
+//! use envoy::host::{Clock, SharedData, StreamInfo};
+//!
+//! struct MyHttpFilter<'a> {
+//!     limiter: RateLimiter<'a>,
+//!     key: RateLimitKey,
+//!     stream_info: &'a dyn StreamInfo,
+//! }
+//!
+//! impl<'a> MyHttpFilter<'a> {
+//!     fn new(shared_data: &'a dyn SharedData, clock: &'a dyn Clock, stream_info: &'a dyn StreamInfo) -> Self {
+//!         let policy = RateLimitPolicy::TokenBucket(TokenBucket {
+//!             max_tokens: 100,
+//!             tokens_per_fill: 10,
+//!             fill_interval: Duration::from_secs(1),
+//!         });
+//!         MyHttpFilter {
+//!             limiter: RateLimiter::new("my_http_filter.per_tenant", policy, shared_data, clock),
+//!             // tenant is set by an authentication filter earlier in the chain,
+//!             // so there are only as many descriptors as there are tenants
+//!             key: RateLimitKey::Header("x-tenant-id".into()),
+//!             stream_info,
+//!         }
+//!     }
+//! }
+//!
+//! impl<'a> HttpFilter for MyHttpFilter<'a> {
+//!     fn on_request_headers(&mut self, _num_headers: usize, _end_of_stream: bool, ops: &dyn RequestHeadersOps) -> Result<FilterHeadersStatus> {
+//!         if let Some(descriptor) = self.key.descriptor(ops, self.stream_info)? {
+//!             let decision = self.limiter.check(&descriptor)?;
+//!             if !decision.is_allowed() {
+//!                 let retry_after = decision.reset_after().as_secs().max(1).to_string();
+//!                 ops.send_response(429, &[("retry-after", &retry_after)], None)?;
+//!                 return Ok(FilterHeadersStatus::StopIteration);
+//!             }
+//!         }
+//!         Ok(FilterHeadersStatus::Continue)
+//!     }
+//! }
+//! ```
+//!
+//! [`RateLimiter`]: struct.RateLimiter.html
+//! [`RateLimitKey`]: enum.RateLimitKey.html
+//! [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::RequestHeadersOps;
+use crate::host::error::is_cas_mismatch;
+use crate::host::shared_data::OptimisticLockVersion;
+use crate::host::time::{nanos, unix_nanos};
+use crate::host::{self, Clock, SharedData, StreamInfo};
+
+/// Token bucket algorithm.
+///
+/// The bucket starts full. Every request takes a token out of the bucket,
+/// requests that find the bucket empty are limited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenBucket {
+    /// Capacity of the bucket.
+    pub max_tokens: u64,
+    /// Number of tokens added to the bucket every `fill_interval`.
+    pub tokens_per_fill: u64,
+    /// Interval between refills.
+    pub fill_interval: Duration,
+}
+
+/// Sliding window algorithm.
+///
+/// The number of requests in the last `window` is estimated from the counts of
+/// the current and the previous fixed windows, weighted by their overlap with the
+/// sliding one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlidingWindow {
+    /// Max number of requests per window.
+    pub limit: u64,
+    /// Duration of the window.
+    pub window: Duration,
+}
+
+/// Rate limiting algorithm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RateLimitPolicy {
+    TokenBucket(TokenBucket),
+    SlidingWindow(SlidingWindow),
+}
+
+/// Source of the descriptor of a request.
+///
+/// State of every descriptor stays in [`SharedData`] for the lifetime of the VM,
+/// so the key must only produce a bounded number of distinct descriptors.
+///
+/// [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RateLimitKey {
+    /// IP address of the downstream client.
+    ///
+    /// **WARNING**: cardinality is unbounded, every new client adds an entry to [`SharedData`].
+    /// Only use it if clients come from a small known set of addresses, e.g. internal services.
+    ///
+    /// [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+    ClientIp,
+    /// Value of a request header.
+    ///
+    /// Cardinality is bounded only if the header takes a bounded set of values,
+    /// e.g. if it is set by a trusted component rather than by the client.
+    Header(String),
+}
+
+impl RateLimitKey {
+    /// Returns the descriptor of a request, if any.
+    pub fn descriptor(
+        &self,
+        ops: &dyn RequestHeadersOps,
+        stream_info: &dyn StreamInfo,
+    ) -> host::Result<Option<String>> {
+        Ok(match self {
+            RateLimitKey::ClientIp => stream_info.source().address()?.map(|address| match address
+                .parse::<SocketAddr>(
+            ) {
+                Ok(address) => address.ip().to_string(),
+                Err(_) => address,
+            }),
+            RateLimitKey::Header(name) => ops.request_header(name)?.map(|value| value.to_string()),
+        })
+    }
+}
+
+/// Outcome of a rate limit check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    allowed: bool,
+    remaining: u64,
+    reset_after: Duration,
+}
+
+impl RateLimitDecision {
+    /// Returns `true` if the request is within the limit.
+    pub fn is_allowed(&self) -> bool {
+        self.allowed
+    }
+
+    /// Returns the number of requests that are still allowed right away.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Returns the time until more requests get allowed.
+    pub fn reset_after(&self) -> Duration {
+        self.reset_after
+    }
+}
+
+/// Rate limiter that keeps its state in [`SharedData`].
+///
+/// State of every descriptor is kept under the key `<name>.<descriptor>`.
+/// Since `Envoy` does not support removal of shared data, avoid descriptors
+/// with unbounded cardinality.
+///
+/// Concurrent updates from other worker threads are detected by means of
+/// [`OptimisticLockVersion`] and the check gets retried.
+///
+/// [`SharedData`]: ../../../../host/shared_data/trait.SharedData.html
+/// [`OptimisticLockVersion`]: ../../../../host/shared_data/type.OptimisticLockVersion.html
+pub struct RateLimiter<'a> {
+    name: String,
+    policy: RateLimitPolicy,
+    shared_data: &'a dyn SharedData,
+    clock: &'a dyn Clock,
+    max_attempts: usize,
+}
+
+impl<'a> RateLimiter<'a> {
+    /// Default limit on attempts to update the state.
+    pub const DEFAULT_MAX_ATTEMPTS: usize = 10;
+
+    /// Creates a new rate limiter.
+    ///
+    /// Rate limiters with the same name share the state.
+    pub fn new(
+        name: &str,
+        policy: RateLimitPolicy,
+        shared_data: &'a dyn SharedData,
+        clock: &'a dyn Clock,
+    ) -> Self {
+        RateLimiter {
+            name: name.to_owned(),
+            policy,
+            shared_data,
+            clock,
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Limits the number of attempts to update the state under contention.
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Returns the policy of the rate limiter.
+    pub fn policy(&self) -> &RateLimitPolicy {
+        &self.policy
+    }
+
+    /// Accounts a request with a given descriptor.
+    ///
+    /// Limited requests do not consume the limit.
+    ///
+    /// If the state is still being modified concurrently after [`max_attempts`],
+    /// the request is allowed without consuming the limit.
+    ///
+    /// [`max_attempts`]: #method.with_max_attempts
+    pub fn check(&self, descriptor: &str) -> host::Result<RateLimitDecision> {
+        let key = format!("{}.{}", self.name, descriptor);
+        let mut attempt = 1;
+        loop {
+            let (state, version) = self.load(&key)?;
+            // read the clock after the state, so that it is less likely to be behind
+            // the state stored by another worker
+            let now = unix_nanos(self.clock.now()?);
+            let (decision, state) = match &self.policy {
+                RateLimitPolicy::TokenBucket(bucket) => bucket.check(state, now),
+                RateLimitPolicy::SlidingWindow(window) => window.check(state, now),
+            };
+            let state = match state {
+                Some(state) => state,
+                // nothing has changed
+                None => return Ok(decision),
+            };
+            match self.store(&key, state, version) {
+                Ok(()) => return Ok(decision),
+                Err(err) if is_cas_mismatch(&err) && attempt < self.max_attempts => attempt += 1,
+                // fail open rather than reject requests because of contention
+                Err(err) if is_cas_mismatch(&err) => return Ok(decision),
+                Err(err) => {
+                    return Err(
+                        err.context(format!("failed to update rate limit state of {:?}", key))
+                    )
+                }
+            }
+        }
+    }
+
+    fn load(&self, key: &str) -> host::Result<(Option<State>, Option<OptimisticLockVersion>)> {
+        let (value, version) = self.shared_data.get(key)?;
+        Ok((value.and_then(|value| State::decode(&value)), version))
+    }
+
+    fn store(
+        &self,
+        key: &str,
+        state: State,
+        version: Option<OptimisticLockVersion>,
+    ) -> host::Result<()> {
+        self.shared_data.set(key, &state.encode(), version)
+    }
+}
+
+/// Encoded state of a descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State([u64; 3]);
+
+impl State {
+    fn encode(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        if value.len() != 24 {
+            return None;
+        }
+        let mut state = [0; 3];
+        for (i, chunk) in value.chunks_exact(8).enumerate() {
+            state[i] = u64::from_le_bytes(chunk.try_into().ok()?);
+        }
+        Some(State(state))
+    }
+}
+
+impl TokenBucket {
+    /// State: number of tokens, time of the last refill.
+    ///
+    /// State stored by a worker whose clock is ahead is taken as is.
+    fn check(&self, state: Option<State>, now: u64) -> (RateLimitDecision, Option<State>) {
+        let interval = nanos(self.fill_interval).max(1);
+        let (mut tokens, mut last_fill, now) = match state {
+            Some(State([tokens, last_fill, _])) => (tokens, last_fill, now.max(last_fill)),
+            None => (self.max_tokens, now, now),
+        };
+        let fills = (now - last_fill) / interval;
+        if fills > 0 {
+            tokens = tokens
+                .saturating_add(fills.saturating_mul(self.tokens_per_fill))
+                .min(self.max_tokens);
+            last_fill += fills * interval;
+        }
+        let reset_after = Duration::from_nanos(interval - (now - last_fill));
+        if tokens == 0 {
+            let decision = RateLimitDecision {
+                allowed: false,
+                remaining: 0,
+                reset_after,
+            };
+            return (decision, None);
+        }
+        tokens -= 1;
+        let decision = RateLimitDecision {
+            allowed: true,
+            remaining: tokens,
+            reset_after,
+        };
+        (decision, Some(State([tokens, last_fill, 0])))
+    }
+}
+
+impl SlidingWindow {
+    /// State: index of the current window, count of the current window,
+    /// count of the previous window.
+    ///
+    /// State stored by a worker whose clock is ahead is taken as is.
+    fn check(&self, state: Option<State>, now: u64) -> (RateLimitDecision, Option<State>) {
+        let window = nanos(self.window).max(1);
+        let now = match state {
+            Some(State([i, _, _])) => now.max(i.saturating_mul(window)),
+            None => now,
+        };
+        let index = now / window;
+        let (current, previous) = match state {
+            Some(State([i, current, _])) if i + 1 == index => (0, current),
+            Some(State([i, current, previous])) if i == index => (current, previous),
+            _ => (0, 0),
+        };
+        let elapsed = now % window;
+        let previous_weight = (window - elapsed) as f64 / window as f64;
+        let estimate = (previous as f64 * previous_weight) as u64 + current;
+        let reset_after = Duration::from_nanos(window - elapsed);
+        if estimate >= self.limit {
+            let decision = RateLimitDecision {
+                allowed: false,
+                remaining: 0,
+                reset_after,
+            };
+            return (decision, None);
+        }
+        let decision = RateLimitDecision {
+            allowed: true,
+            remaining: self.limit - estimate - 1,
+            reset_after,
+        };
+        (decision, Some(State([index, current + 1, previous])))
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-route configuration of `HTTP Filter`s.
+//!
+//! [`ExtensionFactory::on_configure`] receives configuration that applies to all HTTP requests
+//! on a `Listener`. [`PerRouteConfig`] lets individual routes override it.
+//!
+//! Route-scoped overrides are read from the route metadata, namely from a `string` value
+//! of `filter_metadata[<filter name>].config`, e.g.
+//!
+//! ```yaml
+//! routes:
+//! - name: admin
+//!   match: { prefix: "/admin" }
+//!   route: { cluster: backend }
+//!   metadata:
+//!     filter_metadata:
+//!       my_http_filter:
+//!         config: '{"policy": "deny"}'
+//! ```
+//!
+//! Overrides are parsed and merged with the `Listener`-level configuration once per route name,
+//! and the result is cached until the route configuration changes or the extension gets
+//! re-configured.
+//!
+//! # Examples
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use std::rc::Rc;
+//! use envoy::extension::{factory, ConfigStatus, ExtensionFactory, HttpFilter, InstanceId, Result};
+//! use envoy::extension::filter::http::{FilterHeadersStatus, RequestHeadersOps};
+//! use envoy::extension::filter::http::route::{PerRouteConfig, RouteConfig};
+//! use envoy::host::{ByteString, StreamInfo};
+//!
+//! #[derive(Default)]
+//! struct MyConfig {
+//!     deny: bool,
+//! }
+//!
+//! impl RouteConfig for MyConfig {
+//!     fn merge(&self, route_config: &[u8]) -> Result<Self> {
+//!         Ok(MyConfig { deny: route_config == b"deny" })
+//!     }
+//! }
+//!
+//! struct MyHttpFilter<'a> {
+//!     config: Rc<PerRouteConfig<MyConfig>>,
+//!     stream_info: &'a dyn StreamInfo,
+//! }
+//!
+//! impl<'a> HttpFilter for MyHttpFilter<'a> {
+//!     fn on_request_headers(
+//!         &mut self,
+//!         _num_headers: usize,
+//!         _end_of_stream: bool,
+//!         filter_ops: &dyn RequestHeadersOps,
+//!     ) -> Result<FilterHeadersStatus> {
+//!         let config = self.config.get(self.stream_info)?;
+//!         if config.deny {
+//!             filter_ops.send_response(403, &[], None)?;
+//!             return Ok(FilterHeadersStatus::StopIteration);
+//!         }
+//!         Ok(FilterHeadersStatus::Continue)
+//!     }
+//! }
+//!
+//! struct MyHttpFilterFactory<'a> {
+//!     config: Rc<PerRouteConfig<MyConfig>>,
+//!     stream_info: &'a dyn StreamInfo,
+//! }
+//!
+//! impl<'a> ExtensionFactory for MyHttpFilterFactory<'a> {
+//!     type Extension = MyHttpFilter<'a>;
+//!
+//!     fn name() -> &'static str { "my_http_filter" }
+//!
+//!     fn on_configure(&mut self, config: ByteString, _ops: &dyn factory::ConfigureOps) -> Result<ConfigStatus> {
+//!         self.config.set_base(MyConfig { deny: config == "deny" });
+//!         Ok(ConfigStatus::Accepted)
+//!     }
+//!
+//!     fn new_extension(&mut self, _instance_id: InstanceId) -> Result<Self::Extension> {
+//!         Ok(MyHttpFilter {
+//!             config: Rc::clone(&self.config),
+//!             stream_info: self.stream_info,
+//!         })
+//!     }
+//! }
+//!
+//! impl<'a> MyHttpFilterFactory<'a> {
+//!     fn new(stream_info: &'a dyn StreamInfo) -> Self {
+//!         MyHttpFilterFactory {
+//!             config: Rc::new(PerRouteConfig::new(Self::name(), MyConfig::default())),
+//!             stream_info,
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! [`ExtensionFactory::on_configure`]: ../../../factory/trait.ExtensionFactory.html#method.on_configure
+//! [`PerRouteConfig`]: struct.PerRouteConfig.html
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::ErrorContext;
+use crate::extension::Result;
+use crate::host::{ByteString, StreamInfo};
+
+/// Key inside the filter namespace of the route metadata that holds route-scoped configuration.
+pub const ROUTE_CONFIG_METADATA_KEY: &str = "config";
+
+/// An interface of a configuration that can be overridden on a per-route basis.
+pub trait RouteConfig: Sized {
+    /// Returns a new configuration that is a result of applying route-scoped overrides
+    /// on top of `self`, i.e. on top of the `Listener`-level configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `route_config` - route-scoped configuration as it appears in the route metadata.
+    fn merge(&self, route_config: &[u8]) -> Result<Self>;
+}
+
+/// Cache of per-route configurations.
+///
+/// Is meant to be created by an [`ExtensionFactory`] and shared with `HTTP Filter` instances
+/// through an [`Rc`].
+///
+/// [`ExtensionFactory`]: ../../../factory/trait.ExtensionFactory.html
+/// [`Rc`]: https://doc.rust-lang.org/std/rc/struct.Rc.html
+pub struct PerRouteConfig<C> {
+    filter_name: String,
+    base: RefCell<Rc<C>>,
+    routes: RefCell<HashMap<String, CachedRouteConfig<C>>>,
+}
+
+struct CachedRouteConfig<C> {
+    source: ByteString,
+    config: Rc<C>,
+}
+
+impl<C> PerRouteConfig<C>
+where
+    C: RouteConfig,
+{
+    /// Creates a new cache of per-route configurations.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter_name` - filter namespace in the route metadata to read overrides from.
+    /// * `base`        - `Listener`-level configuration.
+    pub fn new<N>(filter_name: N, base: C) -> Self
+    where
+        N: Into<String>,
+    {
+        PerRouteConfig {
+            filter_name: filter_name.into(),
+            base: RefCell::new(Rc::new(base)),
+            routes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `Listener`-level configuration.
+    pub fn base(&self) -> Rc<C> {
+        Rc::clone(&self.base.borrow())
+    }
+
+    /// Replaces `Listener`-level configuration, e.g. when extension gets re-configured.
+    ///
+    /// All cached per-route configurations get discarded.
+    pub fn set_base(&self, base: C) {
+        self.base.replace(Rc::new(base));
+        self.routes.borrow_mut().clear();
+    }
+
+    /// Returns configuration that applies to the route of a given HTTP stream.
+    ///
+    /// If the route has no overrides, `Listener`-level configuration is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream_info` - [`StreamInfo`] of the HTTP stream.
+    ///
+    /// [`StreamInfo`]: ../../../../host/stream_info/trait.StreamInfo.html
+    pub fn get(&self, stream_info: &dyn StreamInfo) -> Result<Rc<C>> {
+        let route = stream_info.route();
+        let source = match route.metadata(&self.filter_name, ROUTE_CONFIG_METADATA_KEY)? {
+            Some(source) => source,
+            None => return Ok(self.base()),
+        };
+        let route_name = route.name()?.unwrap_or_default();
+
+        if let Some(cached) = self.routes.borrow().get(&route_name) {
+            if cached.source == source {
+                return Ok(Rc::clone(&cached.config));
+            }
+        }
+
+        let config = Rc::new(self.base.borrow().merge(&source).with_context(|| {
+            format!(
+                "failed to apply configuration of the route \"{}\"",
+                route_name
+            )
+        })?);
+        self.routes.borrow_mut().insert(
+            route_name,
+            CachedRouteConfig {
+                source,
+                config: Rc::clone(&config),
+            },
+        );
+        Ok(config)
+    }
+}
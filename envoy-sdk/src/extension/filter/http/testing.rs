@@ -0,0 +1,247 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fake [`Ops`] for unit tests of `HTTP Filter` internals.
+//!
+//! [`Ops`]: ../trait.Ops.html
+
+use std::cell::RefCell;
+
+use super::grpc::GrpcStatus;
+use super::{
+    ExchangeCompleteOps, RequestBodyOps, RequestFlowOps, RequestHeadersOps, RequestTrailersOps,
+    ResponseBodyOps, ResponseFlowOps, ResponseHeadersOps, ResponseTrailersOps,
+};
+use crate::host::http::client::HttpClientResponseOps;
+use crate::host::{self, ByteString, HeaderMap, StreamInfo};
+
+/// Records names of the operations that have been called.
+#[derive(Default)]
+pub(crate) struct FakeOps {
+    calls: RefCell<Vec<&'static str>>,
+}
+
+impl FakeOps {
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.calls.borrow().clone()
+    }
+
+    fn record<T: Default>(&self, op: &'static str) -> host::Result<T> {
+        self.calls.borrow_mut().push(op);
+        Ok(T::default())
+    }
+}
+
+impl RequestHeadersOps for FakeOps {
+    fn request_headers(&self) -> host::Result<HeaderMap> {
+        self.record("request_headers")
+    }
+
+    fn request_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("request_header")
+    }
+
+    fn set_request_headers(&self, _headers: &HeaderMap) -> host::Result<()> {
+        self.record("set_request_headers")
+    }
+
+    fn set_request_header_bytes(&self, _name: &str, _value: &[u8]) -> host::Result<()> {
+        self.record("set_request_header_bytes")
+    }
+
+    fn remove_request_header(&self, _name: &str) -> host::Result<()> {
+        self.record("remove_request_header")
+    }
+
+    fn clear_route_cache(&self) -> host::Result<()> {
+        self.record("clear_route_cache")
+    }
+}
+
+impl RequestBodyOps for FakeOps {
+    fn request_data(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+        self.record("request_data")
+    }
+
+    fn set_request_data(&self, _start: usize, _size: usize, _data: &[u8]) -> host::Result<()> {
+        self.record("set_request_data")
+    }
+}
+
+impl RequestTrailersOps for FakeOps {
+    fn request_trailers(&self) -> host::Result<HeaderMap> {
+        self.record("request_trailers")
+    }
+
+    fn request_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("request_trailer")
+    }
+
+    fn set_request_trailers(&self, _trailers: &HeaderMap) -> host::Result<()> {
+        self.record("set_request_trailers")
+    }
+
+    fn set_request_trailer_bytes(&self, _name: &str, _value: &[u8]) -> host::Result<()> {
+        self.record("set_request_trailer_bytes")
+    }
+
+    fn remove_request_trailer(&self, _name: &str) -> host::Result<()> {
+        self.record("remove_request_trailer")
+    }
+}
+
+impl RequestFlowOps for FakeOps {
+    fn resume_request(&self) -> host::Result<()> {
+        self.record("resume_request")
+    }
+
+    fn send_response(
+        &self,
+        _status_code: u32,
+        _headers: &[(&str, &str)],
+        _body: Option<&[u8]>,
+    ) -> host::Result<()> {
+        self.record("send_response")
+    }
+
+    fn send_grpc_response(
+        &self,
+        _grpc_status: GrpcStatus,
+        _grpc_message: Option<&str>,
+        _headers: &[(&str, &str)],
+    ) -> host::Result<()> {
+        self.record("send_grpc_response")
+    }
+}
+
+impl ResponseHeadersOps for FakeOps {
+    fn response_headers(&self) -> host::Result<HeaderMap> {
+        self.record("response_headers")
+    }
+
+    fn response_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("response_header")
+    }
+
+    fn set_response_headers(&self, _headers: &HeaderMap) -> host::Result<()> {
+        self.record("set_response_headers")
+    }
+
+    fn set_response_header_bytes(&self, _name: &str, _value: &[u8]) -> host::Result<()> {
+        self.record("set_response_header_bytes")
+    }
+
+    fn remove_response_header(&self, _name: &str) -> host::Result<()> {
+        self.record("remove_response_header")
+    }
+}
+
+impl ResponseBodyOps for FakeOps {
+    fn response_data(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+        self.record("response_data")
+    }
+
+    fn set_response_data(&self, _start: usize, _size: usize, _data: &[u8]) -> host::Result<()> {
+        self.record("set_response_data")
+    }
+}
+
+impl ResponseTrailersOps for FakeOps {
+    fn response_trailers(&self) -> host::Result<HeaderMap> {
+        self.record("response_trailers")
+    }
+
+    fn response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("response_trailer")
+    }
+
+    fn set_response_trailers(&self, _trailers: &HeaderMap) -> host::Result<()> {
+        self.record("set_response_trailers")
+    }
+
+    fn set_response_trailer_bytes(&self, _name: &str, _value: &[u8]) -> host::Result<()> {
+        self.record("set_response_trailer_bytes")
+    }
+
+    fn remove_response_trailer(&self, _name: &str) -> host::Result<()> {
+        self.record("remove_response_trailer")
+    }
+}
+
+impl ResponseFlowOps for FakeOps {
+    fn resume_response(&self) -> host::Result<()> {
+        self.record("resume_response")
+    }
+}
+
+impl ExchangeCompleteOps for FakeOps {
+    fn request_headers(&self) -> host::Result<HeaderMap> {
+        self.record("exchange_complete.request_headers")
+    }
+
+    fn request_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("exchange_complete.request_header")
+    }
+
+    fn request_trailers(&self) -> host::Result<HeaderMap> {
+        self.record("exchange_complete.request_trailers")
+    }
+
+    fn request_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("exchange_complete.request_trailer")
+    }
+
+    fn response_headers(&self) -> host::Result<HeaderMap> {
+        self.record("exchange_complete.response_headers")
+    }
+
+    fn response_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("exchange_complete.response_header")
+    }
+
+    fn response_trailers(&self) -> host::Result<HeaderMap> {
+        self.record("exchange_complete.response_trailers")
+    }
+
+    fn response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("exchange_complete.response_trailer")
+    }
+
+    fn stream_info(&self) -> &dyn StreamInfo {
+        // referring to the default implementation would link `Envoy` host functions
+        unimplemented!("StreamInfo is not available in unit tests of HTTP Filter internals")
+    }
+}
+
+impl HttpClientResponseOps for FakeOps {
+    fn http_call_response_headers(&self) -> host::Result<HeaderMap> {
+        self.record("http_call_response_headers")
+    }
+
+    fn http_call_response_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("http_call_response_header")
+    }
+
+    fn http_call_response_body(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+        self.record("http_call_response_body")
+    }
+
+    fn http_call_response_trailers(&self) -> host::Result<HeaderMap> {
+        self.record("http_call_response_trailers")
+    }
+
+    fn http_call_response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("http_call_response_trailer")
+    }
+}
@@ -16,3 +16,7 @@
 
 pub mod http;
 pub mod network;
+
+/// Whether filter operations should be checked against the current phase
+/// of the stream lifecycle before making a call to `Envoy` ABI.
+pub(crate) const PHASE_CHECKS_ENABLED: bool = cfg!(any(debug_assertions, feature = "phase-checks"));
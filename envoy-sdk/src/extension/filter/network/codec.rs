@@ -0,0 +1,437 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Framing of TCP protocols for `Network Filter`s.
+//!
+//! [`NetworkFilter`] receives data in chunks of arbitrary size. A chunk might contain
+//! several protocol frames, only a part of a frame, or both.
+//!
+//! [`Codec`] describes how to split a stream of bytes into frames, and [`FramedNetworkFilter`]
+//! turns a frame-oriented [`FrameHandler`] into a [`NetworkFilter`] by taking care of
+//! * holding back incomplete frames until the rest of their data arrives,
+//! * passing unmodified frames through as they are,
+//! * re-encoding frames the handler has modified and removing frames the handler has dropped,
+//! * passing through trailing bytes that don't make up a complete frame at the end of stream.
+//!
+//! # Examples
+//!
+//! #### Line-based protocol:
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use envoy::extension::{NetworkFilter, Result};
+//! use envoy::extension::filter::network::ConnectionFlowOps;
+//! use envoy::extension::filter::network::codec::{Codec, FrameHandler, FrameStatus, FramedNetworkFilter};
+//! use envoy::error::bail;
+//!
+//! /// Splits data into `\n`-terminated lines.
+//! struct LineCodec;
+//!
+//! impl Codec for LineCodec {
+//!     type Frame = String;
+//!
+//!     fn decode(&mut self, data: &[u8]) -> Result<Option<(Self::Frame, usize)>> {
+//!         match data.iter().position(|&b| b == b'\n') {
+//!             Some(pos) => Ok(Some((String::from_utf8(data[..pos].to_vec())?, pos + 1))),
+//!             None if data.len() > 1024 => bail!("line is too long"),
+//!             None => Ok(None),
+//!         }
+//!     }
+//!
+//!     fn encode(&mut self, frame: &Self::Frame, buf: &mut Vec<u8>) -> Result<()> {
+//!         buf.extend_from_slice(frame.as_bytes());
+//!         buf.push(b'\n');
+//!         Ok(())
+//!     }
+//! }
+//!
+//! /// Hides secrets sent by the client and drops comments.
+//! struct MyHandler;
+//!
+//! impl FrameHandler for MyHandler {
+//!     type DownstreamCodec = LineCodec;
+//!     type UpstreamCodec = LineCodec;
+//!
+//!     fn on_downstream_frame(&mut self, line: &mut String, _ops: &dyn ConnectionFlowOps) -> Result<FrameStatus> {
+//!         if line.starts_with('#') {
+//!             return Ok(FrameStatus::Drop);
+//!         }
+//!         if line.starts_with("PASSWORD ") {
+//!             *line = "PASSWORD ***".to_owned();
+//!             return Ok(FrameStatus::Modified);
+//!         }
+//!         Ok(FrameStatus::Continue)
+//!     }
+//! }
+//!
+//! fn new_filter() -> impl NetworkFilter {
+//!     FramedNetworkFilter::new(MyHandler, LineCodec, LineCodec)
+//! }
+//! ```
+//!
+//! [`NetworkFilter`]: ../trait.NetworkFilter.html
+//! [`Codec`]: trait.Codec.html
+//! [`FrameHandler`]: trait.FrameHandler.html
+//! [`FramedNetworkFilter`]: struct.FramedNetworkFilter.html
+
+use super::{
+    ConnectionCompleteOps, ConnectionFlowOps, DownstreamCloseOps, DownstreamDataOps, FilterStatus,
+    NetworkFilter, NewConnectionOps, Ops, UpstreamCloseOps, UpstreamDataOps,
+};
+use crate::abi::proxy_wasm::types::PeerType;
+use crate::error::{bail, Result};
+use crate::host;
+use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
+
+/// An interface of a protocol framing.
+pub trait Codec {
+    /// Protocol frame.
+    type Frame;
+
+    /// Decodes the next frame from the beginning of `data`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - accumulated data that has not been decoded yet.
+    ///
+    /// # Return value
+    ///
+    /// * `Ok(Some((frame, consumed)))` - a complete frame and the number of bytes it occupies.
+    /// * `Ok(None)`                    - `data` doesn't contain a complete frame yet.
+    /// * `Err(err)`                    - `data` violates the protocol, the connection will be closed.
+    fn decode(&mut self, data: &[u8]) -> Result<Option<(Self::Frame, usize)>>;
+
+    /// Decodes the next frame when no more data will arrive.
+    ///
+    /// By default, behaves the same as [`decode`], so that remaining bytes that don't
+    /// make up a complete frame are passed through as they are.
+    ///
+    /// [`decode`]: #tymethod.decode
+    fn decode_eof(&mut self, data: &[u8]) -> Result<Option<(Self::Frame, usize)>> {
+        self.decode(data)
+    }
+
+    /// Encodes a frame by appending its wire format to `buf`.
+    fn encode(&mut self, frame: &Self::Frame, buf: &mut Vec<u8>) -> Result<()>;
+}
+
+/// Return codes for [`on_downstream_frame`] and [`on_upstream_frame`] handler invocations.
+///
+/// [`on_downstream_frame`]: trait.FrameHandler.html#method.on_downstream_frame
+/// [`on_upstream_frame`]: trait.FrameHandler.html#method.on_upstream_frame
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum FrameStatus {
+    /// Pass the original bytes of the frame through.
+    Continue,
+    /// Frame has been modified and has to be encoded again.
+    Modified,
+    /// Remove the frame from the stream.
+    Drop,
+}
+
+/// An interface of a frame-oriented `Network Filter`.
+///
+/// Use [`FramedNetworkFilter`] to turn it into a [`NetworkFilter`].
+///
+/// [`FramedNetworkFilter`]: struct.FramedNetworkFilter.html
+/// [`NetworkFilter`]: ../trait.NetworkFilter.html
+pub trait FrameHandler {
+    /// Framing of data sent by `Downstream`.
+    type DownstreamCodec: Codec;
+    /// Framing of data sent by `Upstream`.
+    type UpstreamCodec: Codec;
+
+    /// Called when a connection is first established.
+    fn on_new_connection(&mut self, _ops: &dyn NewConnectionOps) -> Result<FilterStatus> {
+        Ok(FilterStatus::Continue)
+    }
+
+    /// Called for every complete frame received from `Downstream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - decoded frame, can be modified in place.
+    /// * `ops`   - a [`trait object`][`ConnectionFlowOps`] through which the handler can close the connection.
+    ///
+    /// [`ConnectionFlowOps`]: ../trait.ConnectionFlowOps.html
+    fn on_downstream_frame(
+        &mut self,
+        _frame: &mut <Self::DownstreamCodec as Codec>::Frame,
+        _ops: &dyn ConnectionFlowOps,
+    ) -> Result<FrameStatus> {
+        Ok(FrameStatus::Continue)
+    }
+
+    /// Called for every complete frame received from `Upstream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - decoded frame, can be modified in place.
+    /// * `ops`   - a [`trait object`][`ConnectionFlowOps`] through which the handler can close the connection.
+    ///
+    /// [`ConnectionFlowOps`]: ../trait.ConnectionFlowOps.html
+    fn on_upstream_frame(
+        &mut self,
+        _frame: &mut <Self::UpstreamCodec as Codec>::Frame,
+        _ops: &dyn ConnectionFlowOps,
+    ) -> Result<FrameStatus> {
+        Ok(FrameStatus::Continue)
+    }
+
+    /// Called when downstream connection is closed.
+    fn on_downstream_close(
+        &mut self,
+        _peer_type: PeerType,
+        _ops: &dyn DownstreamCloseOps,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when upstream connection is closed.
+    fn on_upstream_close(
+        &mut self,
+        _peer_type: PeerType,
+        _ops: &dyn UpstreamCloseOps,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the connection is complete.
+    fn on_connection_complete(&mut self, _ops: &dyn ConnectionCompleteOps) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the async HTTP request made by the handler is complete.
+    fn on_http_call_response(
+        &mut self,
+        _request: HttpClientRequestHandle,
+        _num_headers: usize,
+        _body_size: usize,
+        _num_trailers: usize,
+        _filter_ops: &dyn Ops,
+        _http_client_ops: &dyn HttpClientResponseOps,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Adapts a [`FrameHandler`] to the [`NetworkFilter`] interface.
+///
+/// [`FrameHandler`]: trait.FrameHandler.html
+/// [`NetworkFilter`]: ../trait.NetworkFilter.html
+pub struct FramedNetworkFilter<H>
+where
+    H: FrameHandler,
+{
+    handler: H,
+    downstream: FramedStream<H::DownstreamCodec>,
+    upstream: FramedStream<H::UpstreamCodec>,
+}
+
+impl<H> FramedNetworkFilter<H>
+where
+    H: FrameHandler,
+{
+    /// Creates a new `Network Filter` out of a frame handler and codecs for both directions.
+    pub fn new(
+        handler: H,
+        downstream_codec: H::DownstreamCodec,
+        upstream_codec: H::UpstreamCodec,
+    ) -> Self {
+        FramedNetworkFilter {
+            handler,
+            downstream: FramedStream::new(downstream_codec),
+            upstream: FramedStream::new(upstream_codec),
+        }
+    }
+
+    /// Returns the frame handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Returns the frame handler.
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+}
+
+impl<H> NetworkFilter for FramedNetworkFilter<H>
+where
+    H: FrameHandler,
+{
+    fn on_new_connection(&mut self, ops: &dyn NewConnectionOps) -> Result<FilterStatus> {
+        self.handler.on_new_connection(ops)
+    }
+
+    fn on_downstream_data(
+        &mut self,
+        data_size: usize,
+        end_of_stream: bool,
+        ops: &dyn DownstreamDataOps,
+    ) -> Result<FilterStatus> {
+        let data = ops.downstream_data(0, data_size)?;
+        let handler = &mut self.handler;
+        let flow_ops = FlowOps(ops);
+        if let Some(data) = self.downstream.process(&data, end_of_stream, |frame| {
+            handler.on_downstream_frame(frame, &flow_ops)
+        })? {
+            ops.set_downstream_data(0, data_size, &data)?;
+        }
+        Ok(FilterStatus::Continue)
+    }
+
+    fn on_downstream_close(
+        &mut self,
+        peer_type: PeerType,
+        ops: &dyn DownstreamCloseOps,
+    ) -> Result<()> {
+        self.handler.on_downstream_close(peer_type, ops)
+    }
+
+    fn on_upstream_data(
+        &mut self,
+        data_size: usize,
+        end_of_stream: bool,
+        ops: &dyn UpstreamDataOps,
+    ) -> Result<FilterStatus> {
+        let data = ops.upstream_data(0, data_size)?;
+        let handler = &mut self.handler;
+        let flow_ops = FlowOps(ops);
+        if let Some(data) = self.upstream.process(&data, end_of_stream, |frame| {
+            handler.on_upstream_frame(frame, &flow_ops)
+        })? {
+            ops.set_upstream_data(0, data_size, &data)?;
+        }
+        Ok(FilterStatus::Continue)
+    }
+
+    fn on_upstream_close(&mut self, peer_type: PeerType, ops: &dyn UpstreamCloseOps) -> Result<()> {
+        self.handler.on_upstream_close(peer_type, ops)
+    }
+
+    fn on_connection_complete(&mut self, ops: &dyn ConnectionCompleteOps) -> Result<()> {
+        self.handler.on_connection_complete(ops)
+    }
+
+    fn on_http_call_response(
+        &mut self,
+        request: HttpClientRequestHandle,
+        num_headers: usize,
+        body_size: usize,
+        num_trailers: usize,
+        filter_ops: &dyn Ops,
+        http_client_ops: &dyn HttpClientResponseOps,
+    ) -> Result<()> {
+        self.handler.on_http_call_response(
+            request,
+            num_headers,
+            body_size,
+            num_trailers,
+            filter_ops,
+            http_client_ops,
+        )
+    }
+}
+
+/// State of framing in one direction of the connection.
+struct FramedStream<C> {
+    codec: C,
+    /// Data of an incomplete frame held back until the rest of it arrives.
+    pending: Vec<u8>,
+}
+
+impl<C> FramedStream<C>
+where
+    C: Codec,
+{
+    fn new(codec: C) -> Self {
+        FramedStream {
+            codec,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Decodes frames out of the next chunk of data.
+    ///
+    /// Returns data that should replace the chunk or `None` if the chunk can be passed through
+    /// as it is.
+    fn process<F>(
+        &mut self,
+        chunk: &[u8],
+        end_of_stream: bool,
+        mut on_frame: F,
+    ) -> Result<Option<Vec<u8>>>
+    where
+        F: FnMut(&mut C::Frame) -> Result<FrameStatus>,
+    {
+        let mut untouched = self.pending.is_empty();
+        self.pending.extend_from_slice(chunk);
+
+        let mut output = Vec::with_capacity(self.pending.len());
+        let mut offset = 0;
+        while offset < self.pending.len() {
+            let data = &self.pending[offset..];
+            let decoded = if end_of_stream {
+                self.codec.decode_eof(data)?
+            } else {
+                self.codec.decode(data)?
+            };
+            let (mut frame, consumed) = match decoded {
+                Some(decoded) => decoded,
+                None => break,
+            };
+            if consumed == 0 || consumed > data.len() {
+                bail!(
+                    "codec reported an invalid frame size {} with {} bytes available",
+                    consumed,
+                    data.len()
+                );
+            }
+            match on_frame(&mut frame)? {
+                FrameStatus::Continue => output.extend_from_slice(&data[..consumed]),
+                FrameStatus::Modified => {
+                    self.codec.encode(&frame, &mut output)?;
+                    untouched = false;
+                }
+                FrameStatus::Drop => untouched = false,
+            }
+            offset += consumed;
+        }
+
+        if end_of_stream {
+            output.extend_from_slice(&self.pending[offset..]);
+            offset = self.pending.len();
+        } else if offset < self.pending.len() {
+            untouched = false;
+        }
+        self.pending.drain(..offset);
+
+        Ok(if untouched { None } else { Some(output) })
+    }
+}
+
+/// Exposes [`ConnectionFlowOps`] of a data ops object to the frame handler.
+///
+/// [`ConnectionFlowOps`]: ../trait.ConnectionFlowOps.html
+struct FlowOps<'a, T: ?Sized>(&'a T);
+
+impl<'a, T> ConnectionFlowOps for FlowOps<'a, T>
+where
+    T: ConnectionFlowOps + ?Sized,
+{
+    fn close_downstream(&self) -> host::Result<()> {
+        self.0.close_downstream()
+    }
+}
@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{FilterStatus, NetworkFilter, Ops};
+use super::phase::{Phase, PhaseCheckedOps};
+use super::{ConnectionFlowOps, FilterStatus, NetworkFilter, Ops};
 use crate::abi::proxy_wasm::traits::{Context, StreamContext};
 use crate::abi::proxy_wasm::types::{Action, PeerType};
 use crate::extension::error::ErrorSink;
+use crate::extension::panic::Poisonable;
 use crate::extension::Error;
 use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
 
@@ -23,8 +25,8 @@ pub(crate) struct NetworkFilterContext<'a, F>
 where
     F: NetworkFilter,
 {
-    filter: F,
-    filter_ops: &'a dyn Ops,
+    filter: Poisonable<F>,
+    filter_ops: PhaseCheckedOps<'a>,
     http_client_ops: &'a dyn HttpClientResponseOps,
     error_sink: &'a dyn ErrorSink,
 }
@@ -34,7 +36,12 @@ where
     F: NetworkFilter,
 {
     fn on_new_connection(&mut self) -> Action {
-        match self.filter.on_new_connection() {
+        self.filter_ops.enter(Phase::NewConnection);
+        let ops = self.filter_ops.as_new_connection_ops();
+        match self
+            .filter
+            .call("on_new_connection", |filter| filter.on_new_connection(ops))
+        {
             Ok(status) => status.as_action(),
             Err(err) => {
                 self.error_sink
@@ -46,11 +53,11 @@ where
     }
 
     fn on_downstream_data(&mut self, data_size: usize, end_of_stream: bool) -> Action {
-        match self.filter.on_downstream_data(
-            data_size,
-            end_of_stream,
-            self.filter_ops.as_downstream_data_ops(),
-        ) {
+        self.filter_ops.enter(Phase::DownstreamData);
+        let ops = self.filter_ops.as_downstream_data_ops();
+        match self.filter.call("on_downstream_data", |filter| {
+            filter.on_downstream_data(data_size, end_of_stream, ops)
+        }) {
             Ok(status) => status.as_action(),
             Err(err) => {
                 self.error_sink
@@ -62,23 +69,23 @@ where
     }
 
     fn on_downstream_close(&mut self, peer_type: PeerType) {
-        if let Err(err) = self
-            .filter
-            .on_downstream_close(peer_type, self.filter_ops.as_downstream_close_ops())
-        {
+        self.filter_ops.enter(Phase::DownstreamClose);
+        let ops = self.filter_ops.as_downstream_close_ops();
+        if let Err(err) = self.filter.call("on_downstream_close", |filter| {
+            filter.on_downstream_close(peer_type, ops)
+        }) {
             self.error_sink
                 .observe("failed to handle connection close by the downstream", &err);
-            // TODO(yskopets): do we still need to do anything to terminate the connection?
-            self.handle_error(err);
+            // connection is already being closed, so there is no need to do it explicitly
         }
     }
 
     fn on_upstream_data(&mut self, data_size: usize, end_of_stream: bool) -> Action {
-        match self.filter.on_upstream_data(
-            data_size,
-            end_of_stream,
-            self.filter_ops.as_upstream_data_ops(),
-        ) {
+        self.filter_ops.enter(Phase::UpstreamData);
+        let ops = self.filter_ops.as_upstream_data_ops();
+        match self.filter.call("on_upstream_data", |filter| {
+            filter.on_upstream_data(data_size, end_of_stream, ops)
+        }) {
             Ok(status) => status.as_action(),
             Err(err) => {
                 self.error_sink
@@ -90,14 +97,14 @@ where
     }
 
     fn on_upstream_close(&mut self, peer_type: PeerType) {
-        if let Err(err) = self
-            .filter
-            .on_upstream_close(peer_type, self.filter_ops.as_upstream_close_ops())
-        {
+        self.filter_ops.enter(Phase::UpstreamClose);
+        let ops = self.filter_ops.as_upstream_close_ops();
+        if let Err(err) = self.filter.call("on_upstream_close", |filter| {
+            filter.on_upstream_close(peer_type, ops)
+        }) {
             self.error_sink
                 .observe("failed to handle connection close by the upstream", &err);
-            // TODO(yskopets): do we still need to do anything to terminate the connection?
-            self.handle_error(err);
+            // connection is already being closed, so there is no need to do it explicitly
         }
     }
 }
@@ -107,10 +114,11 @@ where
     F: NetworkFilter,
 {
     fn on_done(&mut self) -> bool {
-        if let Err(err) = self
-            .filter
-            .on_connection_complete(self.filter_ops.as_connection_complete_ops())
-        {
+        self.filter_ops.enter(Phase::ConnectionComplete);
+        let ops = self.filter_ops.as_connection_complete_ops();
+        if let Err(err) = self.filter.call("on_connection_complete", |filter| {
+            filter.on_connection_complete(ops)
+        }) {
             self.error_sink
                 .observe("failed to handle completion of a connection", &err);
             // connection is already being terminated, so there is no need to do it explicitly
@@ -127,14 +135,19 @@ where
         body_size: usize,
         num_trailers: usize,
     ) {
-        if let Err(err) = self.filter.on_http_call_response(
-            HttpClientRequestHandle::from(token_id),
-            num_headers,
-            body_size,
-            num_trailers,
-            self.filter_ops,
-            self.http_client_ops,
-        ) {
+        self.filter_ops.enter(Phase::HttpCallResponse);
+        let filter_ops = &self.filter_ops;
+        let http_client_ops = self.http_client_ops;
+        if let Err(err) = self.filter.call("on_http_call_response", |filter| {
+            filter.on_http_call_response(
+                HttpClientRequestHandle::from(token_id),
+                num_headers,
+                body_size,
+                num_trailers,
+                filter_ops,
+                http_client_ops,
+            )
+        }) {
             self.error_sink.observe(
                 "failed to process a response to an HTTP request made by the extension",
                 &err,
@@ -149,22 +162,24 @@ where
     F: NetworkFilter,
 {
     pub fn new(
+        extension: &'static str,
         filter: F,
         filter_ops: &'a dyn Ops,
         http_client_ops: &'a dyn HttpClientResponseOps,
         error_sink: &'a dyn ErrorSink,
     ) -> Self {
         NetworkFilterContext {
-            filter,
-            filter_ops,
+            filter: Poisonable::new(extension, filter),
+            filter_ops: PhaseCheckedOps::new(filter_ops),
             http_client_ops,
             error_sink,
         }
     }
 
     /// Creates a new network filter context bound to the actual Envoy ABI.
-    pub fn with_default_ops(filter: F) -> Self {
+    pub fn with_default_ops(extension: &'static str, filter: F) -> Self {
         Self::new(
+            extension,
             filter,
             Ops::default(),
             HttpClientResponseOps::default(),
@@ -173,8 +188,10 @@ where
     }
 
     fn handle_error(&self, _err: Error) {
-        // TODO(yskopets): Proxy Wasm should provide ABI for closing the downstream connection
-        // https://github.com/tetratelabs/envoy-wasm-rust-sdk/issues/29
+        if let Err(err) = self.filter_ops.close_downstream() {
+            self.error_sink
+                .observe("failed to close the connection after an error", &err);
+        }
     }
 }
 
@@ -187,23 +204,22 @@ where
 /// at this point.
 ///
 /// Instead, we have to memorize the error and wait until [`proxy_on_new_connection`]
-/// callback when it will be safe to use [`not yet supported ABI`] to stop further processing.
+/// callback when it will be safe to close the connection.
 ///
 /// [`StreamContext`]: https://docs.rs/proxy-wasm/0.1.0/proxy_wasm/traits/trait.StreamContext.html
 /// [`proxy_on_context_create`]: https://github.com/proxy-wasm/spec/tree/master/abi-versions/vNEXT#proxy_on_context_create
 /// [`proxy_on_new_connection`]: https://github.com/proxy-wasm/spec/tree/master/abi-versions/vNEXT#proxy_on_new_connection
-/// [`not yet supported ABI`]: https://github.com/tetratelabs/envoy-wasm-rust-sdk/issues/29
 pub(crate) struct VoidNetworkFilterContext<'a> {
     err: Error,
-    _filter_ops: &'a dyn Ops,
+    filter_ops: &'a dyn Ops,
     error_sink: &'a dyn ErrorSink,
 }
 
 impl<'a> VoidNetworkFilterContext<'a> {
-    pub fn new(err: Error, _filter_ops: &'a dyn Ops, error_sink: &'a dyn ErrorSink) -> Self {
+    pub fn new(err: Error, filter_ops: &'a dyn Ops, error_sink: &'a dyn ErrorSink) -> Self {
         VoidNetworkFilterContext {
             err,
-            _filter_ops,
+            filter_ops,
             error_sink,
         }
     }
@@ -218,10 +234,178 @@ impl<'a> StreamContext for VoidNetworkFilterContext<'a> {
     fn on_new_connection(&mut self) -> Action {
         self.error_sink
             .observe("failed to create Proxy Wasm Stream Context", &self.err);
-        // TODO(yskopets): Proxy Wasm should provide ABI for closing the downstream connection
-        // https://github.com/tetratelabs/envoy-wasm-rust-sdk/issues/29
+        if let Err(err) = self.filter_ops.close_downstream() {
+            self.error_sink
+                .observe("failed to close the connection after an error", &err);
+        }
         FilterStatus::StopIteration.as_action()
     }
 }
 
 impl<'a> Context for VoidNetworkFilterContext<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::super::testing::FakeOps;
+    use super::super::{
+        DownstreamCloseOps, DownstreamDataOps, NewConnectionOps, UpstreamCloseOps, UpstreamDataOps,
+    };
+    use super::*;
+    use crate::error::{bail, format_err};
+    use crate::extension::testing::FakeErrorSink;
+    use crate::extension::Result;
+
+    /// Fails in every callback.
+    struct FailingFilter;
+
+    impl NetworkFilter for FailingFilter {
+        fn on_downstream_data(
+            &mut self,
+            _data_size: usize,
+            _end_of_stream: bool,
+            _ops: &dyn DownstreamDataOps,
+        ) -> Result<FilterStatus> {
+            bail!("unexpected data")
+        }
+
+        fn on_downstream_close(
+            &mut self,
+            _peer_type: PeerType,
+            _ops: &dyn DownstreamCloseOps,
+        ) -> Result<()> {
+            bail!("unexpected close")
+        }
+
+        fn on_upstream_close(
+            &mut self,
+            _peer_type: PeerType,
+            _ops: &dyn UpstreamCloseOps,
+        ) -> Result<()> {
+            bail!("unexpected close")
+        }
+    }
+
+    #[test]
+    fn test_close_connection_on_error() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let mut ctx =
+            NetworkFilterContext::new("my_network_filter", FailingFilter, &ops, &ops, &error_sink);
+
+        let action = ctx.on_downstream_data(5, false);
+
+        assert_eq!(action, FilterStatus::StopIteration.as_action());
+        assert_eq!(ops.calls(), vec!["close_downstream"]);
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to handle data from the downstream: unexpected data"]
+        );
+    }
+
+    #[test]
+    fn test_close_connection_not_on_error_in_close_callbacks() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let mut ctx =
+            NetworkFilterContext::new("my_network_filter", FailingFilter, &ops, &ops, &error_sink);
+
+        ctx.on_downstream_close(PeerType::Remote);
+        ctx.on_upstream_close(PeerType::Local);
+
+        assert!(ops.calls().is_empty());
+        assert_eq!(error_sink.errors().len(), 2);
+    }
+
+    #[test]
+    fn test_close_connection_if_filter_could_not_be_created() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let mut ctx =
+            VoidNetworkFilterContext::new(format_err!("invalid config"), &ops, &error_sink);
+
+        let action = ctx.on_new_connection();
+
+        assert_eq!(action, FilterStatus::StopIteration.as_action());
+        assert_eq!(ops.calls(), vec!["close_downstream"]);
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to create Proxy Wasm Stream Context: invalid config"]
+        );
+    }
+
+    /// Panics on a new connection and counts the callbacks it has been called with.
+    #[derive(Default)]
+    struct PanickingFilter {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl NetworkFilter for PanickingFilter {
+        fn on_new_connection(&mut self, _ops: &dyn NewConnectionOps) -> Result<FilterStatus> {
+            self.calls.set(self.calls.get() + 1);
+            panic!("unexpected connection")
+        }
+
+        fn on_upstream_data(
+            &mut self,
+            _data_size: usize,
+            _end_of_stream: bool,
+            _ops: &dyn UpstreamDataOps,
+        ) -> Result<FilterStatus> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(FilterStatus::Continue)
+        }
+    }
+
+    #[test]
+    fn test_close_connection_on_panic() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let mut ctx = NetworkFilterContext::new(
+            "my_network_filter",
+            PanickingFilter::default(),
+            &ops,
+            &ops,
+            &error_sink,
+        );
+
+        let action = ctx.on_new_connection();
+
+        assert_eq!(action, FilterStatus::StopIteration.as_action());
+        assert_eq!(ops.calls(), vec!["close_downstream"]);
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to handle connection opening: extension panicked: unexpected connection"]
+        );
+    }
+
+    #[test]
+    fn test_poisoned_filter_is_not_called() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let filter = PanickingFilter::default();
+        let calls = Rc::clone(&filter.calls);
+        let mut ctx =
+            NetworkFilterContext::new("my_network_filter", filter, &ops, &ops, &error_sink);
+
+        ctx.on_new_connection();
+        let action = ctx.on_upstream_data(5, false);
+        assert!(ctx.on_done());
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(action, FilterStatus::StopIteration.as_action());
+        let errors = error_sink.errors();
+        assert_eq!(errors.len(), 3);
+        for err in &errors[1..] {
+            assert!(
+                err.ends_with(
+                    "extension is poisoned since it has panicked earlier: unexpected connection"
+                ),
+                "{}",
+                err
+            );
+        }
+    }
+}
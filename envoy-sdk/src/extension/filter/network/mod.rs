@@ -102,12 +102,18 @@
 use crate::abi::proxy_wasm::types::{Action, PeerType};
 use crate::extension::Result;
 use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
-use crate::host::{self, ByteString};
+use crate::host::{self, ByteString, StreamInfo};
 
 pub(crate) use self::context::{NetworkFilterContext, VoidNetworkFilterContext};
 
+pub mod codec;
+pub mod sniff;
+
 mod context;
 mod ops;
+mod phase;
+#[cfg(test)]
+mod testing;
 
 /// Return codes for [`on_downstream_data`] and [`on_upstream_data`] filter
 /// invocations.
@@ -156,14 +162,14 @@ impl FilterStatus {
 /// ```
 /// # use envoy_sdk as envoy;
 /// use envoy::extension::{NetworkFilter, Result};
-/// use envoy::extension::filter::network::FilterStatus;
+/// use envoy::extension::filter::network::{FilterStatus, NewConnectionOps};
 /// use envoy::host::log;
 ///
 /// /// My very own `NetworkFilter`.
 /// struct MyNetworkFilter;
 ///
 /// impl NetworkFilter for MyNetworkFilter {
-///     fn on_new_connection(&mut self) -> Result<FilterStatus> {
+///     fn on_new_connection(&mut self, _ops: &dyn NewConnectionOps) -> Result<FilterStatus> {
 ///         log::info!("a new connection has been established");
 ///         Ok(FilterStatus::Continue)
 ///     }
@@ -181,6 +187,16 @@ impl FilterStatus {
 /// For comparison, if the extension choose to panic, this will, at best, affect all ongoing TCP connections
 /// handled by that extension, and, at worst, will crash `Envoy` entirely (as of July 2020).
 ///
+/// Where stack unwinding is supported, a panic is turned into an error that closes
+/// the connection, and the filter instance is never called again. WebAssembly targets
+/// do not support stack unwinding, so inside `Envoy` a panic still aborts the WebAssembly VM.
+///
+/// # Phase checks
+///
+/// In debug builds (or with `phase-checks` feature enabled), `Envoy SDK` verifies that
+/// operations are called at a proper point of the connection lifecycle, e.g. it rejects
+/// calling `upstream_data` from `on_downstream_data`, with an error that explains where the operation is allowed.
+///
 /// [`Result::Err(x)`]: https://doc.rust-lang.org/core/result/enum.Result.html#variant.Err
 pub trait NetworkFilter {
     /// Called when a connection is first established.
@@ -188,12 +204,17 @@ pub trait NetworkFilter {
     /// Filters should do one time long term processing that needs to be done when a connection is
     /// established. Filter chain iteration can be stopped if needed.
     ///
+    /// # Arguments
+    ///
+    /// * `ops` - a [`trait object`][`NewConnectionOps`] to inspect and reject the connection through.
+    ///
     /// # Return value
     ///
     /// [`FilterStatus`] telling `Envoy` how to manage further filter iteration.
     ///
     /// [`FilterStatus`]: enum.FilterStatus.html
-    fn on_new_connection(&mut self) -> Result<FilterStatus> {
+    /// [`NewConnectionOps`]: trait.NewConnectionOps.html
+    fn on_new_connection(&mut self, _ops: &dyn NewConnectionOps) -> Result<FilterStatus> {
         Ok(FilterStatus::Continue)
     }
 
@@ -310,8 +331,39 @@ pub trait NetworkFilter {
     }
 }
 
+/// An interface for operations available in the context of [`on_new_connection`]
+/// filter invocation.
+///
+/// # Examples
+///
+/// #### Rejecting connections by `SNI`:
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::{NetworkFilter, Result};
+/// use envoy::extension::filter::network::{FilterStatus, NewConnectionOps};
+///
+/// struct MyNetworkFilter;
+///
+/// impl NetworkFilter for MyNetworkFilter {
+///     fn on_new_connection(&mut self, ops: &dyn NewConnectionOps) -> Result<FilterStatus> {
+///         let connection = ops.stream_info().connection();
+///         match connection.requested_server_name()? {
+///             Some(sni) if sni.ends_with(".internal") => Ok(FilterStatus::Continue),
+///             _ => {
+///                 ops.close_downstream()?;
+///                 Ok(FilterStatus::StopIteration)
+///             }
+///         }
+///     }
+/// }
+/// ```
+///
+/// [`on_new_connection`]: trait.NetworkFilter.html#method.on_new_connection
+pub trait NewConnectionOps: ConnectionFlowOps + ConnectionInfoOps {}
+
 /// An interface for manipulating data in the read buffer from `Downstream`.
-pub trait DownstreamDataOps {
+pub trait DownstreamDataOps: ConnectionFlowOps {
     /// Returns data in the read buffer from `Downstream`.
     ///
     /// # Arguments
@@ -319,11 +371,20 @@ pub trait DownstreamDataOps {
     /// * `offset`   - offset to start reading data from.
     /// * `max_size` - maximum size of data to return.
     fn downstream_data(&self, offset: usize, max_size: usize) -> host::Result<ByteString>;
+
+    /// Replaces data in the read buffer from `Downstream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - offset to start replacing data from.
+    /// * `size`  - size of data to replace.
+    /// * `data`  - new data.
+    fn set_downstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()>;
 }
 
 /// An interface for manipulating data received from `Upstream`
 /// before they reach the write buffer for `Downstream`.
-pub trait UpstreamDataOps {
+pub trait UpstreamDataOps: ConnectionFlowOps {
     /// Returns data received from `Upstream`.
     ///
     /// # Arguments
@@ -331,36 +392,99 @@ pub trait UpstreamDataOps {
     /// * `offset`   - offset to start reading data from.
     /// * `max_size` - maximum size of data to return.
     fn upstream_data(&self, offset: usize, max_size: usize) -> host::Result<ByteString>;
+
+    /// Replaces data received from `Upstream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - offset to start replacing data from.
+    /// * `size`  - size of data to replace.
+    /// * `data`  - new data.
+    fn set_upstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()>;
 }
 
 /// An interface for operations available in the context of [`on_downstream_close`]
 /// filter invocation.
 ///
 /// [`on_downstream_close`]: trait.NetworkFilter.html#method.on_downstream_close
-pub trait DownstreamCloseOps {
-    // TODO(yskopets): TBD
-}
+pub trait DownstreamCloseOps: ConnectionFlowOps {}
 
 /// An interface for operations available in the context of [`on_upstream_close`]
 /// filter invocation.
 ///
+/// E.g., a filter can close connection with `Downstream` right away
+/// once `Upstream` has gone away.
+///
 /// [`on_upstream_close`]: trait.NetworkFilter.html#method.on_upstream_close
-pub trait UpstreamCloseOps {
-    // TODO(yskopets): TBD
+pub trait UpstreamCloseOps: ConnectionFlowOps {}
+
+/// An interface for changing connection flow.
+///
+/// # Examples
+///
+/// #### Rejecting a connection that violates the protocol:
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::{NetworkFilter, Result};
+/// use envoy::extension::filter::network::{DownstreamDataOps, FilterStatus};
+///
+/// struct MyNetworkFilter;
+///
+/// impl NetworkFilter for MyNetworkFilter {
+///     fn on_downstream_data(
+///         &mut self,
+///         data_size: usize,
+///         _end_of_stream: bool,
+///         ops: &dyn DownstreamDataOps,
+///     ) -> Result<FilterStatus> {
+///         let data = ops.downstream_data(0, data_size)?;
+///         if !data.starts_with(b"HELLO") {
+///             ops.close_downstream()?;
+///             return Ok(FilterStatus::StopIteration);
+///         }
+///         Ok(FilterStatus::Continue)
+///     }
+/// }
+/// ```
+pub trait ConnectionFlowOps {
+    /// Closes connection with `Downstream`.
+    ///
+    /// `Envoy` writes out data pending for `Downstream` before closing the connection
+    /// and then closes connection with `Upstream` as well.
+    ///
+    /// `Proxy Wasm` doesn't support closing connection with `Upstream` alone
+    /// or discarding pending data.
+    fn close_downstream(&self) -> host::Result<()>;
 }
 
 /// An interface for operations available in the context of [`on_connection_complete`]
 /// filter invocation.
 ///
+/// Provides read-only access to the final state of the connection, e.g. to record
+/// per-connection summary metrics.
+///
 /// [`on_connection_complete`]: trait.NetworkFilter.html#method.on_connection_complete
-pub trait ConnectionCompleteOps {
-    // TODO(yskopets): TBD
+pub trait ConnectionCompleteOps: ConnectionInfoOps {}
+
+/// An interface for accessing properties of the connection.
+pub trait ConnectionInfoOps {
+    /// Provides access to properties of the connection, e.g. source and destination
+    /// addresses, `requested_server_name` and `TLS` peer certificate.
+    fn stream_info(&self) -> &dyn StreamInfo;
 }
 
 /// An interface for manipulating data in both read and write buffers.
 pub trait Ops:
-    DownstreamDataOps + UpstreamDataOps + DownstreamCloseOps + UpstreamCloseOps + ConnectionCompleteOps
+    NewConnectionOps
+    + DownstreamDataOps
+    + UpstreamDataOps
+    + DownstreamCloseOps
+    + UpstreamCloseOps
+    + ConnectionCompleteOps
 {
+    fn as_new_connection_ops(&self) -> &dyn NewConnectionOps;
+
     fn as_downstream_data_ops(&self) -> &dyn DownstreamDataOps;
 
     fn as_upstream_data_ops(&self) -> &dyn UpstreamDataOps;
@@ -374,12 +498,17 @@ pub trait Ops:
 
 impl<T> Ops for T
 where
-    T: DownstreamDataOps
+    T: NewConnectionOps
+        + DownstreamDataOps
         + UpstreamDataOps
         + DownstreamCloseOps
         + UpstreamCloseOps
         + ConnectionCompleteOps,
 {
+    fn as_new_connection_ops(&self) -> &dyn NewConnectionOps {
+        self
+    }
+
     fn as_downstream_data_ops(&self) -> &dyn DownstreamDataOps {
         self
     }
@@ -13,12 +13,13 @@
 // limitations under the License.
 
 use crate::abi::proxy_wasm::hostcalls;
-use crate::abi::proxy_wasm::types::BufferType;
+use crate::abi::proxy_wasm::types::{BufferType, ConnectionStreamType};
 
 use super::{
-    ConnectionCompleteOps, DownstreamCloseOps, DownstreamDataOps, UpstreamCloseOps, UpstreamDataOps,
+    ConnectionCompleteOps, ConnectionFlowOps, ConnectionInfoOps, DownstreamCloseOps,
+    DownstreamDataOps, NewConnectionOps, UpstreamCloseOps, UpstreamDataOps,
 };
-use crate::host::{self, ByteString};
+use crate::host::{self, ByteString, StreamInfo};
 
 pub(super) struct Host;
 
@@ -26,16 +27,38 @@ impl DownstreamDataOps for Host {
     fn downstream_data(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
         hostcalls::get_buffer(BufferType::DownstreamData, start, max_size)
     }
+
+    fn set_downstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        hostcalls::set_buffer(BufferType::DownstreamData, start, size, data)
+    }
 }
 
 impl UpstreamDataOps for Host {
     fn upstream_data(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
         hostcalls::get_buffer(BufferType::UpstreamData, start, max_size)
     }
+
+    fn set_upstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        hostcalls::set_buffer(BufferType::UpstreamData, start, size, data)
+    }
 }
 
 impl DownstreamCloseOps for Host {}
 
 impl UpstreamCloseOps for Host {}
 
+impl ConnectionFlowOps for Host {
+    fn close_downstream(&self) -> host::Result<()> {
+        hostcalls::close_stream(ConnectionStreamType::Downstream)
+    }
+}
+
+impl NewConnectionOps for Host {}
+
 impl ConnectionCompleteOps for Host {}
+
+impl ConnectionInfoOps for Host {
+    fn stream_info(&self) -> &dyn StreamInfo {
+        <dyn StreamInfo>::default()
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime checks that `Network Filter` operations are called at a proper point
+//! of the connection lifecycle.
+
+use std::cell::Cell;
+
+use super::{
+    ConnectionCompleteOps, ConnectionFlowOps, ConnectionInfoOps, DownstreamCloseOps,
+    DownstreamDataOps, NewConnectionOps, Ops, UpstreamCloseOps, UpstreamDataOps,
+};
+use crate::extension::error::PhaseError;
+use crate::extension::filter::PHASE_CHECKS_ENABLED;
+use crate::host::{self, ByteString, StreamInfo};
+
+/// Phase of the connection lifecycle, i.e. the filter callback being executed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(super) enum Phase {
+    NewConnection,
+    DownstreamData,
+    DownstreamClose,
+    UpstreamData,
+    UpstreamClose,
+    HttpCallResponse,
+    ConnectionComplete,
+}
+
+impl Phase {
+    fn name(&self) -> &'static str {
+        use Phase::*;
+        match self {
+            NewConnection => "on_new_connection",
+            DownstreamData => "on_downstream_data",
+            DownstreamClose => "on_downstream_close",
+            UpstreamData => "on_upstream_data",
+            UpstreamClose => "on_upstream_close",
+            HttpCallResponse => "on_http_call_response",
+            ConnectionComplete => "on_connection_complete",
+        }
+    }
+}
+
+/// Decorates [`Ops`] with checks of the current phase of the connection lifecycle.
+///
+/// Checks are only performed if [`PHASE_CHECKS_ENABLED`], otherwise all calls go straight
+/// to the underlying [`Ops`].
+///
+/// [`Ops`]: ../trait.Ops.html
+/// [`PHASE_CHECKS_ENABLED`]: ../../constant.PHASE_CHECKS_ENABLED.html
+pub(super) struct PhaseCheckedOps<'a> {
+    ops: &'a dyn Ops,
+    phase: Cell<Option<Phase>>,
+}
+
+impl<'a> PhaseCheckedOps<'a> {
+    pub fn new(ops: &'a dyn Ops) -> Self {
+        PhaseCheckedOps {
+            ops,
+            phase: Cell::new(None),
+        }
+    }
+
+    /// Marks the beginning of a filter callback.
+    pub fn enter(&self, phase: Phase) {
+        self.phase.set(Some(phase));
+    }
+
+    /// Checks that a given callback is being executed.
+    fn check(&self, op: &'static str, allowed_in: Phase) -> host::Result<()> {
+        let phase = self.phase.get();
+        if !PHASE_CHECKS_ENABLED || phase == Some(allowed_in) {
+            Ok(())
+        } else {
+            Err(PhaseError::new(
+                op,
+                phase.map(|phase| phase.name()).unwrap_or("<none>"),
+                format!("it is only allowed in `{}`", allowed_in.name()),
+            )
+            .into())
+        }
+    }
+
+    /// Checks that connection is still open, i.e. has not completed yet.
+    fn check_open(&self, op: &'static str) -> host::Result<()> {
+        match self.phase.get() {
+            Some(Phase::ConnectionComplete) if PHASE_CHECKS_ENABLED => Err(PhaseError::new(
+                op,
+                Phase::ConnectionComplete.name(),
+                "connection has already been closed".into(),
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'a> DownstreamDataOps for PhaseCheckedOps<'a> {
+    fn downstream_data(&self, offset: usize, max_size: usize) -> host::Result<ByteString> {
+        self.check("downstream_data", Phase::DownstreamData)?;
+        self.ops.downstream_data(offset, max_size)
+    }
+
+    fn set_downstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        self.check("set_downstream_data", Phase::DownstreamData)?;
+        self.ops.set_downstream_data(start, size, data)
+    }
+}
+
+impl<'a> UpstreamDataOps for PhaseCheckedOps<'a> {
+    fn upstream_data(&self, offset: usize, max_size: usize) -> host::Result<ByteString> {
+        self.check("upstream_data", Phase::UpstreamData)?;
+        self.ops.upstream_data(offset, max_size)
+    }
+
+    fn set_upstream_data(&self, start: usize, size: usize, data: &[u8]) -> host::Result<()> {
+        self.check("set_upstream_data", Phase::UpstreamData)?;
+        self.ops.set_upstream_data(start, size, data)
+    }
+}
+
+impl<'a> ConnectionFlowOps for PhaseCheckedOps<'a> {
+    fn close_downstream(&self) -> host::Result<()> {
+        self.check_open("close_downstream")?;
+        self.ops.close_downstream()
+    }
+}
+
+impl<'a> DownstreamCloseOps for PhaseCheckedOps<'a> {}
+
+impl<'a> UpstreamCloseOps for PhaseCheckedOps<'a> {}
+
+impl<'a> NewConnectionOps for PhaseCheckedOps<'a> {}
+
+impl<'a> ConnectionCompleteOps for PhaseCheckedOps<'a> {}
+
+impl<'a> ConnectionInfoOps for PhaseCheckedOps<'a> {
+    fn stream_info(&self) -> &dyn StreamInfo {
+        self.ops.stream_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::FakeOps;
+    use super::*;
+
+    #[test]
+    fn test_phase_check_close_before_completion() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::NewConnection);
+        assert!(ops.close_downstream().is_ok());
+        ops.enter(Phase::UpstreamData);
+        assert!(ops.close_downstream().is_ok());
+        ops.enter(Phase::UpstreamClose);
+        assert!(ops.as_upstream_close_ops().close_downstream().is_ok());
+        ops.enter(Phase::DownstreamClose);
+        assert!(ops.as_downstream_close_ops().close_downstream().is_ok());
+
+        assert_eq!(fake.calls(), vec!["close_downstream"; 4]);
+    }
+
+    #[test]
+    fn test_phase_check_close_after_completion() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::ConnectionComplete);
+        let err = ops.close_downstream().unwrap_err();
+
+        assert!(err.downcast_ref::<PhaseError>().is_some());
+        assert!(fake.calls().is_empty());
+    }
+
+    #[test]
+    fn test_phase_check_other_direction() {
+        let fake = FakeOps::default();
+        let ops = PhaseCheckedOps::new(&fake);
+
+        ops.enter(Phase::DownstreamData);
+        assert!(ops.downstream_data(0, 10).is_ok());
+        assert!(ops.upstream_data(0, 10).is_err());
+
+        assert_eq!(fake.calls(), vec!["downstream_data"]);
+    }
+}
@@ -0,0 +1,554 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of the application protocol from the first bytes sent by `Downstream`.
+//!
+//! Recognizes
+//! * `HTTP/1.x` requests,
+//! * `HTTP/2` connection preface,
+//! * `TLS` `ClientHello` (with `SNI` and `ALPN` extraction),
+//! * `Redis` `RESP` commands,
+//! * `PostgreSQL` startup packets.
+//!
+//! # Examples
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use envoy::extension::{NetworkFilter, Result};
+//! use envoy::extension::filter::network::{DownstreamDataOps, FilterStatus};
+//! use envoy::extension::filter::network::sniff::{Detection, ProtocolSniffer};
+//! use envoy::host::log;
+//!
+//! struct MyNetworkFilter {
+//!     sniffer: ProtocolSniffer,
+//! }
+//!
+//! impl NetworkFilter for MyNetworkFilter {
+//!     fn on_downstream_data(
+//!         &mut self,
+//!         data_size: usize,
+//!         _end_of_stream: bool,
+//!         ops: &dyn DownstreamDataOps,
+//!     ) -> Result<FilterStatus> {
+//!         if !self.sniffer.is_done() {
+//!             match self.sniffer.read_downstream_data(data_size, ops)? {
+//!                 Detection::Detected(protocol) => log::info!("detected {:?}", protocol),
+//!                 Detection::Unknown => log::info!("unknown protocol"),
+//!                 Detection::NeedMoreData => (),
+//!             }
+//!         }
+//!         Ok(FilterStatus::Continue)
+//!     }
+//! }
+//! ```
+
+use std::convert::TryInto;
+
+use super::DownstreamDataOps;
+use crate::error::Result;
+
+/// Default limit on the number of bytes [`ProtocolSniffer`] inspects before giving up.
+///
+/// Is large enough to fit a `TLS` record of maximum size.
+///
+/// [`ProtocolSniffer`]: struct.ProtocolSniffer.html
+pub const DEFAULT_MAX_SNIFF_SIZE: usize = 16 * 1024 + 5;
+
+/// `HTTP/2` connection preface.
+pub const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const HTTP1_METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+const POSTGRES_PROTOCOL_V3: u32 = 196_608;
+const POSTGRES_CANCEL_REQUEST: u32 = 80_877_102;
+const POSTGRES_SSL_REQUEST: u32 = 80_877_103;
+const POSTGRES_GSSENC_REQUEST: u32 = 80_877_104;
+const POSTGRES_MAX_STARTUP_SIZE: usize = 10_000;
+
+/// Application protocol of a connection.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
+pub enum Protocol {
+    /// `HTTP/1.0` or `HTTP/1.1`.
+    Http1(Http1Request),
+    /// `HTTP/2` with prior knowledge.
+    Http2,
+    /// `TLS`.
+    Tls(ClientHello),
+    /// `Redis` `RESP`.
+    Redis,
+    /// `PostgreSQL` frontend/backend protocol.
+    Postgres(PostgresStartup),
+}
+
+/// Request line of an `HTTP/1.x` request.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Http1Request {
+    /// Request method, e.g. `GET`.
+    pub method: String,
+    /// Request target, e.g. `/index.html`.
+    pub path: String,
+    /// Protocol version, e.g. `HTTP/1.1`.
+    pub version: String,
+}
+
+/// Properties of a `TLS` `ClientHello` message.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ClientHello {
+    /// Host name from the `server_name` extension (`SNI`).
+    pub server_name: Option<String>,
+    /// Protocols from the `application_layer_protocol_negotiation` extension (`ALPN`).
+    pub alpn: Vec<String>,
+}
+
+/// The first packet of a `PostgreSQL` connection.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PostgresStartup {
+    /// `StartupMessage`.
+    Startup {
+        /// Value of the `user` parameter.
+        user: Option<String>,
+        /// Value of the `database` parameter.
+        database: Option<String>,
+    },
+    /// `SSLRequest`, i.e. the client wants to upgrade the connection to `TLS`.
+    SslRequest,
+    /// `GSSENCRequest`, i.e. the client wants to upgrade the connection to `GSSAPI` encryption.
+    GssEncRequest,
+    /// `CancelRequest`.
+    CancelRequest,
+}
+
+/// Result of the protocol detection.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Detection {
+    /// Protocol has been recognized.
+    Detected(Protocol),
+    /// Data seen so far is a valid beginning of at least one of the known protocols.
+    NeedMoreData,
+    /// Data doesn't belong to any of the known protocols.
+    Unknown,
+}
+
+/// Detects the protocol from the first bytes sent by `Downstream`.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::filter::network::sniff::{self, Detection, Protocol};
+///
+/// assert_eq!(sniff::detect(b"*1\r\n$4\r\nPING\r\n"), Detection::Detected(Protocol::Redis));
+/// assert_eq!(sniff::detect(b"GE"), Detection::NeedMoreData);
+/// assert_eq!(sniff::detect(b"SSH-2.0-OpenSSH_8.2\r\n"), Detection::Unknown);
+/// ```
+pub fn detect(data: &[u8]) -> Detection {
+    let mut need_more_data = false;
+    for detector in &[
+        detect_http2,
+        detect_http1,
+        detect_tls,
+        detect_redis,
+        detect_postgres,
+    ] {
+        match detector(data) {
+            Detection::Detected(protocol) => return Detection::Detected(protocol),
+            Detection::NeedMoreData => need_more_data = true,
+            Detection::Unknown => {}
+        }
+    }
+    if need_more_data {
+        Detection::NeedMoreData
+    } else {
+        Detection::Unknown
+    }
+}
+
+/// Accumulates the first bytes sent by `Downstream` until the protocol can be detected.
+///
+/// `ProtocolSniffer` expects to see every chunk of data exactly once,
+/// i.e. the filter should return [`FilterStatus::Continue`] from `on_downstream_data`.
+///
+/// [`FilterStatus::Continue`]: ../enum.FilterStatus.html#variant.Continue
+pub struct ProtocolSniffer {
+    buffer: Vec<u8>,
+    max_size: usize,
+    result: Option<Detection>,
+}
+
+impl Default for ProtocolSniffer {
+    fn default() -> Self {
+        Self::with_max_size(DEFAULT_MAX_SNIFF_SIZE)
+    }
+}
+
+impl ProtocolSniffer {
+    /// Creates a new sniffer with the [`default`] limit on the number of bytes to inspect.
+    ///
+    /// [`default`]: constant.DEFAULT_MAX_SNIFF_SIZE.html
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new sniffer with a given limit on the number of bytes to inspect.
+    pub fn with_max_size(max_size: usize) -> Self {
+        ProtocolSniffer {
+            buffer: Vec::new(),
+            max_size,
+            result: None,
+        }
+    }
+
+    /// Returns `true` once the protocol has been either detected or found unknown.
+    pub fn is_done(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Returns the final result of the detection, if any.
+    pub fn result(&self) -> Option<&Detection> {
+        self.result.as_ref()
+    }
+
+    /// Consumes the next chunk of data and returns the result of the detection.
+    ///
+    /// Once the result is final, i.e. not [`NeedMoreData`], further data is ignored
+    /// and the same result is returned.
+    ///
+    /// [`NeedMoreData`]: enum.Detection.html#variant.NeedMoreData
+    pub fn feed(&mut self, data: &[u8]) -> Detection {
+        if let Some(result) = &self.result {
+            return result.clone();
+        }
+        let available = self.max_size.saturating_sub(self.buffer.len());
+        self.buffer
+            .extend_from_slice(&data[..data.len().min(available)]);
+
+        let result = match detect(&self.buffer) {
+            Detection::NeedMoreData if self.buffer.len() >= self.max_size => Detection::Unknown,
+            result => result,
+        };
+        if result != Detection::NeedMoreData {
+            self.buffer = Vec::new();
+            self.result = Some(result.clone());
+        }
+        result
+    }
+
+    /// Reads the current chunk of data from `Downstream` and returns the result of the detection.
+    ///
+    /// # Arguments
+    ///
+    /// * `data_size` - size of data, as passed to `on_downstream_data`.
+    /// * `ops`       - a [`trait object`][`DownstreamDataOps`] to read data through.
+    ///
+    /// [`DownstreamDataOps`]: ../trait.DownstreamDataOps.html
+    pub fn read_downstream_data(
+        &mut self,
+        data_size: usize,
+        ops: &dyn DownstreamDataOps,
+    ) -> Result<Detection> {
+        if let Some(result) = &self.result {
+            return Ok(result.clone());
+        }
+        let data = ops.downstream_data(0, data_size)?;
+        Ok(self.feed(&data))
+    }
+}
+
+/// Returns `Ok(())` if `data` is a prefix of `expected` or vice versa.
+fn expect_prefix(data: &[u8], expected: &[u8]) -> std::result::Result<(), Detection> {
+    let len = data.len().min(expected.len());
+    if data[..len] != expected[..len] {
+        Err(Detection::Unknown)
+    } else if len < expected.len() {
+        Err(Detection::NeedMoreData)
+    } else {
+        Ok(())
+    }
+}
+
+fn detect_http2(data: &[u8]) -> Detection {
+    match expect_prefix(data, HTTP2_PREFACE) {
+        Ok(()) => Detection::Detected(Protocol::Http2),
+        Err(detection) => detection,
+    }
+}
+
+fn detect_http1(data: &[u8]) -> Detection {
+    // the request line should fit into the default `max_request_headers_kb` of `Envoy`
+    const MAX_REQUEST_LINE_SIZE: usize = 60 * 1024;
+
+    let method = match data.iter().position(|&b| b == b' ') {
+        Some(pos) => &data[..pos],
+        None => {
+            return if HTTP1_METHODS
+                .iter()
+                .any(|method| method.as_bytes().starts_with(data))
+            {
+                Detection::NeedMoreData
+            } else {
+                Detection::Unknown
+            };
+        }
+    };
+    if !HTTP1_METHODS.iter().any(|m| m.as_bytes() == method) {
+        return Detection::Unknown;
+    }
+    let line = match data.iter().position(|&b| b == b'\n') {
+        Some(pos) => &data[..pos],
+        None if data.len() < MAX_REQUEST_LINE_SIZE => return Detection::NeedMoreData,
+        None => return Detection::Unknown,
+    };
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = match std::str::from_utf8(line) {
+        Ok(line) => line,
+        Err(_) => return Detection::Unknown,
+    };
+    let mut parts = line.splitn(3, ' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version))
+            if !path.is_empty() && (version == "HTTP/1.0" || version == "HTTP/1.1") =>
+        {
+            Detection::Detected(Protocol::Http1(Http1Request {
+                method: method.to_owned(),
+                path: path.to_owned(),
+                version: version.to_owned(),
+            }))
+        }
+        _ => Detection::Unknown,
+    }
+}
+
+fn detect_tls(data: &[u8]) -> Detection {
+    const RECORD_HEADER_SIZE: usize = 5;
+    const HANDSHAKE_HEADER_SIZE: usize = 4;
+    const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+    const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 0x01;
+
+    // reassemble the handshake message out of (possibly several) `TLS` records
+    let mut handshake = Vec::new();
+    let mut records = data;
+    loop {
+        if records.is_empty() {
+            return Detection::NeedMoreData;
+        }
+        if records[0] != CONTENT_TYPE_HANDSHAKE {
+            return Detection::Unknown;
+        }
+        if records.len() >= 2 && records[1] != 0x03 {
+            return Detection::Unknown;
+        }
+        if records.len() >= 3 && records[2] > 0x04 {
+            return Detection::Unknown;
+        }
+        if records.len() < RECORD_HEADER_SIZE {
+            return Detection::NeedMoreData;
+        }
+        let record_size = u16::from_be_bytes([records[3], records[4]]) as usize;
+        let available = (records.len() - RECORD_HEADER_SIZE).min(record_size);
+        handshake.extend_from_slice(&records[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + available]);
+
+        if matches!(handshake.first(), Some(&t) if t != HANDSHAKE_TYPE_CLIENT_HELLO) {
+            return Detection::Unknown;
+        }
+        if handshake.len() >= HANDSHAKE_HEADER_SIZE {
+            let message_size =
+                u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
+            if handshake.len() >= HANDSHAKE_HEADER_SIZE + message_size {
+                let message =
+                    &handshake[HANDSHAKE_HEADER_SIZE..HANDSHAKE_HEADER_SIZE + message_size];
+                return match parse_client_hello(message) {
+                    Some(hello) => Detection::Detected(Protocol::Tls(hello)),
+                    None => Detection::Unknown,
+                };
+            }
+        }
+        if available < record_size {
+            return Detection::NeedMoreData;
+        }
+        records = &records[RECORD_HEADER_SIZE + record_size..];
+    }
+}
+
+fn parse_client_hello(message: &[u8]) -> Option<ClientHello> {
+    const EXTENSION_SERVER_NAME: u16 = 0;
+    const EXTENSION_ALPN: u16 = 16;
+    const SERVER_NAME_TYPE_HOST_NAME: u8 = 0;
+
+    let mut reader = Reader::new(message);
+    reader.skip(2 + 32)?; // client_version + random
+    reader.read_vec8()?; // session_id
+    reader.read_vec16()?; // cipher_suites
+    reader.read_vec8()?; // compression_methods
+
+    let mut hello = ClientHello::default();
+    if reader.is_empty() {
+        return Some(hello);
+    }
+    let mut extensions = Reader::new(reader.read_vec16()?);
+    while !extensions.is_empty() {
+        let extension_type = extensions.read_u16()?;
+        let mut extension = Reader::new(extensions.read_vec16()?);
+        match extension_type {
+            EXTENSION_SERVER_NAME => {
+                let mut names = Reader::new(extension.read_vec16()?);
+                while !names.is_empty() {
+                    let name_type = names.read_u8()?;
+                    let name = names.read_vec16()?;
+                    if name_type == SERVER_NAME_TYPE_HOST_NAME {
+                        hello.server_name = Some(String::from_utf8(name.to_vec()).ok()?);
+                    }
+                }
+            }
+            EXTENSION_ALPN => {
+                let mut protocols = Reader::new(extension.read_vec16()?);
+                while !protocols.is_empty() {
+                    let protocol = protocols.read_vec8()?;
+                    hello
+                        .alpn
+                        .push(String::from_utf8_lossy(protocol).into_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(hello)
+}
+
+fn detect_redis(data: &[u8]) -> Detection {
+    // clients send commands as `RESP` arrays of bulk strings, e.g. `*1\r\n$4\r\nPING\r\n`
+    fn expect_length(data: &[u8], marker: u8) -> std::result::Result<usize, Detection> {
+        match data.first() {
+            None => return Err(Detection::NeedMoreData),
+            Some(&b) if b != marker => return Err(Detection::Unknown),
+            _ => {}
+        }
+        let digits = data[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+        let rest = &data[1 + digits..];
+        if digits > 10 {
+            return Err(Detection::Unknown);
+        }
+        expect_prefix(rest, b"\r\n")?;
+        if digits == 0 {
+            return Err(Detection::Unknown);
+        }
+        Ok(1 + digits + 2)
+    }
+
+    let array_header = match expect_length(data, b'*') {
+        Ok(size) => size,
+        Err(detection) => return detection,
+    };
+    match expect_length(&data[array_header..], b'$') {
+        Ok(_) => Detection::Detected(Protocol::Redis),
+        Err(detection) => detection,
+    }
+}
+
+fn detect_postgres(data: &[u8]) -> Detection {
+    if data.len() < 8 {
+        // message length is a big-endian `int32` that can't exceed `POSTGRES_MAX_STARTUP_SIZE`
+        return if data.iter().take(2).all(|&b| b == 0) {
+            Detection::NeedMoreData
+        } else {
+            Detection::Unknown
+        };
+    }
+    let length = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+    let code = u32::from_be_bytes(data[4..8].try_into().unwrap());
+    let startup = match (code, length) {
+        (POSTGRES_SSL_REQUEST, 8) => PostgresStartup::SslRequest,
+        (POSTGRES_GSSENC_REQUEST, 8) => PostgresStartup::GssEncRequest,
+        (POSTGRES_CANCEL_REQUEST, 16) => PostgresStartup::CancelRequest,
+        (POSTGRES_PROTOCOL_V3, length) if length > 8 && length <= POSTGRES_MAX_STARTUP_SIZE => {
+            if data.len() < length {
+                return Detection::NeedMoreData;
+            }
+            match parse_postgres_parameters(&data[8..length]) {
+                Some((user, database)) => PostgresStartup::Startup { user, database },
+                None => return Detection::Unknown,
+            }
+        }
+        _ => return Detection::Unknown,
+    };
+    Detection::Detected(Protocol::Postgres(startup))
+}
+
+/// Parses `name\0value\0...\0` parameters of a `StartupMessage`.
+fn parse_postgres_parameters(data: &[u8]) -> Option<(Option<String>, Option<String>)> {
+    let mut user = None;
+    let mut database = None;
+    let mut strings = data.split(|&b| b == 0);
+    loop {
+        let name = strings.next()?;
+        if name.is_empty() {
+            return Some((user, database));
+        }
+        let value = String::from_utf8(strings.next()?.to_vec()).ok()?;
+        match name {
+            b"user" => user = Some(value),
+            b"database" => database = Some(value),
+            _ => {}
+        }
+    }
+}
+
+/// Reads big-endian values out of a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn read(&mut self, size: usize) -> Option<&'a [u8]> {
+        if self.data.len() < size {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(size);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, size: usize) -> Option<()> {
+        self.read(size).map(|_| ())
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        self.read(1).map(|bytes| bytes[0])
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        self.read(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_vec8(&mut self) -> Option<&'a [u8]> {
+        let size = self.read_u8()? as usize;
+        self.read(size)
+    }
+
+    fn read_vec16(&mut self) -> Option<&'a [u8]> {
+        let size = self.read_u16()? as usize;
+        self.read(size)
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fake [`Ops`] for unit tests of `Network Filter` internals.
+//!
+//! [`Ops`]: ../trait.Ops.html
+
+use std::cell::RefCell;
+
+use super::{
+    ConnectionCompleteOps, ConnectionFlowOps, ConnectionInfoOps, DownstreamCloseOps,
+    DownstreamDataOps, NewConnectionOps, UpstreamCloseOps, UpstreamDataOps,
+};
+use crate::host::http::client::HttpClientResponseOps;
+use crate::host::{self, ByteString, HeaderMap, StreamInfo};
+
+/// Records names of the operations that have been called.
+#[derive(Default)]
+pub(crate) struct FakeOps {
+    calls: RefCell<Vec<&'static str>>,
+}
+
+impl FakeOps {
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.calls.borrow().clone()
+    }
+
+    fn record<T: Default>(&self, op: &'static str) -> host::Result<T> {
+        self.calls.borrow_mut().push(op);
+        Ok(T::default())
+    }
+}
+
+impl DownstreamDataOps for FakeOps {
+    fn downstream_data(&self, _offset: usize, _max_size: usize) -> host::Result<ByteString> {
+        self.record("downstream_data")
+    }
+
+    fn set_downstream_data(&self, _start: usize, _size: usize, _data: &[u8]) -> host::Result<()> {
+        self.record("set_downstream_data")
+    }
+}
+
+impl UpstreamDataOps for FakeOps {
+    fn upstream_data(&self, _offset: usize, _max_size: usize) -> host::Result<ByteString> {
+        self.record("upstream_data")
+    }
+
+    fn set_upstream_data(&self, _start: usize, _size: usize, _data: &[u8]) -> host::Result<()> {
+        self.record("set_upstream_data")
+    }
+}
+
+impl ConnectionFlowOps for FakeOps {
+    fn close_downstream(&self) -> host::Result<()> {
+        self.record("close_downstream")
+    }
+}
+
+impl ConnectionInfoOps for FakeOps {
+    fn stream_info(&self) -> &dyn StreamInfo {
+        // referring to the default implementation would link `Envoy` host functions
+        unimplemented!("StreamInfo is not available in unit tests of Network Filter internals")
+    }
+}
+
+impl DownstreamCloseOps for FakeOps {}
+
+impl UpstreamCloseOps for FakeOps {}
+
+impl NewConnectionOps for FakeOps {}
+
+impl ConnectionCompleteOps for FakeOps {}
+
+impl HttpClientResponseOps for FakeOps {
+    fn http_call_response_headers(&self) -> host::Result<HeaderMap> {
+        self.record("http_call_response_headers")
+    }
+
+    fn http_call_response_header(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("http_call_response_header")
+    }
+
+    fn http_call_response_body(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+        self.record("http_call_response_body")
+    }
+
+    fn http_call_response_trailers(&self) -> host::Result<HeaderMap> {
+        self.record("http_call_response_trailers")
+    }
+
+    fn http_call_response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        self.record("http_call_response_trailer")
+    }
+}
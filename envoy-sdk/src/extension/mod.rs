@@ -21,15 +21,22 @@ pub use self::error::{Error, ErrorContext, Result};
 pub use self::factory::{ConfigStatus, DrainStatus, ExtensionFactory};
 pub use self::filter::http::HttpFilter;
 pub use self::filter::network::NetworkFilter;
-pub use self::module::{install, Module};
+pub use self::module::{install, Module, VmSettings};
+pub use self::service::Service;
 pub use crate::entrypoint;
 
 mod module;
+mod panic;
+#[cfg(test)]
+mod testing;
 
 pub mod access_logger;
 pub mod error;
 pub mod factory;
 pub mod filter;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod service;
 
 /// Opaque identifier of an extension instance.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
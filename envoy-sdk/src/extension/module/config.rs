@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::vm::{VmSettings, VmStartHook};
 use super::{ContextFactory, ContextFactoryHashMap};
 
 use crate::abi::proxy_wasm::traits::{HttpContext, RootContext, StreamContext};
@@ -22,11 +23,14 @@ use crate::extension::filter::http::{HttpFilter, HttpFilterContext, VoidHttpFilt
 use crate::extension::filter::network::{
     NetworkFilter, NetworkFilterContext, VoidNetworkFilterContext,
 };
+use crate::extension::service::{Service, ServiceContext};
 use crate::extension::{InstanceId, Result};
+use crate::host::ByteString;
 
 /// Registry of extensions provided by the WebAssembly module.
 pub struct Module {
     factories: ContextFactoryHashMap,
+    vm_start: Option<Box<VmStartHook>>,
 }
 
 impl Default for Module {
@@ -39,9 +43,88 @@ impl Module {
     pub fn new() -> Self {
         Module {
             factories: ContextFactoryHashMap::new(),
+            vm_start: None,
         }
     }
 
+    /// Registers a hook that will be called once per WebAssembly VM with
+    /// the VM configuration, i.e. `vm_config.configuration` in `Envoy` config.
+    ///
+    /// The hook is called before any extension gets configured, which makes it
+    /// a proper place to parse VM-wide settings, e.g. shared keys or feature toggles.
+    /// Parsed settings are made available through `settings`.
+    ///
+    /// If the hook returns an error, `Envoy` will be notified that the VM
+    /// failed to start.
+    ///
+    /// Registering a hook again replaces the previous one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use envoy_sdk as envoy;
+    /// # use envoy::extension::HttpFilter;
+    /// #
+    /// # /// My very own `HttpFilter`.
+    /// # struct MyHttpFilter;
+    /// # impl HttpFilter for MyHttpFilter {}
+    /// #
+    /// use envoy::extension::{factory, ConfigStatus, ExtensionFactory, InstanceId, Module, Result, VmSettings};
+    /// use envoy::error::{bail, format_err};
+    /// use envoy::host::ByteString;
+    ///
+    /// /// VM-wide settings.
+    /// struct MySettings {
+    ///     api_key: String,
+    /// }
+    ///
+    /// struct MyHttpFilterFactory {
+    ///     settings: VmSettings<MySettings>,
+    /// }
+    ///
+    /// impl ExtensionFactory for MyHttpFilterFactory {
+    ///     type Extension = MyHttpFilter;
+    ///
+    ///     fn name() -> &'static str { "my_http_filter" }
+    ///
+    ///     fn on_configure(&mut self, _config: ByteString, _ops: &dyn factory::ConfigureOps) -> Result<ConfigStatus> {
+    ///         let settings = self.settings.get().ok_or_else(|| format_err!("VM has not been started"))?;
+    ///         // use `settings.api_key`
+    ///         Ok(ConfigStatus::Accepted)
+    ///     }
+    ///
+    ///     fn new_extension(&mut self, _instance_id: InstanceId) -> Result<Self::Extension> {
+    ///         Ok(MyHttpFilter)
+    ///     }
+    /// }
+    ///
+    /// fn initialize() -> Result<Module> {
+    ///     let settings = VmSettings::new();
+    ///     Module::new()
+    ///         .on_vm_start(&settings, |config| {
+    ///             if config.is_empty() {
+    ///                 bail!("VM configuration must not be empty");
+    ///             }
+    ///             Ok(MySettings { api_key: config.to_string() })
+    ///         })
+    ///         .add_http_filter(move |_instance_id| {
+    ///             Ok(MyHttpFilterFactory { settings: settings.clone() })
+    ///         })
+    /// }
+    /// ```
+    pub fn on_vm_start<T, F>(mut self, settings: &VmSettings<T>, mut on_vm_start: F) -> Self
+    where
+        T: 'static,
+        F: FnMut(ByteString) -> Result<T> + 'static,
+    {
+        let settings = settings.clone();
+        self.vm_start = Some(Box::new(move |config| {
+            settings.set(on_vm_start(config)?);
+            Ok(())
+        }));
+        self
+    }
+
     fn add_extension(mut self, name: &'static str, factory: Box<ContextFactory>) -> Result<Self> {
         if self.factories.insert(name.to_string(), factory).is_some() {
             Err(ModuleError::DuplicateRegistration(name.to_string()).into())
@@ -64,6 +147,30 @@ impl Module {
         self.add_extension(T::name(), factory)
     }
 
+    pub fn add_service<T, F>(self, new: F) -> Result<Self>
+    where
+        T: Service + 'static,
+        F: FnMut(InstanceId) -> Result<T> + 'static,
+    {
+        self.add_service_with(new, |service| {
+            // Bridge between Service abstraction and Proxy Wasm ABI
+            Box::new(ServiceContext::with_default_ops(service))
+        })
+    }
+
+    fn add_service_with<T, F, B>(self, mut new: F, bridge: B) -> Result<Self>
+    where
+        T: Service + 'static,
+        F: FnMut(InstanceId) -> Result<T> + 'static,
+        B: Fn(T) -> Box<dyn RootContext> + 'static,
+    {
+        let factory = Box::new(move |context_id| -> Result<Box<dyn RootContext>> {
+            let service = new(InstanceId::from(context_id))?;
+            Ok(bridge(service))
+        });
+        self.add_extension(T::name(), factory)
+    }
+
     pub fn add_network_filter<T, F>(self, mut new: F) -> Result<Self>
     where
         T: ExtensionFactory + 'static,
@@ -77,19 +184,14 @@ impl Module {
             Ok(Box::new(ExtensionFactoryContext::with_default_ops(
                 network_filter_factory,
                 ChildContextFactory::StreamContextFactory(
-                    |network_filter_factory, instance_id| -> Box<dyn StreamContext> {
-                        let stream_context: Box<dyn StreamContext> =
-                            match <T as ExtensionFactory>::new_extension(
-                                network_filter_factory,
-                                instance_id,
-                            ) {
-                                Ok(network_filter) => {
-                                    Box::new(NetworkFilterContext::with_default_ops(network_filter))
-                                }
-                                Err(err) => {
-                                    Box::new(VoidNetworkFilterContext::with_default_ops(err))
-                                }
-                            };
+                    |network_filter| -> Box<dyn StreamContext> {
+                        let stream_context: Box<dyn StreamContext> = match network_filter {
+                            Ok(network_filter) => Box::new(NetworkFilterContext::with_default_ops(
+                                T::name(),
+                                network_filter,
+                            )),
+                            Err(err) => Box::new(VoidNetworkFilterContext::with_default_ops(err)),
+                        };
                         // Bridge between Network Filter abstraction and Proxy Wasm ABI
                         stream_context
                     },
@@ -112,17 +214,15 @@ impl Module {
             Ok(Box::new(ExtensionFactoryContext::with_default_ops(
                 http_filter_factory,
                 ChildContextFactory::HttpContextFactory(
-                    |http_filter_factory, instance_id| -> Box<dyn HttpContext> {
-                        let http_context: Box<dyn HttpContext> =
-                            match <T as ExtensionFactory>::new_extension(
-                                http_filter_factory,
+                    |instance_id, http_filter| -> Box<dyn HttpContext> {
+                        let http_context: Box<dyn HttpContext> = match http_filter {
+                            Ok(http_filter) => Box::new(HttpFilterContext::with_default_ops(
+                                T::name(),
                                 instance_id,
-                            ) {
-                                Ok(http_filter) => {
-                                    Box::new(HttpFilterContext::with_default_ops(http_filter))
-                                }
-                                Err(err) => Box::new(VoidHttpFilterContext::with_default_ops(err)),
-                            };
+                                http_filter,
+                            )),
+                            Err(err) => Box::new(VoidHttpFilterContext::with_default_ops(err)),
+                        };
                         // Bridge between HTTP Filter abstraction and Proxy Wasm ABI
                         http_context
                     },
@@ -133,8 +233,118 @@ impl Module {
     }
 }
 
-impl Into<ContextFactoryHashMap> for Module {
-    fn into(self) -> ContextFactoryHashMap {
-        self.factories
+impl Module {
+    pub(super) fn into_parts(self) -> (ContextFactoryHashMap, Option<Box<VmStartHook>>) {
+        (self.factories, self.vm_start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::error::bail;
+    use crate::extension::service::testing::FakeOps;
+    use crate::extension::service::ConfigureOps;
+    use crate::extension::testing::FakeErrorSink;
+    use crate::extension::ConfigStatus;
+
+    struct MyService;
+
+    impl Service for MyService {
+        fn name() -> &'static str {
+            "my_service"
+        }
+
+        fn on_configure(
+            &mut self,
+            config: ByteString,
+            ops: &dyn ConfigureOps,
+        ) -> Result<ConfigStatus> {
+            ops.set_tick_period(Duration::from_secs(config.to_string().parse()?))?;
+            Ok(ConfigStatus::Accepted)
+        }
+    }
+
+    /// Fake ops have to outlive contexts created by the module.
+    fn leak<T>(value: T) -> &'static T {
+        Box::leak(Box::new(value))
+    }
+
+    #[test]
+    fn test_module_service_contexts() -> Result<()> {
+        let ops = leak(FakeOps::with_config("5"));
+        let error_sink = leak(FakeErrorSink::default());
+        let instances = Rc::new(RefCell::new(Vec::new()));
+        let created = Rc::clone(&instances);
+        let module = Module::new().add_service_with(
+            move |instance_id| {
+                created.borrow_mut().push(instance_id);
+                Ok(MyService)
+            },
+            move |service| Box::new(ServiceContext::new(service, ops, ops, ops, error_sink)),
+        )?;
+
+        let (mut factories, _) = module.into_parts();
+        let factory = factories.get_mut("my_service").unwrap();
+        let mut root_context = factory(5)?;
+
+        assert_eq!(*instances.borrow(), vec![InstanceId::from(5)]);
+        assert!(root_context.on_configure(1));
+        assert_eq!(
+            ops.calls(),
+            vec!["configuration(0, 1)", "set_tick_period(5s)"]
+        );
+        assert!(error_sink.errors().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_service_context_on_error() -> Result<()> {
+        let module = Module::new().add_service_with(
+            |_| -> Result<MyService> { bail!("invalid instance") },
+            |_| unreachable!(),
+        )?;
+
+        let (mut factories, _) = module.into_parts();
+        let factory = factories.get_mut("my_service").unwrap();
+
+        assert_eq!(factory(5).err().unwrap().to_string(), "invalid instance");
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_vm_settings() -> Result<()> {
+        let settings = VmSettings::new();
+        let module = Module::new().on_vm_start(&settings, |config| -> Result<u32> {
+            Ok(config.to_string().parse()?)
+        });
+
+        let (_, vm_start) = module.into_parts();
+        let mut vm_start = vm_start.unwrap();
+
+        assert_eq!(settings.get(), None);
+        assert!(vm_start("x".into()).is_err());
+        assert_eq!(settings.get(), None);
+        vm_start("42".into())?;
+        assert_eq!(settings.get(), Some(Rc::new(42)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_module_duplicate_service() {
+        let err = Module::new()
+            .add_service_with(|_| Ok(MyService), |_| unreachable!())
+            .and_then(|module| module.add_service_with(|_| Ok(MyService), |_| unreachable!()))
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            ModuleError::DuplicateRegistration("my_service".into()).to_string()
+        );
     }
 }
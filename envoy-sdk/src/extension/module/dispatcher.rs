@@ -12,32 +12,51 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 
-use super::ContextFactoryHashMap;
+use super::vm::{VmLifecycle, VmLifecycleContext, VmStartHook};
+use super::{ContextFactory, ContextFactoryHashMap};
 
 use crate::abi::proxy_wasm;
 use crate::abi::proxy_wasm::traits::{Context, RootContext};
 use crate::extension::error::ConfigurationError;
 use crate::extension::error::ErrorSink;
+use crate::extension::panic::Poisonable;
 use crate::extension::{Error, Result};
 use crate::host::StreamInfo;
 
 pub(crate) struct ContextSelector<'a> {
-    factories: ContextFactoryHashMap,
+    factories: HashMap<String, Poisonable<Box<ContextFactory>>>,
+    vm: Option<Rc<RefCell<VmLifecycle>>>,
     stream_info: &'a dyn StreamInfo,
 }
 
 impl<'a> ContextSelector<'a> {
-    pub fn new(factories: ContextFactoryHashMap, stream_info: &'a dyn StreamInfo) -> Self {
+    pub fn new(
+        factories: ContextFactoryHashMap,
+        vm_start: Option<Box<VmStartHook>>,
+        stream_info: &'a dyn StreamInfo,
+    ) -> Self {
         ContextSelector {
-            factories,
+            factories: factories
+                .into_iter()
+                .map(|(name, factory)| {
+                    let factory = Poisonable::new(name.clone(), factory);
+                    (name, factory)
+                })
+                .collect(),
+            vm: vm_start.map(|hook| Rc::new(RefCell::new(VmLifecycle::new(hook)))),
             stream_info,
         }
     }
 
-    pub fn with_default_ops(factories: ContextFactoryHashMap) -> Self {
-        Self::new(factories, StreamInfo::default())
+    pub fn with_default_ops(
+        factories: ContextFactoryHashMap,
+        vm_start: Option<Box<VmStartHook>>,
+    ) -> Self {
+        Self::new(factories, vm_start, StreamInfo::default())
     }
 
     fn new_root_context(&mut self, context_id: u32) -> Result<Box<dyn RootContext>> {
@@ -46,11 +65,11 @@ impl<'a> ContextSelector<'a> {
             None => String::default(),
         };
         if let Some(root_context_factory) = self.factories.get_mut(&name) {
-            return root_context_factory(context_id);
+            return root_context_factory.call("constructor", |new| new(context_id));
         }
         if name.is_empty() && self.factories.keys().len() == 1 {
             if let Some(root_context_factory) = self.factories.values_mut().next() {
-                return root_context_factory(context_id);
+                return root_context_factory.call("constructor", |new| new(context_id));
             }
         }
         Err(ConfigurationError::UnknownExtension {
@@ -75,8 +94,19 @@ impl ContextSelector<'static> {
             // Specifically, we're relying on the fact that every `proxy_on_context_create`
             // call will be followed by `proxy_on_configure` where we can legally
             // report back to Envoy that configuration is not valid.
-            self.new_root_context(context_id)
-                .unwrap_or_else(|e| Box::new(VoidRootContext::with_default_ops(e)))
+            // On targets that do support stack unwinding, i.e. not inside Envoy,
+            // a panic inside the extension factory is caught and reported the same way.
+            let root_context = self
+                .new_root_context(context_id)
+                .unwrap_or_else(|e| Box::new(VoidRootContext::with_default_ops(e)));
+            // Call the VM start hook, if any, before the root context gets a chance to.
+            match &self.vm {
+                Some(vm) => Box::new(VmLifecycleContext::with_default_ops(
+                    root_context,
+                    Rc::clone(vm),
+                )),
+                None => root_context,
+            }
         });
     }
 }
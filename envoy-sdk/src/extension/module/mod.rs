@@ -19,10 +19,12 @@ use crate::extension::Result;
 
 pub use self::config::Module;
 pub use self::start::install;
+pub use self::vm::VmSettings;
 
 mod config;
 mod dispatcher;
 mod start;
+mod vm;
 
 type ContextFactory = dyn FnMut(u32) -> Result<Box<dyn RootContext>>;
 type ContextFactoryHashMap = HashMap<String, Box<ContextFactory>>;
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::dispatcher::{ContextSelector, VoidContextSelector};
+use crate::extension::panic;
 use crate::extension::{Module, Result};
 
 /// Generates the [`_start`] function that will be called by `Envoy` to let
@@ -127,8 +128,16 @@ macro_rules! entrypoint {
 
 #[doc(hidden)]
 pub fn install(config: Result<Module>) {
+    // Without stack unwinding, i.e. inside `Envoy`, a panic cannot be caught
+    // by the callback it has been raised in.
+    if cfg!(panic = "abort") {
+        panic::set_hook();
+    }
     match config {
-        Ok(module) => ContextSelector::with_default_ops(module.into()).install(),
+        Ok(module) => {
+            let (factories, vm_start) = module.into_parts();
+            ContextSelector::with_default_ops(factories, vm_start).install()
+        }
         Err(err) => VoidContextSelector::new(err).install(),
     }
 }
@@ -0,0 +1,471 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::abi::proxy_wasm::hostcalls;
+use crate::abi::proxy_wasm::traits::{Context, HttpContext, RootContext, StreamContext};
+use crate::abi::proxy_wasm::types::ContextType;
+use crate::extension::error::ErrorSink;
+use crate::extension::panic;
+use crate::extension::{Error, ErrorContext, Result};
+use crate::host::{self, ByteString};
+
+pub(super) type VmStartHook = dyn FnMut(ByteString) -> Result<()>;
+
+/// VM-wide settings parsed by the hook registered with [`Module::on_vm_start`].
+///
+/// Is meant to be cloned into extension factories, which can read the settings
+/// starting from [`ExtensionFactory::on_configure`], since the hook is called
+/// before any extension gets configured.
+///
+/// [`Module::on_vm_start`]: struct.Module.html#method.on_vm_start
+/// [`ExtensionFactory::on_configure`]: factory/trait.ExtensionFactory.html#method.on_configure
+pub struct VmSettings<T> {
+    value: Rc<RefCell<Option<Rc<T>>>>,
+}
+
+impl<T> VmSettings<T> {
+    /// Creates a handle to settings that are not available yet.
+    pub fn new() -> Self {
+        VmSettings {
+            value: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Returns the settings or `None` if the VM has not been started yet
+    /// or the hook has failed.
+    pub fn get(&self) -> Option<Rc<T>> {
+        self.value.borrow().clone()
+    }
+
+    pub(super) fn set(&self, value: T) {
+        *self.value.borrow_mut() = Some(Rc::new(value));
+    }
+}
+
+impl<T> Clone for VmSettings<T> {
+    fn clone(&self) -> Self {
+        VmSettings {
+            value: Rc::clone(&self.value),
+        }
+    }
+}
+
+impl<T> Default for VmSettings<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An interface for accessing VM config.
+pub(crate) trait VmOps {
+    /// Returns VM config.
+    fn configuration(&self, start: usize, max_size: usize) -> host::Result<ByteString>;
+}
+
+impl dyn VmOps {
+    /// Returns the default implementation that interacts with `Envoy`
+    /// through its [`ABI`].
+    ///
+    /// [`ABI`]: https://github.com/proxy-wasm/spec
+    pub fn default() -> &'static dyn VmOps {
+        &Host
+    }
+}
+
+struct Host;
+
+impl VmOps for Host {
+    fn configuration(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
+        hostcalls::get_vm_configuration(start, max_size)
+    }
+}
+
+/// VM-wide state shared by all root contexts of the WebAssembly module.
+pub(super) struct VmLifecycle {
+    on_vm_start: Box<VmStartHook>,
+    outcome: Option<std::result::Result<(), Rc<Error>>>,
+}
+
+impl VmLifecycle {
+    pub fn new(on_vm_start: Box<VmStartHook>) -> Self {
+        VmLifecycle {
+            on_vm_start,
+            outcome: None,
+        }
+    }
+
+    /// Calls the hook unless it has already been called on behalf of another root context.
+    fn start(
+        &mut self,
+        vm_configuration_size: usize,
+        ops: &dyn VmOps,
+    ) -> std::result::Result<(), Rc<Error>> {
+        if let Some(outcome) = &self.outcome {
+            return outcome.clone();
+        }
+        let config = if vm_configuration_size == 0 {
+            Ok(ByteString::default())
+        } else {
+            ops.configuration(0, vm_configuration_size)
+                .context("failed to read VM configuration")
+        };
+        let outcome = config
+            .and_then(|config| {
+                let on_vm_start = &mut self.on_vm_start;
+                panic::catch_unwind(|| on_vm_start(config))
+            })
+            .map_err(Rc::new);
+        self.outcome = Some(outcome.clone());
+        outcome
+    }
+}
+
+/// `Proxy Wasm` [`RootContext`] that calls the VM start hook registered on [`Module`]
+/// before delegating to the actual root context of the extension.
+///
+/// `Envoy` calls [`proxy_on_vm_start`] on every root context, while the hook is only
+/// called once per VM.
+///
+/// [`RootContext`]: https://docs.rs/proxy-wasm/0.1.0/proxy_wasm/traits/trait.RootContext.html
+/// [`Module`]: struct.Module.html
+/// [`proxy_on_vm_start`]: https://github.com/proxy-wasm/spec/tree/master/abi-versions/vNEXT#proxy_on_vm_start
+pub(super) struct VmLifecycleContext<'a> {
+    inner: Box<dyn RootContext>,
+    vm: Rc<RefCell<VmLifecycle>>,
+    vm_ops: &'a dyn VmOps,
+    error_sink: &'a dyn ErrorSink,
+}
+
+impl<'a> VmLifecycleContext<'a> {
+    fn new(
+        inner: Box<dyn RootContext>,
+        vm: Rc<RefCell<VmLifecycle>>,
+        vm_ops: &'a dyn VmOps,
+        error_sink: &'a dyn ErrorSink,
+    ) -> Self {
+        VmLifecycleContext {
+            inner,
+            vm,
+            vm_ops,
+            error_sink,
+        }
+    }
+
+    pub fn with_default_ops(inner: Box<dyn RootContext>, vm: Rc<RefCell<VmLifecycle>>) -> Self {
+        Self::new(
+            inner,
+            vm,
+            <dyn VmOps>::default(),
+            <dyn ErrorSink>::default(),
+        )
+    }
+}
+
+impl<'a> RootContext for VmLifecycleContext<'a> {
+    fn on_vm_start(&mut self, vm_configuration_size: usize) -> bool {
+        let outcome = self
+            .vm
+            .borrow_mut()
+            .start(vm_configuration_size, self.vm_ops);
+        match outcome {
+            Ok(()) => self.inner.on_vm_start(vm_configuration_size),
+            Err(err) => {
+                self.error_sink
+                    .observe("failed to start WebAssembly VM", &err);
+                false // indicate to Envoy that WebAssembly module is in invalid state
+            }
+        }
+    }
+
+    fn on_configure(&mut self, plugin_configuration_size: usize) -> bool {
+        self.inner.on_configure(plugin_configuration_size)
+    }
+
+    fn on_tick(&mut self) {
+        self.inner.on_tick()
+    }
+
+    fn on_queue_ready(&mut self, queue_id: u32) {
+        self.inner.on_queue_ready(queue_id)
+    }
+
+    fn on_log(&mut self) {
+        self.inner.on_log()
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        self.inner.create_http_context(context_id)
+    }
+
+    fn create_stream_context(&self, context_id: u32) -> Option<Box<dyn StreamContext>> {
+        self.inner.create_stream_context(context_id)
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        self.inner.get_type()
+    }
+}
+
+impl<'a> Context for VmLifecycleContext<'a> {
+    fn on_http_call_response(
+        &mut self,
+        token_id: u32,
+        num_headers: usize,
+        body_size: usize,
+        num_trailers: usize,
+    ) {
+        self.inner
+            .on_http_call_response(token_id, num_headers, body_size, num_trailers)
+    }
+
+    fn on_done(&mut self) -> bool {
+        self.inner.on_done()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::error::bail;
+    use crate::extension::testing::FakeErrorSink;
+
+    /// Serves VM configuration and counts how many times it has been read.
+    struct FakeVmOps {
+        config: &'static str,
+        reads: Cell<usize>,
+    }
+
+    impl FakeVmOps {
+        fn with_config(config: &'static str) -> Self {
+            FakeVmOps {
+                config,
+                reads: Cell::new(0),
+            }
+        }
+    }
+
+    impl VmOps for FakeVmOps {
+        fn configuration(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+            self.reads.set(self.reads.get() + 1);
+            Ok(self.config.into())
+        }
+    }
+
+    /// Records callbacks it has been called with.
+    struct FakeRootContext {
+        calls: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl FakeRootContext {
+        fn record(&self, call: String) {
+            self.calls.borrow_mut().push(call);
+        }
+    }
+
+    impl RootContext for FakeRootContext {
+        fn on_vm_start(&mut self, vm_configuration_size: usize) -> bool {
+            self.record(format!("on_vm_start({})", vm_configuration_size));
+            true
+        }
+
+        fn on_configure(&mut self, plugin_configuration_size: usize) -> bool {
+            self.record(format!("on_configure({})", plugin_configuration_size));
+            false
+        }
+
+        fn on_tick(&mut self) {
+            self.record("on_tick".into());
+        }
+
+        fn on_queue_ready(&mut self, queue_id: u32) {
+            self.record(format!("on_queue_ready({})", queue_id));
+        }
+
+        fn on_log(&mut self) {
+            self.record("on_log".into());
+        }
+
+        fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+            self.record(format!("create_http_context({})", context_id));
+            None
+        }
+
+        fn create_stream_context(&self, context_id: u32) -> Option<Box<dyn StreamContext>> {
+            self.record(format!("create_stream_context({})", context_id));
+            None
+        }
+
+        fn get_type(&self) -> Option<ContextType> {
+            Some(ContextType::StreamContext)
+        }
+    }
+
+    impl Context for FakeRootContext {
+        fn on_http_call_response(
+            &mut self,
+            token_id: u32,
+            num_headers: usize,
+            body_size: usize,
+            num_trailers: usize,
+        ) {
+            self.record(format!(
+                "on_http_call_response({}, {}, {}, {})",
+                token_id, num_headers, body_size, num_trailers
+            ));
+        }
+
+        fn on_done(&mut self) -> bool {
+            self.record("on_done".into());
+            false
+        }
+    }
+
+    fn fake_root_context() -> (Box<dyn RootContext>, Rc<RefCell<Vec<String>>>) {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let inner = FakeRootContext {
+            calls: Rc::clone(&calls),
+        };
+        (Box::new(inner), calls)
+    }
+
+    #[test]
+    fn test_vm_start_once() {
+        let settings = VmSettings::new();
+        let hook_settings = settings.clone();
+        let starts = Rc::new(Cell::new(0));
+        let hook_starts = Rc::clone(&starts);
+        let vm = Rc::new(RefCell::new(VmLifecycle::new(Box::new(move |config| {
+            hook_starts.set(hook_starts.get() + 1);
+            hook_settings.set(config.to_string());
+            Ok(())
+        }))));
+        let vm_ops = FakeVmOps::with_config("feature=on");
+        let error_sink = FakeErrorSink::default();
+        let (inner1, calls1) = fake_root_context();
+        let (inner2, calls2) = fake_root_context();
+        let mut ctx1 = VmLifecycleContext::new(inner1, Rc::clone(&vm), &vm_ops, &error_sink);
+        let mut ctx2 = VmLifecycleContext::new(inner2, Rc::clone(&vm), &vm_ops, &error_sink);
+
+        assert_eq!(settings.get(), None);
+        assert!(ctx1.on_vm_start(10));
+        assert!(ctx2.on_vm_start(10));
+
+        assert_eq!(starts.get(), 1);
+        assert_eq!(vm_ops.reads.get(), 1);
+        assert_eq!(settings.get(), Some(Rc::new(String::from("feature=on"))));
+        assert_eq!(*calls1.borrow(), vec!["on_vm_start(10)"]);
+        assert_eq!(*calls2.borrow(), vec!["on_vm_start(10)"]);
+        assert!(error_sink.errors().is_empty());
+    }
+
+    #[test]
+    fn test_vm_start_without_configuration() {
+        let vm = Rc::new(RefCell::new(VmLifecycle::new(Box::new(|config| {
+            assert!(config.is_empty());
+            Ok(())
+        }))));
+        let vm_ops = FakeVmOps::with_config("unexpected");
+        let error_sink = FakeErrorSink::default();
+        let (inner, _) = fake_root_context();
+        let mut ctx = VmLifecycleContext::new(inner, vm, &vm_ops, &error_sink);
+
+        assert!(ctx.on_vm_start(0));
+        assert_eq!(vm_ops.reads.get(), 0);
+    }
+
+    #[test]
+    fn test_vm_start_failure_is_remembered() {
+        let starts = Rc::new(Cell::new(0));
+        let hook_starts = Rc::clone(&starts);
+        let vm = Rc::new(RefCell::new(VmLifecycle::new(Box::new(move |_| {
+            hook_starts.set(hook_starts.get() + 1);
+            bail!("invalid VM config")
+        }))));
+        let vm_ops = FakeVmOps::with_config("feature=maybe");
+        let error_sink = FakeErrorSink::default();
+        let (inner1, calls1) = fake_root_context();
+        let (inner2, calls2) = fake_root_context();
+        let mut ctx1 = VmLifecycleContext::new(inner1, Rc::clone(&vm), &vm_ops, &error_sink);
+        let mut ctx2 = VmLifecycleContext::new(inner2, Rc::clone(&vm), &vm_ops, &error_sink);
+
+        assert!(!ctx1.on_vm_start(13));
+        assert!(!ctx2.on_vm_start(13));
+
+        assert_eq!(starts.get(), 1);
+        assert!(calls1.borrow().is_empty());
+        assert!(calls2.borrow().is_empty());
+        assert_eq!(
+            error_sink.errors(),
+            vec![
+                "failed to start WebAssembly VM: invalid VM config",
+                "failed to start WebAssembly VM: invalid VM config",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vm_start_hook_panic() {
+        let vm = Rc::new(RefCell::new(VmLifecycle::new(Box::new(|_| {
+            panic!("unexpected VM config")
+        }))));
+        let vm_ops = FakeVmOps::with_config("");
+        let error_sink = FakeErrorSink::default();
+        let (inner, calls) = fake_root_context();
+        let mut ctx = VmLifecycleContext::new(inner, vm, &vm_ops, &error_sink);
+
+        assert!(!ctx.on_vm_start(0));
+
+        assert!(calls.borrow().is_empty());
+        assert_eq!(error_sink.errors().len(), 1);
+        assert!(error_sink.errors()[0].contains("unexpected VM config"));
+    }
+
+    #[test]
+    fn test_vm_context_delegates_to_inner_context() {
+        let vm = Rc::new(RefCell::new(VmLifecycle::new(Box::new(|_| Ok(())))));
+        let vm_ops = FakeVmOps::with_config("");
+        let error_sink = FakeErrorSink::default();
+        let (inner, calls) = fake_root_context();
+        let mut ctx = VmLifecycleContext::new(inner, vm, &vm_ops, &error_sink);
+
+        assert!(!ctx.on_configure(5));
+        ctx.on_tick();
+        ctx.on_queue_ready(7);
+        ctx.on_log();
+        ctx.on_http_call_response(3, 1, 10, 0);
+        assert!(ctx.create_http_context(11).is_none());
+        assert!(ctx.create_stream_context(12).is_none());
+        assert_eq!(ctx.get_type(), Some(ContextType::StreamContext));
+        assert!(!ctx.on_done());
+
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                "on_configure(5)",
+                "on_tick",
+                "on_queue_ready(7)",
+                "on_log",
+                "on_http_call_response(3, 1, 10, 0)",
+                "create_http_context(11)",
+                "create_stream_context(12)",
+                "on_done",
+            ]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of `Access Logger` entries as `OTLP` log records.
+
+use serde_json::{json, Value};
+
+use super::trace::TraceParent;
+use super::{
+    delegate_to_shipper, envoy_attributes, hex, http_attributes, int_attribute, string_attribute,
+    Exporter, OtlpConfig, LOGS,
+};
+use crate::extension::access_logger::format::LogFormat;
+use crate::extension::access_logger::{LogEntryKind, LogOps};
+use crate::extension::Result;
+use crate::host::http::client::{HttpClient, HttpClientRequestHandle, HttpClientResponseOps};
+use crate::host::time::unix_nanos;
+use crate::host::{Clock, Stats};
+
+const SEVERITY_INFO: u8 = 9;
+const SEVERITY_ERROR: u8 = 17;
+
+/// Exporter of `Access Logger` entries as `OTLP` log records.
+///
+/// HTTP entries carry `OpenTelemetry` HTTP semantic attributes and, if the request
+/// has a valid `traceparent` header, are correlated with the trace.
+/// TCP entries carry connection-level attributes.
+///
+/// Entries of failed requests (status `5xx`) and connections (non-empty response flags)
+/// get `ERROR` severity, the rest get `INFO` severity.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::access_logger::{AccessLogger, LogOps};
+/// use envoy::extension::otlp::{OtlpConfig, OtlpLogExporter};
+/// use envoy::extension::Result;
+/// use envoy::host::{Clock, HttpClient, HttpClientRequestHandle, HttpClientResponseOps, Stats};
+///
+/// struct MyAccessLogger<'a> {
+///     exporter: OtlpLogExporter<'a>,
+/// }
+///
+/// impl<'a> MyAccessLogger<'a> {
+///     fn new(http_client: &'a dyn HttpClient, clock: &'a dyn Clock, stats: &dyn Stats) -> Result<Self> {
+///         let mut config = OtlpConfig::default();
+///         config.shipper.cluster = "otel_collector".into();
+///         config.service_name = "my-service".into();
+///         Ok(MyAccessLogger {
+///             exporter: OtlpLogExporter::new(&config, http_client, clock, stats)?,
+///         })
+///     }
+/// }
+///
+/// impl<'a> AccessLogger for MyAccessLogger<'a> {
+///     fn name() -> &'static str { "my_access_logger" }
+///
+///     fn on_log(&mut self, logger_ops: &dyn LogOps) -> Result<()> {
+///         self.exporter.export(logger_ops)?;
+///         self.exporter.poll()
+///     }
+///
+///     fn on_http_call_response(
+///         &mut self,
+///         request_id: HttpClientRequestHandle,
+///         num_headers: usize,
+///         _body_size: usize,
+///         _num_trailers: usize,
+///         http_client_ops: &dyn HttpClientResponseOps,
+///     ) -> Result<()> {
+///         self.exporter.on_http_call_response(request_id, num_headers, http_client_ops)?;
+///         Ok(())
+///     }
+/// }
+/// ```
+pub struct OtlpLogExporter<'a> {
+    exporter: Exporter<'a>,
+    clock: &'a dyn Clock,
+    body_format: Option<LogFormat>,
+}
+
+impl<'a> OtlpLogExporter<'a> {
+    /// Creates a new exporter.
+    pub fn new(
+        config: &OtlpConfig,
+        http_client: &'a dyn HttpClient,
+        clock: &'a dyn Clock,
+        stats: &dyn Stats,
+    ) -> Result<Self> {
+        Ok(OtlpLogExporter {
+            exporter: Exporter::new(config, &LOGS, &config.logs_path, http_client, clock, stats)?,
+            clock,
+            body_format: None,
+        })
+    }
+
+    /// Renders body of log records according to a given format.
+    ///
+    /// By default, log records have no body.
+    pub fn with_body_format(mut self, format: LogFormat) -> Self {
+        self.body_format = Some(format);
+        self
+    }
+
+    /// Encodes a log entry as a log record and adds it to the current batch.
+    pub fn export(&mut self, ops: &dyn LogOps) -> Result<()> {
+        let info = ops.stream_info();
+        let now = self.clock.now()?;
+        let time = info.request().time()?.unwrap_or(now);
+
+        let (failed, attributes, trace_parent) = match ops.entry_kind()? {
+            LogEntryKind::Tcp => {
+                let entry = ops.connection_entry()?;
+                let mut attributes = vec![
+                    string_attribute("envoy.log.kind", "tcp"),
+                    int_attribute("network.io.received", entry.bytes_received),
+                    int_attribute("network.io.sent", entry.bytes_sent),
+                ];
+                if let Some(duration) = entry.duration {
+                    attributes.push(int_attribute(
+                        "envoy.duration_ms",
+                        duration.as_millis() as u64,
+                    ));
+                }
+                if let Some(details) = &entry.termination_details {
+                    attributes.push(string_attribute(
+                        "envoy.connection.termination_details",
+                        details,
+                    ));
+                }
+                attributes.extend(envoy_attributes(info)?);
+                (!entry.response_flags.is_empty(), attributes, None)
+            }
+            LogEntryKind::Http => {
+                let mut attributes = vec![string_attribute("envoy.log.kind", "http")];
+                attributes.extend(http_attributes(&|name| ops.request_header(name), info)?);
+                if let Some(duration) = info.request().duration()? {
+                    attributes.push(int_attribute(
+                        "envoy.duration_ms",
+                        duration.as_millis() as u64,
+                    ));
+                }
+                let trace_parent = ops
+                    .request_header(TraceParent::HEADER)?
+                    .and_then(|value| TraceParent::parse(&value.to_string()).ok());
+                let status_code = info.response().status_code()?;
+                (
+                    matches!(status_code, Some(code) if code >= 500),
+                    attributes,
+                    trace_parent,
+                )
+            }
+        };
+
+        let (severity_number, severity_text) = if failed {
+            (SEVERITY_ERROR, "ERROR")
+        } else {
+            (SEVERITY_INFO, "INFO")
+        };
+        let mut record = json!({
+            "timeUnixNano": unix_nanos(time).to_string(),
+            "observedTimeUnixNano": unix_nanos(now).to_string(),
+            "severityNumber": severity_number,
+            "severityText": severity_text,
+            "attributes": attributes,
+        });
+        if let Some(format) = &self.body_format {
+            record["body"] = json!({ "stringValue": format.render(ops)? });
+        }
+        if let Some(parent) = trace_parent {
+            record["traceId"] = Value::String(hex(&parent.trace_id));
+            record["spanId"] = Value::String(hex(&parent.parent_id));
+            record["flags"] = parent.flags.into();
+        }
+        self.exporter.push(&record)
+    }
+
+    delegate_to_shipper!();
+}
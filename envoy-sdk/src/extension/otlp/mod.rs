@@ -0,0 +1,265 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of access logs and HTTP exchanges to an `OpenTelemetry` collector.
+//!
+//! Requires `otlp` feature.
+//!
+//! * [`OtlpLogExporter`] encodes `Access Logger` entries as `OTLP` log records.
+//! * [`OtlpSpanExporter`] exports HTTP exchanges seen by an `HTTP Filter` as `OTLP` spans.
+//!   [`SpanContext`] takes care of `W3C` `traceparent` propagation.
+//!
+//! Both exporters send data in `OTLP/HTTP` `JSON` encoding through [`HttpClient`] to
+//! an `Envoy` `Cluster` of the collector. Batching, retries and counters are provided
+//! by [`LogShipper`].
+//!
+//! [`OtlpLogExporter`]: logs/struct.OtlpLogExporter.html
+//! [`OtlpSpanExporter`]: trace/struct.OtlpSpanExporter.html
+//! [`SpanContext`]: trace/struct.SpanContext.html
+//! [`HttpClient`]: ../../host/http/client/trait.HttpClient.html
+//! [`LogShipper`]: ../access_logger/shipper/struct.LogShipper.html
+
+use serde_json::{json, Value};
+
+use crate::extension::access_logger::shipper::{LogShipper, LogShipperConfig};
+use crate::extension::Result;
+use crate::host::http::client::HttpClient;
+use crate::host::{self, ByteString, Clock, Stats, StreamInfo};
+
+pub use self::logs::OtlpLogExporter;
+pub use self::trace::{IdGenerator, OtlpSpan, OtlpSpanExporter, SpanContext, TraceParent};
+
+pub mod logs;
+pub mod trace;
+
+/// Name of the instrumentation scope of exported data.
+const SCOPE_NAME: &str = "envoy-sdk";
+
+/// Configuration of `OTLP` exporters.
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// Configuration of the collector endpoint, batching and retries.
+    ///
+    /// `path` and `content_type` are set by exporters, `stat_prefix` is extended
+    /// with either `.logs` or `.spans`.
+    pub shipper: LogShipperConfig,
+    /// Path of the `OTLP/HTTP` logs endpoint.
+    pub logs_path: String,
+    /// Path of the `OTLP/HTTP` traces endpoint.
+    pub traces_path: String,
+    /// Value of `service.name` resource attribute.
+    pub service_name: String,
+    /// Extra resource attributes.
+    pub resource_attributes: Vec<(String, String)>,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        OtlpConfig {
+            shipper: LogShipperConfig {
+                stat_prefix: "otlp".into(),
+                ..LogShipperConfig::default()
+            },
+            logs_path: "/v1/logs".into(),
+            traces_path: "/v1/traces".into(),
+            service_name: "envoy".into(),
+            resource_attributes: Vec::new(),
+        }
+    }
+}
+
+/// Layout of `OTLP/JSON` payload of a single signal.
+struct Signal {
+    /// Name of the signal, used in stat names.
+    name: &'static str,
+    /// Name of the list of resource-scoped data.
+    resource_field: &'static str,
+    /// Name of the list of scope-scoped data.
+    scope_field: &'static str,
+    /// Name of the list of records.
+    records_field: &'static str,
+}
+
+const LOGS: Signal = Signal {
+    name: "logs",
+    resource_field: "resourceLogs",
+    scope_field: "scopeLogs",
+    records_field: "logRecords",
+};
+
+const SPANS: Signal = Signal {
+    name: "spans",
+    resource_field: "resourceSpans",
+    scope_field: "scopeSpans",
+    records_field: "spans",
+};
+
+/// Batching exporter of a single `OTLP` signal.
+struct Exporter<'a> {
+    shipper: LogShipper<'a>,
+}
+
+impl<'a> Exporter<'a> {
+    fn new(
+        config: &OtlpConfig,
+        signal: &Signal,
+        path: &str,
+        http_client: &'a dyn HttpClient,
+        clock: &'a dyn Clock,
+        stats: &dyn Stats,
+    ) -> Result<Self> {
+        let mut attributes = vec![string_attribute("service.name", &config.service_name)];
+        attributes.extend(
+            config
+                .resource_attributes
+                .iter()
+                .map(|(key, value)| string_attribute(key, value)),
+        );
+        let resource = json!({ "attributes": attributes });
+        let scope = json!({ "name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION") });
+        let prefix = format!(
+            r#"{{"{}":[{{"resource":{},"{}":[{{"scope":{},"{}":["#,
+            signal.resource_field, resource, signal.scope_field, scope, signal.records_field
+        );
+
+        let shipper = LogShipperConfig {
+            path: path.to_owned(),
+            content_type: "application/json".into(),
+            stat_prefix: format!("{}.{}", config.shipper.stat_prefix, signal.name),
+            batch_prefix: prefix.into_bytes(),
+            batch_separator: b",".to_vec(),
+            batch_suffix: b"]}]}]}".to_vec(),
+            ..config.shipper.clone()
+        };
+        Ok(Exporter {
+            shipper: LogShipper::new(shipper, http_client, clock, stats)?,
+        })
+    }
+
+    fn push(&mut self, record: &Value) -> Result<()> {
+        self.shipper.push(serde_json::to_vec(record)?)
+    }
+}
+
+/// Generates common methods of signal-specific exporters.
+macro_rules! delegate_to_shipper {
+    () => {
+        /// Sends the current batch if it has reached one of its thresholds
+        /// and re-sends failed batches whose backoff has elapsed.
+        pub fn poll(&mut self) -> Result<()> {
+            self.exporter.shipper.poll()
+        }
+
+        /// Sends the current batch regardless of thresholds.
+        pub fn flush(&mut self) -> Result<()> {
+            self.exporter.shipper.flush()
+        }
+
+        /// Returns `true` if there is no data waiting to be sent, waiting for a response
+        /// or waiting for a retry.
+        pub fn is_idle(&self) -> bool {
+            self.exporter.shipper.is_idle()
+        }
+
+        /// Handles a response of the collector.
+        ///
+        /// # Return value
+        ///
+        /// `true` if the response belongs to a request made by the exporter, `false` otherwise.
+        pub fn on_http_call_response(
+            &mut self,
+            request_id: HttpClientRequestHandle,
+            num_headers: usize,
+            http_client_ops: &dyn HttpClientResponseOps,
+        ) -> Result<bool> {
+            self.exporter
+                .shipper
+                .on_http_call_response(request_id, num_headers, http_client_ops)
+        }
+    };
+}
+
+use delegate_to_shipper;
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int_attribute(key: &str, value: u64) -> Value {
+    // 64-bit integers are encoded as strings in `OTLP/JSON`
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns `OpenTelemetry` semantic attributes of an HTTP request.
+fn http_attributes(
+    request_header: &dyn Fn(&str) -> host::Result<Option<ByteString>>,
+    info: &dyn StreamInfo,
+) -> Result<Vec<Value>> {
+    let mut attributes = Vec::new();
+    for (key, header) in &[
+        ("http.request.method", ":method"),
+        ("url.path", ":path"),
+        ("url.scheme", ":scheme"),
+        ("server.address", ":authority"),
+        ("user_agent.original", "user-agent"),
+    ] {
+        if let Some(value) = request_header(header)? {
+            attributes.push(string_attribute(key, &value.to_string()));
+        }
+    }
+    if let Some(protocol) = info.request().protocol()? {
+        attributes.push(string_attribute("network.protocol.name", &protocol));
+    }
+    if let Some(status_code) = info.response().status_code()? {
+        attributes.push(int_attribute(
+            "http.response.status_code",
+            status_code.into(),
+        ));
+    }
+    if let Some(size) = info.request().size()? {
+        attributes.push(int_attribute("http.request.body.size", size));
+    }
+    if let Some(size) = info.response().size()? {
+        attributes.push(int_attribute("http.response.body.size", size));
+    }
+    attributes.extend(envoy_attributes(info)?);
+    Ok(attributes)
+}
+
+/// Returns attributes of a connection that are common for HTTP and TCP.
+fn envoy_attributes(info: &dyn StreamInfo) -> Result<Vec<Value>> {
+    let mut attributes = Vec::new();
+    if let Some(address) = info.source().address()? {
+        attributes.push(string_attribute("client.address", &address));
+    }
+    if let Some(address) = info.upstream().address()? {
+        attributes.push(string_attribute("envoy.upstream.host", &address));
+    }
+    if let Some(cluster) = info.cluster().name()? {
+        attributes.push(string_attribute("envoy.upstream.cluster", &cluster));
+    }
+    if let Some(route) = info.route().name()? {
+        attributes.push(string_attribute("envoy.route.name", &route));
+    }
+    if let Some(flags) = info.response().flags()? {
+        if !flags.is_empty() {
+            attributes.push(string_attribute("envoy.response.flags", &flags.to_string()));
+        }
+    }
+    Ok(attributes)
+}
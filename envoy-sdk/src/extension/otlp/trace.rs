@@ -0,0 +1,466 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of HTTP exchanges as `OTLP` spans.
+
+use std::cell::Cell;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{delegate_to_shipper, hex, http_attributes, Exporter, OtlpConfig, SPANS};
+use crate::error::{bail, ensure};
+use crate::extension::filter::http::{ExchangeCompleteOps, RequestHeadersOps};
+use crate::extension::Result;
+use crate::host::http::client::{HttpClient, HttpClientRequestHandle, HttpClientResponseOps};
+use crate::host::time::unix_nanos;
+use crate::host::{self, Clock, Stats};
+
+/// `W3C` `traceparent` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    /// Id of the trace.
+    pub trace_id: [u8; 16],
+    /// Id of the parent span.
+    pub parent_id: [u8; 8],
+    /// Trace flags.
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// Name of the HTTP header.
+    pub const HEADER: &'static str = "traceparent";
+
+    /// `sampled` trace flag.
+    pub const FLAG_SAMPLED: u8 = 0x01;
+
+    /// Parses value of the `traceparent` header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use envoy_sdk as envoy;
+    /// use envoy::extension::otlp::TraceParent;
+    ///
+    /// let parent = TraceParent::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")?;
+    /// assert!(parent.is_sampled());
+    /// assert_eq!(parent.to_string(), "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01");
+    /// # Ok::<(), envoy::extension::Error>(())
+    /// ```
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        ensure!(
+            value.is_ascii() && value.len() >= 55,
+            "invalid traceparent {:?}: too short",
+            value
+        );
+        let mut version = [0; 1];
+        parse_hex(&value[0..2], &mut version)?;
+        match version[0] {
+            0x00 => ensure!(
+                value.len() == 55,
+                "invalid traceparent {:?}: unexpected trailing data",
+                value
+            ),
+            0xff => bail!("invalid traceparent {:?}: forbidden version", value),
+            // future versions may append fields
+            _ => ensure!(
+                value.len() == 55 || value.as_bytes()[55] == b'-',
+                "invalid traceparent {:?}: unexpected trailing data",
+                value
+            ),
+        }
+        ensure!(
+            value.as_bytes()[2] == b'-'
+                && value.as_bytes()[35] == b'-'
+                && value.as_bytes()[52] == b'-',
+            "invalid traceparent {:?}: malformed",
+            value
+        );
+        let mut trace_id = [0; 16];
+        parse_hex(&value[3..35], &mut trace_id)?;
+        let mut parent_id = [0; 8];
+        parse_hex(&value[36..52], &mut parent_id)?;
+        let mut flags = [0; 1];
+        parse_hex(&value[53..55], &mut flags)?;
+        let flags = flags[0];
+        ensure!(
+            trace_id != [0; 16] && parent_id != [0; 8],
+            "invalid traceparent {:?}: all-zero id",
+            value
+        );
+        Ok(TraceParent {
+            trace_id,
+            parent_id,
+            flags,
+        })
+    }
+
+    /// Returns `true` if the caller may have recorded trace data.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & Self::FLAG_SAMPLED != 0
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.parent_id),
+            self.flags
+        )
+    }
+}
+
+fn parse_hex(value: &str, bytes: &mut [u8]) -> Result<()> {
+    ensure!(
+        value.len() == 2 * bytes.len(),
+        "invalid hex value {:?}",
+        value
+    );
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let digits = &value[2 * i..2 * i + 2];
+        // upper case is not allowed by the spec
+        ensure!(
+            digits
+                .bytes()
+                .all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')),
+            "invalid hex value {:?}",
+            value
+        );
+        *byte = u8::from_str_radix(digits, 16)?;
+    }
+    Ok(())
+}
+
+/// Generator of trace and span ids.
+///
+/// Ids are not cryptographically secure.
+#[derive(Debug)]
+pub struct IdGenerator {
+    state: Cell<u64>,
+}
+
+impl IdGenerator {
+    /// Creates a new generator with a given seed.
+    pub fn new(seed: u64) -> Self {
+        IdGenerator {
+            state: Cell::new(seed),
+        }
+    }
+
+    /// Creates a new generator seeded with the current time.
+    pub fn from_clock(clock: &dyn Clock) -> host::Result<Self> {
+        Ok(Self::new(unix_nanos(clock.now()?)))
+    }
+
+    /// Returns a new non-zero trace id.
+    pub fn trace_id(&self) -> [u8; 16] {
+        let mut id = [0; 16];
+        id[..8].copy_from_slice(&self.next().to_be_bytes());
+        id[8..].copy_from_slice(&self.next_non_zero().to_be_bytes());
+        id
+    }
+
+    /// Returns a new non-zero span id.
+    pub fn span_id(&self) -> [u8; 8] {
+        self.next_non_zero().to_be_bytes()
+    }
+
+    fn next_non_zero(&self) -> u64 {
+        loop {
+            let value = self.next();
+            if value != 0 {
+                return value;
+            }
+        }
+    }
+
+    // splitmix64
+    fn next(&self) -> u64 {
+        let state = self.state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.state.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Context of a span that represents an HTTP exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanContext {
+    /// Id of the trace.
+    pub trace_id: [u8; 16],
+    /// Id of the span.
+    pub span_id: [u8; 8],
+    /// Id of the parent span, if any.
+    pub parent_span_id: Option<[u8; 8]>,
+    /// Trace flags.
+    pub flags: u8,
+}
+
+impl SpanContext {
+    /// Creates a child of a given parent or, in its absence, a root span.
+    ///
+    /// Root spans are sampled.
+    pub fn child_of(parent: Option<&TraceParent>, ids: &IdGenerator) -> Self {
+        match parent {
+            Some(parent) => SpanContext {
+                trace_id: parent.trace_id,
+                span_id: ids.span_id(),
+                parent_span_id: Some(parent.parent_id),
+                flags: parent.flags,
+            },
+            None => SpanContext {
+                trace_id: ids.trace_id(),
+                span_id: ids.span_id(),
+                parent_span_id: None,
+                flags: TraceParent::FLAG_SAMPLED,
+            },
+        }
+    }
+
+    /// Starts a span for the request and propagates it upstream.
+    ///
+    /// Reads `traceparent` header of the request (a malformed one is ignored)
+    /// and replaces it with the one that refers to the new span.
+    ///
+    /// Intended to be called from [`on_request_headers`].
+    ///
+    /// [`on_request_headers`]: ../../filter/http/trait.HttpFilter.html#method.on_request_headers
+    pub fn propagate(ops: &dyn RequestHeadersOps, ids: &IdGenerator) -> Result<Self> {
+        let parent = ops
+            .request_header(TraceParent::HEADER)?
+            .and_then(|value| TraceParent::parse(&value.to_string()).ok());
+        let span = Self::child_of(parent.as_ref(), ids);
+        ops.set_request_header(TraceParent::HEADER, &span.traceparent().to_string())?;
+        Ok(span)
+    }
+
+    /// Returns `traceparent` header that refers to this span.
+    pub fn traceparent(&self) -> TraceParent {
+        TraceParent {
+            trace_id: self.trace_id,
+            parent_id: self.span_id,
+            flags: self.flags,
+        }
+    }
+
+    /// Returns `true` if the span should be recorded.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & TraceParent::FLAG_SAMPLED != 0
+    }
+}
+
+/// `OTLP` span of a completed HTTP exchange.
+///
+/// Spans are of kind `SERVER` and carry `OpenTelemetry` HTTP semantic attributes.
+///
+/// A span can be sent through a [`QueueSender`] to the extension that owns
+/// [`OtlpSpanExporter`].
+///
+/// [`QueueSender`]: ../../../host/shared_queue/struct.QueueSender.html
+/// [`OtlpSpanExporter`]: struct.OtlpSpanExporter.html
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OtlpSpan(Value);
+
+impl OtlpSpan {
+    /// Encodes a completed HTTP exchange as a span.
+    ///
+    /// Intended to be called from [`on_exchange_complete`].
+    ///
+    /// # Return value
+    ///
+    /// `None` if the span should not be recorded.
+    ///
+    /// [`on_exchange_complete`]: ../../filter/http/trait.HttpFilter.html#method.on_exchange_complete
+    pub fn from_exchange(
+        ops: &dyn ExchangeCompleteOps,
+        span: &SpanContext,
+        clock: &dyn Clock,
+    ) -> Result<Option<Self>> {
+        if !span.is_sampled() {
+            return Ok(None);
+        }
+        let info = ops.stream_info();
+        let end = clock.now()?;
+        let start = info.request().time()?.unwrap_or(end);
+        let end = match info.request().duration()? {
+            Some(duration) => start + duration,
+            None => end,
+        };
+        let method = ops
+            .request_header(":method")?
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| "HTTP".into());
+        let name = match info.route().name()? {
+            Some(route) => format!("{} {}", method, route),
+            None => method,
+        };
+        let status_code = info.response().status_code()?;
+        let attributes = http_attributes(&|name| ops.request_header(name), info)?;
+
+        let mut record = json!({
+            "traceId": hex(&span.trace_id),
+            "spanId": hex(&span.span_id),
+            "flags": span.flags,
+            "name": name,
+            "kind": 2, // SPAN_KIND_SERVER
+            "startTimeUnixNano": unix_nanos(start).to_string(),
+            "endTimeUnixNano": unix_nanos(end).to_string(),
+            "attributes": attributes,
+        });
+        if let Some(parent_span_id) = span.parent_span_id {
+            record["parentSpanId"] = hex(&parent_span_id).into();
+        }
+        if matches!(status_code, Some(code) if code >= 500) {
+            record["status"] = json!({ "code": 2 }); // STATUS_CODE_ERROR
+        }
+        Ok(Some(OtlpSpan(record)))
+    }
+}
+
+/// Exporter of [`OtlpSpan`]s.
+///
+/// Responses of the collector are delivered to the context that has made the request.
+/// An `HTTP Filter` context is gone once its HTTP exchange is complete, so the exporter
+/// should be owned by a long-lived extension, e.g. a [`Service`], and `HTTP Filter`s
+/// should hand their spans over to it, e.g. through a [`shared queue`].
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::filter::http::{ExchangeCompleteOps, FilterHeadersStatus, HttpFilter, RequestHeadersOps};
+/// use envoy::extension::otlp::{IdGenerator, OtlpSpan, OtlpSpanExporter, SpanContext};
+/// use envoy::extension::{Result, Service};
+/// use envoy::host::shared_queue::{QueueReceiver, QueueSender, SharedQueueHandle};
+/// use envoy::host::{Clock, HttpClientRequestHandle, HttpClientResponseOps};
+///
+/// /// Records spans of HTTP exchanges.
+/// struct MyHttpFilter<'a> {
+///     spans: &'a QueueSender<'a, OtlpSpan>,
+///     ids: &'a IdGenerator,
+///     clock: &'a dyn Clock,
+///     span: Option<SpanContext>,
+/// }
+///
+/// impl<'a> HttpFilter for MyHttpFilter<'a> {
+///     fn on_request_headers(&mut self, _num_headers: usize, _end_of_stream: bool, ops: &dyn RequestHeadersOps) -> Result<FilterHeadersStatus> {
+///         self.span = Some(SpanContext::propagate(ops, self.ids)?);
+///         Ok(FilterHeadersStatus::Continue)
+///     }
+///
+///     fn on_exchange_complete(&mut self, ops: &dyn ExchangeCompleteOps) -> Result<()> {
+///         if let Some(span) = self.span.take() {
+///             if let Some(span) = OtlpSpan::from_exchange(ops, &span, self.clock)? {
+///                 self.spans.send(&span)?;
+///             }
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// /// Exports spans recorded by `MyHttpFilter`s.
+/// struct MyService<'a> {
+///     spans: QueueReceiver<'a, OtlpSpan>,
+///     exporter: OtlpSpanExporter<'a>,
+/// }
+///
+/// impl<'a> Service for MyService<'a> {
+///     fn name() -> &'static str { "my_service" }
+///
+///     fn on_queue_ready(&mut self, queue_id: SharedQueueHandle) -> Result<()> {
+///         if queue_id == self.spans.handle() {
+///             for span in self.spans.receive_batch(100)? {
+///                 self.exporter.export(&span)?;
+///             }
+///             self.exporter.poll()?;
+///         }
+///         Ok(())
+///     }
+///
+///     fn on_tick(&mut self, _ops: &dyn envoy::extension::service::TickOps) -> Result<()> {
+///         self.exporter.poll()
+///     }
+///
+///     fn on_http_call_response(
+///         &mut self,
+///         request_id: HttpClientRequestHandle,
+///         num_headers: usize,
+///         _body_size: usize,
+///         _num_trailers: usize,
+///         http_client_ops: &dyn HttpClientResponseOps,
+///     ) -> Result<()> {
+///         self.exporter.on_http_call_response(request_id, num_headers, http_client_ops)?;
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// [`OtlpSpan`]: struct.OtlpSpan.html
+/// [`Service`]: ../../service/trait.Service.html
+/// [`shared queue`]: ../../../host/shared_queue/index.html
+pub struct OtlpSpanExporter<'a> {
+    exporter: Exporter<'a>,
+    clock: &'a dyn Clock,
+}
+
+impl<'a> OtlpSpanExporter<'a> {
+    /// Creates a new exporter.
+    pub fn new(
+        config: &OtlpConfig,
+        http_client: &'a dyn HttpClient,
+        clock: &'a dyn Clock,
+        stats: &dyn Stats,
+    ) -> Result<Self> {
+        Ok(OtlpSpanExporter {
+            exporter: Exporter::new(
+                config,
+                &SPANS,
+                &config.traces_path,
+                http_client,
+                clock,
+                stats,
+            )?,
+            clock,
+        })
+    }
+
+    /// Adds a span to the current batch.
+    pub fn export(&mut self, span: &OtlpSpan) -> Result<()> {
+        self.exporter.push(&span.0)
+    }
+
+    /// Encodes a completed HTTP exchange as a span and adds it to the current batch.
+    ///
+    /// Spans that are not sampled are not exported.
+    pub fn export_exchange(
+        &mut self,
+        ops: &dyn ExchangeCompleteOps,
+        span: &SpanContext,
+    ) -> Result<()> {
+        match OtlpSpan::from_exchange(ops, span, self.clock)? {
+            Some(span) => self.export(&span),
+            None => Ok(()),
+        }
+    }
+
+    delegate_to_shipper!();
+}
@@ -0,0 +1,287 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Isolation of panics raised by extension callbacks.
+//!
+//! On targets that support stack unwinding, a panic inside an extension callback
+//! is caught and handled the same way as a returned error.
+//!
+//! On WebAssembly, i.e. inside `Envoy`, a panic cannot be caught.
+//! At the moment, `wasm32-unknown-unknown` and `wasm32-wasi` targets do not support
+//! stack unwinding, so a panic aborts the WebAssembly VM right after the panic hook
+//! has run, and the hook has no way to resume the callback with an error.
+//! Instead, the panic hook installed by [`entrypoint!`] reports the panic together with
+//! the name of the extension and the callback that has panicked, and marks the extension
+//! as poisoned before the VM gets aborted.
+//!
+//! [`entrypoint!`]: ../../macro.entrypoint.html
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use crate::extension::error::{ErrorSink, PanicError};
+use crate::extension::Result;
+
+thread_local! {
+    /// Extension callback being executed on the current thread, if any.
+    static CURRENT_CALLBACK: RefCell<Option<(Poison, &'static str)>> = const { RefCell::new(None) };
+}
+
+/// Calls a given function, converting a panic into an error.
+///
+/// On WebAssembly, a panic aborts the VM instead.
+pub(crate) fn catch_unwind<R, F>(f: F) -> Result<R>
+where
+    F: FnOnce() -> Result<R>,
+{
+    // It's safe to assert unwind safety since the state left behind by a panic
+    // is never observed again, see `Poisonable`.
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(PanicError::Panicked(message(payload.as_ref())).into()))
+}
+
+/// Installs a panic hook that reports a panic inside an extension callback through
+/// the default [`ErrorSink`] and marks the extension as poisoned.
+///
+/// The hook installed previously gets called afterwards.
+///
+/// Is only needed on targets that do not support stack unwinding, otherwise a panic
+/// gets caught and reported by the callback itself.
+///
+/// [`ErrorSink`]: ../error/trait.ErrorSink.html
+pub(crate) fn set_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        on_panic(info.payload(), <dyn ErrorSink>::default());
+        previous(info);
+    }));
+}
+
+fn on_panic(payload: &(dyn Any + Send), error_sink: &dyn ErrorSink) {
+    // the hook must not panic itself, e.g. if the thread is exiting
+    let current = CURRENT_CALLBACK
+        .try_with(|current| {
+            current
+                .try_borrow()
+                .ok()
+                .and_then(|current| current.clone())
+        })
+        .ok()
+        .flatten();
+    if let Some((poison, callback)) = current {
+        let message = message(payload);
+        poison.set(message.clone());
+        error_sink.observe(
+            &format!(
+                "failed to call {} of extension {}",
+                callback, poison.state.extension
+            ),
+            &PanicError::Panicked(message).into(),
+        );
+    }
+}
+
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Box<Any>")
+    }
+}
+
+/// Record of a panic raised by an extension.
+///
+/// Is shared by all the places that call into the same extension, e.g. an `HTTP Filter`
+/// instance that gets resumed by another instance once a callout it waits for completes.
+#[derive(Clone)]
+pub(crate) struct Poison {
+    state: Rc<PoisonState>,
+}
+
+struct PoisonState {
+    extension: Cow<'static, str>,
+    panic: RefCell<Option<String>>,
+}
+
+impl Poison {
+    pub fn new<N>(extension: N) -> Self
+    where
+        N: Into<Cow<'static, str>>,
+    {
+        Poison {
+            state: Rc::new(PoisonState {
+                extension: extension.into(),
+                panic: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Returns `true` if the extension has panicked before.
+    pub fn is_poisoned(&self) -> bool {
+        self.state.panic.borrow().is_some()
+    }
+
+    /// Calls a given callback of the extension unless the extension has panicked before,
+    /// recording a panic raised by it.
+    pub fn guard<R, F>(&self, callback: &'static str, f: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        if let Some(message) = self.state.panic.borrow().as_ref() {
+            return Err(PanicError::Poisoned(message.clone()).into());
+        }
+        // callbacks can be nested, e.g. when an `HTTP Filter` instance resumes another one
+        let previous =
+            CURRENT_CALLBACK.with(|current| current.replace(Some((self.clone(), callback))));
+        let result = catch_unwind(f);
+        CURRENT_CALLBACK.with(|current| current.replace(previous));
+        if let Err(err) = &result {
+            if let Some(PanicError::Panicked(message)) = err.downcast_ref::<PanicError>() {
+                self.set(message.clone());
+            }
+        }
+        result
+    }
+
+    fn set(&self, message: String) {
+        if let Ok(mut panic) = self.state.panic.try_borrow_mut() {
+            panic.get_or_insert(message);
+        }
+    }
+}
+
+/// Extension that is no longer called once it has panicked.
+///
+/// After a panic, the state of the extension might be inconsistent, so every further
+/// callback fails with an error without calling into the extension.
+pub(crate) struct Poisonable<T> {
+    inner: T,
+    poison: Poison,
+}
+
+impl<T> Poisonable<T> {
+    pub fn new<N>(extension: N, inner: T) -> Self
+    where
+        N: Into<Cow<'static, str>>,
+    {
+        Poisonable {
+            inner,
+            poison: Poison::new(extension),
+        }
+    }
+
+    /// Returns `true` if the extension has panicked before.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.is_poisoned()
+    }
+
+    /// Returns the record of a panic raised by the extension.
+    pub fn poison(&self) -> &Poison {
+        &self.poison
+    }
+
+    /// Calls a given callback of the extension unless the extension has panicked before.
+    pub fn call<R, F>(&mut self, callback: &'static str, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> Result<R>,
+    {
+        let inner = &mut self.inner;
+        self.poison.guard(callback, || f(inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::testing::FakeErrorSink;
+
+    #[test]
+    fn test_panic_hook_reports_current_callback() {
+        let error_sink = FakeErrorSink::default();
+        let mut extension = Poisonable::new("my_http_filter", ());
+
+        let result: Result<()> = extension.call("on_request_headers", |_| {
+            on_panic(&"unexpected request", &error_sink);
+            Ok(())
+        });
+
+        assert!(result.is_ok());
+        assert!(extension.is_poisoned());
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to call on_request_headers of extension my_http_filter: extension panicked: unexpected request"]
+        );
+    }
+
+    #[test]
+    fn test_panic_hook_reports_innermost_callback() {
+        let error_sink = FakeErrorSink::default();
+        let mut owner = Poisonable::new("my_http_filter", ());
+        let waiter = Poison::new("my_http_filter");
+
+        let result: Result<()> = owner.call("on_http_call_response", |_| {
+            waiter.guard("on_response", || Ok(()))?;
+            waiter.guard("on_response", || {
+                on_panic(&"unexpected callout", &error_sink);
+                Ok(())
+            })
+        });
+
+        assert!(result.is_ok());
+        assert!(!owner.is_poisoned());
+        assert!(waiter.is_poisoned());
+        assert_eq!(
+            error_sink.errors(),
+            vec!["failed to call on_response of extension my_http_filter: extension panicked: unexpected callout"]
+        );
+    }
+
+    #[test]
+    fn test_panic_hook_ignores_panics_outside_callbacks() {
+        let error_sink = FakeErrorSink::default();
+        let mut extension = Poisonable::new("my_http_filter", ());
+        extension.call("on_request_headers", |_| Ok(())).unwrap();
+
+        on_panic(&"unexpected panic", &error_sink);
+
+        assert!(!extension.is_poisoned());
+        assert!(error_sink.errors().is_empty());
+    }
+
+    #[test]
+    fn test_poisoned_extension_is_not_called() {
+        let mut extension = Poisonable::new("my_http_filter", 0);
+
+        let panicked: Result<()> = extension.call("on_request_headers", |_| panic!("boom"));
+        let poisoned = extension.call("on_response_headers", |calls| {
+            *calls += 1;
+            Ok(())
+        });
+
+        assert_eq!(
+            panicked.unwrap_err().to_string(),
+            "extension panicked: boom"
+        );
+        assert_eq!(
+            poisoned.unwrap_err().to_string(),
+            "extension is poisoned since it has panicked earlier: boom"
+        );
+        assert_eq!(extension.inner, 0);
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{ContextOps, Ops, Service};
+use crate::abi::proxy_wasm::traits::{Context, RootContext};
+use crate::extension::error::ErrorSink;
+use crate::extension::panic::Poisonable;
+use crate::extension::{ConfigStatus, DrainStatus};
+use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
+use crate::host::shared_queue::SharedQueueHandle;
+use crate::host::ByteString;
+
+pub(crate) struct ServiceContext<'a, S>
+where
+    S: Service,
+{
+    service: Poisonable<S>,
+    context_ops: &'a dyn ContextOps,
+    service_ops: &'a dyn Ops,
+    http_client_ops: &'a dyn HttpClientResponseOps,
+    error_sink: &'a dyn ErrorSink,
+}
+
+impl<'a, S> RootContext for ServiceContext<'a, S>
+where
+    S: Service,
+{
+    fn on_configure(&mut self, configuration_size: usize) -> bool {
+        let config = if configuration_size == 0 {
+            Ok(ByteString::default())
+        } else {
+            self.context_ops.configuration(0, configuration_size)
+        };
+        let ops = self.service_ops.as_configure_ops();
+        let service = &mut self.service;
+        match config.and_then(|config| {
+            service.call("on_configure", |service| service.on_configure(config, ops))
+        }) {
+            Ok(status) => status.as_bool(),
+            Err(err) => {
+                self.error_sink
+                    .observe("failed to configure extension", &err);
+                ConfigStatus::Rejected.as_bool()
+            }
+        }
+    }
+
+    fn on_tick(&mut self) {
+        let ops = self.service_ops.as_tick_ops();
+        if let Err(err) = self.service.call("on_tick", |service| service.on_tick(ops)) {
+            self.error_sink
+                .observe("failed to handle a timer tick", &err);
+        }
+    }
+
+    fn on_queue_ready(&mut self, queue_id: u32) {
+        if let Err(err) = self.service.call("on_queue_ready", |service| {
+            service.on_queue_ready(SharedQueueHandle::from(queue_id))
+        }) {
+            self.error_sink
+                .observe("failed to consume messages from a shared queue", &err);
+        }
+    }
+}
+
+impl<'a, S> Context for ServiceContext<'a, S>
+where
+    S: Service,
+{
+    fn on_done(&mut self) -> bool {
+        match self.service.call("on_drain", |service| service.on_drain()) {
+            Ok(status) => status.as_bool(),
+            Err(err) => {
+                self.error_sink
+                    .observe("failed to initiate draining of the extension", &err);
+                // a poisoned extension is never going to complete draining
+                if self.service.is_poisoned() {
+                    DrainStatus::Complete.as_bool()
+                } else {
+                    DrainStatus::Ongoing.as_bool()
+                }
+            }
+        }
+    }
+
+    // Http Client callbacks
+
+    fn on_http_call_response(
+        &mut self,
+        token_id: u32,
+        num_headers: usize,
+        body_size: usize,
+        num_trailers: usize,
+    ) {
+        let http_client_ops = self.http_client_ops;
+        if let Err(err) = self.service.call("on_http_call_response", |service| {
+            service.on_http_call_response(
+                HttpClientRequestHandle::from(token_id),
+                num_headers,
+                body_size,
+                num_trailers,
+                http_client_ops,
+            )
+        }) {
+            self.error_sink.observe(
+                "failed to process a response to an HTTP request made by the extension",
+                &err,
+            );
+        }
+    }
+}
+
+impl<'a, S> ServiceContext<'a, S>
+where
+    S: Service,
+{
+    pub fn new(
+        service: S,
+        context_ops: &'a dyn ContextOps,
+        service_ops: &'a dyn Ops,
+        http_client_ops: &'a dyn HttpClientResponseOps,
+        error_sink: &'a dyn ErrorSink,
+    ) -> Self {
+        ServiceContext {
+            service: Poisonable::new(S::name(), service),
+            context_ops,
+            service_ops,
+            http_client_ops,
+            error_sink,
+        }
+    }
+
+    /// Creates a new Service context bound to the actual Envoy ABI.
+    pub fn with_default_ops(service: S) -> Self {
+        Self::new(
+            service,
+            <dyn ContextOps>::default(),
+            <dyn Ops>::default(),
+            <dyn HttpClientResponseOps>::default(),
+            <dyn ErrorSink>::default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::super::testing::FakeOps;
+    use super::super::{ConfigureOps, TickOps};
+    use super::*;
+    use crate::error::bail;
+    use crate::extension::testing::FakeErrorSink;
+    use crate::extension::Result;
+
+    /// Records callbacks it has been called with.
+    #[derive(Default)]
+    struct RecordingService {
+        events: Rc<RefCell<Vec<String>>>,
+        drain_status: Option<DrainStatus>,
+    }
+
+    impl RecordingService {
+        fn record(&self, event: String) {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
+    impl Service for RecordingService {
+        fn name() -> &'static str {
+            "recording_service"
+        }
+
+        fn on_configure(
+            &mut self,
+            config: ByteString,
+            ops: &dyn ConfigureOps,
+        ) -> Result<ConfigStatus> {
+            self.record(format!("on_configure({:?})", config.to_string()));
+            if config.is_empty() {
+                return Ok(ConfigStatus::Accepted);
+            }
+            let seconds = config.to_string().parse()?;
+            ops.set_tick_period(Duration::from_secs(seconds))?;
+            Ok(ConfigStatus::Accepted)
+        }
+
+        fn on_tick(&mut self, ops: &dyn TickOps) -> Result<()> {
+            self.record("on_tick".into());
+            ops.set_tick_period(Duration::from_secs(0))?;
+            Ok(())
+        }
+
+        fn on_queue_ready(&mut self, queue_id: SharedQueueHandle) -> Result<()> {
+            self.record(format!("on_queue_ready({:?})", queue_id));
+            Ok(())
+        }
+
+        fn on_drain(&mut self) -> Result<DrainStatus> {
+            self.record("on_drain".into());
+            Ok(self.drain_status.unwrap_or(DrainStatus::Complete))
+        }
+
+        fn on_http_call_response(
+            &mut self,
+            request_id: HttpClientRequestHandle,
+            num_headers: usize,
+            body_size: usize,
+            num_trailers: usize,
+            http_client_ops: &dyn HttpClientResponseOps,
+        ) -> Result<()> {
+            let status = http_client_ops.http_call_response_header(":status")?;
+            self.record(format!(
+                "on_http_call_response({:?}, {}, {}, {}): {:?}",
+                request_id,
+                num_headers,
+                body_size,
+                num_trailers,
+                status.map(|status| status.to_string())
+            ));
+            Ok(())
+        }
+    }
+
+    /// Fails in every callback.
+    struct FailingService;
+
+    impl Service for FailingService {
+        fn name() -> &'static str {
+            "failing_service"
+        }
+
+        fn on_configure(
+            &mut self,
+            _config: ByteString,
+            _ops: &dyn ConfigureOps,
+        ) -> Result<ConfigStatus> {
+            bail!("invalid config")
+        }
+
+        fn on_tick(&mut self, _ops: &dyn TickOps) -> Result<()> {
+            bail!("tick failed")
+        }
+
+        fn on_queue_ready(&mut self, _queue_id: SharedQueueHandle) -> Result<()> {
+            bail!("queue failed")
+        }
+
+        fn on_drain(&mut self) -> Result<DrainStatus> {
+            bail!("drain failed")
+        }
+
+        fn on_http_call_response(
+            &mut self,
+            _request_id: HttpClientRequestHandle,
+            _num_headers: usize,
+            _body_size: usize,
+            _num_trailers: usize,
+            _http_client_ops: &dyn HttpClientResponseOps,
+        ) -> Result<()> {
+            bail!("response failed")
+        }
+    }
+
+    #[test]
+    fn test_service_configuration() {
+        let ops = FakeOps::with_config("5");
+        let error_sink = FakeErrorSink::default();
+        let service = RecordingService::default();
+        let events = Rc::clone(&service.events);
+        let mut ctx = ServiceContext::new(service, &ops, &ops, &ops, &error_sink);
+
+        assert!(ctx.on_configure(1));
+
+        assert_eq!(*events.borrow(), vec![r#"on_configure("5")"#]);
+        assert_eq!(
+            ops.calls(),
+            vec!["configuration(0, 1)", "set_tick_period(5s)"]
+        );
+        assert!(error_sink.errors().is_empty());
+    }
+
+    #[test]
+    fn test_service_empty_configuration() {
+        let ops = FakeOps::with_config("unexpected");
+        let error_sink = FakeErrorSink::default();
+        let service = RecordingService::default();
+        let events = Rc::clone(&service.events);
+        let mut ctx = ServiceContext::new(service, &ops, &ops, &ops, &error_sink);
+
+        assert!(ctx.on_configure(0));
+
+        assert_eq!(*events.borrow(), vec![r#"on_configure("")"#]);
+        assert!(ops.calls().is_empty());
+    }
+
+    #[test]
+    fn test_service_callbacks() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let service = RecordingService::default();
+        let events = Rc::clone(&service.events);
+        let mut ctx = ServiceContext::new(service, &ops, &ops, &ops, &error_sink);
+
+        ctx.on_tick();
+        ctx.on_queue_ready(7);
+        ctx.on_http_call_response(3, 1, 10, 0);
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "on_tick".to_string(),
+                format!("on_queue_ready({:?})", SharedQueueHandle::from(7)),
+                format!(
+                    "on_http_call_response({:?}, 1, 10, 0): Some(\"200\")",
+                    HttpClientRequestHandle::from(3)
+                ),
+            ]
+        );
+        assert_eq!(ops.calls(), vec!["set_tick_period(0ns)"]);
+        assert!(error_sink.errors().is_empty());
+    }
+
+    #[test]
+    fn test_service_drain_status() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+
+        let mut ctx =
+            ServiceContext::new(RecordingService::default(), &ops, &ops, &ops, &error_sink);
+        assert!(ctx.on_done());
+
+        let service = RecordingService {
+            drain_status: Some(DrainStatus::Ongoing),
+            ..RecordingService::default()
+        };
+        let events = Rc::clone(&service.events);
+        let mut ctx = ServiceContext::new(service, &ops, &ops, &ops, &error_sink);
+        assert!(!ctx.on_done());
+        assert_eq!(*events.borrow(), vec!["on_drain"]);
+    }
+
+    #[test]
+    fn test_service_errors() {
+        let ops = FakeOps::with_config("5");
+        let error_sink = FakeErrorSink::default();
+        let mut ctx = ServiceContext::new(FailingService, &ops, &ops, &ops, &error_sink);
+
+        assert!(!ctx.on_configure(1));
+        ctx.on_tick();
+        ctx.on_queue_ready(7);
+        ctx.on_http_call_response(3, 1, 10, 0);
+        assert!(!ctx.on_done());
+
+        assert_eq!(
+            error_sink.errors(),
+            vec![
+                "failed to configure extension: invalid config",
+                "failed to handle a timer tick: tick failed",
+                "failed to consume messages from a shared queue: queue failed",
+                "failed to process a response to an HTTP request made by the extension: response failed",
+                "failed to initiate draining of the extension: drain failed",
+            ]
+        );
+    }
+
+    /// Panics on a timer tick.
+    struct PanickingService;
+
+    impl Service for PanickingService {
+        fn name() -> &'static str {
+            "panicking_service"
+        }
+
+        fn on_tick(&mut self, _ops: &dyn TickOps) -> Result<()> {
+            panic!("unexpected tick")
+        }
+
+        fn on_drain(&mut self) -> Result<DrainStatus> {
+            Ok(DrainStatus::Ongoing)
+        }
+    }
+
+    #[test]
+    fn test_poisoned_service_completes_draining() {
+        let ops = FakeOps::default();
+        let error_sink = FakeErrorSink::default();
+        let mut ctx = ServiceContext::new(PanickingService, &ops, &ops, &ops, &error_sink);
+
+        assert!(!ctx.on_done());
+        ctx.on_tick();
+        ctx.on_queue_ready(7);
+        assert!(ctx.on_done());
+
+        assert_eq!(
+            error_sink.errors(),
+            vec![
+                "failed to handle a timer tick: extension panicked: unexpected tick",
+                "failed to consume messages from a shared queue: extension is poisoned since it has panicked earlier: unexpected tick",
+                "failed to initiate draining of the extension: extension is poisoned since it has panicked earlier: unexpected tick",
+            ]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `Envoy` `Service` extension.
+//!
+//! `Service` is a root-only extension that runs independently of traffic, e.g.
+//! a singleton that aggregates events published by `HTTP Filter`s through a shared queue,
+//! refreshes data in [`SharedData`] on a timer or performs HTTP callouts on its own.
+//!
+//! In `Envoy` configuration, a `Service` corresponds to a `WasmService` under
+//! `bootstrap_extensions`, with `singleton: true` to run a single instance per `Envoy`
+//! instead of one per worker thread. `root_id` selects the `Service` by its [`name`].
+//!
+//! Creating a new `Service` extension using `Envoy SDK` consists of the following steps:
+//!
+//! 1. Implement [`Service`] trait to define core logic of your extension
+//! 2. [`Register`] your extension on WebAssembly module start up
+//!
+//! # Examples
+//!
+//! #### Basic [`Service`]:
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! use std::time::Duration;
+//!
+//! use envoy::extension::{ConfigStatus, Result, Service};
+//! use envoy::extension::service::{ConfigureOps, TickOps};
+//! use envoy::host::{ByteString, log};
+//!
+//! /// My very own `Service`.
+//! struct MyService {
+//!     ticks: u64,
+//! }
+//!
+//! impl Service for MyService {
+//!     fn name() -> &'static str { "my_service" }
+//!
+//!     fn on_configure(&mut self, _config: ByteString, ops: &dyn ConfigureOps) -> Result<ConfigStatus> {
+//!         ops.set_tick_period(Duration::from_secs(10))?;
+//!         Ok(ConfigStatus::Accepted)
+//!     }
+//!
+//!     fn on_tick(&mut self, _ops: &dyn TickOps) -> Result<()> {
+//!         self.ticks += 1;
+//!         log::info!("tick #{}", self.ticks);
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! #### Registration of `MyService` on start up:
+//!
+//! ```
+//! # use envoy_sdk as envoy;
+//! # use envoy::extension::Service;
+//! #
+//! # /// My very own `Service`.
+//! # struct MyService;
+//! #
+//! # impl Service for MyService {
+//! #     fn name() -> &'static str { "my_service" }
+//! # }
+//! #
+//! use envoy::extension::{entrypoint, Module, Result};
+//!
+//! entrypoint! { initialize } // put initialization logic into a function to make it unit testable
+//!
+//! fn initialize() -> Result<Module> {
+//!     Module::new()
+//!         .add_service(|_instance_id| Ok(MyService))
+//! }
+//! ```
+//!
+//! [`Service`]: trait.Service.html
+//! [`name`]: trait.Service.html#tymethod.name
+//! [`SharedData`]: ../../host/shared_data/trait.SharedData.html
+//! [`Register`]: ../../macro.entrypoint.html
+
+use std::time::Duration;
+
+use crate::extension::{ConfigStatus, DrainStatus, Result};
+use crate::host::http::client::{HttpClientRequestHandle, HttpClientResponseOps};
+use crate::host::shared_queue::SharedQueueHandle;
+use crate::host::{self, ByteString};
+
+pub(crate) use self::context::ServiceContext;
+
+mod context;
+mod ops;
+#[cfg(test)]
+pub(crate) mod testing;
+
+/// An interface of the `Envoy` `Service` extension.
+///
+/// In contrast to [`AccessLogger`], [`HttpFilter`] and [`NetworkFilter`], `Service` is not
+/// involved in handling of HTTP requests or TCP connections.
+///
+/// # Examples
+///
+/// #### `Service` that aggregates events published by `HTTP Filter`s:
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::extension::{Result, Service};
+/// use envoy::host::shared_queue::SharedQueueHandle;
+/// use envoy::host::{log, SharedQueue};
+///
+/// struct MyService<'a> {
+///     shared_queue: &'a dyn SharedQueue,
+///     events: SharedQueueHandle,
+///     total: usize,
+/// }
+///
+/// impl<'a> Service for MyService<'a> {
+///     fn name() -> &'static str { "my_service" }
+///
+///     fn on_queue_ready(&mut self, queue_id: SharedQueueHandle) -> Result<()> {
+///         if queue_id != self.events {
+///             return Ok(());
+///         }
+///         while let Some(_event) = self.shared_queue.dequeue(queue_id)? {
+///             self.total += 1;
+///         }
+///         log::info!("{} events so far", self.total);
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// # NOTE
+///
+/// **This trait MUST NOT panic!**
+///
+/// If a service invocation cannot proceed normally, it should return [`Result::Err(x)`].
+/// In that case, `Envoy SDK` will be able to handle the error gracefully.
+///
+/// [`AccessLogger`]: ../access_logger/trait.AccessLogger.html
+/// [`HttpFilter`]: ../filter/http/trait.HttpFilter.html
+/// [`NetworkFilter`]: ../filter/network/trait.NetworkFilter.html
+/// [`Result::Err(x)`]: https://doc.rust-lang.org/core/result/enum.Result.html#variant.Err
+pub trait Service {
+    /// Returns a name the extension should be referred to in `Envoy` configuration.
+    fn name() -> &'static str
+    where
+        Self: Sized;
+
+    /// Called when `Service` is being (re-)configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `_config` - configuration.
+    /// * `_ops`    - a [`trait object`][`ConfigureOps`] through which `Service` can start a timer.
+    ///
+    /// # Return value
+    ///
+    /// [`ConfigStatus`] telling `Envoy` whether configuration has been successfully applied.
+    ///
+    /// [`ConfigStatus`]: ../factory/enum.ConfigStatus.html
+    /// [`ConfigureOps`]: trait.ConfigureOps.html
+    fn on_configure(
+        &mut self,
+        _config: ByteString,
+        _ops: &dyn ConfigureOps,
+    ) -> Result<ConfigStatus> {
+        Ok(ConfigStatus::Accepted)
+    }
+
+    /// Called every tick period set through [`TimerOps::set_tick_period`].
+    ///
+    /// # Arguments
+    ///
+    /// * `_ops` - a [`trait object`][`TickOps`] through which `Service` can change the tick period.
+    ///
+    /// [`TimerOps::set_tick_period`]: trait.TimerOps.html#tymethod.set_tick_period
+    /// [`TickOps`]: trait.TickOps.html
+    fn on_tick(&mut self, _ops: &dyn TickOps) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when new messages have been enqueued into a shared queue
+    /// registered by this `Service`.
+    ///
+    /// # Arguments
+    ///
+    /// * `_queue_id` - opaque identifier of the queue.
+    fn on_queue_ready(&mut self, _queue_id: SharedQueueHandle) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when `Service` is about to be destroyed.
+    ///
+    /// # Return value
+    ///
+    /// [`DrainStatus`] telling `Envoy` whether `Service` has already been drained
+    /// and can be now removed safely.
+    ///
+    /// [`DrainStatus`]: ../factory/enum.DrainStatus.html
+    fn on_drain(&mut self) -> Result<DrainStatus> {
+        Ok(DrainStatus::Complete)
+    }
+
+    // Http Client callbacks
+
+    /// Called when the async HTTP request made through [`Envoy HTTP Client API`][`HttpClient`] is complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id`      - opaque identifier of the request that is now complete.
+    /// * `num_headers`     - number of headers in the response.
+    /// * `body_size`       - size of the response body.
+    /// * `num_trailers`    - number of tarilers in the response.
+    /// * `http_client_ops` - a [`trait object`][`HttpClientResponseOps`] through which `Service` can access
+    ///   data of the response received by [`HttpClient`], including headers, body and trailers.
+    ///
+    /// [`HttpClient`]: ../../host/http/client/trait.HttpClient.html
+    /// [`HttpClientResponseOps`]: ../../host/http/client/trait.HttpClientResponseOps.html
+    fn on_http_call_response(
+        &mut self,
+        _request_id: HttpClientRequestHandle,
+        _num_headers: usize,
+        _body_size: usize,
+        _num_trailers: usize,
+        _http_client_ops: &dyn HttpClientResponseOps,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An interface for accessing extension config.
+pub(crate) trait ContextOps {
+    /// Returns extension config.
+    fn configuration(&self, start: usize, max_size: usize) -> host::Result<ByteString>;
+}
+
+impl dyn ContextOps {
+    /// Returns the default implementation that interacts with `Envoy`
+    /// through its [`ABI`].
+    ///
+    /// [`ABI`]: https://github.com/proxy-wasm/spec
+    pub fn default() -> &'static dyn ContextOps {
+        &ops::Host
+    }
+}
+
+/// An interface for managing the timer of a `Service`.
+pub trait TimerOps {
+    /// Makes `Envoy` call [`on_tick`] every `period`.
+    ///
+    /// A zero `period` stops the timer.
+    ///
+    /// [`on_tick`]: trait.Service.html#method.on_tick
+    fn set_tick_period(&self, period: Duration) -> host::Result<()>;
+}
+
+/// An interface for operations available in the context of [`on_configure`]
+/// invocation.
+///
+/// [`on_configure`]: trait.Service.html#method.on_configure
+pub trait ConfigureOps: TimerOps {}
+
+/// An interface for operations available in the context of [`on_tick`]
+/// invocation.
+///
+/// [`on_tick`]: trait.Service.html#method.on_tick
+pub trait TickOps: TimerOps {}
+
+#[doc(hidden)]
+pub trait Ops: ConfigureOps + TickOps {
+    fn as_configure_ops(&self) -> &dyn ConfigureOps;
+
+    fn as_tick_ops(&self) -> &dyn TickOps;
+}
+
+impl<T> Ops for T
+where
+    T: ConfigureOps + TickOps,
+{
+    fn as_configure_ops(&self) -> &dyn ConfigureOps {
+        self
+    }
+
+    fn as_tick_ops(&self) -> &dyn TickOps {
+        self
+    }
+}
+
+impl dyn Ops {
+    /// Returns the default implementation that interacts with `Envoy`
+    /// through its [`ABI`].
+    ///
+    /// [`ABI`]: https://github.com/proxy-wasm/spec
+    pub fn default() -> &'static dyn Ops {
+        &ops::Host
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fake [`Ops`] for unit tests of `Service` internals.
+//!
+//! [`Ops`]: ../trait.Ops.html
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use super::{ConfigureOps, ContextOps, TickOps, TimerOps};
+use crate::host::http::client::HttpClientResponseOps;
+use crate::host::{self, ByteString, HeaderMap};
+
+/// Serves configuration and records what `Service` has done through it.
+#[derive(Default)]
+pub(crate) struct FakeOps {
+    config: &'static str,
+    calls: RefCell<Vec<String>>,
+}
+
+impl FakeOps {
+    pub fn with_config(config: &'static str) -> Self {
+        FakeOps {
+            config,
+            calls: RefCell::default(),
+        }
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.borrow().clone()
+    }
+}
+
+impl ContextOps for FakeOps {
+    fn configuration(&self, start: usize, max_size: usize) -> host::Result<ByteString> {
+        self.calls
+            .borrow_mut()
+            .push(format!("configuration({}, {})", start, max_size));
+        Ok(self.config.into())
+    }
+}
+
+impl TimerOps for FakeOps {
+    fn set_tick_period(&self, period: Duration) -> host::Result<()> {
+        self.calls
+            .borrow_mut()
+            .push(format!("set_tick_period({:?})", period));
+        Ok(())
+    }
+}
+
+impl ConfigureOps for FakeOps {}
+
+impl TickOps for FakeOps {}
+
+impl HttpClientResponseOps for FakeOps {
+    fn http_call_response_headers(&self) -> host::Result<HeaderMap> {
+        Ok(HeaderMap::default())
+    }
+
+    fn http_call_response_header(&self, name: &str) -> host::Result<Option<ByteString>> {
+        Ok(if name == ":status" {
+            Some("200".into())
+        } else {
+            None
+        })
+    }
+
+    fn http_call_response_body(&self, _start: usize, _max_size: usize) -> host::Result<ByteString> {
+        Ok(ByteString::default())
+    }
+
+    fn http_call_response_trailers(&self) -> host::Result<HeaderMap> {
+        Ok(HeaderMap::default())
+    }
+
+    fn http_call_response_trailer(&self, _name: &str) -> host::Result<Option<ByteString>> {
+        Ok(None)
+    }
+}
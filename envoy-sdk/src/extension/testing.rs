@@ -0,0 +1,41 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fakes for unit tests of extension internals.
+
+use std::cell::RefCell;
+
+use super::error::ErrorSink;
+use super::Error;
+
+/// Records errors instead of logging them.
+#[derive(Default)]
+pub(crate) struct FakeErrorSink {
+    errors: RefCell<Vec<String>>,
+}
+
+impl FakeErrorSink {
+    /// Returns observed errors formatted as `"{context}: {error}"`.
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.borrow().clone()
+    }
+}
+
+impl ErrorSink for FakeErrorSink {
+    fn observe(&self, context: &str, err: &Error) {
+        self.errors
+            .borrow_mut()
+            .push(format!("{}: {}", context, err));
+    }
+}
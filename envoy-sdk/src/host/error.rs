@@ -78,6 +78,62 @@ impl std::error::Error for HostResponseError {
     }
 }
 
+/// An error caused by a concurrent modification of shared data,
+/// i.e. a mismatch of the optimistic lock version.
+///
+/// Unlike other host errors, it is expected under contention and the operation
+/// can be retried with a fresh version.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use envoy::host::error::{is_cas_mismatch, CasMismatchError};
+/// use envoy::host::Error;
+///
+/// let err: Error = CasMismatchError::new("shared_key").into();
+///
+/// assert!(is_cas_mismatch(&err));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CasMismatchError {
+    key: String,
+}
+
+impl CasMismatchError {
+    /// Creates a new error for a given key of shared data.
+    pub fn new<K>(key: K) -> Self
+    where
+        K: Into<String>,
+    {
+        CasMismatchError { key: key.into() }
+    }
+
+    /// Returns the key of shared data.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl fmt::Display for CasMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "shared data under the key \"{}\" has been modified concurrently",
+            self.key
+        )
+    }
+}
+
+impl std::error::Error for CasMismatchError {}
+
+/// Returns `true` if a given error has been caused by a concurrent modification of shared data.
+///
+/// Takes into account errors wrapped with a context.
+pub fn is_cas_mismatch(err: &Error) -> bool {
+    err.downcast_ref::<CasMismatchError>().is_some()
+}
+
 /// Represents a host ABI function.
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub(crate) struct Function {
@@ -20,6 +20,9 @@ use crate::host::{self, ByteString, HeaderMap};
 
 pub use crate::abi::proxy_wasm::types::HttpRequestHandle as HttpClientRequestHandle;
 
+/// Extra time to wait for a response after the request timeout has elapsed.
+pub(crate) const IN_FLIGHT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
 /// An interface of the `Envoy` `HTTP Client`.
 ///
 /// # Examples
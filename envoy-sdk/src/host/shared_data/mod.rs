@@ -13,11 +13,21 @@
 // limitations under the License.
 
 //! `Envoy` `Shared Data API`.
+//!
+//! With `json` feature enabled, [`TypedSharedData`] stores `serde`-serializable values
+//! and retries updates that conflict with concurrent modifications.
+//!
+//! [`TypedSharedData`]: struct.TypedSharedData.html
 
 use crate::host::{self, ByteString};
 
+#[cfg(feature = "json")]
+pub use self::typed::TypedSharedData;
 pub use crate::abi::proxy_wasm::types::OptimisticLockVersion;
 
+#[cfg(feature = "json")]
+mod typed;
+
 /// An interface of the `Envoy` `Shared Data API`.
 ///
 /// Basic usage of [`SharedData`]:
@@ -0,0 +1,160 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shared data with typed values.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{OptimisticLockVersion, SharedData};
+use crate::error::ErrorContext;
+use crate::host;
+use crate::host::error::is_cas_mismatch;
+
+/// A typed view of [`SharedData`].
+///
+/// Values are encoded as `JSON`.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # use envoy::host::Result;
+/// # fn action() -> Result<()> {
+/// use envoy::host::SharedData;
+/// use envoy::host::shared_data::TypedSharedData;
+///
+/// let shared_data = TypedSharedData::new(SharedData::default());
+///
+/// let total: u64 = shared_data.update("requests_total", |total: Option<u64>| {
+///     Ok(total.unwrap_or(0) + 1)
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`SharedData`]: trait.SharedData.html
+pub struct TypedSharedData<'a> {
+    shared_data: &'a dyn SharedData,
+    max_attempts: usize,
+}
+
+impl<'a> TypedSharedData<'a> {
+    /// Default limit on attempts of [`update`].
+    ///
+    /// [`update`]: #method.update
+    pub const DEFAULT_MAX_ATTEMPTS: usize = 10;
+
+    /// Creates a new typed view of a given [`SharedData`].
+    ///
+    /// [`SharedData`]: trait.SharedData.html
+    pub fn new(shared_data: &'a dyn SharedData) -> Self {
+        TypedSharedData {
+            shared_data,
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Limits the number of attempts of [`update`].
+    ///
+    /// [`update`]: #method.update
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Returns the value under a given key.
+    pub fn get<T>(&self, key: &str) -> host::Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.get_with_version(key).map(|(value, _)| value)
+    }
+
+    /// Returns the value under a given key together with its optimistic lock version.
+    pub fn get_with_version<T>(
+        &self,
+        key: &str,
+    ) -> host::Result<(Option<T>, Option<OptimisticLockVersion>)>
+    where
+        T: DeserializeOwned,
+    {
+        let (value, version) = self.shared_data.get(key)?;
+        let value = match value {
+            Some(value) if !value.is_empty() => Some(
+                serde_json::from_slice(&value)
+                    .with_context(|| format!("failed to decode shared data under key {:?}", key))?,
+            ),
+            _ => None,
+        };
+        Ok((value, version))
+    }
+
+    /// Stores a value under a given key.
+    ///
+    /// If `version` is given and the value has been modified since then,
+    /// fails with [`CasMismatchError`].
+    ///
+    /// [`CasMismatchError`]: ../error/struct.CasMismatchError.html
+    pub fn set<T>(
+        &self,
+        key: &str,
+        value: &T,
+        version: Option<OptimisticLockVersion>,
+    ) -> host::Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        let value = serde_json::to_vec(value)
+            .with_context(|| format!("failed to encode shared data under key {:?}", key))?;
+        self.shared_data.set(key, &value, version)
+    }
+
+    /// Replaces the value under a given key with the one computed from the current value.
+    ///
+    /// If the value gets modified concurrently, e.g. by another worker thread,
+    /// the update is retried with the fresh value, up to the configured number of attempts.
+    /// Once attempts are exhausted, fails with [`CasMismatchError`].
+    ///
+    /// Since there is no version for a missing key, concurrent updates of
+    /// a missing key are not detected.
+    ///
+    /// # Return value
+    ///
+    /// The new value.
+    ///
+    /// [`CasMismatchError`]: ../error/struct.CasMismatchError.html
+    pub fn update<T, F>(&self, key: &str, mut f: F) -> host::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnMut(Option<T>) -> host::Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            let (value, version) = self.get_with_version(key)?;
+            let value = f(value)?;
+            match self.set(key, &value, version) {
+                Ok(()) => return Ok(value),
+                Err(err) if is_cas_mismatch(&err) && attempt < self.max_attempts => attempt += 1,
+                Err(err) if is_cas_mismatch(&err) => {
+                    return Err(err.context(format!(
+                        "failed to update shared data under key {:?} in {} attempts",
+                        key, attempt
+                    )))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
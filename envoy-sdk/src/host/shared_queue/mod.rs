@@ -13,11 +13,22 @@
 // limitations under the License.
 
 //! `Envoy` `Shared Queue API`.
+//!
+//! With `json` feature enabled, [`QueueSender`] and [`QueueReceiver`] exchange
+//! `serde`-serializable messages, e.g. between `HTTP Filter`s and a singleton aggregator.
+//!
+//! [`QueueSender`]: struct.QueueSender.html
+//! [`QueueReceiver`]: struct.QueueReceiver.html
 
 use crate::host::{self, ByteString};
 
+#[cfg(feature = "json")]
+pub use self::typed::{QueueReceiver, QueueSender};
 pub use crate::abi::proxy_wasm::types::SharedQueueHandle;
 
+#[cfg(feature = "json")]
+mod typed;
+
 /// An interface of the `Envoy` `Shared Queue API`.
 ///
 /// Basic usage of [`SharedQueue`]:
@@ -0,0 +1,329 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shared queues with typed messages.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::{SharedQueue, SharedQueueHandle};
+use crate::error::ErrorContext;
+use crate::host::{self, Clock};
+
+/// Encoded message along with the version of its schema.
+#[derive(Serialize)]
+struct Envelope<'m, T> {
+    version: u32,
+    message: &'m T,
+}
+
+#[derive(Deserialize)]
+struct RawEnvelope {
+    version: u32,
+    message: serde_json::Value,
+}
+
+/// Sending side of a typed shared queue.
+///
+/// Messages are encoded as `JSON` and tagged with a version of their schema.
+///
+/// The queue is resolved lazily, so that a `HTTP Filter` can be created before the VM
+/// that consumes the queue is up. Until then, messages are kept in a bounded buffer and
+/// the queue is looked up again, at most once per [`retry_interval`].
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # use envoy::host::Result;
+/// # fn action() -> Result<()> {
+/// use serde::Serialize;
+/// use envoy::host::{Clock, SharedQueue};
+/// use envoy::host::shared_queue::QueueSender;
+///
+/// #[derive(Serialize)]
+/// struct RequestEvent {
+///     path: String,
+///     status: u16,
+/// }
+///
+/// let sender = QueueSender::new(SharedQueue::default(), Clock::default(), "aggregator", "events");
+///
+/// sender.send(&RequestEvent { path: "/".into(), status: 200 })?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`retry_interval`]: #method.with_retry_interval
+pub struct QueueSender<'a, T> {
+    shared_queue: &'a dyn SharedQueue,
+    clock: &'a dyn Clock,
+    vm_id: String,
+    name: String,
+    version: u32,
+    retry_interval: Duration,
+    max_pending: usize,
+
+    handle: Cell<Option<SharedQueueHandle>>,
+    next_lookup_at: Cell<Option<SystemTime>>,
+    pending: RefCell<VecDeque<Vec<u8>>>,
+    dropped: Cell<u64>,
+    _message: PhantomData<fn(&T)>,
+}
+
+impl<'a, T> QueueSender<'a, T>
+where
+    T: Serialize,
+{
+    /// Default interval between lookups of a queue that has not been registered yet.
+    pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Default limit on the number of messages kept until the queue gets resolved.
+    pub const DEFAULT_MAX_PENDING: usize = 1000;
+
+    /// Creates a new sender.
+    ///
+    /// # Arguments
+    ///
+    /// * `shared_queue` - [`SharedQueue`] to send messages through.
+    /// * `clock`        - [`Clock`] to schedule lookups of the queue with.
+    /// * `vm_id`        - id of the VM that has registered the queue.
+    /// * `name`         - name of the queue.
+    ///
+    /// [`SharedQueue`]: trait.SharedQueue.html
+    /// [`Clock`]: ../time/trait.Clock.html
+    pub fn new(
+        shared_queue: &'a dyn SharedQueue,
+        clock: &'a dyn Clock,
+        vm_id: &str,
+        name: &str,
+    ) -> Self {
+        QueueSender {
+            shared_queue,
+            clock,
+            vm_id: vm_id.to_owned(),
+            name: name.to_owned(),
+            version: 1,
+            retry_interval: Self::DEFAULT_RETRY_INTERVAL,
+            max_pending: Self::DEFAULT_MAX_PENDING,
+            handle: Cell::new(None),
+            next_lookup_at: Cell::new(None),
+            pending: RefCell::new(VecDeque::new()),
+            dropped: Cell::new(0),
+            _message: PhantomData,
+        }
+    }
+
+    /// Tags messages with a given version of their schema. Defaults to `1`.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets interval between lookups of a queue that has not been registered yet.
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Limits the number of messages kept until the queue gets resolved.
+    ///
+    /// Once the limit is reached, the oldest messages get dropped.
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Returns the number of messages waiting for the queue to be resolved.
+    pub fn pending(&self) -> usize {
+        self.pending.borrow().len()
+    }
+
+    /// Returns the number of messages that have been dropped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.get()
+    }
+
+    /// Sends a message.
+    ///
+    /// If the queue is not registered yet, the message is kept until it gets resolved.
+    pub fn send(&self, message: &T) -> host::Result<()> {
+        let value = serde_json::to_vec(&Envelope {
+            version: self.version,
+            message,
+        })
+        .context("failed to encode a message")?;
+        self.pending.borrow_mut().push_back(value);
+        let result = self.flush();
+        let mut pending = self.pending.borrow_mut();
+        while pending.len() > self.max_pending {
+            pending.pop_front();
+            self.dropped.set(self.dropped.get() + 1);
+        }
+        result
+    }
+
+    /// Sends messages kept until the queue gets resolved.
+    pub fn flush(&self) -> host::Result<()> {
+        loop {
+            let value = match self.pending.borrow_mut().pop_front() {
+                Some(value) => value,
+                None => return Ok(()),
+            };
+            if let Err(err) = self.enqueue(&value) {
+                self.pending.borrow_mut().push_front(value);
+                return err.map_or(Ok(()), Err);
+            }
+        }
+    }
+
+    /// Enqueues a message, returning `Err(None)` if the queue is not registered yet.
+    fn enqueue(&self, value: &[u8]) -> Result<(), Option<host::Error>> {
+        let handle = self.resolve()?.ok_or(None)?;
+        if self.shared_queue.enqueue(handle, value).is_ok() {
+            return Ok(());
+        }
+        // the consumer VM might have been restarted
+        self.handle.set(None);
+        self.next_lookup_at.set(None);
+        let handle = self.resolve()?.ok_or(None)?;
+        self.shared_queue.enqueue(handle, value).map_err(Some)
+    }
+
+    fn resolve(&self) -> host::Result<Option<SharedQueueHandle>> {
+        if let Some(handle) = self.handle.get() {
+            return Ok(Some(handle));
+        }
+        let now = self.clock.now()?;
+        if let Some(next_lookup_at) = self.next_lookup_at.get() {
+            if now < next_lookup_at {
+                return Ok(None);
+            }
+        }
+        let handle = self.shared_queue.lookup(&self.vm_id, &self.name)?;
+        match handle {
+            Some(handle) => self.handle.set(Some(handle)),
+            None => self.next_lookup_at.set(Some(now + self.retry_interval)),
+        }
+        Ok(handle)
+    }
+}
+
+/// Receiving side of a typed shared queue.
+///
+/// Messages with a version other than the expected one, as well as messages that cannot
+/// be decoded, are skipped.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # use envoy::host::Result;
+/// # fn action() -> Result<()> {
+/// use serde::Deserialize;
+/// use envoy::host::SharedQueue;
+/// use envoy::host::shared_queue::QueueReceiver;
+///
+/// #[derive(Deserialize)]
+/// struct RequestEvent {
+///     path: String,
+///     status: u16,
+/// }
+///
+/// let receiver = QueueReceiver::<RequestEvent>::register(SharedQueue::default(), "events")?;
+///
+/// for event in receiver.receive_batch(100)? {
+///     // aggregate events
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct QueueReceiver<'a, T> {
+    shared_queue: &'a dyn SharedQueue,
+    handle: SharedQueueHandle,
+    version: u32,
+    skipped: Cell<u64>,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<'a, T> QueueReceiver<'a, T>
+where
+    T: DeserializeOwned,
+{
+    /// Registers a queue with a given name and returns the receiver of its messages.
+    pub fn register(shared_queue: &'a dyn SharedQueue, name: &str) -> host::Result<Self> {
+        let handle = shared_queue
+            .register(name)
+            .with_context(|| format!("failed to register shared queue {:?}", name))?;
+        Ok(QueueReceiver {
+            shared_queue,
+            handle,
+            version: 1,
+            skipped: Cell::new(0),
+            _message: PhantomData,
+        })
+    }
+
+    /// Accepts messages with a given version of their schema. Defaults to `1`.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Returns the handle of the queue.
+    pub fn handle(&self) -> SharedQueueHandle {
+        self.handle
+    }
+
+    /// Returns the number of messages that have been skipped.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.get()
+    }
+
+    /// Receives the next message, if any.
+    pub fn receive(&self) -> host::Result<Option<T>> {
+        while let Some(value) = self.shared_queue.dequeue(self.handle)? {
+            match self.decode(&value) {
+                Some(message) => return Ok(Some(message)),
+                None => self.skipped.set(self.skipped.get() + 1),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Receives up to `max_messages` messages.
+    pub fn receive_batch(&self, max_messages: usize) -> host::Result<Vec<T>> {
+        let mut messages = Vec::new();
+        while messages.len() < max_messages {
+            match self.receive()? {
+                Some(message) => messages.push(message),
+                None => break,
+            }
+        }
+        Ok(messages)
+    }
+
+    fn decode(&self, value: &[u8]) -> Option<T> {
+        let envelope: RawEnvelope = serde_json::from_slice(value).ok()?;
+        if envelope.version != self.version {
+            return None;
+        }
+        serde_json::from_value(envelope.message).ok()
+    }
+}
@@ -0,0 +1,239 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics with labels.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{Counter, Gauge, Histogram, Stats};
+use crate::error::ensure;
+use crate::host;
+
+/// Kind of a metric that can be grouped into a [`MetricFamily`].
+///
+/// [`MetricFamily`]: struct.MetricFamily.html
+pub trait Metric {
+    /// Defines a metric with a given name.
+    fn define(stats: &dyn Stats, name: &str) -> host::Result<Rc<Self>>;
+}
+
+impl Metric for dyn Counter {
+    fn define(stats: &dyn Stats, name: &str) -> host::Result<Rc<Self>> {
+        stats.counter(name).map(Rc::from)
+    }
+}
+
+impl Metric for dyn Gauge {
+    fn define(stats: &dyn Stats, name: &str) -> host::Result<Rc<Self>> {
+        stats.gauge(name).map(Rc::from)
+    }
+}
+
+impl Metric for dyn Histogram {
+    fn define(stats: &dyn Stats, name: &str) -> host::Result<Rc<Self>> {
+        stats.histogram(name).map(Rc::from)
+    }
+}
+
+/// A family of [`Counter`]s that share the name but differ in label values.
+///
+/// [`Counter`]: trait.Counter.html
+pub type CounterFamily<'a> = MetricFamily<'a, dyn Counter>;
+
+/// A family of [`Gauge`]s that share the name but differ in label values.
+///
+/// [`Gauge`]: trait.Gauge.html
+pub type GaugeFamily<'a> = MetricFamily<'a, dyn Gauge>;
+
+/// A family of [`Histogram`]s that share the name but differ in label values.
+///
+/// [`Histogram`]: trait.Histogram.html
+pub type HistogramFamily<'a> = MetricFamily<'a, dyn Histogram>;
+
+/// A family of metrics that share the name but differ in label values,
+/// e.g. `requests_total{response_code="200"}` and `requests_total{response_code="503"}`.
+///
+/// Label values are encoded into the stat name following the convention
+/// `<label>=.=<value>;.;` (for each label) followed by the name of the family, e.g.
+/// `response_code=.=200;.;requests_total`.
+///
+/// To let `Envoy` extract labels into tags, add a [`stats_tags`] entry per label
+/// into the `Bootstrap` config, using the regex returned by [`tag_extraction_regex`]:
+///
+/// ```yaml
+/// stats_config:
+///   stats_tags:
+///   - tag_name: response_code
+///     regex: '(response_code=\.=(.*?);\.;)'
+/// ```
+///
+/// Metric handles are cached per set of label values.
+///
+/// To protect `Envoy` from unbounded number of stats, the number of distinct sets of
+/// label values can be limited with [`with_max_cardinality`]. Once the limit is reached,
+/// new sets of label values are accounted under the overflow metric, where every label has
+/// value [`OVERFLOW`].
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # use envoy::host::Result;
+/// # fn action() -> Result<()> {
+/// use envoy::host::Stats;
+/// use envoy::host::stats::CounterFamily;
+///
+/// let stats = Stats::default();
+///
+/// let requests_total = CounterFamily::new(stats, "requests_total", &["route", "response_code"])?
+///     .with_max_cardinality(100);
+///
+/// requests_total.with_labels(&["orders", "200"])?.inc()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`stats_tags`]: https://www.envoyproxy.io/docs/envoy/latest/api-v3/config/metrics/v3/stats.proto#config-metrics-v3-tagspecifier
+/// [`tag_extraction_regex`]: #method.tag_extraction_regex
+/// [`with_max_cardinality`]: #method.with_max_cardinality
+/// [`OVERFLOW`]: #associatedconstant.OVERFLOW
+pub struct MetricFamily<'a, M: ?Sized> {
+    stats: &'a dyn Stats,
+    name: String,
+    labels: Vec<String>,
+    max_cardinality: Option<usize>,
+    metrics: RefCell<HashMap<Vec<String>, Rc<M>>>,
+    overflow: RefCell<Option<Rc<M>>>,
+}
+
+impl<'a, M> MetricFamily<'a, M>
+where
+    M: Metric + ?Sized,
+{
+    /// Value of every label of the overflow metric.
+    pub const OVERFLOW: &'static str = "overflow";
+
+    /// Creates a new family of metrics.
+    ///
+    /// Label names must be non-empty and consist of `[a-zA-Z0-9_]` characters only.
+    pub fn new(stats: &'a dyn Stats, name: &str, labels: &[&str]) -> host::Result<Self> {
+        for label in labels {
+            ensure!(
+                !label.is_empty()
+                    && label
+                        .bytes()
+                        .all(|c| c.is_ascii_alphanumeric() || c == b'_'),
+                "invalid label name {:?} of metric {:?}",
+                label,
+                name
+            );
+        }
+        Ok(MetricFamily {
+            stats,
+            name: name.to_owned(),
+            labels: labels.iter().map(|label| (*label).to_owned()).collect(),
+            max_cardinality: None,
+            metrics: RefCell::new(HashMap::new()),
+            overflow: RefCell::new(None),
+        })
+    }
+
+    /// Limits the number of distinct sets of label values.
+    pub fn with_max_cardinality(mut self, max_cardinality: usize) -> Self {
+        self.max_cardinality = Some(max_cardinality);
+        self
+    }
+
+    /// Returns the name of the family.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns label names of the family.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Returns the number of distinct sets of label values seen so far,
+    /// not counting the overflow metric.
+    pub fn cardinality(&self) -> usize {
+        self.metrics.borrow().len()
+    }
+
+    /// Returns the metric for a given set of label values.
+    ///
+    /// Label values must be given in the same order as label names.
+    pub fn with_labels(&self, values: &[&str]) -> host::Result<Rc<M>> {
+        let key = self.key(values)?;
+        if let Some(metric) = self.metrics.borrow().get(&key) {
+            return Ok(Rc::clone(metric));
+        }
+        if matches!(self.max_cardinality, Some(max) if self.cardinality() >= max) {
+            return self.overflow();
+        }
+        let metric = M::define(self.stats, &self.encode(&key))?;
+        self.metrics.borrow_mut().insert(key, Rc::clone(&metric));
+        Ok(metric)
+    }
+
+    /// Returns the stat name of the metric with a given set of label values.
+    pub fn stat_name(&self, values: &[&str]) -> host::Result<String> {
+        self.key(values).map(|key| self.encode(&key))
+    }
+
+    /// Returns the regex that extracts a given label from stat names.
+    pub fn tag_extraction_regex(label: &str) -> String {
+        format!(r"({}=\.=(.*?);\.;)", label)
+    }
+
+    fn key(&self, values: &[&str]) -> host::Result<Vec<String>> {
+        ensure!(
+            values.len() == self.labels.len(),
+            "metric {:?} has {} labels, got {} values",
+            self.name,
+            self.labels.len(),
+            values.len()
+        );
+        Ok(values.iter().map(|value| sanitize(value)).collect())
+    }
+
+    fn overflow(&self) -> host::Result<Rc<M>> {
+        if let Some(metric) = self.overflow.borrow().as_ref() {
+            return Ok(Rc::clone(metric));
+        }
+        let key = vec![Self::OVERFLOW.to_owned(); self.labels.len()];
+        let metric = M::define(self.stats, &self.encode(&key))?;
+        *self.overflow.borrow_mut() = Some(Rc::clone(&metric));
+        Ok(metric)
+    }
+
+    fn encode(&self, values: &[String]) -> String {
+        let mut name = String::new();
+        for (label, value) in self.labels.iter().zip(values) {
+            name.push_str(label);
+            name.push_str("=.=");
+            name.push_str(value);
+            name.push_str(";.;");
+        }
+        name.push_str(&self.name);
+        name
+    }
+}
+
+/// Replaces characters that would break tag extraction.
+fn sanitize(value: &str) -> String {
+    value.replace(&[';', '='][..], "_")
+}
@@ -13,12 +13,25 @@
 // limitations under the License.
 
 //! `Envoy` `Stats API`.
+//!
+//! With `derive` feature enabled, `#[derive(Stats)]` generates a constructor
+//! of a struct holding stats.
 
 use std::ops::Deref;
 use std::rc::Rc;
 
 use crate::host;
 
+pub use self::family::{CounterFamily, GaugeFamily, HistogramFamily, Metric, MetricFamily};
+pub use self::scope::ScopedStats;
+pub use self::timer::{ExchangeTimer, TimeUnit, Timer};
+#[cfg(feature = "derive")]
+pub use envoy_sdk_derive::Stats;
+
+mod family;
+mod scope;
+mod timer;
+
 /// An interface of the `Envoy` `Stats API`.
 ///
 /// # Examples
@@ -0,0 +1,122 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stats with name prefixes.
+
+use super::{Counter, Gauge, Histogram, Stats};
+use crate::error::format_err;
+use crate::extension::ExtensionFactory;
+use crate::host::{self, StreamInfo};
+
+/// A view of [`Stats`] that prefixes names of all stats with a scope name.
+///
+/// Scopes can be nested, names of nested scopes are joined with `.`.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # use envoy::host::Result;
+/// # fn action() -> Result<()> {
+/// use envoy::host::Stats;
+/// use envoy::host::stats::ScopedStats;
+///
+/// let stats = ScopedStats::new(Stats::default(), "my_http_filter");
+///
+/// // creates `my_http_filter.requests_total`
+/// stats.counter("requests_total")?.inc()?;
+///
+/// // creates `my_http_filter.upstream.requests_total`
+/// stats.scope("upstream").counter("requests_total")?.inc()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Stats`]: trait.Stats.html
+pub struct ScopedStats<'a> {
+    stats: &'a dyn Stats,
+    prefix: String,
+}
+
+impl<'a> ScopedStats<'a> {
+    /// Creates a new scope with a given name.
+    ///
+    /// An empty name results in no prefix.
+    pub fn new(stats: &'a dyn Stats, name: &str) -> Self {
+        ScopedStats {
+            stats,
+            prefix: join("", name),
+        }
+    }
+
+    /// Creates a new scope named after the `Envoy` plugin,
+    /// i.e. the `name` field of the `Wasm` plugin config.
+    pub fn for_plugin(stats: &'a dyn Stats, stream_info: &dyn StreamInfo) -> host::Result<Self> {
+        let name = stream_info
+            .plugin()
+            .name()?
+            .ok_or_else(|| format_err!("name of the plugin is not available"))?;
+        Ok(Self::new(stats, &name))
+    }
+
+    /// Creates a new scope named after the extension, i.e. [`ExtensionFactory::name()`].
+    ///
+    /// [`ExtensionFactory::name()`]: ../../extension/factory/trait.ExtensionFactory.html#tymethod.name
+    pub fn for_extension<F>(stats: &'a dyn Stats) -> Self
+    where
+        F: ExtensionFactory,
+    {
+        Self::new(stats, F::name())
+    }
+
+    /// Creates a nested scope with a given name.
+    pub fn scope(&self, name: &str) -> ScopedStats<'a> {
+        ScopedStats {
+            stats: self.stats,
+            prefix: join(&self.prefix, name),
+        }
+    }
+
+    /// Returns the prefix of stat names, including the trailing `.`.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+}
+
+impl<'a> Stats for ScopedStats<'a> {
+    fn counter(&self, name: &str) -> host::Result<Box<dyn Counter>> {
+        self.stats.counter(&self.name(name))
+    }
+
+    fn gauge(&self, name: &str) -> host::Result<Box<dyn Gauge>> {
+        self.stats.gauge(&self.name(name))
+    }
+
+    fn histogram(&self, name: &str) -> host::Result<Box<dyn Histogram>> {
+        self.stats.histogram(&self.name(name))
+    }
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    let name = name.trim_matches('.');
+    if name.is_empty() {
+        prefix.to_owned()
+    } else {
+        format!("{}{}.", prefix, name)
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2020 Tetrate
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of latencies into histograms.
+
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use super::Histogram;
+use crate::host::{self, Clock};
+
+/// Unit in which durations are recorded into a [`Histogram`].
+///
+/// [`Histogram`]: trait.Histogram.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl TimeUnit {
+    /// Converts a duration into a value in this unit, rounding down.
+    pub fn convert(&self, duration: Duration) -> u64 {
+        let value = match self {
+            TimeUnit::Nanoseconds => duration.as_nanos(),
+            TimeUnit::Microseconds => duration.as_micros(),
+            TimeUnit::Milliseconds => duration.as_millis(),
+            TimeUnit::Seconds => duration.as_secs().into(),
+        };
+        if value > u64::MAX.into() {
+            u64::MAX
+        } else {
+            value as u64
+        }
+    }
+}
+
+/// A running measurement of latency.
+///
+/// Elapsed time gets recorded into the [`Histogram`] when the timer is either stopped
+/// or dropped. Use [`cancel`] to discard the measurement.
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// # use envoy::host::Result;
+/// # fn action() -> Result<()> {
+/// use std::rc::Rc;
+/// use envoy::host::{Clock, Stats};
+/// use envoy::host::stats::{TimeUnit, Timer};
+///
+/// let histogram = Rc::from(Stats::default().histogram("upstream_call_duration_ms")?);
+///
+/// let timer = Timer::start(Clock::default(), histogram, TimeUnit::Milliseconds)?;
+///
+/// # stringify! {
+/// ... do some work ...
+/// # };
+///
+/// timer.stop()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Histogram`]: trait.Histogram.html
+/// [`cancel`]: #method.cancel
+pub struct Timer<'a> {
+    clock: &'a dyn Clock,
+    histogram: Rc<dyn Histogram>,
+    unit: TimeUnit,
+    start: SystemTime,
+    done: bool,
+}
+
+impl<'a> Timer<'a> {
+    /// Starts a new timer.
+    pub fn start(
+        clock: &'a dyn Clock,
+        histogram: Rc<dyn Histogram>,
+        unit: TimeUnit,
+    ) -> host::Result<Self> {
+        Ok(Timer {
+            clock,
+            histogram,
+            unit,
+            start: clock.now()?,
+            done: false,
+        })
+    }
+
+    /// Returns time elapsed since the timer has been started.
+    pub fn elapsed(&self) -> host::Result<Duration> {
+        Ok(self
+            .clock
+            .now()?
+            .duration_since(self.start)
+            .unwrap_or_default())
+    }
+
+    /// Stops the timer and records elapsed time.
+    pub fn stop(mut self) -> host::Result<Duration> {
+        self.done = true;
+        self.record()
+    }
+
+    /// Stops the timer without recording elapsed time.
+    pub fn cancel(mut self) {
+        self.done = true;
+    }
+
+    fn record(&self) -> host::Result<Duration> {
+        let elapsed = self.elapsed()?;
+        self.histogram.record(self.unit.convert(elapsed))?;
+        Ok(elapsed)
+    }
+}
+
+impl<'a> Drop for Timer<'a> {
+    fn drop(&mut self) {
+        if !self.done {
+            // there is no way to report an error from `drop`
+            let _ = self.record();
+        }
+    }
+}
+
+/// Records latency of HTTP exchanges, from [`on_request_headers`]
+/// to [`on_exchange_complete`].
+///
+/// # Examples
+///
+/// ```
+/// # use envoy_sdk as envoy;
+/// use std::rc::Rc;
+///
+/// use envoy::extension::filter::http::{ExchangeCompleteOps, FilterHeadersStatus, HttpFilter, RequestHeadersOps};
+/// use envoy::extension::Result;
+/// use envoy::host::stats::{ExchangeTimer, Histogram, TimeUnit};
+/// use envoy::host::Clock;
+///
+/// struct MyHttpFilter<'a> {
+///     latency: ExchangeTimer<'a>,
+/// }
+///
+/// impl<'a> MyHttpFilter<'a> {
+///     fn new(clock: &'a dyn Clock, request_duration_ms: Rc<dyn Histogram>) -> Self {
+///         MyHttpFilter {
+///             latency: ExchangeTimer::new(clock, request_duration_ms, TimeUnit::Milliseconds),
+///         }
+///     }
+/// }
+///
+/// impl<'a> HttpFilter for MyHttpFilter<'a> {
+///     fn on_request_headers(&mut self, _num_headers: usize, _end_of_stream: bool, _ops: &dyn RequestHeadersOps) -> Result<FilterHeadersStatus> {
+///         self.latency.on_request_headers()?;
+///         Ok(FilterHeadersStatus::Continue)
+///     }
+///
+///     fn on_exchange_complete(&mut self, _ops: &dyn ExchangeCompleteOps) -> Result<()> {
+///         self.latency.on_exchange_complete()?;
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// [`on_request_headers`]: ../../extension/filter/http/trait.HttpFilter.html#method.on_request_headers
+/// [`on_exchange_complete`]: ../../extension/filter/http/trait.HttpFilter.html#method.on_exchange_complete
+pub struct ExchangeTimer<'a> {
+    clock: &'a dyn Clock,
+    histogram: Rc<dyn Histogram>,
+    unit: TimeUnit,
+    timer: Option<Timer<'a>>,
+}
+
+impl<'a> ExchangeTimer<'a> {
+    /// Creates a new timer.
+    pub fn new(clock: &'a dyn Clock, histogram: Rc<dyn Histogram>, unit: TimeUnit) -> Self {
+        ExchangeTimer {
+            clock,
+            histogram,
+            unit,
+            timer: None,
+        }
+    }
+
+    /// Starts measurement unless it has been started already.
+    pub fn on_request_headers(&mut self) -> host::Result<()> {
+        if self.timer.is_none() {
+            self.timer = Some(Timer::start(
+                self.clock,
+                Rc::clone(&self.histogram),
+                self.unit,
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Stops measurement and records elapsed time.
+    ///
+    /// # Return value
+    ///
+    /// Elapsed time, or `None` if measurement has not been started.
+    pub fn on_exchange_complete(&mut self) -> host::Result<Option<Duration>> {
+        self.timer.take().map(Timer::stop).transpose()
+    }
+}
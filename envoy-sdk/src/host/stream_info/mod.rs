@@ -345,6 +345,11 @@ impl<'a> ConnectionInfo<'a> {
         self.stream.property(Connection::REQUESTED_SERVER_NAME)
     }
 
+    /// Returns details on why the downstream connection has been terminated.
+    pub fn termination_details(&self) -> host::Result<Option<String>> {
+        self.stream.property(Connection::TERMINATION_DETAILS)
+    }
+
     /// Provides access to `TLS` properties of the downstream connection.
     pub fn tls(&'a self) -> DownstreamConnectionTlsInfo<'a> {
         DownstreamConnectionTlsInfo {
@@ -538,6 +543,19 @@ impl<'a> RouteInfo<'a> {
     pub fn name(&self) -> host::Result<Option<String>> {
         self.stream.property(Route::NAME)
     }
+
+    /// Returns the value of a given key in the route metadata under a given filter namespace,
+    /// i.e. `filter_metadata[filter][key]`.
+    ///
+    /// Only values of type `string` are supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - filter namespace in the route metadata.
+    /// * `key`    - key inside the filter namespace.
+    pub fn metadata(&self, filter: &str, key: &str) -> host::Result<Option<ByteString>> {
+        self.stream.property(&Route::metadata(filter, key))
+    }
 }
 
 /// Provides access to `plugin` properties.
@@ -311,6 +311,19 @@ impl Connection {
         _proxy_wasm_type: PhantomData,
     };
 
+    /// Details on why the downstream connection has been terminated.
+    pub const TERMINATION_DETAILS: &'static Property<
+        'static,
+        String,
+        proxy_wasm::types::ByteString,
+    > = &Property {
+        path: Path {
+            inner: PathKind::Static(&["connection", "termination_details"]),
+        },
+        _type: PhantomData,
+        _proxy_wasm_type: PhantomData,
+    };
+
     /// TLS version of the downstream TLS connection.
     pub const TLS_VERSION: &'static Property<'static, String, proxy_wasm::types::ByteString> =
         &Property {
@@ -653,6 +666,20 @@ impl Cluster {
 pub(super) struct Route {}
 
 impl Route {
+    /// Value of a given key in the route metadata under a given filter namespace.
+    pub fn metadata<'a>(
+        filter: &'a str,
+        key: &'a str,
+    ) -> Property<'a, ByteString, proxy_wasm::types::ByteString> {
+        Property {
+            path: Path {
+                inner: PathKind::Custom(vec!["route_metadata", "filter_metadata", filter, key]),
+            },
+            _type: PhantomData,
+            _proxy_wasm_type: PhantomData,
+        }
+    }
+
     /// Route name.
     pub const NAME: &'static Property<'static, String, proxy_wasm::types::ByteString> = &Property {
         path: Path {
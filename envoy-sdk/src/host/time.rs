@@ -14,7 +14,8 @@
 
 //! `Envoy` `Time API`.
 
-use std::time::SystemTime;
+use std::convert::TryInto;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::host;
 
@@ -76,6 +77,16 @@ impl dyn Clock {
     }
 }
 
+/// Returns the number of nanoseconds since the Unix epoch, saturating at `u64::MAX`.
+pub(crate) fn unix_nanos(time: SystemTime) -> u64 {
+    nanos(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+/// Returns the number of nanoseconds in a duration, saturating at `u64::MAX`.
+pub(crate) fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
 mod impls {
     use std::time::SystemTime;
 
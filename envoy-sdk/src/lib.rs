@@ -39,6 +39,7 @@
 //! * [`HttpFilter`]
 //! * [`NetworkFilter`]
 //! * [`AccessLogger`]
+//! * [`Service`]
 //!
 //! ## Supported Envoy APIs
 //!
@@ -66,6 +67,7 @@
 //! [`HttpFilter`]: extension/filter/http/index.html
 //! [`NetworkFilter`]: extension/filter/network/index.html
 //! [`AccessLogger`]: extension/access_logger/index.html
+//! [`Service`]: extension/service/index.html
 //!
 //! [`Clock`]: host/time/trait.Clock.html
 //! [`HttpClient`]: host/http/client/trait.HttpClient.html
@@ -46,11 +46,7 @@ impl<'a> SampleHttpFilterFactory<'a> {
         stream_info: &'a dyn StreamInfo,
         stats: &'a dyn Stats,
     ) -> Result<Self> {
-        let stats = SampleHttpFilterStats::new(
-            stats.counter("examples.http_filter.requests_total")?,
-            stats.gauge("examples.http_filter.requests_active")?,
-            stats.histogram("examples.http_filter.response_body_size_bytes")?,
-        );
+        let stats = SampleHttpFilterStats::new(stats)?;
         // Inject dependencies on Envoy host APIs
         Ok(SampleHttpFilterFactory {
             config: Rc::new(SampleHttpFilterConfig::default()),
@@ -12,9 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use envoy::host::stats::{Counter, Gauge, Histogram};
+use envoy::host::stats::{Counter, Gauge, Histogram, Stats};
 
 // Sample stats.
+#[derive(Stats)]
+#[stats(prefix = "examples.http_filter.")]
 pub struct SampleHttpFilterStats {
     requests_total: Box<dyn Counter>,
     requests_active: Box<dyn Gauge>,
@@ -22,18 +24,6 @@ pub struct SampleHttpFilterStats {
 }
 
 impl SampleHttpFilterStats {
-    pub fn new(
-        requests_total: Box<dyn Counter>,
-        requests_active: Box<dyn Gauge>,
-        response_body_size_bytes: Box<dyn Histogram>,
-    ) -> Self {
-        SampleHttpFilterStats {
-            requests_total,
-            requests_active,
-            response_body_size_bytes,
-        }
-    }
-
     pub fn requests_total(&self) -> &dyn Counter {
         &*self.requests_total
     }
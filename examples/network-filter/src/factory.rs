@@ -44,11 +44,7 @@ impl<'a> SampleNetworkFilterFactory<'a> {
         http_client: &'a dyn HttpClient,
         stats: &'a dyn Stats,
     ) -> Result<Self> {
-        let stats = SampleNetworkFilterStats::new(
-            stats.counter("examples.network_filter.requests_total")?,
-            stats.gauge("examples.network_filter.requests_active")?,
-            stats.histogram("examples.network_filter.response_body_size_bytes")?,
-        );
+        let stats = SampleNetworkFilterStats::new(stats)?;
         // Inject dependencies on Envoy host APIs
         Ok(SampleNetworkFilterFactory {
             config: Rc::new(SampleNetworkFilterConfig::default()),
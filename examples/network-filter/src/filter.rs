@@ -66,7 +66,10 @@ impl<'a> SampleNetworkFilter<'a> {
 
 impl<'a> NetworkFilter for SampleNetworkFilter<'a> {
     /// Is called when a new TCP connection is opened.
-    fn on_new_connection(&mut self) -> Result<network::FilterStatus> {
+    fn on_new_connection(
+        &mut self,
+        _ops: &dyn network::NewConnectionOps,
+    ) -> Result<network::FilterStatus> {
         // Update stats
         self.stats.requests_active().inc()?;
 